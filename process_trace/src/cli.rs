@@ -0,0 +1,655 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+//! Description of the command line, the usage text, `--help-json` and the
+//! shell completions are all generated from it so they never drift apart.
+
+use procutils::codes::EVENT_CODES;
+use procutils::loki::escape_json;
+use procutils::proc_analysis::{COLLECTORS, CSV_COLUMNS};
+use std::fmt::Write;
+
+/// One option of a command
+pub struct OptionSpec {
+    /// long name, with the leading dashes
+    pub name: &'static str,
+    /// short alias, with the leading dash
+    pub short: Option<&'static str>,
+    /// name of the value, None for a flag
+    pub value: Option<&'static str>,
+    /// one line help
+    pub help: &'static str,
+}
+
+/// One subcommand
+pub struct CommandSpec {
+    /// subcommand name
+    pub name: &'static str,
+    /// positional arguments
+    pub args: &'static str,
+    /// one line help
+    pub help: &'static str,
+    /// options
+    pub options: &'static [OptionSpec],
+}
+
+/// Shells supported by `completion`
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// All subcommands
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "trace",
+        args: "",
+        help: "trace processes (default command)",
+        options: &[
+            OptionSpec { name: "--name", short: Some("-n"), value: Some("name"), help: "trace processes by name, repeatable" },
+            OptionSpec { name: "--pid", short: Some("-p"), value: Some("pid"), help: "trace a process by pid, repeatable" },
+            OptionSpec { name: "--target", short: Some("-t"), value: Some("scheme:query"), help: "trace what a resolver finds, e.g. uid:1000, package:<name>, cgroup:/top-app, service:<name>, repeatable" },
+            OptionSpec { name: "--match", short: Some("-m"), value: Some("exact|substring|regex"), help: "how --name matches comm and command lines, default exact" },
+            OptionSpec { name: "--all-matches", short: None, value: None, help: "trace every process a --name matches, default the oldest" },
+            OptionSpec { name: "--duration", short: Some("-d"), value: Some("seconds"), help: "trace duration, default 60" },
+            OptionSpec { name: "--until-exit", short: None, value: None, help: "trace until the targets exit" },
+            OptionSpec { name: "--follow", short: None, value: None, help: "wait for targets to start and re-attach when they restart" },
+            OptionSpec { name: "--interval", short: Some("-i"), value: Some("seconds|auto"), help: "sampling interval, default 10, auto calibrates it" },
+            OptionSpec { name: "--overhead-budget", short: None, value: Some("percent"), help: "share of one core --interval auto may spend, default 1" },
+            OptionSpec { name: "--profile", short: None, value: Some("states:interval[:metrics]"), help: "interval and metric groups while screen-on, screen-off, charging or battery (+ joined), repeatable" },
+            OptionSpec { name: "--output-dir", short: Some("-O"), value: Some("dir"), help: "where the session directory is created, may use {name}, {pid}, {date}, {device_serial}, {id} and {tag:<key>}" },
+            OptionSpec { name: "--session-name", short: None, value: Some("template"), help: "name of the session directory instead of trace_session_<id>, same tokens as --output-dir, must not exist yet" },
+            OptionSpec { name: "--format", short: Some("-f"), value: Some("csv|jsonl|perfetto|threads"), help: "output format, repeatable, default csv and jsonl" },
+            OptionSpec { name: "--per-thread", short: None, value: None, help: "also record every thread, adds the threads format" },
+            OptionSpec { name: "--metrics", short: None, value: Some("io,fd,oom,cgroup,sched|none"), help: "optional metric groups, default all, io and fd need ptrace access" },
+            OptionSpec { name: "--tag", short: None, value: Some("key=value"), help: "tag every output of the trace, repeatable" },
+            OptionSpec { name: "--transform", short: None, value: Some("column:op[+op]"), help: "transform a column before it is written: rate, scale=<factor>, ema=<alpha>, clamp=<min>..<max>, repeatable" },
+            OptionSpec { name: "--compact", short: None, value: None, help: "write priority, nice, thread count and other rarely changing columns only when they change in the jsonl and perfetto outputs" },
+            OptionSpec { name: "--smooth", short: None, value: Some("column:ema|sma=window"), help: "add a moving average of a column over this many samples as a column, after the transforms, repeatable" },
+            OptionSpec { name: "--trend", short: None, value: Some("store"), help: "append the summary of the csv outputs to a trend store, keyed by the build tag" },
+            OptionSpec { name: "--score", short: None, value: Some("column[/s]:weight@full,..."), help: "health score formula of the --trend summaries, default cpuOccupancyRate:0.4@0.25,pss:0.3@500000,majflt/s:0.2@20,voluntaryCtxtSwitches/s:0.1@1000" },
+            OptionSpec { name: "--idle-baseline", short: None, value: Some("seconds"), help: "measure the idle rates of the targets this long first and also summarize without them" },
+            OptionSpec { name: "--alert", short: None, value: Some("column>limit|column<limit[:clear=level][:for=seconds]"), help: "print when a sample crosses a limit and when it is back past the clear level, after staying there for the seconds, repeatable" },
+            OptionSpec { name: "--simpleperf", short: None, value: Some("seconds"), help: "record a process with simpleperf this long when a CPU --alert fires, up to 3 times" },
+            OptionSpec { name: "--resume", short: None, value: Some("session"), help: "resume a killed session" },
+            OptionSpec { name: "--drop-to", short: None, value: Some("user[:group]"), help: "as root, drop to this user once the targets are open" },
+            OptionSpec { name: "--keep-ptrace", short: None, value: None, help: "keep CAP_SYS_PTRACE after --drop-to, for new threads" },
+            OptionSpec { name: "--slow-fraction", short: None, value: Some("fraction"), help: "warn when a sample takes this fraction of the interval, default 0.5" },
+            OptionSpec { name: "--rebaseline-on-exec", short: None, value: None, help: "start the deltas over when a target execs another binary, e.g. behind a wrapper script" },
+            OptionSpec { name: "--memory-trigger", short: None, value: Some("percent"), help: "sample between the ticks when the RSS changes by this percent or the cgroup reports memory.events" },
+            OptionSpec { name: "--binder-priority", short: None, value: Some("ms"), help: "read the priority of the binder threads this often and record every boost and restore as an event" },
+            OptionSpec { name: "--cpu-split", short: None, value: None, help: "report the CPU used in the foreground (top-app), in the background and with the screen off" },
+            OptionSpec { name: "--smaps-incremental", short: None, value: Some("samples"), help: "without smaps_rollup, parse only the smaps mappings whose header changed, all of them every this many samples" },
+            OptionSpec { name: "--flush-bytes", short: None, value: Some("bytes"), help: "bytes of output buffered before they are written, default 65536" },
+            OptionSpec { name: "--page-cache", short: None, value: Some("auto|keep|drop"), help: "drop written output pages from the page cache, default auto drops them with at most 2 GiB of RAM" },
+            OptionSpec { name: "--precision", short: None, value: Some("column=digits"), help: "digits after the decimal point of a fractional column, repeatable" },
+            OptionSpec { name: "--progress", short: None, value: Some("seconds"), help: "report the time traced, samples, output size and ETA on stderr this often" },
+            OptionSpec { name: "--read-timeout", short: None, value: Some("ms"), help: "skip collector nodes hanging this long, quarantine them after 3 timeouts, default 1000, 0 never times out" },
+        ],
+    },
+    CommandSpec {
+        name: "calibrate",
+        args: "",
+        help: "measure the sampling cost of targets and recommend an interval",
+        options: &[
+            OptionSpec { name: "--name", short: Some("-n"), value: Some("name"), help: "calibrate processes by name, repeatable" },
+            OptionSpec { name: "--pid", short: Some("-p"), value: Some("pid"), help: "calibrate a process by pid, repeatable" },
+            OptionSpec { name: "--target", short: Some("-t"), value: Some("scheme:query"), help: "calibrate what a resolver finds, e.g. uid:1000, package:<name>, cgroup:/top-app, service:<name>, repeatable" },
+            OptionSpec { name: "--match", short: Some("-m"), value: Some("exact|substring|regex"), help: "how --name matches comm and command lines, default exact" },
+            OptionSpec { name: "--per-thread", short: None, value: None, help: "include the cost of per thread samples" },
+            OptionSpec { name: "--metrics", short: None, value: Some("io,fd,oom,cgroup,sched|none"), help: "optional metric groups, default all" },
+            OptionSpec { name: "--overhead-budget", short: None, value: Some("percent"), help: "share of one core sampling may spend, default 1" },
+        ],
+    },
+    CommandSpec {
+        name: "noise",
+        args: "",
+        help: "trace a target in idle windows and store the spread of every column as the noise floor of the device",
+        options: &[
+            OptionSpec { name: "--name", short: Some("-n"), value: Some("name"), help: "measure a process by name" },
+            OptionSpec { name: "--pid", short: Some("-p"), value: Some("pid"), help: "measure a process by pid" },
+            OptionSpec { name: "--target", short: Some("-t"), value: Some("scheme:query"), help: "measure what a resolver finds" },
+            OptionSpec { name: "--match", short: Some("-m"), value: Some("exact|substring|regex"), help: "how --name matches comm and command lines, default exact" },
+            OptionSpec { name: "--windows", short: None, value: Some("count"), help: "idle windows, default 5" },
+            OptionSpec { name: "--window", short: None, value: Some("seconds"), help: "length of a window, default 10" },
+            OptionSpec { name: "--interval", short: Some("-i"), value: Some("seconds"), help: "sampling interval, default 1" },
+            OptionSpec { name: "--metrics", short: None, value: Some("io,fd,oom,cgroup,sched|none"), help: "optional metric groups, default all" },
+            OptionSpec { name: "--output-dir", short: Some("-O"), value: Some("dir"), help: "where the window sessions are created" },
+            OptionSpec { name: "--store", short: None, value: Some("dir"), help: "directory of the noise_<device>.csv stores, default the current one" },
+            OptionSpec { name: "--device", short: None, value: Some("name"), help: "device the floor is stored for, default the serial" },
+        ],
+    },
+    CommandSpec {
+        name: "run",
+        args: "-- <command> [args]...",
+        help: "run a command under trace from its first instant, sum its children by name and exit with its status",
+        options: &[
+            OptionSpec { name: "--interval", short: Some("-i"), value: Some("seconds"), help: "sampling interval, default 1" },
+            OptionSpec { name: "--output-dir", short: Some("-O"), value: Some("dir"), help: "where the session directory is created, may use {name} of the command, {date}, {device_serial}, {id} and {tag:<key>}" },
+            OptionSpec { name: "--session-name", short: None, value: Some("template"), help: "name of the session directory instead of trace_session_<id>, same tokens as --output-dir, must not exist yet" },
+            OptionSpec { name: "--format", short: Some("-f"), value: Some("csv|jsonl|perfetto|threads"), help: "output format, repeatable, default csv and jsonl" },
+            OptionSpec { name: "--per-thread", short: None, value: None, help: "also record every thread, adds the threads format" },
+            OptionSpec { name: "--metrics", short: None, value: Some("io,fd,oom,cgroup,sched|none"), help: "optional metric groups, default all" },
+            OptionSpec { name: "--tag", short: None, value: Some("key=value"), help: "tag every output of the trace, repeatable" },
+            OptionSpec { name: "--alert", short: None, value: Some("column>limit|column<limit[:clear=level][:for=seconds]"), help: "print when a sample crosses a limit and when it is back past the clear level, after staying there for the seconds, repeatable" },
+            OptionSpec { name: "--rebaseline-on-exec", short: None, value: None, help: "start the deltas over when the command execs another binary" },
+            OptionSpec { name: "--memory-trigger", short: None, value: Some("percent"), help: "sample between the ticks when the RSS changes by this percent or the cgroup reports memory.events" },
+            OptionSpec { name: "--binder-priority", short: None, value: Some("ms"), help: "read the priority of the binder threads this often and record every boost and restore as an event" },
+            OptionSpec { name: "--cpu-split", short: None, value: None, help: "report the CPU used in the foreground (top-app), in the background and with the screen off" },
+            OptionSpec { name: "--smaps-incremental", short: None, value: Some("samples"), help: "without smaps_rollup, parse only the smaps mappings whose header changed, all of them every this many samples" },
+            OptionSpec { name: "--flush-bytes", short: None, value: Some("bytes"), help: "bytes of output buffered before they are written, default 65536" },
+            OptionSpec { name: "--page-cache", short: None, value: Some("auto|keep|drop"), help: "drop written output pages from the page cache, default auto drops them with at most 2 GiB of RAM" },
+            OptionSpec { name: "--precision", short: None, value: Some("column=digits"), help: "digits after the decimal point of a fractional column, repeatable" },
+            OptionSpec { name: "--progress", short: None, value: Some("seconds"), help: "report the time traced, samples, output size and ETA on stderr this often" },
+            OptionSpec { name: "--sandbox", short: None, value: None, help: "run in a fresh cgroup and record its counters, implied by the limits" },
+            OptionSpec { name: "--memory-max", short: None, value: Some("size"), help: "memory limit of the sandbox, e.g. 512M" },
+            OptionSpec { name: "--cpu-max", short: None, value: Some("percent"), help: "CPU limit of the sandbox in percent of one CPU" },
+            OptionSpec { name: "--pids-max", short: None, value: Some("count"), help: "process limit of the sandbox" },
+            OptionSpec { name: "--cgroup-root", short: None, value: Some("dir"), help: "cgroup v2 directory the sandbox is created in, default /sys/fs/cgroup" },
+        ],
+    },
+    CommandSpec {
+        name: "watch",
+        args: "",
+        help: "wait for a process, trace it until it exits and wait for the next one, a session each",
+        options: &[
+            OptionSpec { name: "--name", short: Some("-n"), value: Some("name"), help: "watch processes by name" },
+            OptionSpec { name: "--target", short: Some("-t"), value: Some("scheme:query"), help: "watch what a resolver finds, e.g. uid:1000" },
+            OptionSpec { name: "--match", short: Some("-m"), value: Some("exact|substring|regex"), help: "how --name matches comm and command lines, default exact" },
+            OptionSpec { name: "--interval", short: Some("-i"), value: Some("seconds"), help: "sampling interval, default 1" },
+            OptionSpec { name: "--duration", short: Some("-d"), value: Some("seconds"), help: "stop waiting after this long, default runs until interrupted" },
+            OptionSpec { name: "--count", short: None, value: Some("count"), help: "stop after this many occurrences" },
+            OptionSpec { name: "--poll", short: None, value: Some("ms"), help: "how often to look for new occurrences, default 200" },
+            OptionSpec { name: "--skip-running", short: None, value: None, help: "ignore the processes running when the watch starts" },
+            OptionSpec { name: "--output-dir", short: Some("-O"), value: Some("dir"), help: "where the session directories are created" },
+            OptionSpec { name: "--format", short: Some("-f"), value: Some("csv|jsonl|perfetto|threads"), help: "output format, repeatable, default csv and jsonl" },
+            OptionSpec { name: "--metrics", short: None, value: Some("io,fd,oom,cgroup,sched|none"), help: "optional metric groups, default all" },
+            OptionSpec { name: "--tag", short: None, value: Some("key=value"), help: "tag every session, repeatable" },
+        ],
+    },
+    CommandSpec {
+        name: "soak",
+        args: "",
+        help: "record processes for weeks at a low rate, a session a day rolled up into a trend store",
+        options: &[
+            OptionSpec { name: "--name", short: Some("-n"), value: Some("name"), help: "soak processes by name, repeatable" },
+            OptionSpec { name: "--target", short: Some("-t"), value: Some("scheme:query"), help: "soak what a resolver finds, e.g. uid:1000, repeatable" },
+            OptionSpec { name: "--match", short: Some("-m"), value: Some("exact|substring|regex"), help: "how --name matches comm and command lines, default exact" },
+            OptionSpec { name: "--interval", short: Some("-i"), value: Some("seconds"), help: "sampling interval, default 300" },
+            OptionSpec { name: "--day", short: None, value: Some("seconds"), help: "length of one day session and rollup, default 86400" },
+            OptionSpec { name: "--days", short: None, value: Some("days"), help: "stop after this many days, default runs until interrupted" },
+            OptionSpec { name: "--keep-days", short: None, value: Some("days"), help: "day sessions kept, older ones are removed, 0 keeps all, default 14" },
+            OptionSpec { name: "--output-dir", short: Some("-O"), value: Some("dir"), help: "where the day sessions and daily_rollup.csv are created" },
+            OptionSpec { name: "--format", short: Some("-f"), value: Some("csv|jsonl|perfetto|threads"), help: "output format, repeatable, default csv" },
+            OptionSpec { name: "--metrics", short: None, value: Some("io,fd,oom,cgroup,sched|none"), help: "optional metric groups, default all" },
+            OptionSpec { name: "--tag", short: None, value: Some("key=value"), help: "tag every day session, repeatable" },
+            OptionSpec { name: "--score", short: None, value: Some("column[/s]:weight@full,..."), help: "health score formula of the rollups" },
+        ],
+    },
+    CommandSpec {
+        name: "rollup",
+        args: "",
+        help: "sum the lifetimes, CPU and peak RSS of short-lived processes by name",
+        options: &[
+            OptionSpec { name: "--name", short: Some("-n"), value: Some("name"), help: "account processes by name" },
+            OptionSpec { name: "--target", short: Some("-t"), value: Some("scheme:query"), help: "account what a resolver finds, e.g. uid:1000" },
+            OptionSpec { name: "--match", short: Some("-m"), value: Some("exact|substring|regex"), help: "how --name matches comm and command lines, default exact" },
+            OptionSpec { name: "--duration", short: Some("-d"), value: Some("seconds"), help: "accounting duration, default 60, 0 runs until interrupted" },
+            OptionSpec { name: "--poll", short: None, value: Some("ms"), help: "how often processes are read, default 200" },
+            OptionSpec { name: "--interval", short: Some("-i"), value: Some("seconds"), help: "print the totals this often, default 10, 0 only at the end" },
+            OptionSpec { name: "--output", short: Some("-o"), value: Some("rollup.csv"), help: "also write the totals as CSV" },
+        ],
+    },
+    CommandSpec {
+        name: "binder",
+        args: "",
+        help: "report which processes drove the binder threads of a process, from the binder transaction log",
+        options: &[
+            OptionSpec { name: "--name", short: Some("-n"), value: Some("name"), help: "pair the binder work of a process by name" },
+            OptionSpec { name: "--pid", short: Some("-p"), value: Some("pid"), help: "pair the binder work of a process by pid" },
+            OptionSpec { name: "--target", short: Some("-t"), value: Some("scheme:query"), help: "pair the binder work of what a resolver finds" },
+            OptionSpec { name: "--match", short: Some("-m"), value: Some("exact|substring|regex"), help: "how --name matches comm and command lines, default exact" },
+            OptionSpec { name: "--duration", short: Some("-d"), value: Some("seconds"), help: "report after this long, default 60" },
+            OptionSpec { name: "--poll", short: None, value: Some("ms"), help: "how often the transaction log is read, default 100" },
+        ],
+    },
+    CommandSpec {
+        name: "top",
+        args: "",
+        help: "sample every process and show the top ones, refreshed every interval",
+        options: &[
+            OptionSpec { name: "--sort", short: Some("-s"), value: Some("cpu|pss|rss"), help: "order of the processes, default cpu" },
+            OptionSpec { name: "--count", short: Some("-n"), value: Some("count"), help: "processes shown, default 10" },
+            OptionSpec { name: "--interval", short: Some("-i"), value: Some("seconds"), help: "sampling interval, default 3" },
+            OptionSpec { name: "--duration", short: Some("-d"), value: Some("seconds"), help: "stop after this long, default runs until interrupted" },
+            OptionSpec { name: "--metrics", short: None, value: Some("io,fd,oom,cgroup,sched|none"), help: "optional metric groups of the top processes, default none" },
+            OptionSpec { name: "--group-by-exe", short: None, value: None, help: "also show the top binaries with the summed CPU, RSS and, when sorting by pss, PSS of their processes" },
+            OptionSpec { name: "--color", short: None, value: Some("auto|always|never"), help: "color values over the thresholds, default auto colors on a terminal unless NO_COLOR is set" },
+            OptionSpec { name: "--cpu-warn", short: None, value: Some("percent"), help: "show CPU over this percent of all cores red, default 10" },
+            OptionSpec { name: "--rss-rising", short: None, value: Some("intervals"), help: "show RSS yellow once it grew this many intervals in a row, default 3" },
+        ],
+    },
+    CommandSpec {
+        name: "selftest",
+        args: "",
+        help: "run every collector once against the tracer itself, report which work, which need privileges and implausible values",
+        options: &[
+            OptionSpec { name: "--pid", short: Some("-p"), value: Some("pid"), help: "test against this process instead, e.g. 1 to see what needs root" },
+        ],
+    },
+    CommandSpec {
+        name: "crosscheck",
+        args: "",
+        help: "cross check the parsed values of a process against independent sources and report discrepancies",
+        options: &[
+            OptionSpec { name: "--pid", short: Some("-p"), value: Some("pid"), help: "check this process, default the tracer itself" },
+            OptionSpec { name: "--interval", short: Some("-i"), value: Some("seconds"), help: "seconds between checks, default 10" },
+            OptionSpec { name: "--duration", short: Some("-d"), value: Some("seconds"), help: "how long to check, default 60, 0 checks once" },
+            OptionSpec { name: "--all", short: Some("-a"), value: None, help: "print every check, not only discrepancies" },
+        ],
+    },
+    CommandSpec {
+        name: "load",
+        args: "",
+        help: "generate a known CPU and memory load to trace, printing its ground truth every second",
+        options: &[
+            OptionSpec { name: "--cpu", short: Some("-c"), value: Some("percent"), help: "busy share of one core per thread, default 50" },
+            OptionSpec { name: "--threads", short: Some("-j"), value: Some("n"), help: "threads spinning at --cpu, default 1" },
+            OptionSpec { name: "--memory", short: Some("-m"), value: Some("MB"), help: "memory to allocate and touch, default 0" },
+            OptionSpec { name: "--ramp", short: Some("-r"), value: Some("seconds"), help: "ramp the memory up over this long, default at once" },
+            OptionSpec { name: "--duration", short: Some("-d"), value: Some("seconds"), help: "how long to run, default 60, 0 runs until killed" },
+        ],
+    },
+    CommandSpec { name: "snapshot", args: "<name>", help: "print one cumulative sample of a process, the name may be substring:<text> or regex:<pattern>", options: &[] },
+    CommandSpec {
+        name: "analyze",
+        args: "<trace.csv>",
+        help: "summarize a trace",
+        options: &[
+            OptionSpec { name: "--idle", short: None, value: Some("idle_baseline.csv"), help: "subtract the idle rates of a --idle-baseline first" },
+            OptionSpec { name: "--score", short: None, value: Some("column[/s]:weight@full,..."), help: "health score formula, each column is normalized against its full value, /s divides by the interval" },
+            OptionSpec { name: "--envelope", short: None, value: None, help: "estimate the range of memory and other levels between the samples, with VmHWM" },
+        ],
+    },
+    CommandSpec {
+        name: "compare",
+        args: "[<baseline.csv> <candidate.csv>]",
+        help: "compare two traces, or groups of runs with confidence intervals and significance tests",
+        options: &[
+            OptionSpec { name: "--baseline", short: Some("-b"), value: Some("trace.csv"), help: "a baseline run, repeatable" },
+            OptionSpec { name: "--candidate", short: Some("-c"), value: Some("trace.csv"), help: "a candidate run, repeatable" },
+            OptionSpec { name: "--alpha", short: None, value: Some("level"), help: "significance level, default 0.05" },
+            OptionSpec { name: "--exclude-outliers", short: None, value: None, help: "leave outlier runs of groups of 3 or more out of the tests" },
+            OptionSpec { name: "--noise", short: None, value: Some("noise.csv"), help: "call significant deltas within the noise floor of the device within noise" },
+            OptionSpec { name: "--noise-target", short: None, value: Some("label"), help: "target of the noise store to use, default from the baseline file name" },
+            OptionSpec { name: "--resample", short: None, value: Some("linear|locf"), help: "how traces of different intervals are put on a common time base, default linear" },
+        ],
+    },
+    CommandSpec {
+        name: "report",
+        args: "<trace.csv>",
+        help: "write an HTML report",
+        options: &[
+            OptionSpec { name: "--output", short: Some("-o"), value: Some("out.html"), help: "report path" },
+            OptionSpec { name: "--envelope", short: None, value: None, help: "chart the range of memory and other levels between the samples as bands" },
+        ],
+    },
+    CommandSpec {
+        name: "merge",
+        args: "<trace.csv>...",
+        help: "merge pieces of one trace",
+        options: &[
+            OptionSpec { name: "--output", short: Some("-o"), value: Some("out.csv"), help: "merged trace path" },
+        ],
+    },
+    CommandSpec {
+        name: "convert",
+        args: "<trace.csv>",
+        help: "convert a trace of an older tracer to the current schema",
+        options: &[
+            OptionSpec { name: "--output", short: Some("-o"), value: Some("out.csv"), help: "converted trace path" },
+            OptionSpec { name: "--precision", short: None, value: Some("column=digits"), help: "digits after the decimal point of a fractional column, repeatable" },
+        ],
+    },
+    CommandSpec { name: "verify", args: "<trace.csv>...", help: "check traces for broken rows", options: &[] },
+    CommandSpec {
+        name: "trend",
+        args: "<store>",
+        help: "print the history of run summaries across builds",
+        options: &[
+            OptionSpec { name: "--append", short: Some("-a"), value: Some("trace.csv"), help: "append the summary of a run first, repeatable" },
+            OptionSpec { name: "--key", short: Some("-k"), value: Some("key"), help: "key of appended runs, default their build tag or file name" },
+            OptionSpec { name: "--target", short: Some("-t"), value: Some("label"), help: "target of appended runs, default from the file name" },
+            OptionSpec { name: "--column", short: Some("-c"), value: Some("column"), help: "column to show, repeatable, default cpuOccupancyRate, pss, vmRss and healthScore" },
+            OptionSpec { name: "--score", short: None, value: Some("column[/s]:weight@full,..."), help: "health score formula of appended runs" },
+        ],
+    },
+    CommandSpec {
+        name: "contention",
+        args: "<session>|<trace.csv>...",
+        help: "find targets of one session whose CPU spikes coincide with the run delay spikes of another",
+        options: &[
+            OptionSpec { name: "--threshold", short: None, value: Some("z"), help: "modified z-score of a spike, default 3.5" },
+            OptionSpec { name: "--resample", short: None, value: Some("linear|locf"), help: "how traces of different intervals are put on a common time base, default linear" },
+        ],
+    },
+    CommandSpec {
+        name: "deploy",
+        args: "",
+        help: "build the static binary and push it to a device (host only)",
+        options: &[
+            OptionSpec { name: "--serial", short: Some("-s"), value: Some("serial"), help: "adb serial of the device" },
+            OptionSpec { name: "--abi", short: None, value: Some("abi"), help: "device ABI, default asks the device" },
+            OptionSpec { name: "--binary", short: Some("-b"), value: Some("path"), help: "push a prebuilt static binary, skip the build" },
+            OptionSpec { name: "--install", short: None, value: None, help: "install under /data/local/tmp/bin to keep using it" },
+        ],
+    },
+    CommandSpec {
+        name: "remote",
+        args: "-- <command> <options>...",
+        help: "run a trace on a device over adb, then pull and verify its session (host only)",
+        options: &[
+            OptionSpec { name: "--serial", short: Some("-s"), value: Some("serial"), help: "adb serial of the device" },
+            OptionSpec { name: "--device-binary", short: None, value: Some("path"), help: "tracer on the device, default /data/local/tmp/process_trace" },
+            OptionSpec { name: "--pull-dir", short: None, value: Some("dir"), help: "where the session is pulled to, default the current directory" },
+            OptionSpec { name: "--delete-device-copy", short: None, value: None, help: "remove the session from the device once it is verified" },
+        ],
+    },
+    CommandSpec { name: "completion", args: "<bash|zsh|fish>", help: "print a shell completion script", options: &[] },
+    CommandSpec { name: "help", args: "", help: "print this help", options: &[] },
+];
+
+/// Commands and options of optional features, left out of builds without them
+const FEATURE_GATES: &[(&str, &str)] = &[
+    ("noise", "analysis"), ("analyze", "analysis"), ("compare", "analysis"), ("report", "analysis"),
+    ("merge", "analysis"), ("convert", "analysis"), ("verify", "analysis"), ("trend", "analysis"), ("contention", "analysis"), ("soak", "analysis"),
+    ("--trend", "analysis"), ("--score", "analysis"), ("--idle-baseline", "analysis"),
+    ("watch", "top"), ("rollup", "top"), ("binder", "top"), ("top", "top"),
+    ("--alert", "alert"), ("--simpleperf", "alert"),
+    ("run", "run"),
+];
+
+/// Optional features, see the `procutils` crate, and whether this build has them
+pub const FEATURES: &[(&str, bool)] = &[
+    ("json", cfg!(feature = "json")),
+    ("analysis", cfg!(feature = "analysis")),
+    ("top", cfg!(feature = "top")),
+    ("alert", cfg!(feature = "alert")),
+    ("run", cfg!(feature = "run")),
+];
+
+/// Whether this build has an optional feature
+pub fn has_feature(feature: &str) -> bool {
+    FEATURES.iter().any(|(name, built)| *name == feature && *built)
+}
+
+fn enabled(name: &str) -> bool {
+    FEATURE_GATES.iter().filter(|(gated, _)| *gated == name).all(|(_, feature)| has_feature(feature))
+}
+
+/// Subcommands of this build
+pub fn commands() -> impl Iterator<Item = &'static CommandSpec> {
+    COMMANDS.iter().filter(|c| enabled(c.name))
+}
+
+/// Options of a command in this build
+pub fn options(command: &CommandSpec) -> impl Iterator<Item = &'static OptionSpec> {
+    command.options.iter().filter(|o| enabled(o.name))
+}
+
+/// Options accepted before any command, the `COMMON_OPTIONS` among them
+/// also after every command
+pub const GLOBAL_OPTIONS: &[OptionSpec] = &[
+    OptionSpec { name: "--help", short: Some("-h"), value: None, help: "print this help" },
+    OptionSpec { name: "--help-json", short: None, value: None, help: "describe commands, collectors and columns as JSON" },
+    OptionSpec { name: "--version", short: None, value: None, help: "print the version" },
+    OptionSpec { name: "--strict", short: None, value: None, help: "read-only mode, refuse commands and writes which change the system" },
+    OptionSpec { name: "--audit-log", short: None, value: Some("path"), help: "log every external command and system write, default audit.log of strict sessions" },
+];
+
+// GLOBAL_OPTIONS every command also accepts
+const COMMON_OPTIONS: &[&str] = &["--strict", "--audit-log"];
+
+/// A common option and whether `arg` also holds its value, None for other args
+pub fn common_option(arg: &str) -> Option<(&'static OptionSpec, bool)> {
+    let (key, inline) = match arg.split_once('=') {
+        Some((key, _)) => (key, true),
+        None => (arg, false),
+    };
+    GLOBAL_OPTIONS.iter()
+            .filter(|o| COMMON_OPTIONS.contains(&o.name))
+            .find(|o| o.name == key)
+            .map(|o| (o, inline))
+}
+
+/// Options and positional arguments of a parsed command line
+#[derive(Default)]
+pub struct ParsedArgs {
+    /// options in the given order, keyed by long name
+    pub options: Vec<(&'static str, Option<String>)>,
+    /// positional arguments
+    pub positionals: Vec<String>,
+}
+
+impl ParsedArgs {
+    /// All values of a repeatable option
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.options.iter()
+                .filter(|(n, _)| *n == name)
+                .filter_map(|(_, v)| v.as_deref())
+                .collect()
+    }
+
+    /// Last value of an option
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).pop()
+    }
+
+    /// Whether a flag is given
+    pub fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(n, _)| *n == name)
+    }
+
+    /// Value of an option parsed as a number
+    pub fn number(&self, name: &str) -> Result<Option<i64>, String> {
+        self.value(name)
+                .map(|v| v.parse::<i64>().map_err(|_| format!("{} needs a number, got {}", name, v)))
+                .transpose()
+    }
+}
+
+/// Find a subcommand
+pub fn find_command(name: &str) -> Option<&'static CommandSpec> {
+    commands().find(|c| c.name == name)
+}
+
+/// Parse the args of a command, `--opt value`, `--opt=value` and `-o value` are accepted,
+/// everything after `--` is positional
+pub fn parse_args(command: &CommandSpec, args: &[String]) -> Result<ParsedArgs, String> {
+    let mut parsed = ParsedArgs::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            parsed.positionals.extend(iter.by_ref().cloned());
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            parsed.positionals.push(arg.clone());
+            continue;
+        }
+        let (key, inline_value) = match arg.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let option = options(command)
+                .find(|o| o.name == key || o.short == Some(key))
+                .or_else(|| common_option(key).map(|(o, _)| o))
+                .ok_or_else(|| format!("unknown option {} of {}", key, command.name))?;
+        let value = match (option.value, inline_value) {
+            (Some(_), Some(value)) => Some(value),
+            (Some(_), None) => Some(iter.next()
+                    .ok_or_else(|| format!("missing value of {}", option.name))?
+                    .clone()),
+            (None, Some(_)) => return Err(format!("{} takes no value", option.name)),
+            (None, None) => None,
+        };
+        parsed.options.push((option.name, value));
+    }
+    Ok(parsed)
+}
+
+fn option_usage(option: &OptionSpec) -> String {
+    let name = match option.short {
+        Some(short) => format!("{}, {}", short, option.name),
+        None => option.name.to_string(),
+    };
+    match option.value {
+        Some(value) => format!("{} <{}>", name, value),
+        None => name,
+    }
+}
+
+/// Usage text
+pub fn usage() -> String {
+    let mut out = String::from("usage: process_trace [options] <command> [args]\n\noptions:\n");
+    for option in GLOBAL_OPTIONS {
+        let _ = writeln!(out, "  {:<36} {}", option_usage(option), option.help);
+    }
+    out.push_str("\ncommands:\n");
+    for command in commands() {
+        let _ = writeln!(out, "  {:<36} {}", format!("{} {}", command.name, command.args), command.help);
+        for option in options(command) {
+            let _ = writeln!(out, "    {:<34} {}", option_usage(option), option.help);
+        }
+    }
+    out
+}
+
+fn json_options<'a>(options: impl Iterator<Item = &'a OptionSpec>) -> String {
+    let items: Vec<String> = options.map(|o| format!(
+            "{{\"name\":\"{}\",\"short\":{},\"value\":{},\"help\":\"{}\"}}",
+            escape_json(o.name),
+            o.short.map(|v| format!("\"{}\"", escape_json(v))).unwrap_or_else(|| "null".to_string()),
+            o.value.map(|v| format!("\"{}\"", escape_json(v))).unwrap_or_else(|| "null".to_string()),
+            escape_json(o.help)))
+            .collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Machine readable description of the installed tracer
+pub fn help_json() -> String {
+    let commands: Vec<String> = commands().map(|c| format!(
+            "{{\"name\":\"{}\",\"args\":\"{}\",\"help\":\"{}\",\"options\":{}}}",
+            escape_json(c.name), escape_json(c.args), escape_json(c.help), json_options(options(c))))
+            .collect();
+    let collectors: Vec<String> = COLLECTORS.iter().map(|(name, source)| format!(
+            "{{\"name\":\"{}\",\"source\":\"{}\"}}", escape_json(name), escape_json(source)))
+            .collect();
+    let columns: Vec<String> = CSV_COLUMNS.iter().map(|c| format!("\"{}\"", escape_json(c))).collect();
+    let codes: Vec<String> = EVENT_CODES.iter().map(|c| format!(
+            "{{\"code\":\"{}\",\"event\":\"{}\",\"severity\":\"{}\",\"help\":\"{}\"}}",
+            escape_json(c.code), escape_json(c.event), c.severity.name(), escape_json(c.help)))
+            .collect();
+    let features: Vec<String> = FEATURES.iter()
+            .filter(|(_, built)| *built)
+            .map(|(name, _)| format!("\"{}\"", name))
+            .collect();
+    format!("{{\"name\":\"process_trace\",\"version\":\"{}\",\"schema\":{},\"options\":{},\"commands\":[{}],\
+            \"collectors\":[{}],\"columns\":[{}],\"event_codes\":[{}],\"features\":[{}]}}",
+            procutils::VERSION, procutils::SCHEMA_VERSION, json_options(GLOBAL_OPTIONS.iter()), commands.join(","),
+            collectors.join(","), columns.join(","), codes.join(","), features.join(","))
+}
+
+fn command_names() -> String {
+    commands().map(|c| c.name).collect::<Vec<_>>().join(" ")
+}
+
+fn global_option_names() -> String {
+    GLOBAL_OPTIONS.iter().map(|o| o.name).collect::<Vec<_>>().join(" ")
+}
+
+fn bash_completion() -> String {
+    let mut cases = String::new();
+    for command in commands() {
+        let mut words: Vec<&str> = options(command).map(|o| o.name).collect();
+        if command.name == "completion" {
+            words.extend_from_slice(SHELLS);
+        }
+        let _ = writeln!(cases, "        {}) words=\"{}\" ;;", command.name, words.join(" "));
+    }
+    format!("# bash completion of process_trace\n\
+            _process_trace() {{\n\
+            \x20   local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" words\n\
+            \x20   if [ \"$COMP_CWORD\" -eq 1 ]; then\n\
+            \x20       COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\"))\n\
+            \x20       return\n\
+            \x20   fi\n\
+            \x20   case \"${{COMP_WORDS[1]}}\" in\n\
+            {}\
+            \x20       *) words=\"\" ;;\n\
+            \x20   esac\n\
+            \x20   COMPREPLY=($(compgen -W \"$words\" -f -- \"$cur\"))\n\
+            }}\n\
+            complete -F _process_trace process_trace\n",
+            command_names(), global_option_names(), cases)
+}
+
+fn zsh_completion() -> String {
+    let mut described = String::new();
+    for command in commands() {
+        let _ = write!(described, " '{}:{}'", command.name, command.help.replace('\'', ""));
+    }
+    let mut cases = String::new();
+    for command in commands() {
+        let mut specs: Vec<String> = options(command)
+                .map(|o| format!("'{}[{}]{}'", o.name, o.help, if o.value.is_some() { ":value:_files" } else { "" }))
+                .collect();
+        if command.name == "completion" {
+            specs.push(format!("'1:shell:({})'", SHELLS.join(" ")));
+        } else {
+            specs.push("'*:file:_files'".to_string());
+        }
+        let _ = writeln!(cases, "        {}) _arguments {} ;;", command.name, specs.join(" "));
+    }
+    format!("#compdef process_trace\n\
+            _process_trace() {{\n\
+            \x20   local -a commands\n\
+            \x20   commands=({})\n\
+            \x20   if (( CURRENT == 2 )); then\n\
+            \x20       _describe command commands\n\
+            \x20       return\n\
+            \x20   fi\n\
+            \x20   shift words; (( CURRENT-- ))\n\
+            \x20   case $words[1] in\n\
+            {}\
+            \x20   esac\n\
+            }}\n\
+            _process_trace \"$@\"\n",
+            described.trim_start(), cases)
+}
+
+fn fish_completion() -> String {
+    let mut out = String::from("# fish completion of process_trace\ncomplete -c process_trace -f\n");
+    for command in commands() {
+        let _ = writeln!(out, "complete -c process_trace -n __fish_use_subcommand -a {} -d '{}'",
+                command.name, command.help.replace('\'', ""));
+        for option in options(command) {
+            let _ = writeln!(out, "complete -c process_trace -n '__fish_seen_subcommand_from {}' -l {}{} -d '{}'",
+                    command.name, option.name.trim_start_matches("--"),
+                    if option.value.is_some() { " -r" } else { "" }, option.help.replace('\'', ""));
+        }
+    }
+    let _ = writeln!(out, "complete -c process_trace -n '__fish_seen_subcommand_from completion' -a '{}'",
+            SHELLS.join(" "));
+    out
+}
+
+/// Completion script of a shell, None if the shell is not supported
+pub fn completion(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash_completion()),
+        "zsh" => Some(zsh_completion()),
+        "fish" => Some(fish_completion()),
+        _ => None,
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+//! Host side deploy of the static tracer to a device over adb.
+//!
+//! The static binary is built by Soong from the `process_trace_static`
+//! module for the lunch target, so the ABI of the target has to match the
+//! ABI of the device.
+//!
+//! A remote trace runs the deployed binary over `adb shell`, then pulls the
+//! session it wrote and checks it against the manifest written at its end.
+
+use procutils::audit::{self, Effect};
+use procutils::manifest;
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Soong module of the static binary
+pub const STATIC_MODULE: &str = "process_trace_static";
+
+/// Where the binary is pushed for a one-off run
+pub const DEVICE_TMP_PATH: &str = "/data/local/tmp/process_trace";
+
+/// Where the binary is installed as a persistent tool
+pub const DEVICE_INSTALL_DIR: &str = "/data/local/tmp/bin";
+
+/// Where a remote trace writes its session unless --output-dir is given,
+/// the working directory of `adb shell` is not writable
+pub const DEVICE_SESSION_DIR: &str = "/data/local/tmp/process_trace_sessions";
+
+// Android ABIs and the matching Soong TARGET_ARCH
+const ABI_ARCHS: &[(&str, &str)] = &[
+    ("arm64-v8a", "arm64"),
+    ("armeabi-v7a", "arm"),
+    ("x86_64", "x86_64"),
+    ("x86", "x86"),
+];
+
+/// What `deploy` does
+#[derive(Default)]
+pub struct DeployOptions {
+    /// adb serial of the device, None for the only device
+    pub serial: Option<String>,
+    /// ABI to deploy, None to ask the device
+    pub abi: Option<String>,
+    /// prebuilt static binary, None to build it
+    pub binary: Option<String>,
+    /// install under `DEVICE_INSTALL_DIR` instead of `DEVICE_TMP_PATH`
+    pub install: bool,
+}
+
+/// What `remote_trace` does
+pub struct RemoteOptions {
+    /// adb serial of the device, None for the only device
+    pub serial: Option<String>,
+    /// deployed binary on the device
+    pub device_binary: String,
+    /// local directory the session is pulled into
+    pub pull_dir: String,
+    /// remove the session from the device once the pulled copy is verified
+    pub delete_device_copy: bool,
+}
+
+fn error(msg: String) -> io::Error {
+    io::Error::other(msg)
+}
+
+// Run a command and get its stdout, a non zero exit is an error
+fn run(command: &mut Command, effect: Effect) -> io::Result<String> {
+    let output = audit::run(command, effect)
+            .map_err(|e| error(format!("run {:?} failed: {}", command.get_program(), e)))?;
+    if !output.status.success() {
+        return Err(error(format!("{:?} failed: {}", command,
+                String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn adb_command(serial: &Option<String>) -> Command {
+    let mut command = Command::new("adb");
+    if let Some(serial) = serial {
+        command.arg("-s").arg(serial);
+    }
+    command
+}
+
+fn adb(serial: &Option<String>, args: &[&str], effect: Effect) -> io::Result<String> {
+    run(adb_command(serial).args(args), effect)
+}
+
+// adb shell joins its arguments into one device shell command line, each
+// one is single quoted to reach the command as it is
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+// Session directory from the `Session <dir>[, ...]` or `Resume session <dir>`
+// line a trace prints
+fn session_line(line: &str) -> Option<String> {
+    let dir = line.strip_prefix("Session ").or_else(|| line.strip_prefix("Resume session "))?;
+    Some(dir.split(',').next().unwrap_or(dir).trim().to_string())
+}
+
+/// Soong TARGET_ARCH of an Android ABI
+pub fn abi_arch(abi: &str) -> Option<&'static str> {
+    ABI_ARCHS.iter().find(|(a, _)| *a == abi).map(|(_, arch)| *arch)
+}
+
+/// Primary ABI of the device
+pub fn device_abi(serial: &Option<String>) -> io::Result<String> {
+    adb(serial, &["shell", "getprop", "ro.product.cpu.abi"], Effect::ReadOnly)
+}
+
+/// Build the static binary for `abi` in the lunched tree and get its path
+pub fn build_static(abi: &str) -> io::Result<String> {
+    let arch = abi_arch(abi).ok_or_else(|| error(format!("unsupported ABI {}", abi)))?;
+    let (top, product_out) = match (env::var("ANDROID_BUILD_TOP"), env::var("ANDROID_PRODUCT_OUT")) {
+        (Ok(top), Ok(out)) => (top, out),
+        _ => return Err(error("no lunched tree, run lunch or pass --binary".to_string())),
+    };
+    let soong_ui = format!("{}/build/soong/soong_ui.bash", top);
+    let target_arch = run(Command::new(&soong_ui).current_dir(&top)
+            .args(["--dumpvar-mode", "TARGET_ARCH"]), Effect::ReadOnly)?;
+    if target_arch != arch {
+        return Err(error(format!("lunch target is {}, the device needs {} ({})", target_arch, arch, abi)));
+    }
+    println!("Build {} for {}", STATIC_MODULE, abi);
+    run(Command::new(&soong_ui).current_dir(&top).args(["--make-mode", STATIC_MODULE]), Effect::Writes)?;
+    let binary = format!("{}/system/bin/{}", product_out, STATIC_MODULE);
+    if !Path::new(&binary).is_file() {
+        return Err(error(format!("build did not produce {}", binary)));
+    }
+    Ok(binary)
+}
+
+/// Build if needed, push and check the binary, get its path on the device
+pub fn deploy(options: &DeployOptions) -> io::Result<String> {
+    let abi = match &options.abi {
+        Some(abi) => abi.clone(),
+        None => device_abi(&options.serial)?,
+    };
+    let binary = match &options.binary {
+        Some(binary) => binary.clone(),
+        None => build_static(&abi)?,
+    };
+    let device_path = if options.install {
+        adb(&options.serial, &["shell", "mkdir", "-p", DEVICE_INSTALL_DIR], Effect::Writes)?;
+        format!("{}/process_trace", DEVICE_INSTALL_DIR)
+    } else {
+        DEVICE_TMP_PATH.to_string()
+    };
+    println!("Push {} to {}", binary, device_path);
+    adb(&options.serial, &["push", &binary, &device_path], Effect::Writes)?;
+    adb(&options.serial, &["shell", "chmod", "755", &device_path], Effect::Writes)?;
+    // Running it proves the binary matches the ABI and needs no missing libraries
+    let version = adb(&options.serial, &["shell", &device_path, "--version"], Effect::ReadOnly)?;
+    println!("{}", version);
+    Ok(device_path)
+}
+
+/// Run a command of the deployed binary on the device, its output echoed as
+/// it comes, then pull the session it wrote and verify the pulled copy.
+/// Get the local path of the session
+pub fn remote_trace(options: &RemoteOptions, args: &[String]) -> io::Result<String> {
+    let mut command = adb_command(&options.serial);
+    command.arg("shell").arg(shell_quote(&options.device_binary)).args(args.iter().map(|arg| shell_quote(arg)));
+    let mut session = None;
+    let status = audit::run_lines(&mut command, Effect::ReadOnly, |line| {
+        println!("{}", line);
+        if session.is_none() {
+            session = session_line(line);
+        }
+    }).map_err(|e| error(format!("run adb shell {} failed: {}", options.device_binary, e)))?;
+    let session = session.ok_or_else(|| error("the device command started no session".to_string()))?;
+    // A stopped trace still finishes its session, only a missing one is fatal
+    if !status.success() {
+        println!("device command exited with {}, pulling {}", status.code().unwrap_or(-1), session);
+    }
+    let name = Path::new(&session).file_name()
+            .ok_or_else(|| error(format!("bad session directory {}", session)))?;
+    let local = Path::new(&options.pull_dir).join(name).to_string_lossy().to_string();
+    println!("Pull {} to {}", session, local);
+    adb(&options.serial, &["pull", &session, &options.pull_dir], Effect::Writes)?;
+    match manifest::verify_manifest(&local) {
+        Ok(Ok(files)) => println!("Verified {} files against {}", files, manifest::MANIFEST_FILE),
+        Ok(Err(problems)) => {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            return Err(error(format!("{} does not match its manifest, kept {} on the device", local, session)));
+        },
+        Err(e) => return Err(error(format!("read {}/{} failed: {}, kept {} on the device",
+                local, manifest::MANIFEST_FILE, e, session))),
+    }
+    if options.delete_device_copy {
+        adb(&options.serial, &["shell", "rm", "-r", &shell_quote(&session)], Effect::Writes)?;
+        println!("Deleted {} from the device", session);
+    }
+    Ok(local)
+}
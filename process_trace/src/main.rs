@@ -5,19 +5,1591 @@
 // See the LICENSE file at the root directory of this project for more details.
 
 //! The `process_trace` crate provides functionality for tracing processes.
-//! 
+//!
 //! It uses the `procutils` module to analyze and monitor processes on a Linux system.
 //! Example usage:
-//! 
+//!
 //! ```ignore
 //! let monitor_list: Vec<&str> = vec!["init"];
-//! procutils::proc_analysis::trace_process(60, 10, &monitor_list);
+//! procutils::proc_analysis::trace_process(60, 10, &monitor_list)?;
+//! ```
+//!
+//! The binary is split into subcommands, the analysis ones only read files
+//! so they also work on the host against pulled traces:
+//!
+//! ```text
+//! process_trace [trace] --name init --name zygote64 --duration 120 --interval 5 --output-dir /data/local/tmp
+//! process_trace [trace] --name system_server --tag build=1234 --output-dir '/data/local/tmp/{tag:build}' --session-name '{name}_{device_serial}_{date}'
+//! process_trace [trace] --pid 1234 --until-exit --format csv --format perfetto
+//! process_trace [trace] --name system_server --follow --duration 600
+//! process_trace [trace] --name surfaceflinger --strict --audit-log /data/local/tmp/audit.log
+//! process_trace [trace] --match regex --name '^com\.android\.' --all-matches
+//! process_trace [trace] --target package:com.android.systemui --target cgroup:/top-app --all-matches
+//! process_trace [trace] --resume <session>
+//! process_trace [trace] --name surfaceflinger --drop-to shell --keep-ptrace
+//! process_trace [trace] --name system_server --interval auto --overhead-budget 0.5
+//! process_trace [trace] --name system_server --interval 1 --binder-priority 5
+//! process_trace [trace] --name com.android.chrome --interval 5 --cpu-split
+//! process_trace [trace] --name com.android.chrome --flush-bytes 262144 --page-cache drop
+//! process_trace [trace] --name system_server --precision cpuOccupancyRate=6 --precision confidence=3
+//! process_trace [trace] --name system_server --tag device=pixel8 --tag build=1234 --trend /data/local/tmp/trend.csv
+//! process_trace [trace] --name system_server --alert 'cpuOccupancyRate>0.5' --alert 'pss>400000'
+//! process_trace [trace] --name system_server --alert 'cpuOccupancyRate>0.5:clear=0.3:for=30'
+//! process_trace [trace] --name system_server --alert 'cpuOccupancyRate>0.8' --simpleperf 10
+//! process_trace [trace] --name system_server --duration 14400 --interval 5 --profile screen-off+battery:60:none
+//! process_trace [trace] --name system_server --duration 86400 --interval 5 --format jsonl --format perfetto --compact
+//! process_trace calibrate --name system_server --per-thread
+//! process_trace run --interval 1 --format perfetto -- /data/local/tmp/benchmark --iterations 10
+//! process_trace run --memory-max 256M --cpu-max 50 -- /data/local/tmp/benchmark
+//! process_trace watch --name dex2oat64 --interval 1 --duration 86400 --output-dir /data/local/tmp/dex2oat
+//! process_trace soak --name system_server --name surfaceflinger --interval 300 --keep-days 7 --output-dir /data/local/tmp/soak
+//! process_trace rollup --match substring --name dex2oat --duration 3600 --output /data/local/tmp/dex2oat.csv
+//! process_trace binder --name system_server --duration 30
+//! process_trace top --sort cpu -n 10
+//! process_trace top --sort pss -n 5 --group-by-exe
+//! process_trace top --color always --cpu-warn 25 --rss-rising 5
+//! process_trace snapshot <name>
+//! process_trace selftest [--pid 1]
+//! process_trace crosscheck --pid 1 --interval 10 --duration 600
+//! process_trace load --cpu 50 --threads 2 --memory 256 --ramp 30 --duration 120
+//! process_trace noise --name system_server --windows 5 --window 10 --store /data/local/tmp/noise
+//! process_trace compare --noise /data/local/tmp/noise/noise_<serial>.csv -b base1.csv -b base2.csv -c cand1.csv -c cand2.csv
+//! process_trace trace --name system_server --transform minflt:rate --transform cpuOccupancyRate:ema=0.3+clamp=0..100
+//! process_trace trace --name system_server --transform voluntaryCtxtSwitches:rate --smooth voluntaryCtxtSwitches:ema=5
+//! process_trace analyze --idle <session>/idle_baseline.csv <session>/resource_trace_<name>.csv
+//! process_trace analyze <trace.csv>
+//! process_trace analyze --envelope <trace.csv>
+//! process_trace analyze --score 'cpuOccupancyRate:0.5@0.25,pss:0.3@400000,majflt/s:0.2@20' <trace.csv>
+//! process_trace compare <baseline.csv> <candidate.csv>
+//! process_trace compare -b <base1.csv> -b <base2.csv> -c <cand1.csv> -c <cand2.csv> [--alpha 0.01] [--exclude-outliers]
+//! process_trace compare <interval1.csv> <interval5.csv> --resample locf
+//! process_trace report <trace.csv> [-o <report.html>] [--envelope]
+//! process_trace merge -o <merged.csv> <trace.csv>...
+//! process_trace convert -o <new.csv> <old.csv> [--precision runDelay=6]
+//! process_trace verify <trace.csv>...
+//! process_trace trend /data/local/tmp/trend.csv [--append <trace.csv> --key <build>] [--column pss]
+//! process_trace contention <session> [--threshold 3.5]
+//! process_trace contention <a.csv> <b.csv> --resample locf
+//! process_trace completion <bash|zsh|fish>
+//! process_trace deploy [--serial <serial>] [--binary <path>] [--install]
+//! process_trace remote [--serial <serial>] [--pull-dir <dir>] [--delete-device-copy] -- trace --name system_server --duration 60
+//! process_trace --help-json
 //! ```
 
 pub use procutils::*;
 
+mod cli;
+mod deploy;
+
+use cli::ParsedArgs;
+#[cfg(feature = "alert")]
+use procutils::alert::{format_alert, Alert, AlertRule, ThresholdAlerts};
+#[cfg(feature = "analysis")]
+use procutils::analysis::TraceData;
+#[cfg(feature = "analysis")]
+use procutils::align::{ResampleMode, RESAMPLE_MODES};
+#[cfg(feature = "top")]
+use procutils::binder::{BinderPairing, BINDER_LOG_DIRS};
+use procutils::bus::global_bus;
+#[cfg(feature = "alert")]
+use procutils::bus::spawn_analyzer;
+#[cfg(feature = "run")]
+use procutils::bus::BusMessage;
+#[cfg(feature = "analysis")]
+use procutils::baseline::IdleBaseline;
+#[cfg(feature = "run")]
+use procutils::sandbox::SandboxConfig;
+use procutils::transform::{ColumnTransform, SmoothSpec};
+use procutils::discover::{MatchMode, MATCH_MODES};
+#[cfg(feature = "analysis")]
+use procutils::file_utils::read_path;
+use procutils::loadgen::LoadPattern;
+use procutils::numeric::{ColumnPrecision, MAX_PRECISION};
+use procutils::outfile::{CacheMode, WriteOptions, CACHE_MODES};
+use procutils::output::{parse_tag, OutputFormat, DEFAULT_OUTPUT_FORMATS, THREAD_CSV_COLUMNS};
+use procutils::power::PowerProfile;
+use procutils::privilege::DropPrivileges;
+use procutils::proc_analysis::{MetricGroups, MonitorOptions, TraceTarget, CSV_COLUMNS, METRIC_GROUPS, TARGET_PID_PREFIX};
+use procutils::resolve::{resolver, resolver_schemes};
+#[cfg(feature = "top")]
+use procutils::rollup::{NameRollup, RollupTracker};
+use procutils::session::now_epoch;
+use procutils::selftest::CheckStatus;
+#[cfg(feature = "analysis")]
+use procutils::soak::{SoakConfig, SoakDay};
+use procutils::session::SessionState;
+use procutils::template::{expand_template, has_tokens, TemplateValues};
+#[cfg(feature = "alert")]
+use procutils::simpleperf::{HotspotCapture, CPU_ALERT_COLUMNS};
+#[cfg(feature = "top")]
+use procutils::top::{TopConfig, TopHighlight, TopSample, TopSort, TOP_SORTS};
+#[cfg(feature = "top")]
+use procutils::watch::{Occurrence, WatchConfig};
+use std::env;
+#[cfg(feature = "analysis")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "analysis")]
+use std::io::BufWriter;
+#[cfg(feature = "top")]
+use std::io::IsTerminal;
+#[cfg(any(feature = "top", feature = "analysis"))]
+use std::io::Write;
+#[cfg(feature = "run")]
+use std::os::unix::process::ExitStatusExt;
+#[cfg(feature = "run")]
+use std::path::Path;
+#[cfg(feature = "run")]
+use std::process::Child;
+use std::process::exit;
+#[cfg(feature = "run")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{}\n\n{}", msg, cli::usage());
+    exit(2);
+}
+
+// A failure which is not the fault of the command line
+fn fatal(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    exit(1);
+}
+
+#[cfg(feature = "analysis")]
+fn load_raw(path: &str) -> TraceData {
+    TraceData::load_csv(path).unwrap_or_else(|e| {
+        eprintln!("Load {} failed: {}", path, e);
+        exit(1);
+    })
+}
+
+// Load a trace and convert it to the current schema, or refuse it
+#[cfg(feature = "analysis")]
+fn load(path: &str) -> TraceData {
+    let data = load_raw(path);
+    let schema = data.schema;
+    let data = analysis::upgrade(data).unwrap_or_else(|e| {
+        eprintln!("Load {} failed: {}", path, e);
+        exit(1);
+    });
+    if schema != data.schema {
+        eprintln!("{}: converted from schema {} to {}", path, schema, data.schema);
+    }
+    data
+}
+
+fn number_or_exit(args: &ParsedArgs, name: &str, default: i64) -> i64 {
+    args.number(name).unwrap_or_else(|e| usage_error(&e)).unwrap_or(default)
+}
+
+fn write_options(args: &ParsedArgs) -> WriteOptions {
+    let mut options = WriteOptions::default();
+    match number_or_exit(args, "--flush-bytes", 0) {
+        0 => {},
+        bytes if bytes > 0 => options.flush_bytes = bytes as usize,
+        _ => usage_error("--flush-bytes must be positive"),
+    }
+    if let Some(mode) = args.value("--page-cache") {
+        options.cache = CacheMode::parse(mode)
+                .unwrap_or_else(|| usage_error(&format!("unknown page cache mode {}, use {}", mode, CACHE_MODES.join("|"))));
+    }
+    options
+}
+
+fn column_precisions(args: &ParsedArgs) -> Vec<ColumnPrecision> {
+    args.values("--precision").iter()
+            .map(|spec| ColumnPrecision::parse(spec).unwrap_or_else(|| usage_error(&format!(
+                    "bad precision {}, use <column>=<digits> with at most {} digits", spec, MAX_PRECISION))))
+            .collect()
+}
+
+fn monitor_options(args: &ParsedArgs) -> MonitorOptions {
+    let mut options = MonitorOptions::default();
+    if let Some(spec) = args.value("--drop-to") {
+        if !privilege::is_root() {
+            usage_error("--drop-to needs root");
+        }
+        options.drop_privileges = Some(DropPrivileges::parse(spec, args.flag("--keep-ptrace"))
+                .unwrap_or_else(|| usage_error(&format!("unknown user or group {}", spec))));
+    } else if args.flag("--keep-ptrace") {
+        usage_error("--keep-ptrace needs --drop-to");
+    }
+    if let Some(fraction) = args.value("--slow-fraction") {
+        options.slow_sample_fraction = fraction.parse::<f64>().ok()
+                .filter(|fraction| *fraction > 0.0)
+                .unwrap_or_else(|| usage_error(&format!("--slow-fraction needs a positive number, got {}", fraction)));
+    }
+    match number_or_exit(args, "--read-timeout", -1) {
+        -1 => {},
+        0 => options.read_timeout = None,
+        ms if ms > 0 => options.read_timeout = Some(Duration::from_millis(ms as u64)),
+        _ => usage_error("--read-timeout must be 0 or positive"),
+    }
+    options.rebaseline_on_exec = args.flag("--rebaseline-on-exec");
+    match number_or_exit(args, "--progress", 0) {
+        0 => {},
+        seconds if seconds > 0 => options.progress = Some(Duration::from_secs(seconds as u64)),
+        _ => usage_error("--progress must be positive"),
+    }
+    if let Some(percent) = args.value("--memory-trigger") {
+        options.memory_trigger = Some(percent.parse::<f64>().ok()
+                .filter(|percent| *percent > 0.0)
+                .unwrap_or_else(|| usage_error(&format!("--memory-trigger needs a positive percent, got {}", percent)))
+                / 100.0);
+    }
+    match number_or_exit(args, "--binder-priority", 0) {
+        0 => {},
+        ms if ms > 0 => options.binder_priority = Some(Duration::from_millis(ms as u64)),
+        _ => usage_error("--binder-priority must be positive"),
+    }
+    match number_or_exit(args, "--smaps-incremental", 0) {
+        0 => {},
+        samples if samples > 0 => {
+            if smaps::smaps_rollup_available() {
+                println!("smaps_rollup is available, --smaps-incremental has nothing to save");
+            }
+            options.smaps_full_every = Some(samples as u32);
+        },
+        _ => usage_error("--smaps-incremental must be positive"),
+    }
+    outfile::set_write_options(write_options(args));
+    if let Some(restriction) = hidepid::restriction() {
+        println!("{}", restriction);
+    }
+    let smoothed: Vec<String> = args.values("--smooth").iter()
+            .filter_map(|spec| SmoothSpec::parse(spec))
+            .map(|smooth| smooth.column_name())
+            .collect();
+    let precisions = column_precisions(args);
+    for precision in &precisions {
+        let column = precision.column.as_str();
+        if !CSV_COLUMNS.contains(&column) && !THREAD_CSV_COLUMNS.contains(&column) && !smoothed.iter().any(|c| c == column) {
+            usage_error(&format!("unknown column {} for --precision", column));
+        }
+    }
+    numeric::set_precisions(&precisions);
+    options.cpu_split = args.flag("--cpu-split");
+    if options.cpu_split && !metric_groups(args).cgroup {
+        usage_error("--cpu-split needs the cgroup metric group for the cpuset of the targets");
+    }
+    options
+}
+
+// --output-dir and --session-name, their tokens are expanded once here
+fn create_session(args: &ParsedArgs, tags: &[(String, String)], duration: i64, interval: i64,
+        targets: &[&str], formats: &[OutputFormat]) -> SessionState {
+    let output_dir = args.value("--output-dir").unwrap_or(".");
+    let session_name = args.value("--session-name");
+    let created = if session_name.is_none() && !has_tokens(output_dir) {
+        SessionState::create(output_dir, duration, interval, targets, formats)
+    } else {
+        let session_id = loki::new_run_id();
+        let targets_found: Vec<TraceTarget> = targets.iter().map(|spec| TraceTarget::parse(spec)).collect();
+        // Only resolved when asked for, a resolver may be slow
+        let pids = match output_dir.contains("{pid}") || session_name.is_some_and(|name| name.contains("{pid}")) {
+            true => targets_found.iter()
+                    .filter_map(|target| target.resolve_pids().ok()?.first().copied())
+                    .collect(),
+            false => Vec::new(),
+        };
+        let mut values = TemplateValues::new(targets_found.iter().map(TraceTarget::label).collect(), pids,
+                now_epoch(), &session_id, tags);
+        let expand = |template: &str, values: &mut TemplateValues| expand_template(template, values)
+                .unwrap_or_else(|e| usage_error(&format!("bad output template {}: {}", template, e)));
+        let output_dir = expand(output_dir, &mut values);
+        match session_name {
+            Some(name) => {
+                let dir = format!("{}/{}", output_dir.trim_end_matches('/'), expand(name, &mut values));
+                SessionState::create_named(dir, session_id, duration, interval, targets, formats)
+            },
+            None => SessionState::create(&output_dir, duration, interval, targets, formats),
+        }
+    };
+    match created {
+        Ok(state) => state,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists =>
+            usage_error(&format!("{}, add {{date}} or {{id}} to --session-name", e)),
+        Err(e) => fatal(&format!("Create session failed: {}", e)),
+    }
+}
+
+fn cmd_trace(args: &ParsedArgs) {
+    let options = monitor_options(args);
+    let hooks = session_hooks(args);
+    let strict = args.flag("--strict");
+    if let Some(session) = args.value("--resume") {
+        let mut state = SessionState::load(session)
+                .unwrap_or_else(|e| fatal(&format!("Load session {} failed: {}", session, e)));
+        if !args.values("--tag").is_empty() {
+            usage_error("--tag can not change the tags of a resumed session");
+        }
+        println!("Resume session {}", state.dir);
+        if strict && !state.strict {
+            state.strict = true;
+            state.save().unwrap_or_else(|e| fatal(&format!("Save session failed: {}", e)));
+        }
+        let dir = state.dir.clone();
+        run_session_or_exit(state, options, hooks);
+        finish_session(&dir);
+        return;
+    }
+    let targets = target_specs(args);
+    let follow = args.flag("--follow");
+    if follow && args.flag("--until-exit") {
+        usage_error("--follow traces for a --duration, not until the targets exit");
+    }
+    let duration = if args.flag("--until-exit") { 0 } else { number_or_exit(args, "--duration", 60) };
+    let mut formats = output_formats(args);
+    let metrics = metric_groups(args);
+    let tags = trace_tags(args);
+    let profiles: Vec<PowerProfile> = args.values("--profile").iter()
+            .map(|spec| PowerProfile::parse(spec).unwrap_or_else(|| usage_error(&format!(
+                    "bad profile {}, use <states>:<interval>[:<metrics>] with + joined screen-on, screen-off, charging or battery",
+                    spec))))
+            .collect();
+    let transforms: Vec<ColumnTransform> = args.values("--transform").iter()
+            .map(|spec| ColumnTransform::parse(spec).unwrap_or_else(|| usage_error(&format!(
+                    "bad transform {}, use <column>:<op>[+<op>] with rate, scale=<factor>, ema=<alpha> or clamp=<min>..<max>",
+                    spec))))
+            .collect();
+    let smooths: Vec<SmoothSpec> = args.values("--smooth").iter()
+            .map(|spec| SmoothSpec::parse(spec).unwrap_or_else(|| usage_error(&format!(
+                    "bad smoothing {}, use <column>:ema=<window> or <column>:sma=<window> with a window of 2 samples or more",
+                    spec))))
+            .collect();
+    let per_thread = args.flag("--per-thread");
+    if per_thread && !formats.contains(&OutputFormat::Threads) {
+        formats.push(OutputFormat::Threads);
+    }
+    let compact = args.flag("--compact");
+    let interval = if args.value("--interval") == Some("auto") {
+        let calibration_options = MonitorOptions { per_thread, metrics, ..options };
+        calibrate_interval(&targets, &calibration_options, overhead_budget(args))
+    } else {
+        number_or_exit(args, "--interval", 10)
+    };
+    if interval <= 0 {
+        usage_error("--interval must be positive");
+    }
+    #[cfg(feature = "analysis")]
+    let idle = idle_baseline(args, &targets);
+    let monitor_list: Vec<&str> = targets.iter().map(|t| t.as_str()).collect();
+    let mut state = create_session(args, &tags, duration, interval, &monitor_list, &formats);
+    if per_thread || follow || strict || compact || metrics != state.metrics || !tags.is_empty() || !profiles.is_empty()
+            || !transforms.is_empty() || !smooths.is_empty() {
+        state.per_thread = per_thread;
+        state.follow = follow;
+        state.strict = strict;
+        state.compact = compact;
+        state.metrics = metrics;
+        state.tags = tags;
+        state.profiles = profiles;
+        state.transforms = transforms;
+        state.smooths = smooths;
+        state.save().unwrap_or_else(|e| fatal(&format!("Save session failed: {}", e)));
+    }
+    println!("Session {}", state.dir);
+    let dir = state.dir.clone();
+    #[cfg(feature = "analysis")]
+    if let Some(baselines) = &idle {
+        let path = format!("{}/{}", dir, baseline::IDLE_BASELINE_FILE);
+        if let Err(e) = baseline::write_idle_baseline(&path, baselines) {
+            println!("write {} failed: {}", path, e);
+        }
+    }
+    run_session_or_exit(state, options, hooks);
+    // Summaries without the idle rates, the outputs keep the measured values
+    #[cfg(feature = "analysis")]
+    for baseline in idle.iter().flatten() {
+        let path = format!("{}/{}", dir, OutputFormat::Csv.file_name(&baseline.target));
+        if let Ok(mut data) = TraceData::load_csv(&path) {
+            baseline::subtract_idle(&mut data, baseline);
+            println!("{} without idle:", baseline.target);
+            print!("{}", analysis::format_summary(&analysis::summarize(&data)));
+        }
+    }
+    finish_session(&dir);
+}
+
+// --idle-baseline <seconds>, measured before the session starts
+#[cfg(feature = "analysis")]
+fn idle_baseline(args: &ParsedArgs, targets: &[String]) -> Option<Vec<IdleBaseline>> {
+    let seconds = args.number("--idle-baseline").unwrap_or_else(|e| usage_error(&e))?;
+    if seconds <= 0 {
+        usage_error("--idle-baseline must be positive");
+    }
+    let targets: Vec<TraceTarget> = targets.iter().map(|spec| TraceTarget::parse(spec)).collect();
+    println!("Measuring idle baseline for {}s, keep the device idle", seconds);
+    let baselines = baseline::measure_idle(&targets, Duration::from_secs(seconds as u64))
+            .unwrap_or_else(|e| fatal(&format!("Idle baseline failed: {}", e)));
+    print!("{}", baseline::format_idle(&baselines));
+    println!("Idle baseline done, start the scenario");
+    Some(baselines)
+}
+
+// Target specs of --name and --pid
+fn target_specs(args: &ParsedArgs) -> Vec<String> {
+    let mode = args.value("--match").map(|name| MatchMode::parse(name)
+            .unwrap_or_else(|| usage_error(&format!("unknown match mode {}, use one of {}", name, MATCH_MODES.join(", ")))))
+            .unwrap_or_default();
+    let mut specs: Vec<TraceTarget> = args.values("--name").iter()
+            .map(|name| TraceTarget::matching(name, mode))
+            .collect();
+    for spec in args.values("--target") {
+        match spec.split_once(':') {
+            Some((scheme, _)) if resolver(scheme).is_some() => specs.push(TraceTarget::parse(spec)),
+            _ => usage_error(&format!("bad target {}, use <scheme>:<query> with a scheme of {}",
+                    spec, resolver_schemes().join(", "))),
+        }
+    }
+    let mut targets = Vec::new();
+    for target in specs {
+        if !args.flag("--all-matches") {
+            targets.push(target.spec());
+            continue;
+        }
+        // Pin every match by pid, so a resumed session traces the same processes
+        let pids = target.resolve_pids().unwrap_or_else(|e| {
+            eprintln!("trace failed: {}", e);
+            exit(1);
+        });
+        println!("{} matches {} processes", target.spec(), pids.len());
+        targets.extend(pids.iter().map(|pid| TraceTarget::Pid(*pid).spec()));
+    }
+    for pid in args.values("--pid") {
+        if pid.parse::<i32>().map(|p| p <= 0).unwrap_or(true) {
+            usage_error(&format!("bad pid {}", pid));
+        }
+        targets.push(format!("{}{}", TARGET_PID_PREFIX, pid));
+    }
+    if targets.is_empty() || !args.positionals.is_empty() {
+        usage_error("--name, --pid or --target targets are needed");
+    }
+    targets
+}
+
+// --format, the default formats when none is given
+fn output_formats(args: &ParsedArgs) -> Vec<OutputFormat> {
+    let formats: Vec<OutputFormat> = args.values("--format").iter()
+            .map(|name| OutputFormat::parse(name)
+                    .unwrap_or_else(|| usage_error(&format!("unknown format {}", name))))
+            .collect();
+    if formats.is_empty() {
+        return DEFAULT_OUTPUT_FORMATS.to_vec();
+    }
+    formats
+}
+
+// The sandbox of run, None without limits and --sandbox
+#[cfg(feature = "run")]
+fn sandbox_config(args: &ParsedArgs) -> Option<SandboxConfig> {
+    let mut config = SandboxConfig::new();
+    let mut sandboxed = args.flag("--sandbox");
+    if let Some(root) = args.value("--cgroup-root") {
+        config = config.root(root);
+    }
+    if let Some(size) = args.value("--memory-max") {
+        let bytes = sandbox::parse_size(size)
+                .unwrap_or_else(|| usage_error(&format!("--memory-max needs a size like 512M, got {}", size)));
+        config = config.memory_max(bytes);
+        sandboxed = true;
+    }
+    if let Some(percent) = args.number("--cpu-max").unwrap_or_else(|e| usage_error(&e)) {
+        if percent <= 0 {
+            usage_error("--cpu-max must be positive");
+        }
+        config = config.cpu_max(percent as u64);
+        sandboxed = true;
+    }
+    if let Some(count) = args.number("--pids-max").unwrap_or_else(|e| usage_error(&e)) {
+        if count <= 0 {
+            usage_error("--pids-max must be positive");
+        }
+        config = config.pids_max(count as u64);
+        sandboxed = true;
+    }
+    if sandboxed && audit::is_strict() {
+        usage_error("--sandbox and the limits create a cgroup, strict mode refuses them");
+    }
+    sandboxed.then_some(config)
+}
+
+fn metric_groups(args: &ParsedArgs) -> MetricGroups {
+    args.value("--metrics").map(|list| MetricGroups::parse(list)
+            .unwrap_or_else(|| usage_error(&format!("unknown metric groups {}, use some of {}", list, METRIC_GROUPS.join(",")))))
+            .unwrap_or_default()
+}
+
+// --tag key=value, keys are unique
+fn trace_tags(args: &ParsedArgs) -> Vec<(String, String)> {
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for tag in args.values("--tag") {
+        let (key, value) = parse_tag(tag).unwrap_or_else(|e| usage_error(&e.to_string()));
+        if tags.iter().any(|(other, _)| *other == key) {
+            usage_error(&format!("duplicate tag {}", key));
+        }
+        tags.push((key, value));
+    }
+    tags
+}
+
+// --overhead-budget as a share of one core
+fn overhead_budget(args: &ParsedArgs) -> f64 {
+    match args.value("--overhead-budget") {
+        Some(percent) => percent.parse::<f64>().ok()
+                .filter(|percent| *percent > 0.0)
+                .map(|percent| percent / 100.0)
+                .unwrap_or_else(|| usage_error(&format!("--overhead-budget needs a positive percent, got {}", percent))),
+        None => sampling::DEFAULT_OVERHEAD_BUDGET,
+    }
+}
+
+// Measure the targets and get the interval which keeps sampling under `budget`
+fn calibrate_interval(targets: &[String], options: &MonitorOptions, budget: f64) -> i64 {
+    let calibration = proc_analysis::calibrate(targets, options, proc_analysis::CALIBRATION_SAMPLES)
+            .unwrap_or_else(|e| {
+                eprintln!("calibrate failed: {}", e);
+                exit(1);
+            });
+    let interval = sampling::recommend_interval(calibration.cpu_per_tick, budget);
+    println!("Calibrated {} samples: {:.3}ms CPU and {:.3}ms wall per tick, interval {}s keeps sampling under {}% of one core",
+            calibration.samples, calibration.cpu_per_tick.as_secs_f64() * 1000.0,
+            calibration.wall_per_tick.as_secs_f64() * 1000.0, interval, budget * 100.0);
+    interval
+}
+
+fn cmd_calibrate(args: &ParsedArgs) {
+    let targets = target_specs(args);
+    let options = MonitorOptions { per_thread: args.flag("--per-thread"), metrics: metric_groups(args), ..Default::default() };
+    calibrate_interval(&targets, &options, overhead_budget(args));
+}
+
+// What runs beside and after a session, checked before the session is created
+#[derive(Default)]
+struct SessionHooks {
+    #[cfg(feature = "alert")]
+    alerts: Vec<AlertRule>,
+    #[cfg(feature = "alert")]
+    simpleperf: Option<u64>,
+    #[cfg(feature = "analysis")]
+    trend: Option<String>,
+    #[cfg(feature = "analysis")]
+    score: score::HealthScore,
+}
+
+#[cfg_attr(not(any(feature = "alert", feature = "analysis")), allow(unused_variables))]
+fn session_hooks(args: &ParsedArgs) -> SessionHooks {
+    #[cfg(feature = "alert")]
+    let alerts = alert_rules(args);
+    SessionHooks {
+        #[cfg(feature = "alert")]
+        simpleperf: simpleperf_seconds(args, &alerts),
+        #[cfg(feature = "alert")]
+        alerts,
+        #[cfg(feature = "analysis")]
+        trend: args.value("--trend").map(|store| store.to_string()),
+        #[cfg(feature = "analysis")]
+        score: health_score(args),
+    }
+}
+
+// --score <formula>, the default formula without it
+#[cfg(feature = "analysis")]
+fn health_score(args: &ParsedArgs) -> score::HealthScore {
+    match args.value("--score") {
+        Some(spec) => score::HealthScore::parse(spec)
+                .unwrap_or_else(|| usage_error(&format!("invalid score formula {}, want column[/s]:weight@full,...", spec))),
+        None => score::HealthScore::default(),
+    }
+}
+
+#[cfg_attr(not(any(feature = "alert", feature = "analysis")), allow(unused_variables))]
+fn run_session(state: SessionState, options: MonitorOptions, hooks: SessionHooks) -> Result<(), error::TraceError> {
+    #[cfg(feature = "analysis")]
+    let csv_paths: Vec<(String, String)> = state.targets.iter()
+            .map(|target| TraceTarget::parse(&target.name).label())
+            .filter(|_| state.formats.contains(&OutputFormat::Csv))
+            .map(|label| (format!("{}/{}", state.dir, OutputFormat::Csv.file_name(&label)), label))
+            .collect();
+    #[cfg(feature = "analysis")]
+    let session_id = state.session_id.clone();
+    #[cfg(feature = "alert")]
+    let analyzer = spawn_alerts(&state.dir, hooks.alerts, hooks.simpleperf);
+    #[cfg(feature = "analysis")]
+    let dir = state.dir.clone();
+    shutdown::install_stop_handler();
+    let result = procutils::proc_analysis::trace_session_with_options(state, options);
+    global_bus().close();
+    #[cfg(feature = "alert")]
+    if let Some(analyzer) = analyzer {
+        let _ = analyzer.join();
+    }
+    result?;
+    // Every target flushed, their outputs cover the same stop
+    #[cfg(feature = "analysis")]
+    if csv_paths.len() > 1 {
+        let traces: Vec<(String, TraceData)> = csv_paths.iter()
+                .filter_map(|(path, label)| Some((label.clone(), TraceData::load_csv(path).ok()?)))
+                .collect();
+        let mut summary = joint::format_joint_summary(&joint::joint_summary(&traces));
+        summary.push_str(&contention::format_contention(
+                &contention::find_contention(&traces, contention::DEFAULT_SPIKE_Z)));
+        print!("{}", summary);
+        let path = format!("{}/{}", dir, joint::JOINT_SUMMARY_FILE);
+        if let Err(e) = std::fs::write(&path, summary) {
+            println!("write {} failed: {}", path, e);
+        }
+    }
+    #[cfg(feature = "analysis")]
+    if let Some(store) = &hooks.trend {
+        if csv_paths.is_empty() {
+            println!("no csv output to append to trend store {}", store);
+        }
+        for (path, label) in csv_paths {
+            let appended = TraceData::load_csv(&path).and_then(|mut data| {
+                if let Some(transforms) = analysis::format_transforms(&data) {
+                    println!("warning: {} {}, the store keeps them as they are", label, transforms);
+                }
+                hooks.score.add_column(&mut data);
+                trend::append_run(store, &trend::run_key(&data, &session_id), &label, &data, now_epoch())
+            });
+            match appended {
+                Ok(_) => println!("Appended {} to trend store {}", label, store),
+                Err(e) => println!("append {} to trend store {} failed: {}", path, store, e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_session_or_exit(state: SessionState, options: MonitorOptions, hooks: SessionHooks) {
+    if let Err(e) = run_session(state, options, hooks) {
+        eprintln!("trace failed: {}", e);
+        exit(1);
+    }
+}
+
+// Last, once every output of the command is written, so a puller can tell a
+// finished session from one still written
+fn finish_session(dir: &str) {
+    if let Err(e) = manifest::write_manifest(dir) {
+        println!("write {}/{} failed: {}", dir, manifest::MANIFEST_FILE, e);
+    }
+}
+
+// Alerts run beside the monitors and see the samples as they are collected
+// and CPU alerts may start a simpleperf capture into the session directory
+#[cfg(feature = "alert")]
+fn spawn_alerts(dir: &str, alerts: Vec<AlertRule>, simpleperf: Option<u64>) -> Option<std::thread::JoinHandle<()>> {
+    let mut capture = simpleperf.map(|seconds| HotspotCapture::new(dir, seconds));
+    (!alerts.is_empty()).then(|| spawn_analyzer(global_bus(),
+            ThresholdAlerts::new(alerts, move |alert: &Alert| {
+                println!("{}", format_alert(alert));
+                if let Some(path) = capture.as_mut().and_then(|capture| capture.on_alert(alert)) {
+                    println!("simpleperf of {} records to {}", alert.pid, path);
+                }
+            })))
+}
+
+// --alert <column>> or < <limit>[:clear=<level>][:for=<seconds>]
+// --simpleperf needs an alert on a CPU column to start the captures
+#[cfg(feature = "alert")]
+fn simpleperf_seconds(args: &ParsedArgs, alerts: &[AlertRule]) -> Option<u64> {
+    args.value("--simpleperf")?;
+    let seconds = number_or_exit(args, "--simpleperf", 0);
+    if seconds <= 0 {
+        usage_error("--simpleperf needs a positive number of seconds");
+    }
+    if !alerts.iter().any(|rule| CPU_ALERT_COLUMNS.contains(&rule.column.as_str())) {
+        usage_error(&format!("--simpleperf needs an --alert on one of {}", CPU_ALERT_COLUMNS.join(", ")));
+    }
+    Some(seconds as u64)
+}
+
+#[cfg(feature = "alert")]
+fn alert_rules(args: &ParsedArgs) -> Vec<AlertRule> {
+    args.values("--alert").iter()
+            .map(|rule| AlertRule::parse(rule).unwrap_or_else(|| usage_error(&format!(
+                    "bad alert {}, use <column>><limit> or <column><<limit> with a CSV column, \
+                    then optionally :clear=<level> on the other side of the limit and :for=<seconds>", rule))))
+            .collect()
+}
+
+#[cfg(feature = "top")]
+fn cmd_top(args: &ParsedArgs) {
+    let sort = args.value("--sort").map(|name| TopSort::parse(name)
+            .unwrap_or_else(|| usage_error(&format!("unknown sort {}, use one of {}", name, TOP_SORTS.join("|")))))
+            .unwrap_or_default();
+    let count = number_or_exit(args, "--count", 10);
+    if count <= 0 {
+        usage_error("--count needs a positive number");
+    }
+    let metrics = match args.value("--metrics") {
+        Some(_) => metric_groups(args),
+        None => MetricGroups::none(),
+    };
+    let interval = number_or_exit(args, "--interval", 3);
+    let trace = TopConfig::new()
+            .sort(sort)
+            .count(count as usize)
+            .interval(interval)
+            .duration(number_or_exit(args, "--duration", 0))
+            .options(MonitorOptions { metrics, ..Default::default() })
+            .group_by_exe(args.flag("--group-by-exe"))
+            .build()
+            .unwrap_or_else(|e| usage_error(&e.to_string()));
+    let cpu_warn = args.value("--cpu-warn").map(|percent| percent.parse::<f64>().ok()
+            .filter(|percent| *percent >= 0.0)
+            .unwrap_or_else(|| usage_error("--cpu-warn needs a percent of all cores")))
+            .unwrap_or(10.0);
+    let rss_rising = number_or_exit(args, "--rss-rising", 3);
+    if rss_rising <= 0 {
+        usage_error("--rss-rising needs a positive number of intervals");
+    }
+    // Redraw the table in place on a terminal, append the tables otherwise
+    let refresh = io::stdout().is_terminal();
+    // adb shell without a pty is no terminal, --color always still colors there
+    let color = match args.value("--color").unwrap_or("auto") {
+        "auto" => refresh && env::var_os("NO_COLOR").is_none(),
+        "always" => true,
+        "never" => false,
+        other => usage_error(&format!("unknown color mode {}, use auto|always|never", other)),
+    };
+    let mut highlight = TopHighlight::new(cpu_warn, rss_rising as usize);
+    println!("Sampling every process, the first table comes in {}s", interval);
+    let result = trace.run(&mut |sample: &TopSample| {
+        if refresh {
+            print!("\x1b[H\x1b[2J");
+        }
+        match color {
+            true => println!("{}", top::format_top_highlighted(sample, &mut highlight)),
+            false => println!("{}", top::format_top(sample)),
+        }
+        io::stdout().flush().is_ok()
+    });
+    if let Err(e) = result {
+        eprintln!("top failed: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(feature = "top")]
+fn cmd_watch(args: &ParsedArgs) {
+    let targets = target_specs(args);
+    if targets.len() != 1 || args.flag("--all-matches") || !args.values("--pid").is_empty() {
+        usage_error("watch needs one --name or --target");
+    }
+    let target = TraceTarget::parse(&targets[0]);
+    let formats = output_formats(args);
+    let count = number_or_exit(args, "--count", 0);
+    if count < 0 {
+        usage_error("--count can not be negative");
+    }
+    let poll = number_or_exit(args, "--poll", 200);
+    if poll <= 0 {
+        usage_error("--poll must be positive");
+    }
+    let output_dir = args.value("--output-dir").unwrap_or(".");
+    let watch = WatchConfig::new(target.clone())
+            .output_dir(output_dir)
+            .interval(number_or_exit(args, "--interval", 1))
+            .poll(Duration::from_millis(poll as u64))
+            .duration(number_or_exit(args, "--duration", 0))
+            .count(count as usize)
+            .skip_running(args.flag("--skip-running"))
+            .formats(&formats)
+            .metrics(metric_groups(args))
+            .tags(trace_tags(args))
+            .options(monitor_options(args))
+            .build()
+            .unwrap_or_else(|e| usage_error(&e.to_string()));
+    println!("Watching {}, sessions go to {}", target.spec(), output_dir);
+    shutdown::install_stop_handler();
+    let result = watch.run(&mut |occurrence: &Occurrence| {
+        match &occurrence.error {
+            Some(e) => println!("Occurrence {} pid {} failed after {}s: {}",
+                    occurrence.index, occurrence.pid, occurrence.elapsed, e),
+            None => println!("Occurrence {} pid {} exited after {}s",
+                    occurrence.index, occurrence.pid, occurrence.elapsed),
+        }
+        true
+    });
+    match result {
+        Ok(traced) => println!("Watch finished, {} occurrences traced", traced),
+        Err(e) => {
+            eprintln!("watch failed: {}", e);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_soak(args: &ParsedArgs) {
+    let targets = target_specs(args);
+    let formats = match args.values("--format").is_empty() {
+        true => vec![OutputFormat::Csv],
+        false => output_formats(args),
+    };
+    let (days, keep_days) = (number_or_exit(args, "--days", 0), number_or_exit(args, "--keep-days", 14));
+    if days < 0 || keep_days < 0 {
+        usage_error("--days and --keep-days can not be negative");
+    }
+    let output_dir = args.value("--output-dir").unwrap_or(".");
+    let soak = SoakConfig::new(targets)
+            .output_dir(output_dir)
+            .interval(number_or_exit(args, "--interval", 300))
+            .day(number_or_exit(args, "--day", 86400))
+            .days(days as u64)
+            .keep_days(keep_days as usize)
+            .formats(&formats)
+            .metrics(metric_groups(args))
+            .tags(trace_tags(args))
+            .score(health_score(args))
+            .options(monitor_options(args))
+            .build()
+            .unwrap_or_else(|e| usage_error(&e.to_string()));
+    println!("Soaking, days go to {}, rollups to {}/{}", output_dir, output_dir.trim_end_matches('/'),
+            soak::DAILY_ROLLUP_FILE);
+    shutdown::install_stop_handler();
+    let result = soak.run(&mut |day: &SoakDay| {
+        println!("Day {} {}: {} targets rolled up{}", day.index, day.key, day.rolled_up,
+                day.error.as_ref().map(|e| format!(", {}", e)).unwrap_or_default());
+        for dir in &day.pruned {
+            println!("Pruned {}", dir);
+        }
+        true
+    });
+    match result {
+        Ok(days) => println!("Soak finished after {} days", days),
+        Err(e) => {
+            eprintln!("soak failed: {}", e);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "top")]
+fn cmd_rollup(args: &ParsedArgs) {
+    let targets = target_specs(args);
+    if targets.len() != 1 || args.flag("--all-matches") || !args.values("--pid").is_empty() {
+        usage_error("rollup needs one --name or --target");
+    }
+    let poll = number_or_exit(args, "--poll", 200);
+    if poll <= 0 {
+        usage_error("--poll must be positive");
+    }
+    let interval = number_or_exit(args, "--interval", 10);
+    let target = TraceTarget::parse(&targets[0]);
+    let mut tracker = RollupTracker::new(target.clone());
+    println!("Accounting {} every {}ms", target.spec(), poll);
+    // Print the totals every --interval seconds between the polls
+    let mut last_print = Instant::now();
+    let result = tracker.run(Duration::from_millis(poll as u64), number_or_exit(args, "--duration", 60),
+            &mut |rollup: &[NameRollup]| {
+        if interval > 0 && last_print.elapsed() >= Duration::from_secs(interval as u64) {
+            println!("{}", rollup::format_rollup(rollup));
+            last_print = Instant::now();
+        }
+        true
+    });
+    let rollup = result.unwrap_or_else(|e| {
+        eprintln!("rollup failed: {}", e);
+        exit(1);
+    });
+    print!("{}", rollup::format_rollup(&rollup));
+    if let Some(path) = args.value("--output") {
+        if let Err(e) = rollup::write_rollup_csv(path, &rollup) {
+            eprintln!("write {} failed: {}", path, e);
+            exit(1);
+        }
+        println!("Rollup written to {}", path);
+    }
+}
+
+#[cfg(feature = "top")]
+fn cmd_binder(args: &ParsedArgs) {
+    let targets = target_specs(args);
+    if targets.len() != 1 || args.flag("--all-matches") {
+        usage_error("binder needs one --name, --pid or --target");
+    }
+    let poll = number_or_exit(args, "--poll", 100);
+    if poll <= 0 {
+        usage_error("--poll must be positive");
+    }
+    let pid = TraceTarget::parse(&targets[0]).resolve_pid().unwrap_or_else(|e| {
+        eprintln!("binder failed: {}", e);
+        exit(1);
+    });
+    let mut pairing = BinderPairing::new(pid);
+    match pairing.log_path() {
+        Some(path) => println!("Pairing binder work of {} from {}", pid, path),
+        None => println!("No readable binder transaction log in {}, binder CPU stays unattributed",
+                BINDER_LOG_DIRS.join(" or ")),
+    }
+    if let Err(e) = pairing.run(Duration::from_millis(poll as u64), number_or_exit(args, "--duration", 60)) {
+        eprintln!("binder failed: {}", e);
+        exit(1);
+    }
+    print!("{}", binder::format_pairing(&pairing));
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_noise(args: &ParsedArgs) {
+    let targets = target_specs(args);
+    if targets.len() != 1 || args.flag("--all-matches") {
+        usage_error("noise needs one --name, --pid or --target");
+    }
+    let windows = number_or_exit(args, "--windows", noise::DEFAULT_NOISE_WINDOWS as i64);
+    if windows < 2 {
+        usage_error("--windows must be 2 or more");
+    }
+    let window = number_or_exit(args, "--window", noise::DEFAULT_NOISE_WINDOW);
+    let interval = number_or_exit(args, "--interval", 1);
+    if interval <= 0 || window < interval {
+        usage_error("--interval must be positive and --window at least one interval");
+    }
+    let device = args.value("--device").map(|device| device.to_string()).unwrap_or_else(loki::get_device_name);
+    let store = noise::noise_store_path(args.value("--store").unwrap_or("."), &device);
+    let target = TraceTarget::parse(&targets[0]);
+    let options = MonitorOptions { metrics: metric_groups(args), ..monitor_options(args) };
+    println!("Measuring the noise of {} on {}, keep the device idle", target.label(), device);
+    let traces = noise::measure_windows(&target, args.value("--output-dir").unwrap_or("."), windows as usize,
+            window, interval, options)
+            .unwrap_or_else(|e| {
+                eprintln!("noise failed: {}", e);
+                exit(1);
+            });
+    let floors = noise::noise_floor(&target.label(), &traces);
+    println!("{:<26}{:>14}{:>14}", "column", "mean", "stddev");
+    for floor in &floors {
+        println!("{:<26}{:>14.3}{:>14.3}", floor.column, floor.mean, floor.stddev);
+    }
+    match noise::save_noise_floor(&store, &floors) {
+        Ok(()) => println!("Saved noise floor of {} to {}", target.label(), store),
+        Err(e) => println!("save noise floor to {} failed: {}", store, e),
+    }
+}
+
+#[cfg(feature = "run")]
+fn cmd_run(args: &ParsedArgs) {
+    if args.positionals.is_empty() {
+        usage_error("run needs a command after --");
+    }
+    let interval = number_or_exit(args, "--interval", 1);
+    if interval <= 0 {
+        usage_error("--interval must be positive");
+    }
+    let per_thread = args.flag("--per-thread");
+    let mut formats = output_formats(args);
+    if per_thread && !formats.contains(&OutputFormat::Threads) {
+        formats.push(OutputFormat::Threads);
+    }
+    let (metrics, tags, hooks) = (metric_groups(args), trace_tags(args), session_hooks(args));
+    let options = monitor_options(args);
+    let sandbox_config = sandbox_config(args);
+    // Everything which may exit is done before the command starts, the
+    // session too, so it names the command instead of its pid
+    if [args.value("--output-dir"), args.value("--session-name")].iter().flatten().any(|t| t.contains("{pid}")) {
+        usage_error("run creates its session before the command starts, {pid} is not known yet");
+    }
+    let program = &args.positionals[0];
+    let command = TraceTarget::Name(Path::new(program).file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| program.clone()));
+    let mut state = create_session(args, &tags, 0, interval, &[command.spec().as_str()], &formats);
+    state.per_thread = per_thread;
+    state.metrics = metrics;
+    state.tags = tags;
+    let (child, gate) = launch::spawn_gated(program, &args.positionals[1..])
+            .unwrap_or_else(|e| {
+                eprintln!("run {} failed: {}", program, e);
+                // Nothing was traced into it
+                let _ = std::fs::remove_dir_all(&state.dir);
+                exit(127);
+            });
+    exit(run_command(args, state, options, hooks, sandbox_config, child, gate));
+}
+
+// Sets the flag when dropped, the recorders of a command stop however its run ends
+#[cfg(feature = "run")]
+struct SetOnDrop<'a>(&'a AtomicBool);
+
+#[cfg(feature = "run")]
+impl Drop for SetOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// A closed gate makes a held command exit without running, it is waited for
+// so its sandbox can be removed. Its session is removed, nothing was traced
+#[cfg(feature = "run")]
+fn abandon(gate: launch::Gate, child: &mut Child, dir: &str) -> i32 {
+    drop(gate);
+    let _ = child.wait();
+    let _ = std::fs::remove_dir_all(dir);
+    1
+}
+
+// Trace a started command in its session, returns the exit code of run. The
+// sandbox is owned here and removed when it returns or unwinds, nothing here
+// exits
+#[cfg(feature = "run")]
+fn run_command(args: &ParsedArgs, mut state: SessionState, options: MonitorOptions, hooks: SessionHooks,
+        sandbox_config: Option<SandboxConfig>, mut child: Child, gate: launch::Gate) -> i32 {
+    let program = &args.positionals[0];
+    let pid = child.id() as i32;
+    // The command is still held, everything it starts is born in the sandbox
+    let built = sandbox_config
+            .map(|config| config.build(pid).and_then(|sandbox| sandbox.add(pid).map(|_| sandbox)))
+            .transpose();
+    let sandbox = match built {
+        Ok(sandbox) => sandbox,
+        Err(e) => {
+            eprintln!("sandbox of {} failed: {}", pid, e);
+            return abandon(gate, &mut child, &state.dir);
+        },
+    };
+    let target = TraceTarget::Pid(pid);
+    state.targets[0].name = target.spec();
+    if let Err(e) = state.save() {
+        eprintln!("Save session failed: {}", e);
+        return abandon(gate, &mut child, &state.dir);
+    }
+    if let Some(sandbox) = &sandbox {
+        println!("{} runs in {}", pid, sandbox.path());
+    }
+    println!("Session {}, running {} as {}", state.dir, args.positionals.join(" "), pid);
+    let dir = state.dir.clone();
+    // The command execs once its monitor attached, so nothing it does is missed
+    let attached = global_bus().subscribe();
+    let label = target.label();
+    thread::spawn(move || {
+        while let Some(message) = attached.recv() {
+            if matches!(&*message, BusMessage::Attach { target, .. } if *target == label) {
+                if let Err(e) = gate.open() {
+                    println!("start {} failed: {}", pid, e);
+                }
+                return;
+            }
+        }
+    });
+    // Reaping the command ends its trace, its children are summed by name
+    let waiter = thread::spawn(move || child.wait());
+    let interval = Duration::from_secs(state.monitor_interval as u64);
+    // Nodes read beside the trace time out like the ones of the trace
+    let read_timeout = options.read_timeout;
+    let done = AtomicBool::new(false);
+    let (traced, status) = thread::scope(|scope| {
+        let stop = SetOnDrop(&done);
+        let recorder = sandbox.as_ref().map(|sandbox| {
+            let (path, done) = (format!("{}/{}", dir, sandbox::SANDBOX_STATS_FILE), &done);
+            scope.spawn(move || {
+                file_utils::set_node_timeout(read_timeout);
+                sandbox.record(&path, interval, done)
+            })
+        });
+        #[cfg(feature = "top")]
+        let children_done = &done;
+        #[cfg(feature = "top")]
+        let children = scope.spawn(move || {
+            file_utils::set_node_timeout(read_timeout);
+            RollupTracker::new(TraceTarget::Scheme("children".to_string(), pid.to_string()))
+                    .run(Duration::from_millis(200), 0, &mut |_: &[NameRollup]| !children_done.load(Ordering::Relaxed))
+        });
+        // A failed trace closed the bus, a command still at the gate exits then
+        // and a started one is waited for, so the sandbox can be removed
+        let traced = run_session(state, options, hooks);
+        if let Err(e) = &traced {
+            eprintln!("trace failed: {}", e);
+        }
+        let status = waiter.join().unwrap_or_else(|_| Err(io::Error::other("waiter panicked")));
+        drop(stop);
+        #[cfg(feature = "top")]
+        match children.join() {
+            Ok(Ok(rollup)) if !rollup.is_empty() => {
+                print!("children of {}:\n{}", pid, rollup::format_rollup(&rollup));
+                let path = format!("{}/{}", dir, launch::CHILDREN_ROLLUP_FILE);
+                if let Err(e) = rollup::write_rollup_csv(&path, &rollup) {
+                    println!("write {} failed: {}", path, e);
+                }
+            },
+            Ok(Ok(_)) => {},
+            Ok(Err(e)) => println!("children of {} failed: {}", pid, e),
+            Err(_) => println!("children of {} panicked", pid),
+        }
+        if let (Some(sandbox), Some(recorder)) = (&sandbox, recorder) {
+            match recorder.join() {
+                Ok(Ok(stats)) => println!("sandbox {}: memory peak {} KB, cpu {:.2}s, throttled {:.2}s in {} periods, {} oom kills",
+                        sandbox.path(), stats.memory_peak / 1024, stats.cpu_usage_usec as f64 / 1e6,
+                        stats.throttled_usec as f64 / 1e6, stats.nr_throttled, stats.oom_kill),
+                Ok(Err(e)) => println!("record sandbox {} failed: {}", sandbox.path(), e),
+                Err(_) => println!("record sandbox {} panicked", sandbox.path()),
+            }
+        }
+        (traced, status)
+    });
+    // Removes the cgroup before the manifest is written
+    drop(sandbox);
+    if traced.is_err() {
+        return 1;
+    }
+    finish_session(&dir);
+    match status {
+        Ok(status) => {
+            println!("{} exited with {}", program, status);
+            status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+        },
+        Err(e) => {
+            eprintln!("wait for {} failed: {}", program, e);
+            1
+        },
+    }
+}
+
+fn cmd_selftest(args: &ParsedArgs) {
+    let pid = match number_or_exit(args, "--pid", 0) {
+        0 => std::process::id() as i32,
+        pid if pid > 0 => pid as i32,
+        _ => usage_error("--pid must be positive"),
+    };
+    println!("Self test against {} on kernel {}", pid, selftest::kernel_release());
+    println!("/proc mounted with {}", hidepid::ProcMount::current().describe());
+    if let Some(restriction) = hidepid::restriction() {
+        println!("{}", restriction);
+    }
+    let checks = selftest::run_selftest(pid);
+    print!("{}", selftest::format_selftest(&checks));
+    if checks.iter().any(|check| check.status == CheckStatus::Failed) {
+        exit(1);
+    }
+}
+
+fn cmd_crosscheck(args: &ParsedArgs) {
+    let pid = match number_or_exit(args, "--pid", 0) {
+        0 => std::process::id() as i32,
+        pid if pid > 0 => pid as i32,
+        _ => usage_error("--pid must be positive"),
+    };
+    let interval = number_or_exit(args, "--interval", 10);
+    let duration = number_or_exit(args, "--duration", 60);
+    if interval <= 0 || duration < 0 {
+        usage_error("--interval must be positive and --duration must not be negative");
+    }
+    println!("Cross check of {} on kernel {}", pid, selftest::kernel_release());
+    let (mut rounds, mut differing) = (0, 0);
+    let start = Instant::now();
+    loop {
+        let checks = crosscheck::cross_check(pid);
+        let differs = checks.iter().filter(|check| check.differs()).count();
+        let skipped = checks.iter().filter(|check| check.values.is_err()).count();
+        println!("time={} checks={} differ={} skipped={}", start.elapsed().as_secs(), checks.len(), differs, skipped);
+        print!("{}", crosscheck::format_cross_check(&checks, args.flag("--all")));
+        rounds += 1;
+        differing += differs;
+        if start.elapsed().as_secs() as i64 + interval > duration {
+            break;
+        }
+        thread::sleep(Duration::from_secs(interval as u64));
+    }
+    println!("{} rounds, {} discrepancies", rounds, differing);
+    if differing > 0 {
+        exit(1);
+    }
+}
+
+fn cmd_load(args: &ParsedArgs) {
+    let mut pattern = LoadPattern::default();
+    if let Some(percent) = args.value("--cpu") {
+        pattern.cpu_percent = percent.parse::<f64>().ok()
+                .filter(|percent| (0.0..=100.0).contains(percent))
+                .unwrap_or_else(|| usage_error(&format!("--cpu needs a percent of 0 ~ 100, got {}", percent)));
+    }
+    let number = |name: &str, default: u64| match number_or_exit(args, name, default as i64) {
+        n if n >= 0 => n as u64,
+        _ => usage_error(&format!("{} must not be negative", name)),
+    };
+    pattern.threads = number("--threads", pattern.threads as u64) as usize;
+    pattern.memory_mb = number("--memory", pattern.memory_mb);
+    pattern.ramp = number("--ramp", pattern.ramp);
+    pattern.duration = number("--duration", pattern.duration);
+    println!("Load {} thread(s) at {}% and {} MB over {}s in pid {}, trace it with process_trace trace --pid {}",
+            pattern.threads, pattern.cpu_percent, pattern.memory_mb, pattern.ramp, std::process::id(),
+            std::process::id());
+    procutils::loadgen::run_load(&pattern, |status| println!("{}", status.format(&pattern)));
+}
+
+fn cmd_snapshot(args: &ParsedArgs) {
+    if args.positionals.len() != 1 {
+        usage_error("snapshot needs one process name");
+    }
+    if let Err(e) = procutils::proc_analysis::snapshot_process(&args.positionals[0], &mut io::stdout().lock()) {
+        eprintln!("snapshot failed: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_analyze(args: &ParsedArgs) {
+    if args.positionals.len() != 1 {
+        usage_error("analyze needs one trace");
+    }
+    let path = &args.positionals[0];
+    let mut data = load(path);
+    println!("{}: {} samples", path, data.rows.len());
+    if let Some(idle) = args.value("--idle") {
+        let baselines = baseline::load_idle_baseline(idle)
+                .unwrap_or_else(|e| usage_error(&format!("load {} failed: {}", idle, e)));
+        let baseline = baseline::baseline_for(&baselines, path)
+                .unwrap_or_else(|| usage_error(&format!("{} has no baseline of {}", idle, path)));
+        baseline::subtract_idle(&mut data, baseline);
+        print!("{}", baseline::format_idle(std::slice::from_ref(baseline)));
+    }
+    if !data.tags.is_empty() {
+        let tags: Vec<String> = data.tags.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        println!("tags: {}", tags.join(" "));
+    }
+    if let Some(transforms) = analysis::format_transforms(&data) {
+        println!("{}", transforms);
+    }
+    let score = health_score(args);
+    score.add_column(&mut data);
+    println!("{}: {}", score::HEALTH_SCORE_COLUMN, score.spec());
+    print!("{}", analysis::format_summary(&analysis::summarize(&data)));
+    print!("{}", analysis::format_gaps(&analysis::find_gaps(&data)));
+    if args.flag("--envelope") {
+        print!("{}", envelope::format_envelopes(&envelope::envelopes(&data)));
+    }
+}
+
+#[cfg(feature = "analysis")]
+fn resample_mode(args: &ParsedArgs) -> ResampleMode {
+    args.value("--resample").map(|mode| ResampleMode::parse(mode).unwrap_or_else(|| usage_error(&format!(
+            "unknown resample mode {}, use {}", mode, RESAMPLE_MODES.join("|")))))
+            .unwrap_or_default()
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_compare(args: &ParsedArgs) {
+    let (base, candidate) = (args.values("--baseline"), args.values("--candidate"));
+    let (base, candidate) = if base.is_empty() && candidate.is_empty() {
+        if args.positionals.len() != 2 {
+            usage_error("compare needs a baseline and a candidate trace");
+        }
+        (vec![args.positionals[0].as_str()], vec![args.positionals[1].as_str()])
+    } else {
+        if base.is_empty() || candidate.is_empty() || !args.positionals.is_empty() {
+            usage_error("compare needs --baseline and --candidate runs, without other traces");
+        }
+        (base, candidate)
+    };
+    let alpha = match args.value("--alpha") {
+        Some(alpha) => alpha.parse::<f64>().ok()
+                .filter(|alpha| *alpha > 0.0 && *alpha < 1.0)
+                .unwrap_or_else(|| usage_error(&format!("--alpha needs a level between 0 and 1, got {}", alpha))),
+        None => stats::DEFAULT_ALPHA,
+    };
+    let base_paths = base.clone();
+    let mut base: Vec<TraceData> = base.iter().map(|path| load(path)).collect();
+    let mut candidate: Vec<TraceData> = candidate.iter().map(|path| load(path)).collect();
+    // Transformed and measured values of a column are not comparable
+    if let Some(other) = base.iter().chain(&candidate).find(|run| run.transforms != base[0].transforms) {
+        println!("warning: runs have different transforms, [{}] and [{}]",
+                base[0].transforms.join(" "), other.transforms.join(" "));
+    }
+    // Deltas per sample only compare between runs of the same interval
+    let mode = resample_mode(args);
+    let runs: Vec<TraceData> = base.iter().chain(&candidate).cloned().collect();
+    if let Some((step, mut resampled)) = align::resample_runs(&runs, mode) {
+        println!("runs have different intervals, resampled to {}s ({})", step, mode.name());
+        candidate = resampled.split_off(base.len());
+        base = resampled;
+    }
+    let mut comparison = analysis::compare_groups(&base, &candidate, alpha, args.flag("--exclude-outliers"));
+    if let Some(store) = args.value("--noise") {
+        let floors = noise::load_noise_store(store)
+                .unwrap_or_else(|e| usage_error(&format!("load {} failed: {}", store, e)));
+        let target = args.value("--noise-target").map(|target| target.to_string())
+                .or_else(|| noise::noise_target(&floors, base_paths[0]))
+                .unwrap_or_else(|| usage_error(&format!("{} has several targets, pick one with --noise-target", store)));
+        let applied = noise::apply_noise_floor(&mut comparison, &floors, &target);
+        println!("noise floor of {} from {}, {} columns", target, store, applied);
+    }
+    print!("{}", analysis::format_group_compare(&comparison));
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_report(args: &ParsedArgs) -> io::Result<()> {
+    if args.positionals.len() != 1 {
+        usage_error("report needs one trace");
+    }
+    let path = &args.positionals[0];
+    let output = args.value("--output").map(|o| o.to_string())
+            .unwrap_or_else(|| format!("{}.html", path.trim_end_matches(".csv")));
+    let data = load(path);
+    let envelopes = match args.flag("--envelope") {
+        true => envelope::envelopes(&data),
+        false => Vec::new(),
+    };
+    let html = analysis::render_report(path, &data, &envelopes);
+    File::create(&output)?.write_all(html.as_bytes())?;
+    println!("Report {}", output);
+    Ok(())
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_merge(args: &ParsedArgs) -> io::Result<()> {
+    let output = args.value("--output").unwrap_or_else(|| usage_error("merge needs -o <out.csv>"));
+    if args.positionals.is_empty() {
+        usage_error("merge needs at least one trace");
+    }
+    let pieces: Vec<TraceData> = args.positionals.iter().map(|path| load(path)).collect();
+    let merged = analysis::merge(&pieces)?;
+    let mut out = BufWriter::new(File::create(output)?);
+    merged.write_csv(&mut out)?;
+    out.flush()?;
+    println!("Merged {} samples into {}", merged.rows.len(), output);
+    Ok(())
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_convert(args: &ParsedArgs) -> io::Result<()> {
+    let output = args.value("--output").unwrap_or_else(|| usage_error("convert needs -o <out.csv>"));
+    if args.positionals.len() != 1 {
+        usage_error("convert needs one trace");
+    }
+    numeric::set_precisions(&column_precisions(args));
+    let data = load(&args.positionals[0]);
+    let mut out = BufWriter::new(File::create(output)?);
+    data.write_csv(&mut out)?;
+    out.flush()?;
+    println!("Converted {} to schema {} in {}", args.positionals[0], data.schema, output);
+    Ok(())
+}
+
+// Target label of resource_trace_<label>.csv
+#[cfg(feature = "analysis")]
+fn csv_label(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path).trim_start_matches("resource_trace_").trim_end_matches(".csv")
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_trend(args: &ParsedArgs) -> io::Result<()> {
+    if args.positionals.len() != 1 {
+        usage_error("trend needs one store");
+    }
+    let store = &args.positionals[0];
+    let score = health_score(args);
+    for path in args.values("--append") {
+        let mut data = load_raw(path);
+        if let Some(transforms) = analysis::format_transforms(&data) {
+            println!("warning: {} {}, the store keeps them as they are", path, transforms);
+        }
+        score.add_column(&mut data);
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let label = args.value("--target").unwrap_or_else(|| csv_label(path));
+        let key = args.value("--key").map(|key| key.to_string())
+                .unwrap_or_else(|| trend::run_key(&data, name));
+        trend::append_run(store, &key, label, &data, now_epoch())?;
+        println!("Appended {} as {} {}", path, label, key);
+    }
+    let entries = trend::load_trend(store)?;
+    let columns = match args.values("--column") {
+        columns if columns.is_empty() => trend::DEFAULT_TREND_COLUMNS.to_vec(),
+        columns => columns,
+    };
+    print!("{}", trend::format_trend(&entries, &columns));
+    Ok(())
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_contention(args: &ParsedArgs) -> io::Result<()> {
+    // A session directory stands for the csv outputs of its targets
+    let paths: Vec<String> = match args.positionals.as_slice() {
+        [dir] if std::path::Path::new(dir).is_dir() => {
+            let mut paths: Vec<String> = std::fs::read_dir(dir)?
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .filter(|name| name.starts_with("resource_trace_") && name.ends_with(".csv")
+                            && !name.ends_with(".threads.csv"))
+                    .map(|name| format!("{}/{}", dir.trim_end_matches('/'), name))
+                    .collect();
+            paths.sort();
+            paths
+        },
+        paths => paths.to_vec(),
+    };
+    if paths.len() < 2 {
+        usage_error("contention needs a session or the traces of two targets or more");
+    }
+    let threshold = args.value("--threshold").map(|z| z.parse::<f64>().ok().filter(|z| *z > 0.0)
+            .unwrap_or_else(|| usage_error(&format!("--threshold needs a positive z-score, got {}", z))))
+            .unwrap_or(contention::DEFAULT_SPIKE_Z);
+    let data: Vec<TraceData> = paths.iter().map(|path| load(path)).collect();
+    let aligned = align::align(&data, resample_mode(args))
+            .unwrap_or_else(|e| usage_error(&format!("align traces failed: {}", e)));
+    let traces: Vec<(String, TraceData)> = paths.iter().map(|path| csv_label(path).to_string()).zip(aligned).collect();
+    print!("{}", contention::format_contention(&contention::find_contention(&traces, threshold)));
+    Ok(())
+}
+
+#[cfg(feature = "analysis")]
+fn cmd_verify(args: &ParsedArgs) -> io::Result<()> {
+    if args.positionals.is_empty() {
+        usage_error("verify needs at least one trace");
+    }
+    let mut failed = false;
+    for path in &args.positionals {
+        let issues = analysis::verify_csv(&read_path(path)?);
+        if issues.is_empty() {
+            println!("{}: ok", path);
+        } else {
+            failed = true;
+            println!("{}: {} problems", path, issues.len());
+            for issue in issues {
+                println!("  {}", issue);
+            }
+        }
+    }
+    if failed {
+        exit(1);
+    }
+    Ok(())
+}
+
+fn cmd_completion(args: &ParsedArgs) {
+    if args.positionals.len() != 1 {
+        usage_error("completion needs a shell name");
+    }
+    let shell = &args.positionals[0];
+    match cli::completion(shell) {
+        Some(script) => print!("{}", script),
+        None => usage_error(&format!("unsupported shell {}, use one of {}", shell, cli::SHELLS.join(", "))),
+    }
+}
+
+fn cmd_deploy(args: &ParsedArgs) -> io::Result<()> {
+    if !args.positionals.is_empty() {
+        usage_error("deploy takes no arguments");
+    }
+    let options = deploy::DeployOptions {
+        serial: args.value("--serial").map(|s| s.to_string()),
+        abi: args.value("--abi").map(|s| s.to_string()),
+        binary: args.value("--binary").map(|s| s.to_string()),
+        install: args.flag("--install"),
+    };
+    let path = deploy::deploy(&options)?;
+    if options.install {
+        println!("Installed {}, add {} to PATH to run it as process_trace", path, deploy::DEVICE_INSTALL_DIR);
+    } else {
+        println!("Deployed {}", path);
+    }
+    Ok(())
+}
+
+fn cmd_remote(args: &ParsedArgs) -> io::Result<()> {
+    if args.positionals.is_empty() {
+        usage_error("remote needs the device command after --, e.g. -- trace --name system_server");
+    }
+    // Parsed here so a bad device command fails before anything runs, and so
+    // the default output dir lands before any `--` of the command
+    let (command, rest) = match args.positionals[0].starts_with('-') {
+        true => ("trace", &args.positionals[..]),
+        false => (args.positionals[0].as_str(), &args.positionals[1..]),
+    };
+    let spec = cli::find_command(command)
+            .unwrap_or_else(|| usage_error(&format!("unknown device command {}", command)));
+    let parsed = cli::parse_args(spec, rest).unwrap_or_else(|e| usage_error(&e));
+    let mut device_args = vec![command.to_string()];
+    if parsed.value("--output-dir").is_none() && cli::options(spec).any(|o| o.name == "--output-dir") {
+        device_args.extend(["--output-dir".to_string(), deploy::DEVICE_SESSION_DIR.to_string()]);
+    }
+    device_args.extend(rest.iter().cloned());
+    let options = deploy::RemoteOptions {
+        serial: args.value("--serial").map(|s| s.to_string()),
+        device_binary: args.value("--device-binary").unwrap_or(deploy::DEVICE_TMP_PATH).to_string(),
+        pull_dir: args.value("--pull-dir").unwrap_or(".").to_string(),
+        delete_device_copy: args.flag("--delete-device-copy"),
+    };
+    let path = deploy::remote_trace(&options, &device_args)?;
+    println!("Pulled {}", path);
+    Ok(())
+}
+
 fn main() {
-    // Modify this... To trace process
-    let monitor_list: Vec<&str> = vec!["second_stage"];
-    procutils::proc_analysis::trace_process(60, 10, &monitor_list);
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|a| a.as_str()) {
+        Some("--help") | Some("-h") => { print!("{}", cli::usage()); return; },
+        Some("--help-json") => { println!("{}", cli::help_json()); return; },
+        Some("--version") => { println!("process_trace {}", procutils::VERSION); return; },
+        _ => {},
+    }
+    // No command, or options only, is a trace, common options may come first
+    let mut first = 0;
+    while let Some((option, inline)) = args.get(first).and_then(|arg| cli::common_option(arg)) {
+        first += if option.value.is_some() && !inline { 2 } else { 1 };
+    }
+    let command = match args.get(first) {
+        Some(arg) if !arg.starts_with('-') => args.remove(first),
+        _ => "trace".to_string(),
+    };
+    let spec = cli::find_command(&command)
+            .unwrap_or_else(|| usage_error(&format!("unknown command {}", command)));
+    let args = cli::parse_args(spec, &args).unwrap_or_else(|e| usage_error(&e));
+    // Before the command runs anything, strict mode covers every command
+    if let Some(path) = args.value("--audit-log") {
+        audit::open_log(path).unwrap_or_else(|e| usage_error(&format!("open audit log {} failed: {}", path, e)));
+    }
+    if args.flag("--strict") {
+        audit::set_strict(true);
+    }
+    let result = match command.as_str() {
+        "trace" => { cmd_trace(&args); Ok(()) },
+        "calibrate" => { cmd_calibrate(&args); Ok(()) },
+        #[cfg(feature = "top")]
+        "watch" => { cmd_watch(&args); Ok(()) },
+        #[cfg(feature = "top")]
+        "rollup" => { cmd_rollup(&args); Ok(()) },
+        #[cfg(feature = "top")]
+        "binder" => { cmd_binder(&args); Ok(()) },
+        #[cfg(feature = "run")]
+        "run" => { cmd_run(&args); Ok(()) },
+        #[cfg(feature = "analysis")]
+        "noise" => { cmd_noise(&args); Ok(()) },
+        #[cfg(feature = "top")]
+        "top" => { cmd_top(&args); Ok(()) },
+        "selftest" => { cmd_selftest(&args); Ok(()) },
+        "crosscheck" => { cmd_crosscheck(&args); Ok(()) },
+        "load" => { cmd_load(&args); Ok(()) },
+        "snapshot" => { cmd_snapshot(&args); Ok(()) },
+        #[cfg(feature = "analysis")]
+        "analyze" => { cmd_analyze(&args); Ok(()) },
+        #[cfg(feature = "analysis")]
+        "compare" => { cmd_compare(&args); Ok(()) },
+        #[cfg(feature = "analysis")]
+        "report" => cmd_report(&args),
+        #[cfg(feature = "analysis")]
+        "merge" => cmd_merge(&args),
+        #[cfg(feature = "analysis")]
+        "convert" => cmd_convert(&args),
+        #[cfg(feature = "analysis")]
+        "verify" => cmd_verify(&args),
+        #[cfg(feature = "analysis")]
+        "trend" => cmd_trend(&args),
+        #[cfg(feature = "analysis")]
+        "contention" => cmd_contention(&args),
+        #[cfg(feature = "analysis")]
+        "soak" => { cmd_soak(&args); Ok(()) },
+        "completion" => { cmd_completion(&args); Ok(()) },
+        "deploy" => cmd_deploy(&args),
+        "remote" => cmd_remote(&args),
+        _ => { print!("{}", cli::usage()); Ok(()) },
+    };
+    if let Err(e) = result {
+        eprintln!("{} failed: {}", command, e);
+        exit(1);
+    }
 }
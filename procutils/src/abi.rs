@@ -0,0 +1,117 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::file_utils::read_head;
+use libc::pid_t;
+
+// ELF header
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELF_CLASS_OFFSET: usize = 4;
+const ELF_DATA_OFFSET: usize = 5;
+const ELF_MACHINE_OFFSET: usize = 18;
+const ELF_HEADER_PREFIX_LEN: usize = 20;
+const ELF_CLASS_32: u8 = 1;
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LSB: u8 = 1;
+
+// e_machine
+const EM_386: u16 = 3;
+const EM_ARM: u16 = 40;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const EM_RISCV: u16 = 243;
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_EXE_TEMPLATE { () => { "/proc/{}/exe" }; }
+
+/// ABI of a process, read from the ELF header of its binary
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProcessAbi {
+    /// arm64-v8a
+    Arm64,
+    /// armeabi-v7a
+    Arm,
+    /// x86_64
+    X86_64,
+    /// x86
+    X86,
+    /// riscv64
+    Riscv64,
+    /// the binary can not be read, e.g. no permission to follow `/proc/<pid>/exe`
+    #[default]
+    Unknown,
+}
+
+impl ProcessAbi {
+    /// Read the ABI of a running process
+    pub fn detect(pid: pid_t) -> ProcessAbi {
+        let mut header = [0u8; ELF_HEADER_PREFIX_LEN];
+        match read_head(&format!(TASK_EXE_TEMPLATE!(), pid), &mut header) {
+            Ok(_) => ProcessAbi::from_elf_header(&header),
+            Err(_) => ProcessAbi::Unknown,
+        }
+    }
+
+    /// Get the ABI from the first bytes of an ELF file
+    pub fn from_elf_header(header: &[u8]) -> ProcessAbi {
+        if header.len() < ELF_HEADER_PREFIX_LEN || !header.starts_with(ELF_MAGIC) {
+            return ProcessAbi::Unknown;
+        }
+        let machine = [header[ELF_MACHINE_OFFSET], header[ELF_MACHINE_OFFSET + 1]];
+        let machine = if header[ELF_DATA_OFFSET] == ELF_DATA_LSB {
+            u16::from_le_bytes(machine)
+        } else {
+            u16::from_be_bytes(machine)
+        };
+        match (header[ELF_CLASS_OFFSET], machine) {
+            (ELF_CLASS_64, EM_AARCH64) => ProcessAbi::Arm64,
+            (ELF_CLASS_32, EM_ARM) => ProcessAbi::Arm,
+            (ELF_CLASS_64, EM_X86_64) => ProcessAbi::X86_64,
+            (ELF_CLASS_32, EM_386) => ProcessAbi::X86,
+            (ELF_CLASS_64, EM_RISCV) => ProcessAbi::Riscv64,
+            _ => ProcessAbi::Unknown,
+        }
+    }
+
+    /// ABI of the tracer itself
+    pub fn current() -> ProcessAbi {
+        if cfg!(target_arch = "aarch64") {
+            ProcessAbi::Arm64
+        } else if cfg!(target_arch = "arm") {
+            ProcessAbi::Arm
+        } else if cfg!(target_arch = "x86_64") {
+            ProcessAbi::X86_64
+        } else if cfg!(target_arch = "x86") {
+            ProcessAbi::X86
+        } else if cfg!(target_arch = "riscv64") {
+            ProcessAbi::Riscv64
+        } else {
+            ProcessAbi::Unknown
+        }
+    }
+
+    /// Android ABI name
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessAbi::Arm64 => "arm64-v8a",
+            ProcessAbi::Arm => "armeabi-v7a",
+            ProcessAbi::X86_64 => "x86_64",
+            ProcessAbi::X86 => "x86",
+            ProcessAbi::Riscv64 => "riscv64",
+            ProcessAbi::Unknown => "unknown",
+        }
+    }
+
+    /// Word size in bits, None when unknown
+    pub fn bits(&self) -> Option<u32> {
+        match self {
+            ProcessAbi::Arm | ProcessAbi::X86 => Some(32),
+            ProcessAbi::Unknown => None,
+            _ => Some(64),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use crate::bus::{BusMessage, LiveAnalyzer};
+use crate::proc_analysis::{RecordItem, CSV_COLUMNS};
+use libc::pid_t;
+use std::collections::HashMap;
+
+// Options of a rule after its limit, e.g. `:clear=0.3:for=10`
+const CLEAR_OPTION: &str = "clear=";
+const HOLD_OPTION: &str = "for=";
+
+/// Side of the limit which fires an alert
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertOp {
+    /// the value is greater than the limit
+    Above,
+    /// the value is less than the limit
+    Below,
+}
+
+/// `<column>><limit>` or `<column><<limit>`, e.g. `cpuOccupancyRate>0.5`,
+/// optionally followed by `:clear=<level>` and `:for=<seconds>`
+///
+/// With a clear level a firing rule only clears once the value is past it,
+/// e.g. `cpuOccupancyRate>0.5:clear=0.3` fires above 0.5 and clears below
+/// 0.3. With a hold time the value must stay on the side for that long
+/// before the rule fires or clears, so a flapping metric alerts once.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlertRule {
+    /// CSV column, see `CSV_COLUMNS`
+    pub column: String,
+    /// side of the limit
+    pub op: AlertOp,
+    /// limit in the unit of the column
+    pub limit: f64,
+    /// level a firing rule clears at, None clears at the limit
+    pub clear: Option<f64>,
+    /// seconds the value has to stay past the limit or the clear level
+    /// before the rule fires or clears, 0 changes at once
+    pub hold: i64,
+}
+
+impl AlertRule {
+    /// Parse a rule, None for unknown columns, limits which are not numbers
+    /// or a clear level on the firing side of the limit
+    pub fn parse(rule: &str) -> Option<AlertRule> {
+        let mut parts = rule.split(':');
+        let rule = parts.next()?;
+        let (mut clear, mut hold) = (None, 0);
+        for option in parts.map(str::trim) {
+            if let Some(level) = option.strip_prefix(CLEAR_OPTION) {
+                clear = Some(level.parse::<f64>().ok().filter(|level| level.is_finite())?);
+            } else if let Some(seconds) = option.strip_prefix(HOLD_OPTION) {
+                hold = seconds.trim_end_matches('s').parse::<i64>().ok().filter(|seconds| *seconds >= 0)?;
+            } else {
+                return None;
+            }
+        }
+        let (at, op) = rule.char_indices().find_map(|(i, c)| match c {
+            '>' => Some((i, AlertOp::Above)),
+            '<' => Some((i, AlertOp::Below)),
+            _ => None,
+        })?;
+        let column = rule[..at].trim();
+        let limit = rule[at + 1..].trim().parse::<f64>().ok().filter(|limit| limit.is_finite())?;
+        if !CSV_COLUMNS.contains(&column) {
+            return None;
+        }
+        let hysteresis = match (op, clear) {
+            (AlertOp::Above, Some(level)) => level <= limit,
+            (AlertOp::Below, Some(level)) => level >= limit,
+            (_, None) => true,
+        };
+        hysteresis.then(|| AlertRule { column: column.to_string(), op, limit, clear, hold })
+    }
+
+    /// Whether a sample is on the firing side, samples without a value never are
+    pub fn fires(&self, item: &RecordItem) -> bool {
+        match (item.value(&self.column), self.op) {
+            (Some(value), AlertOp::Above) => value > self.limit,
+            (Some(value), AlertOp::Below) => value < self.limit,
+            (None, _) => false,
+        }
+    }
+
+    /// Whether a firing rule is past its clear level in a sample. Without a
+    /// clear level that is off the firing side, samples without a value then too
+    pub fn clears(&self, item: &RecordItem) -> bool {
+        match (item.value(&self.column), self.clear, self.op) {
+            (Some(value), Some(level), AlertOp::Above) => value < level,
+            (Some(value), Some(level), AlertOp::Below) => value > level,
+            (None, Some(_), _) => false,
+            (_, None, _) => !self.fires(item),
+        }
+    }
+
+    /// Rule text accepted by `parse`
+    pub fn spec(&self) -> String {
+        let op = match self.op { AlertOp::Above => '>', AlertOp::Below => '<' };
+        let mut spec = format!("{}{}{}", self.column, op, self.limit);
+        if let Some(level) = self.clear {
+            spec.push_str(&format!(":{}{}", CLEAR_OPTION, level));
+        }
+        if self.hold > 0 {
+            spec.push_str(&format!(":{}{}", HOLD_OPTION, self.hold));
+        }
+        spec
+    }
+}
+
+/// A rule starting or stopping to fire for a target
+#[derive(Clone, Debug)]
+pub struct Alert {
+    /// target label
+    pub target: String,
+    /// pid of the sample
+    pub pid: pid_t,
+    /// seconds since time 0
+    pub time: i64,
+    /// the rule
+    pub rule: AlertRule,
+    /// value of the column at `time`
+    pub value: f64,
+    /// true when the rule starts firing, false when it clears
+    pub firing: bool,
+    /// seconds since time 0 the value crossed, before `time` when the rule holds
+    pub since: i64,
+}
+
+// Firing state of a rule for one target
+#[derive(Clone, Copy, Debug, Default)]
+struct RuleState {
+    firing: bool,
+    // time the value crossed to the other side, while the rule holds
+    pending: Option<i64>,
+}
+
+/// Live analyzer which reports rules starting and stopping to fire
+///
+/// Every rule fires once per crossing, not on every sample above the limit,
+/// and not before the value stayed past the limit for the hold time of the rule.
+pub struct ThresholdAlerts {
+    rules: Vec<AlertRule>,
+    // state by (target, rule index)
+    states: HashMap<(String, usize), RuleState>,
+    on_alert: Box<dyn FnMut(&Alert) + Send>,
+}
+
+impl ThresholdAlerts {
+    /// Pass every alert to `on_alert`
+    pub fn new(rules: Vec<AlertRule>, on_alert: impl FnMut(&Alert) + Send + 'static) -> ThresholdAlerts {
+        ThresholdAlerts { rules, states: HashMap::new(), on_alert: Box::new(on_alert) }
+    }
+}
+
+impl LiveAnalyzer for ThresholdAlerts {
+    fn on_message(&mut self, message: &BusMessage) {
+        let (target, pid, item) = match message {
+            BusMessage::Sample { target, pid, item } => (target, *pid, item),
+            // A finished target starts over if it is traced again
+            BusMessage::End { target } => {
+                self.states.retain(|(state_target, _), _| state_target != target);
+                return;
+            },
+            _ => return,
+        };
+        for (i, rule) in self.rules.iter().enumerate() {
+            let state = self.states.entry((target.clone(), i)).or_default();
+            let crossed = match state.firing {
+                false => rule.fires(item),
+                true => rule.clears(item),
+            };
+            if !crossed {
+                state.pending = None;
+                continue;
+            }
+            let since = *state.pending.get_or_insert(item.timestamp);
+            if item.timestamp - since < rule.hold {
+                continue;
+            }
+            state.firing = !state.firing;
+            state.pending = None;
+            (self.on_alert)(&Alert {
+                target: target.clone(),
+                pid,
+                time: item.timestamp,
+                rule: rule.clone(),
+                value: item.value(&rule.column).unwrap_or(f64::NAN),
+                firing: state.firing,
+                since,
+            });
+        }
+    }
+
+    fn finish(&mut self, dropped: u64) {
+        if dropped > 0 {
+            println!("alerts missed {} samples", dropped);
+        }
+    }
+}
+
+/// One line describing an alert
+pub fn format_alert(alert: &Alert) -> String {
+    let held = match alert.since < alert.time {
+        true => format!(", crossed at {}s", alert.since),
+        false => String::new(),
+    };
+    format!("alert {} {} {}: {}={} at {}s{}", alert.target, alert.rule.spec(),
+            if alert.firing { "fired" } else { "cleared" }, alert.rule.column, alert.value, alert.time, held)
+}
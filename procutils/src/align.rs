@@ -0,0 +1,199 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::analysis::TraceData;
+use crate::stats::median;
+use std::io;
+
+// Column holding the sample time
+const TIME_COLUMN: &str = "time";
+
+/// Columns holding the change over the interval ending at their sample,
+/// they are resampled through their running sum so totals are kept
+pub const DELTA_COLUMNS: &[&str] = &[
+    "voluntaryCtxtSwitches", "nonvoluntaryCtxtSwitches", "minflt", "majflt", "utime", "stime",
+    "totalcputime", "gutime", "gstime", "gtotalcputime", "readBytes", "writeBytes", "syscr", "syscw",
+    "runDelay", "sleepTime", "offCpuTime",
+];
+
+// Columns of states and flags, never interpolated
+const DISCRETE_COLUMNS: &[&str] = &[
+    "priority", "nice", "numThreads", "startTime", "retries", "qualityFlags", "collectorsOk",
+    "carriedForward", "fdCount", "oomScoreAdj", "seq", "missed",
+];
+
+// Ratio of two delta columns, computed again from the resampled ones
+const RATE_COLUMN: (&str, &str, &str) = ("cpuOccupancyRate", "totalcputime", "gtotalcputime");
+
+// Steps closer than this share a time base
+const STEP_TOLERANCE: f64 = 0.01;
+
+/// How values between two samples are estimated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleMode {
+    /// on the line between the samples before and after
+    #[default]
+    Linear,
+    /// the sample before, last observation carried forward
+    Previous,
+}
+
+/// Names of the modes accepted by `ResampleMode::parse`
+pub const RESAMPLE_MODES: [&str; 2] = ["linear", "locf"];
+
+impl ResampleMode {
+    /// Parse a mode name
+    pub fn parse(name: &str) -> Option<ResampleMode> {
+        match name {
+            "linear" => Some(ResampleMode::Linear),
+            "locf" => Some(ResampleMode::Previous),
+            _ => None,
+        }
+    }
+
+    /// Name accepted by `parse`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResampleMode::Linear => "linear",
+            ResampleMode::Previous => "locf",
+        }
+    }
+}
+
+/// Median seconds between the samples of a trace, None with less than two
+pub fn sample_step(data: &TraceData) -> Option<f64> {
+    let times = data.column(data.column_index(TIME_COLUMN)?);
+    let steps: Vec<f64> = times.windows(2).map(|pair| pair[1] - pair[0]).filter(|step| *step > 0.0).collect();
+    Some(median(&steps)).filter(|step| step.is_finite())
+}
+
+/// Step of a common time base, the coarsest one so no trace is given a
+/// resolution it did not have. None when the traces already share it
+pub fn common_step(traces: &[TraceData]) -> Option<f64> {
+    let steps: Vec<f64> = traces.iter().filter_map(sample_step).collect();
+    let (min, max) = steps.iter().fold((f64::INFINITY, 0.0f64), |(min, max), step| (min.min(*step), max.max(*step)));
+    (max > 0.0 && max - min > max * STEP_TOLERANCE).then_some(max)
+}
+
+/// Times from `start` to `end` every `step` seconds
+pub fn time_grid(start: f64, end: f64, step: f64) -> Vec<f64> {
+    if !(step > 0.0 && end >= start) {
+        return Vec::new();
+    }
+    let count = ((end - start) / step + STEP_TOLERANCE).floor() as usize + 1;
+    (0..count).map(|i| start + i as f64 * step).collect()
+}
+
+// Value of a series sampled at `times` at `time`, NaN before the first sample
+fn value_at(times: &[f64], values: &[f64], time: f64, mode: ResampleMode) -> f64 {
+    let after = times.partition_point(|t| *t <= time);
+    if after == 0 {
+        return f64::NAN;
+    }
+    let (before, value) = (times[after - 1], values[after - 1]);
+    match (mode, times.get(after), values.get(after)) {
+        (ResampleMode::Linear, Some(next_time), Some(next)) if time > before && next.is_finite() && value.is_finite() => {
+            value + (next - value) * (time - before) / (next_time - before)
+        },
+        _ => value,
+    }
+}
+
+// Deltas over the intervals ending at `grid`. The running sum starts one
+// interval before the first sample, the first delta covers the interval
+// of the second one
+fn resample_deltas(times: &[f64], deltas: &[f64], grid: &[f64], step: f64, mode: ResampleMode) -> Vec<f64> {
+    let first_step = match times {
+        [first, second, ..] => second - first,
+        _ => step,
+    };
+    let mut sum_times = vec![times[0] - first_step];
+    sum_times.extend_from_slice(times);
+    let mut sums = vec![0.0];
+    for delta in deltas {
+        let last = sums[sums.len() - 1];
+        sums.push(last + if delta.is_finite() { *delta } else { 0.0 });
+    }
+    grid.iter().map(|time| {
+        let from = (time - step).max(sum_times[0]);
+        let delta = value_at(&sum_times, &sums, *time, mode) - value_at(&sum_times, &sums, from, mode);
+        // An interval cut by the start of the trace is scaled to a full one
+        delta * step / (time - from)
+    }).collect()
+}
+
+/// Resample a trace onto the times of `grid`, `step` seconds apart
+///
+/// Levels are estimated with `mode`, states and flags always take the
+/// sample before, deltas are summed over the new intervals and
+/// cpuOccupancyRate is computed again from them. Times before the first
+/// sample get NaN.
+pub fn resample(data: &TraceData, grid: &[f64], step: f64, mode: ResampleMode) -> TraceData {
+    let mut resampled = TraceData { rows: vec![Vec::with_capacity(data.columns.len()); grid.len()], ..data.clone() };
+    let Some(time) = data.column_index(TIME_COLUMN).filter(|_| !data.rows.is_empty()) else {
+        resampled.rows.clear();
+        return resampled;
+    };
+    let times = data.column(time);
+    for (i, name) in data.columns.iter().enumerate() {
+        let values = data.column(i);
+        let column: Vec<f64> = if i == time {
+            grid.to_vec()
+        } else if DELTA_COLUMNS.contains(&name.as_str()) {
+            resample_deltas(&times, &values, grid, step, mode)
+        } else {
+            let mode = if DISCRETE_COLUMNS.contains(&name.as_str()) { ResampleMode::Previous } else { mode };
+            grid.iter().map(|t| value_at(&times, &values, *t, mode)).collect()
+        };
+        for (row, value) in resampled.rows.iter_mut().zip(column) {
+            row.push(value);
+        }
+    }
+    let (rate, cpu, total) = RATE_COLUMN;
+    if let (Some(rate), Some(cpu), Some(total)) = (data.column_index(rate), data.column_index(cpu), data.column_index(total)) {
+        for row in resampled.rows.iter_mut() {
+            row[rate] = row[cpu] / row[total];
+        }
+    }
+    resampled
+}
+
+/// Resample a trace onto its own time range every `step` seconds
+pub fn resample_step(data: &TraceData, step: f64, mode: ResampleMode) -> TraceData {
+    let times = data.column_index(TIME_COLUMN).map(|time| data.column(time)).unwrap_or_default();
+    let grid = match (times.first(), times.last()) {
+        (Some(first), Some(last)) => time_grid(*first, *last, step),
+        _ => Vec::new(),
+    };
+    resample(data, &grid, step, mode)
+}
+
+/// Resample runs of different intervals to the coarsest one, each over its
+/// own time range. None when they already share a step
+pub fn resample_runs(runs: &[TraceData], mode: ResampleMode) -> Option<(f64, Vec<TraceData>)> {
+    let step = common_step(runs)?;
+    Some((step, runs.iter().map(|run| resample_step(run, step, mode)).collect()))
+}
+
+/// Put traces recorded together on one time base: the time range all of
+/// them cover, every coarsest step. Traces whose samples already share
+/// their times are returned as they are
+pub fn align(traces: &[TraceData], mode: ResampleMode) -> io::Result<Vec<TraceData>> {
+    let times: Vec<Vec<f64>> = traces.iter()
+            .map(|data| data.column_index(TIME_COLUMN).map(|time| data.column(time)).unwrap_or_default())
+            .collect();
+    if times.windows(2).all(|pair| pair[0] == pair[1]) {
+        return Ok(traces.to_vec());
+    }
+    let start = times.iter().filter_map(|t| t.first()).copied().fold(f64::NEG_INFINITY, f64::max);
+    let end = times.iter().filter_map(|t| t.last()).copied().fold(f64::INFINITY, f64::min);
+    let step = traces.iter().filter_map(sample_step).fold(0.0, f64::max);
+    if times.iter().any(|t| t.is_empty()) || end < start || step <= 0.0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "traces do not overlap in time".to_string()));
+    }
+    let grid = time_grid(start, end, step);
+    Ok(traces.iter().map(|data| resample(data, &grid, step, mode)).collect())
+}
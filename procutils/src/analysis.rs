@@ -0,0 +1,784 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::{SCHEMA_VERSION, VERSION};
+use crate::envelope::ColumnEnvelope;
+use crate::file_utils::read_path;
+use crate::noise::NOISE_FACTOR;
+use crate::numeric::{fixed, CsvDialect};
+use crate::output::{tag_columns, tag_values, TAG_COLUMN_PREFIX};
+use crate::proc_analysis::{csv_stamp, BASE_COLLECTOR_COUNT, CSV_COLUMNS, CSV_STAMP_PREFIX, CSV_STAMP_TRANSFORMS};
+use crate::stats::{mann_whitney, mann_whitney_min_p, mean, modified_z_scores, welch_interval, OUTLIER_Z};
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+// Column holding the sample time
+const TIME_COLUMN: &str = "time";
+
+// Column holding the ticks missed before a sample
+const MISSED_COLUMN: &str = "missed";
+
+// Columns ordering the samples, they are not measurements
+const ORDER_COLUMNS: &[&str] = &[TIME_COLUMN, "seq"];
+
+// Columns which differ between runs by design
+const OUTLIER_IGNORED_COLUMNS: &[&str] = &[TIME_COLUMN, "seq", "startTime"];
+
+/// Runs a group needs before its outliers can be told apart
+pub const MIN_OUTLIER_RUNS: usize = 3;
+
+// Outlier columns named per run in reports
+const OUTLIER_REPORT_COLUMNS: usize = 3;
+
+// Columns of schema 1, before the quality columns were added
+const SCHEMA_1_COLUMNS: &[&str] = &[
+    "time", "pss", "vmRss", "vmAnon", "vmFile", "vmShmem", "vmSwap", "voluntaryCtxtSwitches",
+    "nonvoluntaryCtxtSwitches", "minflt", "majflt", "utime", "stime", "totalcputime", "gutime",
+    "gstime", "gtotalcputime", "cpuOccupancyRate", "priority", "nice", "numThreads", "startTime",
+];
+
+// Values of the columns added by schema 2 for converted schema 1 rows,
+// schema 1 tracers wrote no sample unless every collector succeeded
+const SCHEMA_2_DEFAULTS: &[(&str, f64)] = &[
+    ("retries", 0.0),
+    ("qualityFlags", 0.0),
+    ("collectorsOk", BASE_COLLECTOR_COUNT as f64),
+    ("carriedForward", 0.0),
+    ("confidence", 1.0),
+];
+
+// Values of the metric group columns added by schema 3, they were not collected
+const SCHEMA_3_DEFAULTS: &[(&str, f64)] = &[
+    ("readBytes", 0.0),
+    ("writeBytes", 0.0),
+    ("syscr", 0.0),
+    ("syscw", 0.0),
+    ("fdCount", 0.0),
+    ("oomScoreAdj", 0.0),
+];
+
+// Values of the off-CPU columns added by schema 4, they were not collected
+const SCHEMA_4_DEFAULTS: &[(&str, f64)] = &[
+    ("runDelay", 0.0),
+    ("sleepTime", 0.0),
+    ("offCpuTime", 0.0),
+];
+
+// Values of the sequence columns added by schema 5, older samples were not
+// numbered and their gaps are only known from the events
+const SCHEMA_5_DEFAULTS: &[(&str, f64)] = &[
+    ("seq", 0.0),
+    ("missed", 0.0),
+];
+
+// Values of the peak RSS column added by schema 6, it was not collected and
+// the envelopes of older traces only know the samples
+const SCHEMA_6_DEFAULTS: &[(&str, f64)] = &[
+    ("vmHwm", 0.0),
+];
+
+/// A trace loaded back from its CSV output
+#[derive(Default, Clone)]
+pub struct TraceData {
+    /// tracer version from the stamp, empty for unstamped outputs
+    pub version: String,
+    /// output schema, 0 when it can not be detected
+    pub schema: u32,
+    /// column names of the header
+    pub columns: Vec<String>,
+    /// one row per sample, NaN when a field is not a number
+    pub rows: Vec<Vec<f64>>,
+    /// `key=value` tags of the trace, from the `tag:<key>` columns
+    pub tags: Vec<(String, String)>,
+    /// specs of the transforms applied before the trace was written, from the stamp
+    pub transforms: Vec<String>,
+}
+
+/// Statistics of one column
+#[derive(Default, Clone)]
+pub struct ColumnSummary {
+    /// column name
+    pub name: String,
+    /// samples with a finite value
+    pub count: usize,
+    /// min value
+    pub min: f64,
+    /// max value
+    pub max: f64,
+    /// mean value
+    pub mean: f64,
+    /// median
+    pub p50: f64,
+    /// 95th percentile
+    pub p95: f64,
+}
+
+/// Difference of one column between two traces
+#[derive(Default, Clone)]
+pub struct ColumnDelta {
+    /// column name
+    pub name: String,
+    /// mean of the baseline
+    pub base_mean: f64,
+    /// mean of the candidate
+    pub candidate_mean: f64,
+    /// candidate - baseline
+    pub delta: f64,
+    /// delta in percent of the baseline, NaN when the baseline is 0
+    pub delta_percent: f64,
+}
+
+/// Observations of a group comparison
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareUnit {
+    /// the mean of every run, when both groups have two runs or more
+    Runs,
+    /// every sample of the runs, samples of one run are not independent
+    Samples,
+}
+
+impl CompareUnit {
+    /// Name used in reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompareUnit::Runs => "run means",
+            CompareUnit::Samples => "samples",
+        }
+    }
+}
+
+/// Difference of one column between two groups of runs
+#[derive(Default, Clone)]
+pub struct ColumnTest {
+    /// column name
+    pub name: String,
+    /// baseline observations
+    pub base_count: usize,
+    /// candidate observations
+    pub candidate_count: usize,
+    /// mean of the baseline observations
+    pub base_mean: f64,
+    /// mean of the candidate observations
+    pub candidate_mean: f64,
+    /// candidate - baseline
+    pub delta: f64,
+    /// delta in percent of the baseline, NaN when the baseline is 0
+    pub delta_percent: f64,
+    /// lower bound of the Welch confidence interval of the delta
+    pub ci_low: f64,
+    /// upper bound of the Welch confidence interval of the delta
+    pub ci_high: f64,
+    /// two sided p value of the Mann-Whitney U test
+    pub p_value: f64,
+    /// p value below the significance level
+    pub significant: bool,
+    /// standard deviation of the column on the idle device, NaN when unknown,
+    /// see `noise::apply_noise_floor`
+    pub noise: f64,
+}
+
+impl ColumnTest {
+    /// Whether a significant delta is still within the noise of the device
+    pub fn within_noise(&self) -> bool {
+        self.noise.is_finite() && self.delta.abs() <= NOISE_FACTOR * self.noise
+    }
+}
+
+/// A run whose summary differs from the other runs of its group, e.g. after
+/// thermal throttling or a background update during the run
+#[derive(Default, Clone)]
+pub struct OutlierRun {
+    /// index of the run in its group
+    pub index: usize,
+    /// columns whose run mean is an outlier with their modified z-score, largest first
+    pub columns: Vec<(String, f64)>,
+}
+
+/// Comparison of two groups of runs, see `compare_groups`
+#[derive(Clone)]
+pub struct GroupComparison {
+    /// baseline runs
+    pub base_runs: usize,
+    /// candidate runs
+    pub candidate_runs: usize,
+    /// observations of the tests
+    pub unit: CompareUnit,
+    /// significance level, the confidence intervals are at 1 - alpha
+    pub alpha: f64,
+    /// one test per column every run has
+    pub columns: Vec<ColumnTest>,
+    /// outlier runs of the baseline
+    pub base_outliers: Vec<OutlierRun>,
+    /// outlier runs of the candidate
+    pub candidate_outliers: Vec<OutlierRun>,
+    /// whether the outlier runs were left out of the tests
+    pub outliers_excluded: bool,
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl TraceData {
+    /// Parse the content of a CSV output
+    pub fn parse_csv(content: &str) -> io::Result<TraceData> {
+        let mut lines = content.lines().filter(|line| !line.trim().is_empty()).peekable();
+        let stamp = lines.next_if(|line| line.starts_with('#')).map(parse_stamp);
+        let header = lines.next().ok_or_else(|| invalid_data("empty trace".to_string()))?;
+        let dialect = CsvDialect::detect(header);
+        let header = dialect.split(header);
+        // Tag columns hold the same text in every row, they are not samples
+        let is_tag: Vec<bool> = header.iter().map(|c| c.starts_with(TAG_COLUMN_PREFIX)).collect();
+        let columns: Vec<String> = header.iter().zip(&is_tag)
+                .filter(|(_, tag)| !**tag)
+                .map(|(c, _)| c.to_string())
+                .collect();
+        let (version, schema, transforms) = stamp.unwrap_or_else(|| (String::new(), detect_schema(&columns), Vec::new()));
+        let mut rows = Vec::new();
+        let mut tags = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let fields = dialect.split(line);
+            if fields.len() != header.len() {
+                return Err(invalid_data(format!("row {} has {} fields, header has {}",
+                        i + 1, fields.len(), header.len())));
+            }
+            if i == 0 {
+                tags = header.iter().zip(&fields).zip(&is_tag)
+                        .filter(|(_, tag)| **tag)
+                        .map(|((c, v), _)| (c.trim_start_matches(TAG_COLUMN_PREFIX).to_string(), v.to_string()))
+                        .collect();
+            }
+            rows.push(fields.iter().zip(&is_tag)
+                    .filter(|(_, tag)| !**tag)
+                    .map(|(v, _)| dialect.number(v).unwrap_or(f64::NAN))
+                    .collect());
+        }
+        Ok(TraceData { version, schema, columns, rows, tags, transforms })
+    }
+
+    /// Load a CSV output
+    pub fn load_csv(path: &str) -> io::Result<TraceData> {
+        TraceData::parse_csv(&read_path(path)?)
+    }
+
+    /// Write the trace in the same CSV format as the tracer
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "{} \r\n{}{} \r\n", csv_stamp(self.schema, &self.transforms), self.columns.join(","),
+                tag_columns(&self.tags))?;
+        for row in &self.rows {
+            let fields: Vec<String> = row.iter().zip(&self.columns)
+                    .map(|(v, column)| format_value(column, *v))
+                    .collect();
+            write!(out, "{}{} \r\n", fields.join(","), tag_values(&self.tags))?;
+        }
+        Ok(())
+    }
+
+    /// Index of a column
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+
+    /// All values of a column
+    pub fn column(&self, index: usize) -> Vec<f64> {
+        self.rows.iter().map(|row| row[index]).collect()
+    }
+}
+
+// `# process_trace <version> schema <schema> [transforms <spec>...]`
+fn parse_stamp(line: &str) -> (String, u32, Vec<String>) {
+    // Spreadsheets pad the stamp line with empty fields
+    let fields: Vec<&str> = line.trim_start_matches(CSV_STAMP_PREFIX).split_whitespace()
+            .map(|field| field.trim_end_matches([',', ';']))
+            .filter(|field| !field.is_empty())
+            .collect();
+    match fields.as_slice() {
+        [version, "schema", schema, rest @ ..] => (version.to_string(), schema.parse().unwrap_or(0),
+                match rest {
+                    [CSV_STAMP_TRANSFORMS, transforms @ ..] => transforms.iter().map(|t| t.to_string()).collect(),
+                    _ => Vec::new(),
+                }),
+        _ => (String::new(), 0, Vec::new()),
+    }
+}
+
+/// Transforms of a trace as a line of its summaries, None for measured values
+pub fn format_transforms(data: &TraceData) -> Option<String> {
+    (!data.transforms.is_empty()).then(|| format!("transformed before written: {}", data.transforms.join(" ")))
+}
+
+// Guess the schema of an unstamped output from its columns
+fn detect_schema(columns: &[String]) -> u32 {
+    if columns.iter().map(|c| c.as_str()).eq(SCHEMA_1_COLUMNS.iter().copied()) {
+        1
+    } else if columns.iter().map(|c| c.as_str()).eq(CSV_COLUMNS.iter().copied()) {
+        SCHEMA_VERSION
+    } else {
+        0
+    }
+}
+
+// Append columns with a default value to every row
+fn add_columns(mut data: TraceData, defaults: &[(&str, f64)], schema: u32) -> TraceData {
+    for (column, default) in defaults {
+        data.columns.push(column.to_string());
+        for row in data.rows.iter_mut() {
+            row.push(*default);
+        }
+    }
+    data.schema = schema;
+    data
+}
+
+/// Convert a trace of an older schema to the current one
+///
+/// Outputs of a newer tracer or of an unknown schema are refused, since
+/// their columns can not be trusted to mean the same thing.
+pub fn upgrade(data: TraceData) -> io::Result<TraceData> {
+    match data.schema {
+        SCHEMA_VERSION => Ok(data),
+        1 => upgrade(add_columns(data, SCHEMA_2_DEFAULTS, 2)),
+        2 => upgrade(add_columns(data, SCHEMA_3_DEFAULTS, 3)),
+        3 => upgrade(add_columns(data, SCHEMA_4_DEFAULTS, 4)),
+        4 => upgrade(add_columns(data, SCHEMA_5_DEFAULTS, 5)),
+        5 => upgrade(add_columns(data, SCHEMA_6_DEFAULTS, 6)),
+        0 => Err(invalid_data("unknown output schema, columns do not match any tracer version".to_string())),
+        schema if schema > SCHEMA_VERSION => Err(invalid_data(format!(
+                "output schema {} of tracer {} is newer than schema {} of tracer {}, update the tracer",
+                schema, data.version, SCHEMA_VERSION, VERSION))),
+        schema => Err(invalid_data(format!("no conversion from schema {}", schema))),
+    }
+}
+
+// Integers are written without a fraction, like the tracer does
+fn format_value(column: &str, v: f64) -> String {
+    if v.is_finite() && v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{}", v as i64)
+    } else if v.is_finite() {
+        fixed(column, v, 3)
+    } else {
+        "NaN".to_string()
+    }
+}
+
+// Nearest rank percentile of sorted values
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Summarize one series, non finite values are skipped
+pub fn summarize_values(name: &str, values: &[f64]) -> ColumnSummary {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    if sorted.is_empty() {
+        return ColumnSummary {
+            name: name.to_string(),
+            min: f64::NAN, max: f64::NAN, mean: f64::NAN, p50: f64::NAN, p95: f64::NAN,
+            ..Default::default()
+        };
+    }
+    ColumnSummary {
+        name: name.to_string(),
+        count: sorted.len(),
+        min: sorted[0],
+        max: sorted[sorted.len() - 1],
+        mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        p50: percentile(&sorted, 50.0),
+        p95: percentile(&sorted, 95.0),
+    }
+}
+
+/// Summarize every column except time and seq
+pub fn summarize(data: &TraceData) -> Vec<ColumnSummary> {
+    data.columns.iter().enumerate()
+            .filter(|(_, name)| !ORDER_COLUMNS.contains(&name.as_str()))
+            .map(|(i, name)| summarize_values(name, &data.column(i)))
+            .collect()
+}
+
+/// Compare the means of the columns both traces have
+pub fn compare(base: &TraceData, candidate: &TraceData) -> Vec<ColumnDelta> {
+    let base_summary = summarize(base);
+    let candidate_summary = summarize(candidate);
+    base_summary.iter()
+            .filter_map(|b| candidate_summary.iter().find(|c| c.name == b.name).map(|c| (b, c)))
+            .map(|(b, c)| ColumnDelta {
+                name: b.name.clone(),
+                base_mean: b.mean,
+                candidate_mean: c.mean,
+                delta: c.mean - b.mean,
+                delta_percent: if b.mean != 0.0 { (c.mean - b.mean) / b.mean.abs() * 100.0 } else { f64::NAN },
+            })
+            .collect()
+}
+
+// Observations of a column in a group of runs
+fn observations(runs: &[TraceData], name: &str, unit: CompareUnit) -> Vec<f64> {
+    let finite = |run: &TraceData| -> Vec<f64> {
+        run.column_index(name)
+                .map(|i| run.column(i).into_iter().filter(|v| v.is_finite()).collect())
+                .unwrap_or_default()
+    };
+    match unit {
+        CompareUnit::Runs => runs.iter().map(|run| mean(&finite(run))).filter(|v| v.is_finite()).collect(),
+        CompareUnit::Samples => runs.iter().flat_map(finite).collect(),
+    }
+}
+
+/// Find the runs of a group whose mean of a column is far from the means of
+/// the other runs, by modified z-score. Groups with less than
+/// `MIN_OUTLIER_RUNS` runs have none.
+pub fn find_outliers(runs: &[TraceData]) -> Vec<OutlierRun> {
+    let mut outliers: Vec<OutlierRun> = Vec::new();
+    let first = match runs.first() {
+        Some(first) if runs.len() >= MIN_OUTLIER_RUNS => first,
+        _ => return outliers,
+    };
+    for name in &first.columns {
+        if OUTLIER_IGNORED_COLUMNS.contains(&name.as_str()) {
+            continue;
+        }
+        let means: Option<Vec<f64>> = runs.iter()
+                .map(|run| run.column_index(name).map(|i| summarize_values(name, &run.column(i)).mean))
+                .collect();
+        let means = match means {
+            Some(means) if means.iter().all(|mean| mean.is_finite()) => means,
+            _ => continue,
+        };
+        for (index, z) in modified_z_scores(&means).into_iter().enumerate() {
+            if z.abs() <= OUTLIER_Z {
+                continue;
+            }
+            match outliers.iter_mut().find(|outlier| outlier.index == index) {
+                Some(outlier) => outlier.columns.push((name.clone(), z)),
+                None => outliers.push(OutlierRun { index, columns: vec![(name.clone(), z)] }),
+            }
+        }
+    }
+    outliers.sort_by_key(|outlier| outlier.index);
+    for outlier in &mut outliers {
+        outlier.columns.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+    }
+    outliers
+}
+
+// Runs of a group which are not outliers
+fn without_outliers(runs: &[TraceData], outliers: &[OutlierRun]) -> Vec<TraceData> {
+    runs.iter().enumerate()
+            .filter(|(i, _)| !outliers.iter().any(|outlier| outlier.index == *i))
+            .map(|(_, run)| run.clone())
+            .collect()
+}
+
+/// Compare two groups of runs of the same scenario, column by column
+///
+/// With two runs or more in both groups every run counts as one observation,
+/// its mean, otherwise the samples of the runs are compared. Deltas get a
+/// Welch confidence interval at `1 - alpha` and a Mann-Whitney U test, which
+/// is significant below `alpha`. Only columns every run has are compared.
+///
+/// Outlier runs of both groups are flagged, see `find_outliers`, and left out
+/// of the tests with `exclude_outliers`.
+pub fn compare_groups(base: &[TraceData], candidate: &[TraceData], alpha: f64,
+        exclude_outliers: bool) -> GroupComparison {
+    let base_outliers = find_outliers(base);
+    let candidate_outliers = find_outliers(candidate);
+    let mut comparison = if exclude_outliers {
+        compare_runs(&without_outliers(base, &base_outliers), &without_outliers(candidate, &candidate_outliers), alpha)
+    } else {
+        compare_runs(base, candidate, alpha)
+    };
+    comparison.base_runs = base.len();
+    comparison.candidate_runs = candidate.len();
+    comparison.base_outliers = base_outliers;
+    comparison.candidate_outliers = candidate_outliers;
+    comparison.outliers_excluded = exclude_outliers;
+    comparison
+}
+
+fn compare_runs(base: &[TraceData], candidate: &[TraceData], alpha: f64) -> GroupComparison {
+    let unit = if base.len() >= 2 && candidate.len() >= 2 { CompareUnit::Runs } else { CompareUnit::Samples };
+    let columns = base.first()
+            .map(|first| first.columns.iter()
+                    .filter(|name| !ORDER_COLUMNS.contains(&name.as_str()))
+                    .filter(|name| base.iter().chain(candidate).all(|run| run.column_index(name).is_some()))
+                    .map(|name| {
+                        let b = observations(base, name, unit);
+                        let c = observations(candidate, name, unit);
+                        let (base_mean, candidate_mean) = (mean(&b), mean(&c));
+                        let (ci_low, ci_high) = welch_interval(&b, &c, 1.0 - alpha);
+                        let p_value = mann_whitney(&b, &c);
+                        ColumnTest {
+                            name: name.clone(),
+                            base_count: b.len(),
+                            candidate_count: c.len(),
+                            base_mean,
+                            candidate_mean,
+                            delta: candidate_mean - base_mean,
+                            delta_percent: if base_mean != 0.0 {
+                                (candidate_mean - base_mean) / base_mean.abs() * 100.0
+                            } else {
+                                f64::NAN
+                            },
+                            ci_low,
+                            ci_high,
+                            p_value,
+                            significant: p_value < alpha,
+                            noise: f64::NAN,
+                        }
+                    })
+                    .collect())
+            .unwrap_or_default();
+    GroupComparison {
+        base_runs: base.len(),
+        candidate_runs: candidate.len(),
+        unit,
+        alpha,
+        columns,
+        base_outliers: Vec::new(),
+        candidate_outliers: Vec::new(),
+        outliers_excluded: false,
+    }
+}
+
+/// Merge pieces of one trace, e.g. pulled from several runs of a resumed session
+///
+/// Rows are sorted by time and rows with the same time keep the first one.
+pub fn merge(pieces: &[TraceData]) -> io::Result<TraceData> {
+    let first = pieces.first().ok_or_else(|| invalid_data("nothing to merge".to_string()))?;
+    let mut merged = TraceData {
+        version: first.version.clone(),
+        schema: first.schema,
+        columns: first.columns.clone(),
+        rows: Vec::new(),
+        tags: first.tags.clone(),
+        transforms: first.transforms.clone(),
+    };
+    for piece in pieces {
+        if piece.schema != merged.schema {
+            return Err(invalid_data("traces have different schemas, upgrade them first".to_string()));
+        }
+        if piece.columns != merged.columns {
+            return Err(invalid_data("traces have different columns".to_string()));
+        }
+        if piece.tags != merged.tags {
+            return Err(invalid_data("traces have different tags".to_string()));
+        }
+        if piece.transforms != merged.transforms {
+            return Err(invalid_data("traces have different transforms".to_string()));
+        }
+        merged.rows.extend(piece.rows.iter().cloned());
+    }
+    if let Some(time) = merged.column_index(TIME_COLUMN) {
+        merged.rows.sort_by(|a, b| a[time].total_cmp(&b[time]));
+        merged.rows.dedup_by(|a, b| a[time] == b[time]);
+    }
+    Ok(merged)
+}
+
+/// Ticks without a sample before one sample
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleGap {
+    /// time of the sample after the gap
+    pub time: f64,
+    /// ticks missed
+    pub missed: u64,
+}
+
+/// Gaps of a trace, so a constant column can be told apart from one which
+/// was not sampled. Traces before schema 5 have none
+pub fn find_gaps(data: &TraceData) -> Vec<SampleGap> {
+    let (Some(time), Some(missed)) = (data.column_index(TIME_COLUMN), data.column_index(MISSED_COLUMN)) else {
+        return Vec::new();
+    };
+    data.rows.iter()
+            .filter(|row| row[missed] > 0.0)
+            .map(|row| SampleGap { time: row[time], missed: row[missed] as u64 })
+            .collect()
+}
+
+/// Render gaps as text, empty without gaps
+pub fn format_gaps(gaps: &[SampleGap]) -> String {
+    if gaps.is_empty() {
+        return String::new();
+    }
+    let mut out = format!("{} gaps, {} ticks missed\n", gaps.len(), gaps.iter().map(|gap| gap.missed).sum::<u64>());
+    for gap in gaps {
+        let _ = writeln!(out, "  {} ticks before time {}", gap.missed, format_value(TIME_COLUMN, gap.time));
+    }
+    out
+}
+
+/// Check a CSV output, returns the problems found
+pub fn verify_csv(content: &str) -> Vec<String> {
+    let mut issues = Vec::new();
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('#'));
+    let header = match lines.next() {
+        Some(header) => header,
+        None => return vec!["empty trace".to_string()],
+    };
+    let dialect = CsvDialect::detect(header);
+    let columns = dialect.split(header);
+    let time = columns.iter().position(|c| *c == TIME_COLUMN);
+    if time.is_none() {
+        issues.push(format!("no {} column", TIME_COLUMN));
+    }
+    let mut last_time = f64::NEG_INFINITY;
+    for (i, line) in lines.enumerate() {
+        let fields = dialect.split(line);
+        if fields.len() != columns.len() {
+            issues.push(format!("row {}: {} fields, header has {}", i + 1, fields.len(), columns.len()));
+            continue;
+        }
+        for (field, column) in fields.iter().zip(&columns) {
+            if column.starts_with(TAG_COLUMN_PREFIX) {
+                continue;
+            }
+            match dialect.number(field) {
+                Some(v) if v.is_finite() => {},
+                _ => issues.push(format!("row {}: {} is not a finite number: {}", i + 1, column, field)),
+            }
+        }
+        if let Some(t) = time.and_then(|t| dialect.number(fields[t])) {
+            if t <= last_time {
+                issues.push(format!("row {}: time {} is not increasing", i + 1, t));
+            }
+            last_time = t;
+        }
+    }
+    issues
+}
+
+/// Render a summary as a text table
+pub fn format_summary(summaries: &[ColumnSummary]) -> String {
+    let mut out = format!("{:<26}{:>8}{:>14}{:>14}{:>14}{:>14}{:>14}\n",
+            "column", "count", "min", "mean", "p50", "p95", "max");
+    for s in summaries {
+        let _ = writeln!(out, "{:<26}{:>8}{:>14.3}{:>14.3}{:>14.3}{:>14.3}{:>14.3}",
+                s.name, s.count, s.min, s.mean, s.p50, s.p95, s.max);
+    }
+    out
+}
+
+/// Render a comparison as a text table
+pub fn format_compare(deltas: &[ColumnDelta]) -> String {
+    let mut out = format!("{:<26}{:>14}{:>14}{:>14}{:>10}\n",
+            "column", "baseline", "candidate", "delta", "delta%");
+    for d in deltas {
+        let _ = writeln!(out, "{:<26}{:>14.3}{:>14.3}{:>14.3}{:>9.1}%",
+                d.name, d.base_mean, d.candidate_mean, d.delta, d.delta_percent);
+    }
+    out
+}
+
+/// Render a group comparison as a text table
+pub fn format_group_compare(comparison: &GroupComparison) -> String {
+    let mut out = format!("baseline {} runs, candidate {} runs, observations are {}, {:.0}% confidence, alpha {}\n",
+            comparison.base_runs, comparison.candidate_runs, comparison.unit.name(),
+            (1.0 - comparison.alpha) * 100.0, comparison.alpha);
+    for (group, outliers) in [("baseline", &comparison.base_outliers), ("candidate", &comparison.candidate_outliers)] {
+        for outlier in outliers {
+            let columns: Vec<String> = outlier.columns.iter()
+                    .take(OUTLIER_REPORT_COLUMNS)
+                    .map(|(name, z)| format!("{} z {:.1}", name, z))
+                    .collect();
+            let _ = writeln!(out, "{} run {} is an outlier in {} columns ({}), {}", group, outlier.index + 1,
+                    outlier.columns.len(), columns.join(", "),
+                    if comparison.outliers_excluded { "excluded" } else { "included" });
+        }
+    }
+    // Runs left for the tests
+    let excluded = |outliers: &Vec<OutlierRun>| if comparison.outliers_excluded { outliers.len() } else { 0 };
+    let base_runs = comparison.base_runs - excluded(&comparison.base_outliers);
+    let candidate_runs = comparison.candidate_runs - excluded(&comparison.candidate_outliers);
+    if comparison.unit == CompareUnit::Samples {
+        out.push_str("samples of one run are correlated, record two runs or more per group for trustworthy verdicts\n");
+    } else if mann_whitney_min_p(base_runs, candidate_runs) >= comparison.alpha {
+        let _ = writeln!(out, "{} and {} runs can not reach alpha {}, record more runs",
+                base_runs, candidate_runs, comparison.alpha);
+    }
+    let _ = writeln!(out, "{:<26}{:>14}{:>14}{:>14}{:>10}{:>14}{:>14}{:>10}  verdict",
+            "column", "baseline", "candidate", "delta", "delta%", "ci low", "ci high", "p");
+    for c in &comparison.columns {
+        let verdict = match (c.significant, c.delta > 0.0) {
+            (false, _) => "no change",
+            (true, _) if c.within_noise() => "within noise",
+            (true, true) => "higher",
+            (true, false) => "lower",
+        };
+        let _ = writeln!(out, "{:<26}{:>14.3}{:>14.3}{:>14.3}{:>9.1}%{:>14.3}{:>14.3}{:>10.4}  {}",
+                c.name, c.base_mean, c.candidate_mean, c.delta, c.delta_percent,
+                c.ci_low, c.ci_high, c.p_value, verdict);
+    }
+    out
+}
+
+// Small inline chart of a series, with the envelope of a gauge as a band behind it
+fn svg_sparkline(values: &[f64], envelope: Option<&ColumnEnvelope>) -> String {
+    const WIDTH: f64 = 300.0;
+    const HEIGHT: f64 = 40.0;
+    // Rows with a sample, and their band
+    let rows: Vec<(f64, f64, f64)> = values.iter().enumerate()
+            .filter(|(_, v)| v.is_finite())
+            .map(|(i, v)| match envelope {
+                Some(e) if e.low[i].is_finite() && e.high[i].is_finite() => (*v, e.low[i], e.high[i]),
+                _ => (*v, *v, *v),
+            })
+            .collect();
+    if rows.len() < 2 {
+        return String::new();
+    }
+    let min = rows.iter().map(|row| row.1).fold(f64::INFINITY, f64::min);
+    let max = rows.iter().map(|row| row.2).fold(f64::NEG_INFINITY, f64::max);
+    let range = if max > min { max - min } else { 1.0 };
+    let step = WIDTH / (rows.len() - 1) as f64;
+    let point = |i: usize, v: f64| format!("{:.1},{:.1}", i as f64 * step, HEIGHT - (v - min) / range * HEIGHT);
+    let points: Vec<String> = rows.iter().enumerate().map(|(i, row)| point(i, row.0)).collect();
+    let band = match envelope {
+        Some(_) => {
+            let mut outline: Vec<String> = rows.iter().enumerate().map(|(i, row)| point(i, row.2)).collect();
+            outline.extend(rows.iter().enumerate().rev().map(|(i, row)| point(i, row.1)));
+            format!("<polygon fill=\"#36c\" fill-opacity=\"0.2\" points=\"{}\"/>", outline.join(" "))
+        },
+        None => String::new(),
+    };
+    format!("<svg width=\"{}\" height=\"{}\">{}<polyline fill=\"none\" stroke=\"#36c\" points=\"{}\"/></svg>",
+            WIDTH, HEIGHT, band, points.join(" "))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Render a self-contained HTML report of a trace, the columns of
+/// `envelopes` are charted with their band and their peak
+pub fn render_report(title: &str, data: &TraceData, envelopes: &[ColumnEnvelope]) -> String {
+    let mut out = format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title>\
+            <style>body{{font-family:sans-serif}}table{{border-collapse:collapse}}\
+            td,th{{border:1px solid #ccc;padding:2px 8px;text-align:right}}</style></head>\n\
+            <body><h1>{0}</h1><p>{1} samples</p>\n<table><tr><th>column</th><th>min</th>\
+            <th>mean</th><th>p50</th><th>p95</th><th>max</th><th>peak</th><th>trend</th></tr>\n",
+            escape_html(title), data.rows.len());
+    for (i, name) in data.columns.iter().enumerate() {
+        if ORDER_COLUMNS.contains(&name.as_str()) {
+            continue;
+        }
+        let values = data.column(i);
+        let s = summarize_values(name, &values);
+        let envelope = envelopes.iter().find(|e| e.name == *name);
+        let peak = envelope.map(|e| format!("{:.3}", e.peak)).unwrap_or_default();
+        let _ = writeln!(out, "<tr><th>{}</th><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td><td>{:.3}</td>\
+                <td>{:.3}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(name), s.min, s.mean, s.p50, s.p95, s.max, peak, svg_sparkline(&values, envelope));
+    }
+    let _ = writeln!(out, "</table><p>process_trace {} schema {}, trace schema {}</p></body></html>",
+            VERSION, SCHEMA_VERSION, data.schema);
+    out
+}
@@ -0,0 +1,187 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::proc_analysis::RecordItem;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+// Samples pre-allocated for traces until the targets exit
+const DEFAULT_CAPACITY: usize = 1024;
+// Upper bound of the pre-allocation, longer traces grow as they go
+const MAX_CAPACITY: usize = 1 << 20;
+
+// One column per scalar field of `RecordItem`, in the order of its fields
+macro_rules! columns {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        #[derive(Clone, Debug, Default)]
+        struct Columns {
+            $($field: Vec<$ty>,)*
+        }
+
+        impl Columns {
+            fn with_capacity(capacity: usize) -> Columns {
+                Columns { $($field: Vec::with_capacity(capacity),)* }
+            }
+
+            fn push(&mut self, item: &RecordItem) {
+                $(self.$field.push(item.$field);)*
+            }
+
+            fn fill(&self, i: usize, item: &mut RecordItem) {
+                $(item.$field = self.$field[i];)*
+            }
+
+            fn bytes(&self) -> usize {
+                0 $(+ self.$field.capacity() * size_of::<$ty>())*
+            }
+        }
+    };
+}
+
+columns! {
+    timestamp: i64,
+    pss: isize,
+    vm_rss: isize,
+    vm_anon: isize,
+    vm_file: isize,
+    vm_shmem: isize,
+    vm_swap: isize,
+    voluntary_ctxt_switches: usize,
+    nonvoluntary_ctxt_switches: usize,
+    minflt: usize,
+    majflt: usize,
+    utime: f64,
+    stime: f64,
+    totalcputime: f64,
+    global_utime: f64,
+    global_stime: f64,
+    global_total_cpu_time: f64,
+    cpu_occupancy_rate: f64,
+    priority: i64,
+    nice: i64,
+    num_threads: i64,
+    start_time: i64,
+    retries: usize,
+    quality_flags: u32,
+    collectors_ok: u32,
+    carried_forward: bool,
+    confidence: f64,
+    read_bytes: usize,
+    write_bytes: usize,
+    syscr: usize,
+    syscw: usize,
+    fd_count: usize,
+    oom_score_adj: i64,
+    run_delay: f64,
+    sleep_time: f64,
+    off_cpu_time: f64,
+    seq: u64,
+    missed: u64,
+    vm_hwm: isize,
+}
+
+/// Columnar store of the samples a trace keeps in memory
+///
+/// Every scalar field has its own pre-allocated column, cgroup and cpuset
+/// paths are stored once and referenced by index, and the smoothed values of
+/// all samples share one vector. A sample costs its fields only, without the
+/// padding and the string allocations of a `RecordItem`.
+#[derive(Clone, Debug, Default)]
+pub struct SampleArena {
+    columns: Columns,
+    cgroup: Vec<u32>,
+    cpuset: Vec<u32>,
+    strings: Vec<String>,
+    string_index: HashMap<String, u32>,
+    // end of the smoothed values of every sample in `smoothed`
+    smoothed_end: Vec<u32>,
+    smoothed: Vec<f64>,
+}
+
+impl SampleArena {
+    /// Arena with room for `capacity` samples
+    pub fn with_capacity(capacity: usize) -> SampleArena {
+        SampleArena {
+            columns: Columns::with_capacity(capacity),
+            cgroup: Vec::with_capacity(capacity),
+            cpuset: Vec::with_capacity(capacity),
+            smoothed_end: Vec::with_capacity(capacity),
+            ..SampleArena::default()
+        }
+    }
+
+    /// Arena with room for the samples of a trace of `duration` seconds
+    /// sampled every `interval`, a duration <= 0 traces until the targets exit
+    pub fn for_trace(duration: i64, interval: i64) -> SampleArena {
+        let capacity = match duration > 0 && interval > 0 {
+            true => (duration / interval + 1) as usize,
+            false => DEFAULT_CAPACITY,
+        };
+        SampleArena::with_capacity(capacity.min(MAX_CAPACITY))
+    }
+
+    /// Number of samples
+    pub fn len(&self) -> usize {
+        self.columns.timestamp.len()
+    }
+
+    /// Whether there is no sample
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Index of `value` in the string table, added on first use
+    fn intern(&mut self, value: &str) -> u32 {
+        if let Some(index) = self.string_index.get(value) {
+            return *index;
+        }
+        let index = self.strings.len() as u32;
+        self.strings.push(value.to_string());
+        self.string_index.insert(value.to_string(), index);
+        index
+    }
+
+    /// Append a sample
+    pub fn push(&mut self, item: &RecordItem) {
+        self.columns.push(item);
+        let cgroup = self.intern(&item.cgroup);
+        let cpuset = self.intern(&item.cpuset);
+        self.cgroup.push(cgroup);
+        self.cpuset.push(cpuset);
+        self.smoothed.extend_from_slice(&item.smoothed);
+        self.smoothed_end.push(self.smoothed.len() as u32);
+    }
+
+    /// Sample `i`, None past the end
+    pub fn get(&self, i: usize) -> Option<RecordItem> {
+        if i >= self.len() {
+            return None;
+        }
+        let mut item = RecordItem::default();
+        self.columns.fill(i, &mut item);
+        item.cgroup = self.strings[self.cgroup[i] as usize].clone();
+        item.cpuset = self.strings[self.cpuset[i] as usize].clone();
+        let start = if i == 0 { 0 } else { self.smoothed_end[i - 1] as usize };
+        item.smoothed = self.smoothed[start..self.smoothed_end[i] as usize].to_vec();
+        Some(item)
+    }
+
+    /// Samples in the order they were pushed, built one at a time
+    pub fn iter(&self) -> impl Iterator<Item = RecordItem> + '_ {
+        (0..self.len()).filter_map(|i| self.get(i))
+    }
+
+    /// All samples as `RecordItem`s, e.g. for `RecordProcess::record_infos`
+    pub fn to_records(&self) -> Vec<RecordItem> {
+        self.iter().collect()
+    }
+
+    /// Bytes allocated for the samples, the string table not counted
+    pub fn bytes(&self) -> usize {
+        self.columns.bytes() + (self.cgroup.capacity() + self.cpuset.capacity()
+                + self.smoothed_end.capacity()) * size_of::<u32>() + self.smoothed.capacity() * size_of::<f64>()
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::session::now_epoch;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// File name of the audit log inside of a session directory
+pub const AUDIT_LOG_FILE: &str = "audit.log";
+
+// Refuse everything which changes the system
+static STRICT: AtomicBool = AtomicBool::new(false);
+// Where executed commands are logged, None to log nothing
+static AUDIT_LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// What running an external command does to the system
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Effect {
+    /// only reads state, e.g. `getprop <name>` or `dmesg`
+    ReadOnly,
+    /// changes state, e.g. `kill`, `dumpsys` actions or pushing files
+    Writes,
+}
+
+impl Effect {
+    /// Name used in the audit log
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::ReadOnly => "read",
+            Effect::Writes => "write",
+        }
+    }
+}
+
+/// Forbid or allow commands and writes which change the system
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::SeqCst);
+}
+
+/// Whether the tracer is in strict read-only mode
+pub fn is_strict() -> bool {
+    STRICT.load(Ordering::SeqCst)
+}
+
+/// Append the audit log to `path`, it replaces the log opened before
+pub fn open_log(path: &str) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *AUDIT_LOG.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+/// Append the audit log to `path` unless a log is open, returns whether it
+/// was opened. Traces running side by side keep the log opened first
+pub fn open_log_unless_open(path: &str) -> io::Result<bool> {
+    let mut log = AUDIT_LOG.lock().unwrap();
+    if log.is_some() {
+        return Ok(false);
+    }
+    *log = Some(OpenOptions::new().create(true).append(true).open(path)?);
+    Ok(true)
+}
+
+/// Whether an audit log is open
+pub fn log_open() -> bool {
+    AUDIT_LOG.lock().unwrap().is_some()
+}
+
+// Log one action as `<epoch> <effect> <result> <action>`, a broken log
+// only loses the line, it never stops the trace
+fn log(effect: Effect, result: &str, action: &str) {
+    if let Some(file) = AUDIT_LOG.lock().unwrap().as_mut() {
+        if writeln!(file, "{} {} {} {}", now_epoch(), effect.name(), result, action).is_err() {
+            println!("write audit log failed");
+        }
+    }
+}
+
+fn refused(action: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, format!("strict mode refused {}", action))
+}
+
+/// Run an external command and wait for its output
+///
+/// Every command is logged, a command which `Writes` is refused in strict mode.
+/// Commands run in the C locale, so their numbers parse the same on every device.
+pub fn run(command: &mut Command, effect: Effect) -> io::Result<Output> {
+    let action = command_action(command);
+    if effect == Effect::Writes && is_strict() {
+        log(effect, "refused", &action);
+        return Err(refused(&action));
+    }
+    let output = command.env("LC_ALL", "C").output();
+    match &output {
+        Ok(output) => log(effect, &format!("exit={}", output.status.code().unwrap_or(-1)), &action),
+        Err(e) => log(effect, &format!("error={}", e.kind()), &action),
+    }
+    output
+}
+
+// Program and arguments of a command as logged
+fn command_action(command: &Command) -> String {
+    let mut action = command.get_program().to_string_lossy().to_string();
+    for arg in command.get_args() {
+        action.push(' ');
+        action.push_str(&arg.to_string_lossy());
+    }
+    action
+}
+
+/// Run an external command and hand every line of its output to `on_line`
+/// as it comes, for commands which run as long as a trace. Logged and
+/// refused like `run`, stderr is left to the terminal
+pub fn run_lines(command: &mut Command, effect: Effect, mut on_line: impl FnMut(&str)) -> io::Result<ExitStatus> {
+    let action = command_action(command);
+    if effect == Effect::Writes && is_strict() {
+        log(effect, "refused", &action);
+        return Err(refused(&action));
+    }
+    let status = command.env("LC_ALL", "C").stdout(Stdio::piped()).spawn().and_then(|mut child| {
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines() {
+                on_line(&line?);
+            }
+        }
+        child.wait()
+    });
+    match &status {
+        Ok(status) => log(effect, &format!("exit={}", status.code().unwrap_or(-1)), &action),
+        Err(e) => log(effect, &format!("error={}", e.kind()), &action),
+    }
+    status
+}
+
+/// Write a value to a system node such as `/proc/sys/vm/drop_caches`
+///
+/// Every write is logged and refused in strict mode, outputs of the tracer
+/// itself do not go through here.
+pub fn write_system_file(path: &str, value: &str) -> io::Result<()> {
+    change_system(&format!("{} < {}", path, value.trim_end()), || OpenOptions::new().write(true).open(path)
+            .and_then(|mut file| file.write_all(value.as_bytes())))
+}
+
+/// Create a directory in a system hierarchy such as a cgroup, logged and
+/// refused in strict mode like `write_system_file`
+pub fn create_system_dir(path: &str) -> io::Result<()> {
+    change_system(&format!("mkdir {}", path), || fs::create_dir(path))
+}
+
+/// Remove a directory created by `create_system_dir`
+pub fn remove_system_dir(path: &str) -> io::Result<()> {
+    change_system(&format!("rmdir {}", path), || fs::remove_dir(path))
+}
+
+// Log a change of the system, it is refused in strict mode
+fn change_system(action: &str, change: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+    if is_strict() {
+        log(Effect::Writes, "refused", action);
+        return Err(refused(action));
+    }
+    let result = change();
+    match &result {
+        Ok(_) => log(Effect::Writes, "ok", action),
+        Err(e) => log(Effect::Writes, &format!("error={}", e.kind()), action),
+    }
+    result
+}
@@ -0,0 +1,169 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use libc::pid_t;
+use crate::analysis::TraceData;
+use crate::error::TraceError;
+use crate::file_utils::{read_node, read_path, RetryPolicy};
+use crate::output::OutputFormat;
+use crate::proc_analysis::{get_global_cpu_info, parse_task_stat, parse_task_status, RecordItem, Sampler, TraceTarget};
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// File name of the idle baseline, in the session directory
+pub const IDLE_BASELINE_FILE: &str = "idle_baseline.csv";
+
+/// Header of an idle baseline
+pub const IDLE_BASELINE_HEADER: &str = "target,seconds,systemCpuRate,cpuRate,vmRssDrift";
+
+/// Rates of a target while the device idles, before the scenario starts
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdleBaseline {
+    /// target label
+    pub target: String,
+    /// length of the idle window
+    pub seconds: f64,
+    /// CPU seconds of the whole system per second
+    pub system_cpu_rate: f64,
+    /// CPU seconds of the target per second
+    pub cpu_rate: f64,
+    /// VmRSS growth of the target in KB per second
+    pub vm_rss_drift: f64,
+}
+
+// CPU and VmRSS of a process and the CPU of the system
+fn read_counters(pid: pid_t, sampler: &mut Sampler) -> Result<RecordItem, TraceError> {
+    let mut item = RecordItem::default();
+    get_global_cpu_info(&mut item, sampler, &RetryPolicy::default())?;
+    parse_task_stat(&mut item, &read_node(&format!(crate::TASK_STAT_TEMPLATE!(), pid))?)?;
+    parse_task_status(&mut item, &read_node(&format!(crate::TASK_STATUS_TEMPLATE!(), pid))?)?;
+    Ok(item)
+}
+
+/// Measure the idle rates of the targets over `window`, nothing may run
+/// the scenario meanwhile. Targets which are not running are left out
+pub fn measure_idle(targets: &[TraceTarget], window: Duration) -> Result<Vec<IdleBaseline>, TraceError> {
+    let mut sampler = Sampler::default();
+    let mut first = Vec::new();
+    for target in targets {
+        match target.resolve_pid().and_then(|pid| read_counters(pid, &mut sampler).map(|item| (pid, item))) {
+            Ok((pid, item)) => first.push((target.label(), pid, item)),
+            Err(e) => println!("idle baseline of {} skipped: {}", target.label(), e),
+        }
+    }
+    let start = Instant::now();
+    sleep(window);
+    let seconds = start.elapsed().as_secs_f64();
+    let mut baselines = Vec::with_capacity(first.len());
+    for (label, pid, before) in first {
+        // A target which exited or restarted has no idle rate
+        let after = match read_counters(pid, &mut sampler) {
+            Ok(after) if after.start_time == before.start_time => after,
+            _ => {
+                println!("idle baseline of {} skipped: {} exited", label, pid);
+                continue;
+            },
+        };
+        baselines.push(IdleBaseline {
+            target: label,
+            seconds,
+            system_cpu_rate: (after.global_total_cpu_time - before.global_total_cpu_time) / seconds,
+            cpu_rate: (after.totalcputime - before.totalcputime) / seconds,
+            vm_rss_drift: (after.vm_rss - before.vm_rss) as f64 / seconds,
+        });
+    }
+    Ok(baselines)
+}
+
+/// Write baselines as CSV
+pub fn write_idle_baseline(path: &str, baselines: &[IdleBaseline]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{}", IDLE_BASELINE_HEADER)?;
+    for baseline in baselines {
+        writeln!(out, "{},{:.3},{:.6},{:.6},{:.3}", baseline.target, baseline.seconds,
+                baseline.system_cpu_rate, baseline.cpu_rate, baseline.vm_rss_drift)?;
+    }
+    out.flush()
+}
+
+/// Read baselines written by `write_idle_baseline`
+pub fn load_idle_baseline(path: &str) -> io::Result<Vec<IdleBaseline>> {
+    let content = read_path(path)?;
+    if content.lines().next() != Some(IDLE_BASELINE_HEADER) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not an idle baseline", path)));
+    }
+    Ok(content.lines().skip(1).filter_map(|line| {
+        let fields: Vec<&str> = line.split(',').collect();
+        match fields.as_slice() {
+            [target, seconds, system_cpu_rate, cpu_rate, vm_rss_drift] => Some(IdleBaseline {
+                target: target.to_string(),
+                seconds: seconds.parse().ok()?,
+                system_cpu_rate: system_cpu_rate.parse().ok()?,
+                cpu_rate: cpu_rate.parse().ok()?,
+                vm_rss_drift: vm_rss_drift.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }).collect())
+}
+
+/// Subtract the idle rates from a trace: the idle CPU of every sample
+/// interval from `totalcputime` and `gtotalcputime`, never below 0, and the
+/// drift since time 0 from `vmRss`. Other columns are left alone, so
+/// `gutime` and `gstime` no longer add up to `gtotalcputime`.
+/// Returns the rows changed
+pub fn subtract_idle(data: &mut TraceData, baseline: &IdleBaseline) -> usize {
+    let Some(time) = data.column_index("time") else { return 0 };
+    let rates = [("totalcputime", baseline.cpu_rate), ("gtotalcputime", baseline.system_cpu_rate)];
+    let rate_columns: Vec<(usize, f64)> = rates.iter()
+            .filter_map(|(name, rate)| data.column_index(name).map(|index| (index, *rate)))
+            .collect();
+    let rss = data.column_index("vmRss");
+    let mut last_time = 0.0;
+    let mut changed = 0;
+    for row in data.rows.iter_mut() {
+        let now = row[time];
+        if !now.is_finite() {
+            continue;
+        }
+        let interval = (now - last_time).max(0.0);
+        last_time = now;
+        for (index, rate) in &rate_columns {
+            row[*index] = (row[*index] - rate * interval).max(0.0);
+        }
+        if let Some(index) = rss {
+            row[index] -= baseline.vm_rss_drift * now;
+        }
+        changed += 1;
+    }
+    changed
+}
+
+/// Baselines as text, one line per target
+pub fn format_idle(baselines: &[IdleBaseline]) -> String {
+    let mut out = String::new();
+    for baseline in baselines {
+        let _ = writeln!(out, "idle {} over {:.1}s: system CPU {:.3} cores, CPU {:.4} cores, VmRSS drift {:+.1} KB/s",
+                baseline.target, baseline.seconds, baseline.system_cpu_rate, baseline.cpu_rate, baseline.vm_rss_drift);
+    }
+    out
+}
+
+/// Baseline of the target a csv output belongs to, by its file name. A single
+/// baseline applies to any trace
+pub fn baseline_for<'a>(baselines: &'a [IdleBaseline], csv_path: &str) -> Option<&'a IdleBaseline> {
+    let file_name = csv_path.rsplit('/').next().unwrap_or(csv_path);
+    baselines.iter()
+            .find(|baseline| OutputFormat::Csv.file_name(&baseline.target) == file_name)
+            .or(match baselines {
+                [only] => Some(only),
+                _ => None,
+            })
+}
@@ -0,0 +1,277 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use libc::pid_t;
+use crate::binderprio::is_binder_thread;
+use crate::discover::process_info;
+use crate::error::TraceError;
+use crate::file_utils::read_node;
+use crate::proc_analysis::{parse_task_stat, process_alive, RecordItem};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Directories holding the binder transaction log, binderfs first
+pub const BINDER_LOG_DIRS: [&str; 2] = ["/dev/binderfs/binder_logs", "/sys/kernel/debug/binder"];
+
+// Ring of the last transactions in a binder log directory
+const TRANSACTION_LOG_FILE: &str = "transaction_log";
+
+/// Kind of a logged transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionKind {
+    /// a synchronous call, the caller waits for the reply
+    Call,
+    /// a oneway call
+    Async,
+    /// the reply to a call, from the server back to the caller
+    Reply,
+}
+
+/// One line of the transaction log, e.g.
+/// `4211: call  from 1234:1250 to 567:0 context binder node 42 handle 7 size 24:8 ret 0/0 l=0`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinderTransaction {
+    /// debug id, increasing over the life of the kernel
+    pub debug_id: u64,
+    /// kind of the transaction
+    pub kind: TransactionKind,
+    /// sending process
+    pub from_pid: pid_t,
+    /// sending thread
+    pub from_tid: pid_t,
+    /// receiving process
+    pub to_pid: pid_t,
+    /// receiving thread, 0 until a thread picks the transaction up
+    pub to_tid: pid_t,
+    /// binder context, e.g. `binder`, `hwbinder` or `vndbinder`
+    pub context: String,
+}
+
+impl BinderTransaction {
+    /// Parse a log line, None for lines of another format
+    pub fn parse(line: &str) -> Option<BinderTransaction> {
+        let (debug_id, rest) = line.split_once(':')?;
+        let fields: Vec<&str> = rest.split_whitespace().collect();
+        let kind = match *fields.first()? {
+            "call" => TransactionKind::Call,
+            "async" => TransactionKind::Async,
+            "reply" => TransactionKind::Reply,
+            _ => return None,
+        };
+        let endpoint = |name: &str| -> Option<(pid_t, pid_t)> {
+            let at = fields.iter().position(|field| *field == name)?;
+            let (pid, tid) = fields.get(at + 1)?.split_once(':')?;
+            Some((pid.parse().ok()?, tid.parse().ok()?))
+        };
+        let (from_pid, from_tid) = endpoint("from")?;
+        let (to_pid, to_tid) = endpoint("to")?;
+        let context = fields.iter().position(|field| *field == "context")
+                .and_then(|at| fields.get(at + 1))
+                .unwrap_or(&"")
+                .to_string();
+        Some(BinderTransaction {
+            debug_id: debug_id.trim().parse().ok()?,
+            kind,
+            from_pid,
+            from_tid,
+            to_pid,
+            to_tid,
+            context,
+        })
+    }
+}
+
+/// Binder work shared between the target and one peer process
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BinderPeer {
+    /// pid of the peer, 0 for binder thread CPU no transaction explains
+    pub pid: pid_t,
+    /// comm of the peer, empty once it exited before it was named
+    pub name: String,
+    /// calls the peer made to the target
+    pub calls_in: usize,
+    /// calls the target made to the peer
+    pub calls_out: usize,
+    /// CPU seconds of the binder threads of the target attributed to the
+    /// calls of the peer
+    pub cpu: f64,
+}
+
+/// Pairs the binder threads of a process with the peers driving them
+///
+/// The transaction log only keeps the last few transactions, so it is
+/// polled often and a busy system still loses some of them: counts are
+/// samples, CPU is split between the peers by the calls seen in each poll.
+#[derive(Debug)]
+pub struct BinderPairing {
+    pid: pid_t,
+    log_path: Option<String>,
+    last_debug_id: u64,
+    // cumulative CPU seconds of the binder threads by tid
+    thread_cpu: HashMap<pid_t, f64>,
+    peers: HashMap<pid_t, BinderPeer>,
+    binder_cpu: f64,
+    transactions: usize,
+    polls: usize,
+}
+
+impl BinderPairing {
+    /// Pair the binder work of `pid`, the log is looked up in `BINDER_LOG_DIRS`
+    pub fn new(pid: pid_t) -> BinderPairing {
+        let log_path = BINDER_LOG_DIRS.iter()
+                .map(|dir| format!("{}/{}", dir, TRANSACTION_LOG_FILE))
+                .find(|path| fs::File::open(path).is_ok());
+        BinderPairing {
+            pid,
+            log_path,
+            last_debug_id: 0,
+            thread_cpu: HashMap::new(),
+            peers: HashMap::new(),
+            binder_cpu: 0.0,
+            transactions: 0,
+            polls: 0,
+        }
+    }
+
+    /// Path of the readable transaction log, None when it can not be read,
+    /// then all binder CPU stays unattributed
+    pub fn log_path(&self) -> Option<&str> {
+        self.log_path.as_deref()
+    }
+
+    /// Read the binder thread CPU and the new transactions, attribute the CPU
+    /// used since the last poll to the peers which called the target
+    pub fn poll(&mut self) -> Result<(), TraceError> {
+        let cpu = self.binder_thread_cpu()?;
+        let transactions = match &self.log_path {
+            Some(path) => read_node(path)?.lines().filter_map(BinderTransaction::parse).collect(),
+            None => Vec::new(),
+        };
+        // The first poll only sets the baselines
+        let first = self.polls == 0;
+        self.polls += 1;
+        let mut calls: HashMap<pid_t, usize> = HashMap::new();
+        let last_debug_id = self.last_debug_id;
+        for transaction in transactions.iter().filter(|transaction| transaction.debug_id > last_debug_id) {
+            if first || transaction.kind == TransactionKind::Reply {
+                continue;
+            }
+            if transaction.to_pid == self.pid && transaction.from_pid != self.pid {
+                *calls.entry(transaction.from_pid).or_default() += 1;
+                self.peer(transaction.from_pid).calls_in += 1;
+                self.transactions += 1;
+            } else if transaction.from_pid == self.pid && transaction.to_pid != self.pid {
+                self.peer(transaction.to_pid).calls_out += 1;
+                self.transactions += 1;
+            }
+        }
+        self.last_debug_id = transactions.iter().map(|transaction| transaction.debug_id)
+                .max()
+                .unwrap_or(0)
+                .max(self.last_debug_id);
+        let total: usize = calls.values().sum();
+        if cpu > 0.0 && !first {
+            self.binder_cpu += cpu;
+            if total == 0 {
+                self.peer(0).cpu += cpu;
+            }
+            for (pid, count) in calls {
+                self.peer(pid).cpu += cpu * count as f64 / total as f64;
+            }
+        }
+        Ok(())
+    }
+
+    fn peer(&mut self, pid: pid_t) -> &mut BinderPeer {
+        self.peers.entry(pid).or_insert_with(|| BinderPeer {
+            pid,
+            name: process_info(pid).map(|process| process.comm).unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+
+    // CPU seconds the binder threads used since the last poll
+    fn binder_thread_cpu(&mut self) -> Result<f64, TraceError> {
+        let task_dir = format!(crate::SUBTASK_PATH_TEMPLATE!(), self.pid);
+        let mut cpu = 0.0;
+        let mut thread_cpu = HashMap::with_capacity(self.thread_cpu.len());
+        for entry in fs::read_dir(&task_dir)?.flatten() {
+            let Some(tid) = entry.file_name().to_str().and_then(|tid| tid.parse::<pid_t>().ok()) else { continue };
+            let Ok(stat) = read_node(&format!(crate::TASK_STAT_TID_TEMPLATE!(), self.pid, tid)) else { continue };
+            if !is_binder_thread(&stat) {
+                continue;
+            }
+            let mut item = RecordItem::default();
+            if parse_task_stat(&mut item, &stat).is_err() {
+                continue;
+            }
+            // New binder threads spent all their CPU since the last poll
+            cpu += (item.totalcputime - self.thread_cpu.get(&tid).copied().unwrap_or(0.0)).max(0.0);
+            thread_cpu.insert(tid, item.totalcputime);
+        }
+        self.thread_cpu = thread_cpu;
+        Ok(cpu)
+    }
+
+    /// Peers by attributed CPU, then by calls, highest first
+    pub fn peers(&self) -> Vec<BinderPeer> {
+        let mut peers: Vec<BinderPeer> = self.peers.values().cloned().collect();
+        peers.sort_by(|a, b| b.cpu.total_cmp(&a.cpu)
+                .then((b.calls_in + b.calls_out).cmp(&(a.calls_in + a.calls_out)))
+                .then(a.pid.cmp(&b.pid)));
+        peers
+    }
+
+    /// CPU seconds of all binder threads of the target after the first poll
+    pub fn binder_cpu(&self) -> f64 {
+        self.binder_cpu
+    }
+
+    /// Transactions between the target and its peers seen in the log
+    pub fn transactions(&self) -> usize {
+        self.transactions
+    }
+
+    /// Poll every `poll` for `duration` seconds, or until the target exits
+    pub fn run(&mut self, poll: Duration, duration: i64) -> Result<(), TraceError> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(duration.max(0) as u64) {
+            // Keep what was paired once the target exited
+            if !process_alive(self.pid) {
+                break;
+            }
+            self.poll()?;
+            sleep(poll);
+        }
+        Ok(())
+    }
+}
+
+/// Pairing report as a table
+pub fn format_pairing(pairing: &BinderPairing) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "binder threads of {}: {:.3}s CPU, {} transactions with peers{}", pairing.pid,
+            pairing.binder_cpu(), pairing.transactions(),
+            if pairing.log_path().is_none() { ", transaction log not readable" } else { "" });
+    let _ = writeln!(out, "{:>8}  {:<20}{:>10}{:>10}{:>12}{:>8}", "pid", "peer", "calls in", "calls out", "cpu(s)", "cpu%");
+    for peer in pairing.peers() {
+        let name = match peer.pid {
+            0 => "(unattributed)",
+            _ => peer.name.as_str(),
+        };
+        let share = match pairing.binder_cpu() {
+            cpu if cpu > 0.0 => peer.cpu / cpu * 100.0,
+            _ => 0.0,
+        };
+        let _ = writeln!(out, "{:>8}  {:<20}{:>10}{:>10}{:>12.3}{:>7.1}%", peer.pid, name, peer.calls_in,
+                peer.calls_out, peer.cpu, share);
+    }
+    out
+}
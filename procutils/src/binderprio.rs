@@ -0,0 +1,194 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::pid_t;
+use crate::file_utils::{node_timeout, read_node, set_node_timeout};
+use crate::proc_analysis::split_stat;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Fields of a stat line as `split_stat` splits it
+const STAT_PRIORITY_SHIFT: usize = 17;
+const STAT_NICE_SHIFT: usize = 18;
+const STAT_RT_PRIORITY_SHIFT: usize = 39;
+const STAT_POLICY_SHIFT: usize = 40;
+// Binder threads come and go with the load, the list is read again this often
+const THREAD_LIST_REFRESH: Duration = Duration::from_secs(1);
+// Changes kept between two drains, the rest are counted as dropped
+const MAX_PENDING_CHANGES: usize = 1000;
+
+/// Binder threads are named by libbinder, e.g. `binder:1234_5`, `Binder:1234_5`
+/// or `HwBinder:1234_1`, `stat` is their stat line
+pub fn is_binder_thread(stat: &str) -> bool {
+    let comm = stat.split_once('(').map(|(_, rest)| rest).unwrap_or("").to_ascii_lowercase();
+    comm.starts_with("binder") || comm.starts_with("hwbinder")
+}
+
+/// Scheduling priority of a thread from its stat line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThreadPriority {
+    /// kernel priority, 100 + nice - 100 for normal threads and
+    /// -1 - rt_priority for real time ones, lower runs first
+    pub priority: i64,
+    /// nice value, -20 ~ 19
+    pub nice: i64,
+    /// real time priority, 0 for normal threads
+    pub rt_priority: i64,
+    /// scheduling policy, SCHED_OTHER 0, SCHED_FIFO 1, SCHED_RR 2
+    pub policy: i64,
+}
+
+impl ThreadPriority {
+    /// Parse a stat line, None for a broken one
+    pub fn parse(stat: &str) -> Option<ThreadPriority> {
+        let fields = split_stat(stat);
+        let field = |shift: usize| fields.get(shift)?.parse::<i64>().ok();
+        Some(ThreadPriority {
+            priority: field(STAT_PRIORITY_SHIFT)?,
+            nice: field(STAT_NICE_SHIFT)?,
+            rt_priority: field(STAT_RT_PRIORITY_SHIFT).unwrap_or(0),
+            policy: field(STAT_POLICY_SHIFT).unwrap_or(0),
+        })
+    }
+}
+
+/// One priority change of a binder thread seen by the micro-sampling
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriorityChange {
+    /// thread which changed
+    pub tid: pid_t,
+    /// its name
+    pub thread: String,
+    /// priority before the change
+    pub from: ThreadPriority,
+    /// priority after the change
+    pub to: ThreadPriority,
+    /// wall clock milliseconds of the read which saw the change
+    pub epoch_ms: u64,
+    /// when it was seen
+    pub seen: Instant,
+}
+
+impl PriorityChange {
+    /// Whether the thread now runs before it did, e.g. inherited a caller's priority
+    pub fn boost(&self) -> bool {
+        self.to.priority < self.from.priority
+    }
+
+    /// `key=value` event detail
+    pub fn detail(&self) -> String {
+        format!("tid={} thread={} change={} priority={}->{} nice={}->{} policy={}->{} rt_priority={}->{} epoch_ms={}",
+                self.tid, self.thread, if self.boost() { "boost" } else { "restore" },
+                self.from.priority, self.to.priority, self.from.nice, self.to.nice,
+                self.from.policy, self.to.policy, self.from.rt_priority, self.to.rt_priority, self.epoch_ms)
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    changes: Vec<PriorityChange>,
+    dropped: usize,
+}
+
+/// Reads the priority of the binder threads of one process far more often
+/// than the trace samples, in a thread of its own, so a boost lasting a
+/// single transaction is still seen
+pub struct BinderPrioritySampler {
+    /// process sampled
+    pub pid: pid_t,
+    stop: Arc<AtomicBool>,
+    pending: Arc<Mutex<Pending>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BinderPrioritySampler {
+    /// Start sampling the binder threads of `pid` every `interval`
+    pub fn spawn(pid: pid_t, interval: Duration) -> BinderPrioritySampler {
+        let stop = Arc::new(AtomicBool::new(false));
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let handle = {
+            let (stop, pending, timeout) = (stop.clone(), pending.clone(), node_timeout());
+            thread::Builder::new().name(format!("binder-prio-{}", pid))
+                    .spawn(move || {
+                        // Reads time out like the ones of the trace which started it
+                        set_node_timeout(timeout);
+                        sample_priorities(pid, interval, &stop, &pending)
+                    })
+                    .map_err(|e| println!("start binder priority sampling of {} failed: {}", pid, e))
+                    .ok()
+        };
+        BinderPrioritySampler { pid, stop, pending, handle }
+    }
+
+    /// The changes seen since the last call, and how many more were dropped
+    pub fn drain(&self) -> (Vec<PriorityChange>, usize) {
+        let mut pending = self.pending.lock().unwrap();
+        let dropped = std::mem::take(&mut pending.dropped);
+        (std::mem::take(&mut pending.changes), dropped)
+    }
+}
+
+impl Drop for BinderPrioritySampler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Binder threads of `pid` as (tid, name, stat path)
+fn binder_threads(pid: pid_t) -> Vec<(pid_t, String, String)> {
+    let entries = match fs::read_dir(format!(crate::SUBTASK_PATH_TEMPLATE!(), pid)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<pid_t>().ok())
+            .filter_map(|tid| {
+                let path = format!(crate::TASK_STAT_TID_TEMPLATE!(), pid, tid);
+                let stat = read_node(&path).ok().filter(|stat| is_binder_thread(stat))?;
+                let name = split_stat(&stat).get(1)?.trim_start_matches('(').trim_end_matches(')').to_string();
+                Some((tid, name, path))
+            })
+            .collect()
+}
+
+fn sample_priorities(pid: pid_t, interval: Duration, stop: &AtomicBool, pending: &Mutex<Pending>) {
+    let mut threads = Vec::new();
+    let mut last: HashMap<pid_t, ThreadPriority> = HashMap::new();
+    let mut refresh_at = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        if Instant::now() >= refresh_at {
+            threads = binder_threads(pid);
+            last.retain(|tid, _| threads.iter().any(|(thread, _, _)| thread == tid));
+            refresh_at = Instant::now() + THREAD_LIST_REFRESH;
+        }
+        for (tid, name, path) in &threads {
+            let now = match read_node(path).ok().and_then(|stat| ThreadPriority::parse(&stat)) {
+                Some(now) => now,
+                None => continue,
+            };
+            match last.insert(*tid, now) {
+                Some(before) if before != now => {
+                    let epoch_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                    let mut pending = pending.lock().unwrap();
+                    if pending.changes.len() < MAX_PENDING_CHANGES {
+                        pending.changes.push(PriorityChange { tid: *tid, thread: name.clone(), from: before, to: now,
+                                epoch_ms, seen: Instant::now() });
+                    } else {
+                        pending.dropped += 1;
+                    }
+                },
+                _ => {},
+            }
+        }
+        sleep(interval);
+    }
+}
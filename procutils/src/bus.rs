@@ -0,0 +1,195 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use crate::proc_analysis::RecordItem;
+use crate::progress::Progress;
+use libc::pid_t;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+
+/// Messages a subscriber can queue before it misses new ones
+pub const DEFAULT_SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// What the monitors publish, `target` is the label of the traced target
+#[derive(Clone, Debug)]
+pub enum BusMessage {
+    /// the target is attached to a process, again after a restart
+    Attach { target: String, pid: pid_t },
+    /// one sample, counters and times are deltas like in the outputs
+    Sample { target: String, pid: pid_t, item: Box<RecordItem> },
+    /// an event like a gap or a cgroup move, `time` is seconds since time 0
+    Event { target: String, time: i64, event: String, detail: String },
+    /// the trace of the target is over
+    End { target: String },
+    /// progress of the whole trace, see `MonitorOptions::progress`
+    Progress(Progress),
+}
+
+struct Subscriber {
+    sender: SyncSender<Arc<BusMessage>>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// Broadcasts the messages of the monitors to every subscriber
+///
+/// Publishing never blocks the sampling loop: a subscriber whose queue is
+/// full misses the message, and counts it.
+#[derive(Default)]
+pub struct SampleBus {
+    subscribers: Mutex<Vec<Subscriber>>,
+    // Lets publishers skip building messages nobody reads
+    count: AtomicUsize,
+}
+
+impl SampleBus {
+    /// A bus without subscribers
+    pub fn new() -> SampleBus {
+        SampleBus::default()
+    }
+
+    /// Subscribe with `DEFAULT_SUBSCRIBER_CAPACITY`
+    pub fn subscribe(&self) -> Subscription {
+        self.subscribe_with_capacity(DEFAULT_SUBSCRIBER_CAPACITY)
+    }
+
+    /// Subscribe, up to `capacity` messages are queued for the subscriber
+    pub fn subscribe_with_capacity(&self, capacity: usize) -> Subscription {
+        let (sender, receiver) = sync_channel(capacity.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.push(Subscriber { sender, dropped: dropped.clone() });
+        self.count.store(subscribers.len(), Ordering::Relaxed);
+        Subscription { receiver, dropped }
+    }
+
+    /// Whether anyone listens
+    pub fn has_subscribers(&self) -> bool {
+        self.count.load(Ordering::Relaxed) > 0
+    }
+
+    /// Send a message to every subscriber, dropped subscriptions are removed
+    pub fn publish(&self, message: BusMessage) {
+        let message = Arc::new(message);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| match subscriber.sender.try_send(message.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            },
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+        self.count.store(subscribers.len(), Ordering::Relaxed);
+    }
+
+    /// Disconnect every subscriber, their subscriptions end after the
+    /// queued messages
+    pub fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+        self.count.store(0, Ordering::Relaxed);
+    }
+}
+
+// Events posted by analyzers as (target, event, detail), taken by the monitors
+static POSTED: Mutex<Vec<(String, String, String)>> = Mutex::new(Vec::new());
+
+/// The bus every monitor publishes to
+pub fn global_bus() -> &'static SampleBus {
+    static BUS: OnceLock<SampleBus> = OnceLock::new();
+    BUS.get_or_init(SampleBus::new)
+}
+
+// Publish to the global bus, the message is only built when someone listens
+pub(crate) fn publish(message: impl FnOnce() -> BusMessage) {
+    let bus = global_bus();
+    if bus.has_subscribers() {
+        bus.publish(message());
+    }
+}
+
+/// Post an event of a target back to the trace, e.g. the output of a
+/// capture an analyzer started. The monitor of the target records it in every
+/// output after its next tick, events of targets no longer traced are dropped
+pub fn post_event(target: &str, event: &str, detail: &str) {
+    POSTED.lock().unwrap().push((target.to_string(), event.to_string(), detail.to_string()));
+}
+
+// Take the posted events of a target as (event, detail)
+pub(crate) fn take_posted(target: &str) -> Vec<(String, String)> {
+    let mut posted = POSTED.lock().unwrap();
+    if posted.is_empty() {
+        return Vec::new();
+    }
+    let mut taken = Vec::new();
+    posted.retain(|(posted_target, event, detail)| {
+        if posted_target != target {
+            return true;
+        }
+        taken.push((event.clone(), detail.clone()));
+        false
+    });
+    taken
+}
+
+// Drop the events of `targets` nobody took once their trace is over, the
+// events of traces still running are kept
+pub(crate) fn clear_posted(targets: &[String]) {
+    POSTED.lock().unwrap().retain(|(target, _, _)| !targets.contains(target));
+}
+
+/// Messages of one subscriber, dropping it unsubscribes
+pub struct Subscription {
+    receiver: Receiver<Arc<BusMessage>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// Wait for the next message, None once the bus closed the subscription
+    pub fn recv(&self) -> Option<Arc<BusMessage>> {
+        self.receiver.recv().ok()
+    }
+
+    /// Get a queued message without waiting
+    pub fn try_recv(&self) -> Option<Arc<BusMessage>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Messages missed because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Consumer of bus messages running on its own thread, e.g. alerting
+///
+/// Closures taking a `&BusMessage` are analyzers too.
+pub trait LiveAnalyzer: Send {
+    /// Called for every message in publish order
+    fn on_message(&mut self, message: &BusMessage);
+
+    /// Called once the subscription ended, `dropped` messages were missed
+    fn finish(&mut self, _dropped: u64) {}
+}
+
+impl<F: FnMut(&BusMessage) + Send> LiveAnalyzer for F {
+    fn on_message(&mut self, message: &BusMessage) {
+        self(message)
+    }
+}
+
+/// Run an analyzer on the messages of `bus` until the bus is closed
+pub fn spawn_analyzer(bus: &SampleBus, mut analyzer: impl LiveAnalyzer + 'static) -> JoinHandle<()> {
+    let subscription = bus.subscribe();
+    thread::spawn(move || {
+        while let Some(message) = subscription.recv() {
+            analyzer.on_message(&message);
+        }
+        analyzer.finish(subscription.dropped());
+    })
+}
@@ -0,0 +1,119 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+// Key of the code in the detail of an event
+const CODE_KEY: &str = "code=";
+
+/// How bad the condition a code stands for is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// the trace goes on with some data missing or late
+    Warning,
+    /// a collector or helper failed, its data is lost
+    Error,
+}
+
+impl Severity {
+    /// Name used in `--help-json`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// Code of one diagnostic event. The codes are stable, automation alerts on
+/// them instead of the free text of the detail
+#[derive(Clone, Copy, Debug)]
+pub struct EventCode {
+    /// code, `E_` for errors and `W_` for warnings, `<NODE>` is the proc node
+    pub code: &'static str,
+    /// event the code is attached to
+    pub event: &'static str,
+    /// severity of the condition
+    pub severity: Severity,
+    /// what happened
+    pub help: &'static str,
+}
+
+/// Every code an event may carry
+pub const EVENT_CODES: &[EventCode] = &[
+    EventCode { code: "E_<NODE>_EACCES", event: "access_denied", severity: Severity::Error,
+            help: "the tracer may not read a node, e.g. E_SMAPS_EACCES" },
+    EventCode { code: "W_<NODE>_TIMEOUT", event: "collector_timeout", severity: Severity::Warning,
+            help: "the read of a node hung and is skipped until it returns" },
+    EventCode { code: "E_<NODE>_QUARANTINED", event: "collector_quarantined", severity: Severity::Error,
+            help: "the read of a node kept hanging and is no longer collected" },
+    EventCode { code: "W_TICK_OVERRUN", event: "slow_sample", severity: Severity::Warning,
+            help: "a sample took too much of its interval" },
+    EventCode { code: "W_SAMPLE_GAP", event: "gap", severity: Severity::Warning,
+            help: "samples are missing between two sample times" },
+    EventCode { code: "W_MEMORY_PRESSURE", event: "memory_pressure", severity: Severity::Warning,
+            help: "the tracer stopped buffering samples under memory pressure" },
+    EventCode { code: "W_TARGET_EXIT", event: "process_exit", severity: Severity::Warning,
+            help: "the target process exited" },
+    EventCode { code: "W_TARGET_RESTART", event: "process_restart", severity: Severity::Warning,
+            help: "the target runs as a new process" },
+    EventCode { code: "W_BINDER_PRIORITY_DROPPED", event: "binder_priority", severity: Severity::Warning,
+            help: "priority changes of binder threads were dropped" },
+    EventCode { code: "E_SIMPLEPERF_FAILED", event: "simpleperf_failed", severity: Severity::Error,
+            help: "simpleperf could not record the target" },
+];
+
+// Proc and sys nodes with a name of their own in codes, by file name
+const NODES: &[(&str, &str)] = &[
+    ("smaps_rollup", "SMAPS"),
+    ("smaps", "SMAPS"),
+    ("status", "STATUS"),
+    ("stat", "STAT"),
+    ("schedstat", "SCHEDSTAT"),
+    ("io", "IO"),
+    ("fd", "FD"),
+    ("task", "TASK"),
+    ("oom_score_adj", "OOM_SCORE_ADJ"),
+    ("cgroup", "CGROUP"),
+    ("cpuset", "CPUSET"),
+];
+
+// Node part of a code, from the `path=` of a detail
+fn node(detail: &str) -> &'static str {
+    let path = detail.split_whitespace()
+            .find_map(|field| field.strip_prefix("path="))
+            .unwrap_or_default();
+    let file = path.rsplit('/').next().unwrap_or_default();
+    NODES.iter().find(|(name, _)| *name == file).map(|(_, node)| *node).unwrap_or("NODE")
+}
+
+/// Code of an event, None for events which are no diagnostics
+pub fn event_code(event: &str, detail: &str) -> Option<String> {
+    let code = EVENT_CODES.iter().find(|code| code.event == event)?;
+    match event {
+        "binder_priority" if !detail.starts_with("dropped=") => None,
+        _ => Some(code.code.replace("<NODE>", node(detail))),
+    }
+}
+
+/// Detail of an event with its code in front, e.g.
+/// `code=E_SMAPS_EACCES path=/proc/1/smaps_rollup ...`
+pub fn coded_detail(event: &str, detail: &str) -> String {
+    match event_code(event, detail) {
+        Some(code) if !detail.starts_with(CODE_KEY) => match detail.is_empty() {
+            true => format!("{}{}", CODE_KEY, code),
+            false => format!("{}{} {}", CODE_KEY, code, detail),
+        },
+        _ => detail.to_string(),
+    }
+}
+
+/// Log level of an event from the code of its detail, `info` without one
+pub fn detail_level(detail: &str) -> &'static str {
+    match detail.strip_prefix(CODE_KEY).and_then(|code| code.chars().next()) {
+        Some('E') => Severity::Error.name(),
+        Some('W') => Severity::Warning.name(),
+        _ => "info",
+    }
+}
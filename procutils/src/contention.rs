@@ -0,0 +1,139 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::analysis::TraceData;
+use crate::stats::{mean, median, modified_z_scores};
+use std::fmt::Write as _;
+
+/// Modified z-score from which a CPU or run delay value is a spike
+pub const DEFAULT_SPIKE_Z: f64 = 3.5;
+
+// Spikes also need this much in absolute terms, CPU seconds and seconds of
+// run delay per second, so noise of an idle process is no spike
+const CPU_SPIKE_FLOOR: f64 = 0.05;
+const RUN_DELAY_SPIKE_FLOOR: f64 = 0.005;
+
+/// Times one target's CPU spiked while another one waited for a CPU
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentionPair {
+    /// target whose CPU spiked
+    pub culprit: String,
+    /// target whose run delay rose at the same time
+    pub victim: String,
+    /// merged (first, last) trace seconds of the coincident samples
+    pub intervals: Vec<(f64, f64)>,
+    /// samples where both spiked
+    pub coincident: usize,
+    /// CPU spikes of the culprit
+    pub culprit_spikes: usize,
+    /// run delay spikes of the victim
+    pub victim_spikes: usize,
+    /// Pearson correlation of the culprit's CPU and the victim's run delay
+    /// over their common samples, NaN when one of them is constant
+    pub correlation: f64,
+}
+
+// (time, value per second) of a delta column, the first sample covers the
+// interval of the second one
+fn rates(data: &TraceData, column: &str) -> Vec<(f64, f64)> {
+    let (time, index) = match (data.column_index("time"), data.column_index(column)) {
+        (Some(time), Some(index)) => (time, index),
+        _ => return Vec::new(),
+    };
+    let times = data.column(time);
+    data.rows.iter().enumerate().filter_map(|(i, row)| {
+        let seconds = match i {
+            0 => times.get(1).map(|next| next - times[0]),
+            _ => Some(times[i] - times[i - 1]),
+        }.filter(|seconds| *seconds > 0.0)?;
+        Some((times[i], row[index] / seconds)).filter(|(_, rate)| rate.is_finite())
+    }).collect()
+}
+
+// Times of the spikes of a rate series
+fn spikes(series: &[(f64, f64)], z: f64, floor: f64) -> Vec<f64> {
+    let values: Vec<f64> = series.iter().map(|(_, value)| *value).collect();
+    series.iter().zip(modified_z_scores(&values))
+            .filter(|((_, value), score)| *score >= z && *value >= floor)
+            .map(|((time, _), _)| *time)
+            .collect()
+}
+
+fn correlation(pairs: &[(f64, f64)]) -> f64 {
+    let (x, y): (Vec<f64>, Vec<f64>) = pairs.iter().copied().unzip();
+    let (mx, my) = (mean(&x), mean(&y));
+    let cov: f64 = pairs.iter().map(|(a, b)| (a - mx) * (b - my)).sum();
+    let sx: f64 = x.iter().map(|a| (a - mx) * (a - mx)).sum::<f64>().sqrt();
+    let sy: f64 = y.iter().map(|b| (b - my) * (b - my)).sum::<f64>().sqrt();
+    if sx > 0.0 && sy > 0.0 { cov / (sx * sy) } else { f64::NAN }
+}
+
+// Join times no further apart than `step` into intervals
+fn merge_times(times: &[f64], step: f64) -> Vec<(f64, f64)> {
+    let mut intervals: Vec<(f64, f64)> = Vec::new();
+    for time in times {
+        match intervals.last_mut() {
+            Some((_, last)) if time - *last <= step => *last = *time,
+            _ => intervals.push((*time, *time)),
+        }
+    }
+    intervals
+}
+
+/// Find the pairs of `(target, data)` traced together where one target's CPU
+/// spikes at the samples the run delay of another one spikes, most
+/// coincident first. Samples of one session share their ticks, so they are
+/// matched by time
+pub fn find_contention(traces: &[(String, TraceData)], z: f64) -> Vec<ContentionPair> {
+    let cpu: Vec<Vec<(f64, f64)>> = traces.iter().map(|(_, data)| rates(data, "totalcputime")).collect();
+    let delay: Vec<Vec<(f64, f64)>> = traces.iter().map(|(_, data)| rates(data, "runDelay")).collect();
+    let cpu_spikes: Vec<Vec<f64>> = cpu.iter().map(|series| spikes(series, z, CPU_SPIKE_FLOOR)).collect();
+    let delay_spikes: Vec<Vec<f64>> = delay.iter().map(|series| spikes(series, z, RUN_DELAY_SPIKE_FLOOR)).collect();
+    let mut pairs = Vec::new();
+    for (a, (culprit, _)) in traces.iter().enumerate() {
+        for (b, (victim, _)) in traces.iter().enumerate().filter(|(b, _)| *b != a) {
+            let coincident: Vec<f64> = cpu_spikes[a].iter().copied()
+                    .filter(|time| delay_spikes[b].contains(time))
+                    .collect();
+            if coincident.is_empty() {
+                continue;
+            }
+            let common: Vec<(f64, f64)> = cpu[a].iter()
+                    .filter_map(|(time, value)| Some((*value, delay[b].iter().find(|(other, _)| other == time)?.1)))
+                    .collect();
+            let steps: Vec<f64> = cpu[a].windows(2).map(|pair| pair[1].0 - pair[0].0).collect();
+            pairs.push(ContentionPair {
+                culprit: culprit.clone(),
+                victim: victim.clone(),
+                intervals: merge_times(&coincident, median(&steps).max(1.0)),
+                coincident: coincident.len(),
+                culprit_spikes: cpu_spikes[a].len(),
+                victim_spikes: delay_spikes[b].len(),
+                correlation: correlation(&common),
+            });
+        }
+    }
+    pairs.sort_by(|a, b| b.coincident.cmp(&a.coincident)
+            .then(b.correlation.total_cmp(&a.correlation)));
+    pairs
+}
+
+/// Render contention pairs as a text table
+pub fn format_contention(pairs: &[ContentionPair]) -> String {
+    if pairs.is_empty() {
+        return "contention: no CPU spike coincides with the run delay spike of another target\n".to_string();
+    }
+    let mut out = format!("{:<26}{:<26}{:>11}{:>8}{:>8}{:>13}  {}\n", "culprit (cpu)", "victim (runDelay)",
+            "coincident", "spikes", "delays", "correlation", "intervals");
+    for pair in pairs {
+        let intervals: Vec<String> = pair.intervals.iter()
+                .map(|(first, last)| if first == last { format!("{:.0}s", first) } else { format!("{:.0}s~{:.0}s", first, last) })
+                .collect();
+        let _ = writeln!(out, "{:<26}{:<26}{:>11}{:>8}{:>8}{:>13.2}  {}", pair.culprit, pair.victim, pair.coincident,
+                pair.culprit_spikes, pair.victim_spikes, pair.correlation, intervals.join(","));
+    }
+    out
+}
@@ -0,0 +1,156 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{pid_t, sysconf, times, tms, _SC_CLK_TCK};
+use crate::error::TraceError;
+use crate::file_utils::{read_node, RetryPolicy};
+use crate::memwatch::statm_rss_kb;
+use crate::proc_analysis::{get_global_cpu_info, get_pss_info, parse_task_stat, parse_task_status, RecordItem, Sampler};
+use std::fmt::Write as _;
+use std::fs;
+
+const GLOBAL_STAT_PATH: &str = "/proc/stat";
+// Lines of the single CPUs, the `cpu ` line sums them up
+const CPU_LINE_PREFIX: &str = "cpu";
+const SMAPS_PSS_PREFIX: &str = "Pss:";
+
+// Memory may change between the two reads by this share, at least by the floor in kB
+const MEMORY_TOLERANCE: f64 = 0.02;
+const MEMORY_TOLERANCE_FLOOR_KB: f64 = 256.0;
+// Clock ticks two CPU time reads may be apart
+const CPU_TOLERANCE_TICKS: f64 = 2.0;
+
+/// One value of the tracer compared against an independent source
+#[derive(Clone, Debug)]
+pub struct CrossCheck {
+    /// value compared, e.g. `pss`
+    pub name: &'static str,
+    /// what the tracer parses, e.g. `smaps_rollup`
+    pub derived_from: &'static str,
+    /// the independent source, e.g. `smaps`
+    pub reference: &'static str,
+    /// the tracer's and the reference value, or why they could not be read
+    pub values: Result<(f64, f64), String>,
+    /// how far the values may be apart as they are not read at the same time
+    pub tolerance: f64,
+}
+
+impl CrossCheck {
+    /// Whether both values were read and are further apart than the tolerance
+    pub fn differs(&self) -> bool {
+        matches!(self.values, Ok((derived, reference)) if (derived - reference).abs() > self.tolerance)
+    }
+}
+
+fn check(name: &'static str, derived_from: &'static str, reference: &'static str,
+        values: Result<(f64, f64), String>, tolerance: impl Fn(f64) -> f64) -> CrossCheck {
+    let tolerance = values.as_ref().map(|(_, reference)| tolerance(*reference)).unwrap_or(0.0);
+    CrossCheck { name, derived_from, reference, values, tolerance }
+}
+
+fn memory_tolerance(reference: f64) -> f64 {
+    (reference * MEMORY_TOLERANCE).max(MEMORY_TOLERANCE_FLOOR_KB)
+}
+
+fn clock_ticks() -> f64 {
+    // SAFETY:
+    // Safe because sysconf has no side effects
+    unsafe { sysconf(_SC_CLK_TCK) as f64 }
+}
+
+// CPU seconds of this process as times() counts them
+fn times_cpu_time() -> f64 {
+    let mut now = tms { tms_utime: 0, tms_stime: 0, tms_cutime: 0, tms_cstime: 0 };
+    // SAFETY:
+    // Safe because `now` is a valid tms, on failure it stays 0
+    unsafe { times(&mut now) };
+    (now.tms_utime + now.tms_stime) as f64 / clock_ticks()
+}
+
+// Pss of every mapping of /proc/<pid>/smaps summed up, one `Pss:` line per mapping
+fn smaps_pss(pid: pid_t) -> Result<f64, String> {
+    let content = read_node(&format!(crate::TASK_SMAPS_PID_TEMPLATE!(), pid)).map_err(|e| e.to_string())?;
+    Ok(content.lines()
+            .filter_map(|line| line.strip_prefix(SMAPS_PSS_PREFIX))
+            .filter_map(|value| value.trim().trim_end_matches("kB").trim().parse::<f64>().ok())
+            .sum())
+}
+
+// User and system seconds of the single CPU lines of /proc/stat summed up
+fn per_cpu_time() -> Result<(f64, usize), String> {
+    let content = read_node(GLOBAL_STAT_PATH).map_err(|e| e.to_string())?;
+    let mut total = 0.0;
+    let mut cpus = 0;
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some(cpu) if cpu.len() > CPU_LINE_PREFIX.len() && cpu.starts_with(CPU_LINE_PREFIX) => {},
+            _ => continue,
+        }
+        let ticks: Vec<f64> = fields.filter_map(|field| field.parse().ok()).collect();
+        total += ticks.first().unwrap_or(&0.0) + ticks.get(2).unwrap_or(&0.0);
+        cpus += 1;
+    }
+    Ok((total / clock_ticks(), cpus))
+}
+
+fn read_item(path: String, parse: fn(&mut RecordItem, &str) -> Result<(), TraceError>)
+        -> Result<RecordItem, String> {
+    let mut item = RecordItem::default();
+    let content = read_node(&path).map_err(|e| e.to_string())?;
+    parse(&mut item, &content).map_err(|e| e.to_string())?;
+    Ok(item)
+}
+
+/// Compare what the tracer derives for `pid` against independent sources.
+/// The CPU time is compared against times() only when `pid` is the tracer
+pub fn cross_check(pid: pid_t) -> Vec<CrossCheck> {
+    let mut sampler = Sampler::default();
+    let mut checks = Vec::new();
+    if pid == std::process::id() as pid_t {
+        let values = read_item(format!(crate::TASK_STAT_TEMPLATE!(), pid), parse_task_stat)
+                .map(|item| (item.totalcputime, times_cpu_time()));
+        checks.push(check("totalcputime", "stat", "times()", values,
+                |reference| CPU_TOLERANCE_TICKS / clock_ticks() + reference * 0.01));
+    }
+    let mut item = RecordItem::default();
+    let values = get_pss_info(&mut item, pid, &mut sampler, &RetryPolicy::NONE).map_err(|e| e.to_string())
+            .and_then(|_| Ok((item.pss as f64, smaps_pss(pid)?)));
+    checks.push(check("pss", "smaps_rollup", "smaps", values, memory_tolerance));
+    let values = read_item(format!(crate::TASK_STATUS_TEMPLATE!(), pid), parse_task_status)
+            .and_then(|item| {
+                let statm = statm_rss_kb(pid).ok_or_else(|| format!("read statm of {} failed", pid))?;
+                Ok((item.vm_rss as f64, statm as f64))
+            });
+    checks.push(check("vmRss", "status", "statm", values, memory_tolerance));
+    let values = read_item(format!(crate::TASK_STAT_TEMPLATE!(), pid), parse_task_stat).and_then(|item| {
+        let tasks = fs::read_dir(format!(crate::SUBTASK_PATH_TEMPLATE!(), pid)).map_err(|e| e.to_string())?.count();
+        Ok((item.num_threads as f64, tasks as f64))
+    });
+    checks.push(check("numThreads", "stat", "task directory", values, |_| 0.0));
+    let mut item = RecordItem::default();
+    let values = get_global_cpu_info(&mut item, &mut sampler, &RetryPolicy::NONE).map_err(|e| e.to_string())
+            .and_then(|_| per_cpu_time().map(|(total, cpus)| (item.global_total_cpu_time, total, cpus)));
+    let cpus = values.as_ref().map(|(_, _, cpus)| *cpus as f64).unwrap_or(1.0);
+    checks.push(check("gtotalcputime", "stat cpu line", "stat per CPU lines",
+            values.map(|(derived, reference, _)| (derived, reference)),
+            |reference| cpus * CPU_TOLERANCE_TICKS / clock_ticks() + reference * 0.001));
+    checks
+}
+
+/// Format the checks which differ or failed, all of them with `all`
+pub fn format_cross_check(checks: &[CrossCheck], all: bool) -> String {
+    let mut out = String::new();
+    for check in checks.iter().filter(|check| all || check.differs() || check.values.is_err()) {
+        let result = match &check.values {
+            Ok((derived, reference)) => format!("{}={:.2} {}={:.2} tolerance={:.2} {}", check.derived_from, derived,
+                    check.reference, reference, check.tolerance, if check.differs() { "DIFFERS" } else { "ok" }),
+            Err(e) => format!("skipped: {}", e),
+        };
+        let _ = writeln!(out, "{:<16}{}", check.name, result);
+    }
+    out
+}
@@ -0,0 +1,154 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::pid_t;
+use crate::audit::{self, Effect};
+use crate::discover::process_info;
+use crate::proc_analysis::split_stat;
+use std::fs;
+use std::process::Command;
+
+// /proc/<pid>/stat shift of the parent pid
+const PROCESS_STAT_PPID_SHIFT: usize = 3;
+// Parents walked at most, init is reached long before
+const MAX_PARENTS: usize = 32;
+// Services and providers kept of one app
+const MAX_BINDINGS: usize = 32;
+// Processes forking the apps
+const ZYGOTES: &[&str] = &["zygote", "zygote64", "usap32", "usap64", "webview_zygote"];
+// Sections of a ProcessRecord in `dumpsys activity processes`
+const CONNECTIONS_SECTION: &str = "Connections:";
+const PROVIDERS_SECTION: &str = "Connected Providers:";
+
+/// One process of the parent chain
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParentLink {
+    /// pid of the parent
+    pub pid: pid_t,
+    /// comm of the parent
+    pub comm: String,
+}
+
+/// What a process depends on at attach, the context reviewers gather by hand
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DependencySnapshot {
+    /// parents from the direct one up to init
+    pub parents: Vec<ParentLink>,
+    /// whether the process is an app forked by a zygote
+    pub app: bool,
+    /// components of the services the app binds, e.g. `com.google.android.gms/.chimera.GmsBoundBrokerService`
+    pub services: Vec<String>,
+    /// components of the content providers the app is connected to
+    pub providers: Vec<String>,
+}
+
+impl DependencySnapshot {
+    /// Walk the parents of `pid` and, for apps, read the services and
+    /// providers they bind from `dumpsys activity`
+    pub fn take(pid: pid_t) -> DependencySnapshot {
+        let parents = parent_chain(pid);
+        let package = process_info(pid)
+                .and_then(|info| info.cmdline.first().cloned())
+                .filter(|name| name.contains('.'))
+                .map(|name| name.split(':').next().unwrap_or_default().to_string());
+        let app = parents.first().map(|parent| ZYGOTES.contains(&parent.comm.as_str())).unwrap_or(false);
+        let mut snapshot = DependencySnapshot { parents, app, ..Default::default() };
+        if let (true, Some(package)) = (app, package) {
+            let dump = audit::run(Command::new("dumpsys").args(["activity", "processes", &package]), Effect::ReadOnly)
+                    .ok()
+                    .filter(|output| output.status.success())
+                    .map(|output| String::from_utf8_lossy(&output.stdout).to_string());
+            if let Some(dump) = dump {
+                (snapshot.services, snapshot.providers) = parse_bindings(&dump, pid);
+            }
+        }
+        snapshot
+    }
+
+    /// `parents=<pid>:<comm>>... [services=a,b] [providers=c]`, lists without
+    /// entries are left out and `parents=none` for a process without any
+    pub fn detail(&self) -> String {
+        let parents: Vec<String> = self.parents.iter().map(|parent| format!("{}:{}", parent.pid, parent.comm)).collect();
+        let mut detail = format!("parents={}", if parents.is_empty() { "none".to_string() } else { parents.join(">") });
+        for (key, list) in [("services", &self.services), ("providers", &self.providers)] {
+            if !list.is_empty() {
+                detail.push_str(&format!(" {}={}", key, list.join(",")));
+            }
+        }
+        detail
+    }
+}
+
+// Comms may have spaces, a detail value must not
+fn detail_safe(value: &str) -> String {
+    value.replace([' ', ',', '>'], "_")
+}
+
+/// Parents of `pid` from the direct one up to init, empty when it exited
+pub fn parent_chain(pid: pid_t) -> Vec<ParentLink> {
+    let mut parents = Vec::new();
+    let mut current = pid;
+    while parents.len() < MAX_PARENTS {
+        let ppid = match fs::read_to_string(format!(crate::TASK_STAT_TEMPLATE!(), current)).ok()
+                .and_then(|stat| split_stat(&stat).get(PROCESS_STAT_PPID_SHIFT)?.parse::<pid_t>().ok()) {
+            Some(ppid) if ppid > 0 && ppid != current => ppid,
+            _ => break,
+        };
+        let comm = process_info(ppid).map(|info| detail_safe(&info.comm)).unwrap_or_else(|| "?".to_string());
+        parents.push(ParentLink { pid: ppid, comm });
+        current = ppid;
+    }
+    parents
+}
+
+/// Services and providers the ProcessRecord of `pid` in a `dumpsys activity
+/// processes` output is bound to. Lines look like
+/// `- ConnectionRecord{4e1b3d0 u0 CR com.google.android.gms/.GmsBoundBrokerService:@4c5c2d3}`
+/// and `- 8d5f3c1/com.android.providers.settings/.SettingsProvider->13271:com.example/u0a100 s1/1 u0/0`
+pub fn parse_bindings(dump: &str, pid: pid_t) -> (Vec<String>, Vec<String>) {
+    let record = format!(" {}:", pid);
+    let (mut services, mut providers) = (Vec::new(), Vec::new());
+    let mut inside = false;
+    let mut section = "";
+    for line in dump.lines() {
+        let trimmed = line.trim();
+        if trimmed.contains("ProcessRecord{") {
+            inside = trimmed.contains(&record);
+            section = "";
+            continue;
+        }
+        if !inside {
+            continue;
+        }
+        if trimmed == CONNECTIONS_SECTION || trimmed == PROVIDERS_SECTION {
+            section = if trimmed == CONNECTIONS_SECTION { CONNECTIONS_SECTION } else { PROVIDERS_SECTION };
+            continue;
+        }
+        let entry = match trimmed.strip_prefix("- ") {
+            Some(entry) => entry,
+            None => {
+                // A section ends at the next heading
+                section = "";
+                continue;
+            },
+        };
+        let component = match section {
+            CONNECTIONS_SECTION => entry.split_whitespace()
+                    .find(|word| word.contains('/'))
+                    .map(|word| word.split(":@").next().unwrap_or_default().trim_end_matches('}')),
+            PROVIDERS_SECTION => entry.split_once('/')
+                    .and_then(|(_, rest)| rest.split("->").next()),
+            _ => None,
+        };
+        let list = if section == CONNECTIONS_SECTION { &mut services } else { &mut providers };
+        if let Some(component) = component.map(detail_safe).filter(|component| !component.is_empty()) {
+            if !list.contains(&component) && list.len() < MAX_BINDINGS {
+                list.push(component);
+            }
+        }
+    }
+    (services, providers)
+}
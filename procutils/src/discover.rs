@@ -0,0 +1,163 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::error::TraceError;
+use crate::hidepid;
+use crate::pattern::Pattern;
+use crate::proc_analysis::{split_stat, PROCESS_STAT_STARTTIME_SHIFT};
+use libc::{getpid, pid_t};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+// Procfs some path
+pub(crate) const PROC_ROOT: &str = "/proc";
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_COMM_TEMPLATE { () => { "/proc/{}/comm" }; }
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_CMDLINE_TEMPLATE { () => { "/proc/{}/cmdline" }; }
+
+/// How a target name is matched against processes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// the comm, argv[0] or its basename equals the name
+    #[default]
+    Exact,
+    /// the comm or the command line contains the name
+    Substring,
+    /// the comm or the command line matches the name as a pattern, see `Pattern`
+    Regex,
+}
+
+/// Names of the match modes accepted by `MatchMode::parse`
+pub const MATCH_MODES: [&str; 3] = ["exact", "substring", "regex"];
+
+impl MatchMode {
+    /// Parse a mode name
+    pub fn parse(name: &str) -> Option<MatchMode> {
+        match name {
+            "exact" => Some(MatchMode::Exact),
+            "substring" => Some(MatchMode::Substring),
+            "regex" => Some(MatchMode::Regex),
+            _ => None,
+        }
+    }
+
+    /// Name accepted by `parse`
+    pub fn name(&self) -> &'static str {
+        match self {
+            MatchMode::Exact => "exact",
+            MatchMode::Substring => "substring",
+            MatchMode::Regex => "regex",
+        }
+    }
+}
+
+/// Names of a running process
+#[derive(Clone, Debug, Default)]
+pub struct ProcessInfo {
+    /// pid of the process
+    pub pid: pid_t,
+    /// comm, truncated to 15 bytes by the kernel
+    pub comm: String,
+    /// arguments, empty for kernel threads
+    pub cmdline: Vec<String>,
+    /// start time in clock ticks after boot
+    pub start_time: u64,
+}
+
+/// Matches processes against a target name
+#[derive(Clone, Debug)]
+pub struct ProcessMatcher {
+    name: String,
+    mode: MatchMode,
+    pattern: Option<Pattern>,
+}
+
+impl ProcessMatcher {
+    /// Create a matcher, fails on a bad pattern
+    pub fn new(name: &str, mode: MatchMode) -> Result<ProcessMatcher, TraceError> {
+        let pattern = match mode {
+            MatchMode::Regex => Some(Pattern::new(name).map_err(TraceError::InvalidConfig)?),
+            _ => None,
+        };
+        Ok(ProcessMatcher { name: name.to_string(), mode, pattern })
+    }
+
+    /// Whether the process matches
+    pub fn matches(&self, process: &ProcessInfo) -> bool {
+        let argv0 = process.cmdline.first().map(|arg| arg.as_str()).unwrap_or("");
+        match self.mode {
+            MatchMode::Exact => {
+                process.comm == self.name || argv0 == self.name
+                        || (!argv0.is_empty() && argv0.rsplit('/').next() == Some(self.name.as_str()))
+            },
+            MatchMode::Substring => {
+                process.comm.contains(&self.name) || process.cmdline.join(" ").contains(&self.name)
+            },
+            MatchMode::Regex => {
+                let pattern = self.pattern.as_ref().unwrap();
+                pattern.is_match(&process.comm) || pattern.is_match(&process.cmdline.join(" "))
+            },
+        }
+    }
+}
+
+/// Read the names of a process, None once it has exited
+pub fn process_info(pid: pid_t) -> Option<ProcessInfo> {
+    let stat = fs::read_to_string(format!(crate::TASK_STAT_TEMPLATE!(), pid)).ok()?;
+    let start_time = split_stat(&stat).get(PROCESS_STAT_STARTTIME_SHIFT)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+    let comm = fs::read_to_string(format!(TASK_COMM_TEMPLATE!(), pid)).ok()?;
+    // Arguments are NUL terminated and need not be UTF-8
+    let cmdline = fs::read(format!(TASK_CMDLINE_TEMPLATE!(), pid)).ok()?;
+    let cmdline = String::from_utf8_lossy(&cmdline).split('\0')
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.to_string())
+            .collect();
+    Some(ProcessInfo { pid, comm: comm.trim_end_matches('\n').to_string(), cmdline, start_time })
+}
+
+/// List the running processes, in pid order. Under hidepid only those of
+/// the own uid, see `hidepid::visible_uid`
+pub fn list_processes() -> Result<Vec<ProcessInfo>, TraceError> {
+    let only_uid = hidepid::visible_uid();
+    let mut pids: Vec<pid_t> = fs::read_dir(PROC_ROOT)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| match only_uid {
+                Some(uid) => entry.metadata().is_ok_and(|metadata| metadata.uid() == uid),
+                None => true,
+            })
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect();
+    pids.sort_unstable();
+    // Processes exiting during the walk are skipped
+    Ok(pids.into_iter().filter_map(process_info).collect())
+}
+
+/// Find the processes matching a target name, oldest first
+///
+/// The tracer itself is never matched, even if its command line contains the name.
+pub fn find_processes(name: &str, mode: MatchMode) -> Result<Vec<pid_t>, TraceError> {
+    let matcher = ProcessMatcher::new(name, mode)?;
+    find_processes_by(|process| matcher.matches(process))
+}
+
+/// Find the processes `filter` accepts, oldest first, the tracer itself excluded
+pub fn find_processes_by(filter: impl Fn(&ProcessInfo) -> bool) -> Result<Vec<pid_t>, TraceError> {
+    // SAFETY:
+    // Safe because getpid has no side effects and can not fail
+    let own_pid = unsafe { getpid() };
+    let mut found: Vec<ProcessInfo> = list_processes()?.into_iter()
+            .filter(|process| process.pid != own_pid && filter(process))
+            .collect();
+    found.sort_by_key(|process| (process.start_time, process.pid));
+    Ok(found.into_iter().map(|process| process.pid).collect())
+}
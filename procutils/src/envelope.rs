@@ -0,0 +1,117 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::analysis::TraceData;
+use std::fmt::Write as _;
+
+/// Columns sampled as a level, what they did between two samples is unknown.
+/// The other columns are deltas or averages of their interval and exact
+pub const GAUGE_COLUMNS: &[&str] = &[
+    "pss", "vmRss", "vmAnon", "vmFile", "vmShmem", "vmSwap", "numThreads", "fdCount",
+];
+
+// Peak resident set size, see `RecordItem::vm_hwm`
+const HWM_COLUMN: &str = "vmHwm";
+const RSS_COLUMN: &str = "vmRss";
+
+// Columns a growth of the peak RSS lifts, memory faulted in between two
+// samples is resident and, until shared, proportional
+const HWM_BOUND_COLUMNS: &[&str] = &["pss", "vmRss", "vmAnon"];
+
+/// Range a gauge may have taken in every sample interval
+///
+/// An interval is bounded by its two samples. Where VmHWM grew in between,
+/// the RSS reached the new peak inside it and the columns of
+/// `HWM_BOUND_COLUMNS` are lifted by how far the peak is above both samples.
+#[derive(Clone, Debug, Default)]
+pub struct ColumnEnvelope {
+    /// column name
+    pub name: String,
+    /// lowest value of the interval ending at every row, NaN without samples
+    pub low: Vec<f64>,
+    /// highest value of the interval ending at every row, NaN without samples
+    pub high: Vec<f64>,
+    /// largest value sampled
+    pub sampled_max: f64,
+    /// largest value the column may have reached, at least `sampled_max`
+    pub peak: f64,
+    /// intervals lifted by a VmHWM growth
+    pub hwm_intervals: usize,
+}
+
+impl ColumnEnvelope {
+    /// Share of the peak the samples missed, in percent of the sampled max
+    pub fn missed_percent(&self) -> f64 {
+        match self.sampled_max > 0.0 {
+            true => (self.peak - self.sampled_max) / self.sampled_max * 100.0,
+            false => 0.0,
+        }
+    }
+}
+
+// Peak RSS above both samples of the interval ending at `row`, when VmHWM grew in it.
+// A VmHWM of 0 was not collected, a drop is a new process or a reset peak
+fn hwm_excess(rss: &[f64], hwm: &[f64], row: usize) -> Option<f64> {
+    let (last, current) = (hwm[row - 1], hwm[row]);
+    if !(last > 0.0 && current > last) {
+        return None;
+    }
+    let excess = current - rss[row - 1].max(rss[row]);
+    (excess > 0.0).then_some(excess)
+}
+
+/// Envelope of a gauge column, None when the trace has no such column
+pub fn column_envelope(data: &TraceData, name: &str) -> Option<ColumnEnvelope> {
+    let values = data.column(data.column_index(name)?);
+    let hwm = match HWM_BOUND_COLUMNS.contains(&name) {
+        true => data.column_index(HWM_COLUMN).zip(data.column_index(RSS_COLUMN))
+                .map(|(hwm, rss)| (data.column(rss), data.column(hwm))),
+        false => None,
+    };
+    let mut envelope = ColumnEnvelope {
+        name: name.to_string(),
+        sampled_max: f64::NAN,
+        peak: f64::NAN,
+        ..ColumnEnvelope::default()
+    };
+    for (row, value) in values.iter().enumerate() {
+        let last = if row > 0 { values[row - 1] } else { *value };
+        let (low, mut high) = match (last.is_finite(), value.is_finite()) {
+            (true, true) => (last.min(*value), last.max(*value)),
+            (false, true) => (*value, *value),
+            _ => (f64::NAN, f64::NAN),
+        };
+        if let Some(excess) = hwm.as_ref().filter(|_| row > 0 && high.is_finite())
+                .and_then(|(rss, hwm)| hwm_excess(rss, hwm, row)) {
+            high += excess;
+            envelope.hwm_intervals += 1;
+        }
+        envelope.low.push(low);
+        envelope.high.push(high);
+        if value.is_finite() {
+            envelope.sampled_max = envelope.sampled_max.max(*value);
+        }
+        if high.is_finite() {
+            envelope.peak = envelope.peak.max(high);
+        }
+    }
+    Some(envelope)
+}
+
+/// Envelopes of the gauge columns of a trace
+pub fn envelopes(data: &TraceData) -> Vec<ColumnEnvelope> {
+    GAUGE_COLUMNS.iter().filter_map(|name| column_envelope(data, name)).collect()
+}
+
+/// Render envelopes as a text table
+pub fn format_envelopes(envelopes: &[ColumnEnvelope]) -> String {
+    let mut out = format!("{:<26}{:>14}{:>14}{:>10}{:>8}\n", "envelope", "sampled max", "peak", "missed%", "hwm");
+    for e in envelopes {
+        let _ = writeln!(out, "{:<26}{:>14.0}{:>14.0}{:>9.1}%{:>8}",
+                e.name, e.sampled_max, e.peak, e.missed_percent(), e.hwm_intervals);
+    }
+    out
+}
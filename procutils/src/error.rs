@@ -0,0 +1,71 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors of the tracer
+#[derive(Debug)]
+pub enum TraceError {
+    /// no running process matches the target spec
+    ProcessNotFound(String),
+    /// the trace configuration can not be run
+    InvalidConfig(String),
+    /// a procfs value could not be parsed
+    Parse {
+        /// name of the value
+        field: &'static str,
+        /// text which failed to parse
+        value: String,
+    },
+    /// the monitor thread of a target panicked
+    MonitorPanicked(String),
+    /// reading procfs or writing outputs failed
+    Io(io::Error),
+}
+
+impl TraceError {
+    /// Whether the error may go away at the next sample, e.g. a thread
+    /// exiting while it is read
+    pub fn is_transient(&self) -> bool {
+        match self {
+            TraceError::Parse { .. } => true,
+            TraceError::Io(e) => e.kind() == io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::ProcessNotFound(target) => match crate::hidepid::restriction() {
+                Some(restriction) => write!(f, "no visible process matches {}, {}", target, restriction),
+                None => write!(f, "no process matches {}", target),
+            },
+            TraceError::InvalidConfig(msg) => write!(f, "invalid trace config: {}", msg),
+            TraceError::Parse { field, value } => write!(f, "parse {} failed: {:?}", field, value),
+            TraceError::MonitorPanicked(target) => write!(f, "monitor of {} panicked", target),
+            TraceError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> Self {
+        TraceError::Io(e)
+    }
+}
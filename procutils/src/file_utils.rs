@@ -4,11 +4,348 @@
 // This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
 // See the LICENSE file at the root directory of this project for more details.
 
-use std::fs;
-use std::io;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+// Files opened before the tracer dropped its privileges, keyed by path
+static KEPT_FILES: Mutex<Vec<(String, Arc<File>)>> = Mutex::new(Vec::new());
+
+/// Keep a file open, later reads of `path` use it instead of opening the
+/// path again, so they still work after the privileges are dropped
+pub fn keep_open(path: &str) -> io::Result<()> {
+    let file = Arc::new(File::open(path)?);
+    let mut kept = KEPT_FILES.lock().unwrap();
+    kept.retain(|(kept_path, _)| kept_path != path);
+    kept.push((path.to_string(), file));
+    Ok(())
+}
+
+fn kept_file(path: &str) -> Option<Arc<File>> {
+    KEPT_FILES.lock().unwrap().iter()
+            .find(|(kept_path, _)| kept_path == path)
+            .map(|(_, file)| file.clone())
+}
+
+// Read an open file from its start into `content`, positioned reads never race
+// with other threads
+fn read_from_start(file: &File, content: &mut Vec<u8>) -> io::Result<()> {
+    content.clear();
+    let mut buf = [0u8; 4096];
+    loop {
+        let read = file.read_at(&mut buf, content.len() as u64)?;
+        if read == 0 {
+            return Ok(());
+        }
+        content.extend_from_slice(&buf[..read]);
+    }
+}
+
+fn read_kept(file: &File) -> io::Result<String> {
+    let mut content = Vec::new();
+    read_from_start(file, &mut content)?;
+    String::from_utf8(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
 
 /// read a file
 pub fn read_path(path: &str) -> io::Result<String> {
+    if let Some(file) = kept_file(path) {
+        return read_kept(&file);
+    }
     let result = fs::read_to_string(path)?;
     Ok(result)
 }
+
+/// Timeout of `read_node` until `set_node_timeout`, the default of --read-timeout
+pub const DEFAULT_NODE_TIMEOUT: Duration = Duration::from_secs(1);
+
+thread_local! {
+    // Timeout of the `read_node` calls of a thread, None reads in the thread.
+    // Per thread, traces running side by side may time out differently
+    static NODE_TIMEOUT: Cell<Option<Duration>> = const { Cell::new(Some(DEFAULT_NODE_TIMEOUT)) };
+    // Worker and timeouts of the `read_node` calls of a thread
+    static NODE_READS: RefCell<TimedReads> = RefCell::new(TimedReads::default());
+}
+
+/// Read timeout of `read_node` in the calling thread from now on, None reads
+/// in the calling thread. Threads a collector starts take it over with `node_timeout`
+pub fn set_node_timeout(timeout: Option<Duration>) {
+    NODE_TIMEOUT.with(|node_timeout| node_timeout.set(timeout));
+}
+
+/// Read timeout of `read_node` in the calling thread
+pub fn node_timeout() -> Option<Duration> {
+    NODE_TIMEOUT.with(Cell::get)
+}
+
+/// read a procfs, sysfs or cgroup node of a collector which is not read
+/// through an `OpenFiles`. With a timeout it is read like `OpenFiles` does,
+/// a node which keeps hanging is quarantined for the calling thread
+pub fn read_node(path: &str) -> io::Result<String> {
+    let timeout = match node_timeout() {
+        Some(timeout) => timeout,
+        None => return read_path(path),
+    };
+    let mut content = Vec::new();
+    NODE_READS.with(|reads| reads.borrow_mut().read(path, kept_file(path), &mut content, timeout))?;
+    String::from_utf8(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Timeouts of the `read_node` calls of this thread since the last call,
+/// see `OpenFiles::take_timeouts`
+pub fn take_node_timeouts() -> Vec<(String, u32)> {
+    NODE_READS.with(|reads| std::mem::take(&mut reads.borrow_mut().timed_out))
+}
+
+/// Files past this many are read without being kept open by `OpenFiles`
+pub const OPEN_FILES_LIMIT: usize = 256;
+
+/// Timeouts of a path after which `OpenFiles` no longer reads it
+pub const QUARANTINE_TIMEOUTS: u32 = 3;
+
+// Path, its open file if any and the buffer to read into
+type ReadJob = (String, Option<Arc<File>>, Vec<u8>);
+// Open file and the buffer holding its content
+type ReadResult = (io::Result<Arc<File>>, Vec<u8>);
+
+// Thread reading for an `OpenFiles` with a timeout, a worker stuck in a hung
+// node is abandoned and ends once the read returns
+struct ReadWorker {
+    jobs: Sender<ReadJob>,
+    results: Receiver<ReadResult>,
+}
+
+impl ReadWorker {
+    fn spawn() -> io::Result<ReadWorker> {
+        let (jobs, job_receiver) = channel::<ReadJob>();
+        let (result_sender, results) = channel();
+        thread::Builder::new().name("collector-read".to_string()).spawn(move || {
+            for (path, file, mut buf) in job_receiver {
+                let file = match file {
+                    Some(file) => Ok(file),
+                    None => File::open(&path).map(Arc::new),
+                };
+                let result = file.and_then(|file| read_from_start(&file, &mut buf).map(|_| file));
+                if result_sender.send((result, buf)).is_err() {
+                    return;
+                }
+            }
+        })?;
+        Ok(ReadWorker { jobs, results })
+    }
+}
+
+// Reads in a worker thread with a timeout, a path timing out
+// `QUARANTINE_TIMEOUTS` times is not read again
+#[derive(Default)]
+struct TimedReads {
+    worker: Option<ReadWorker>,
+    // paths still read by an abandoned worker
+    stuck: Vec<(String, Receiver<ReadResult>)>,
+    // timeouts so far by path
+    timeouts: HashMap<String, u32>,
+    // (path, timeouts) of the timeouts since they were last taken
+    timed_out: Vec<(String, u32)>,
+}
+
+impl TimedReads {
+    // Read in the worker, the buffer comes back with the result
+    fn read(&mut self, path: &str, file: Option<Arc<File>>, buf: &mut Vec<u8>, timeout: Duration) -> io::Result<Arc<File>> {
+        self.stuck.retain(|(_, results)| matches!(results.try_recv(), Err(TryRecvError::Empty)));
+        let count = self.timeouts.get(path).copied().unwrap_or(0);
+        if count >= QUARANTINE_TIMEOUTS {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{} is quarantined after {} timeouts", path, count)));
+        }
+        if self.stuck.iter().any(|(stuck, _)| stuck == path) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{} is still hung", path)));
+        }
+        let worker = match self.worker.take() {
+            Some(worker) => worker,
+            None => ReadWorker::spawn()?,
+        };
+        if worker.jobs.send((path.to_string(), file, std::mem::take(buf))).is_err() {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "collector read worker exited"));
+        }
+        match worker.results.recv_timeout(timeout) {
+            Ok((result, read)) => {
+                *buf = read;
+                self.worker = Some(worker);
+                result
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                self.stuck.push((path.to_string(), worker.results));
+                self.timeouts.insert(path.to_string(), count + 1);
+                self.timed_out.push((path.to_string(), count + 1));
+                Err(io::Error::new(io::ErrorKind::TimedOut, format!("read {} timed out after {:?}", path, timeout)))
+            },
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "collector read worker exited"))
+            },
+        }
+    }
+}
+
+/// Files read again and again, e.g. the procfs nodes of a traced process.
+/// They stay open between reads, a file not read since the last `sweep` is closed
+///
+/// With a timeout the reads happen in a worker thread, a read taking longer
+/// fails with `TimedOut` and the path is skipped until the stuck read returns.
+/// A path timing out `QUARANTINE_TIMEOUTS` times is not read again.
+#[derive(Default)]
+pub struct OpenFiles {
+    // open file and whether it was read since the last sweep
+    files: HashMap<String, (Arc<File>, bool)>,
+    buf: Vec<u8>,
+    timeout: Option<Duration>,
+    timed: TimedReads,
+}
+
+impl OpenFiles {
+    /// Read with a timeout from now on, None reads in the calling thread
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        if timeout.is_none() {
+            self.timed.worker = None;
+        }
+    }
+
+    /// Timeouts since the last call as (path, timeouts of the path so far),
+    /// the path is quarantined when they reach `QUARANTINE_TIMEOUTS`
+    pub fn take_timeouts(&mut self) -> Vec<(String, u32)> {
+        std::mem::take(&mut self.timed.timed_out)
+    }
+
+    /// read a file into `content`, both the open file and the buffers are reused
+    pub fn read_into(&mut self, path: &str, content: &mut String) -> io::Result<()> {
+        let open = match self.files.get_mut(path) {
+            Some((file, used)) => {
+                *used = true;
+                Some(file.clone())
+            },
+            None => kept_file(path),
+        };
+        let result = match self.timeout {
+            Some(timeout) => self.timed.read(path, open, &mut self.buf, timeout),
+            None => open.map(Ok)
+                    .unwrap_or_else(|| File::open(path).map(Arc::new))
+                    .and_then(|file| read_from_start(&file, &mut self.buf).map(|_| file)),
+        };
+        let file = match result {
+            Ok(file) => file,
+            Err(e) => {
+                // A file of an exited task never reads again, the next read opens the path
+                self.files.remove(path);
+                return Err(e);
+            },
+        };
+        if !self.files.contains_key(path) && self.files.len() < OPEN_FILES_LIMIT {
+            self.files.insert(path.to_string(), (file, true));
+        }
+        content.clear();
+        content.push_str(std::str::from_utf8(&self.buf)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+        Ok(())
+    }
+
+    /// Close the files not read since the last sweep
+    pub fn sweep(&mut self) {
+        self.files.retain(|_, (_, used)| std::mem::replace(used, false));
+    }
+
+    /// Close all files
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    /// Release the read buffer, it grows again with the next read
+    pub fn shrink(&mut self) {
+        self.buf = Vec::new();
+    }
+}
+
+/// read the first `buf.len()` bytes of a file
+pub fn read_head(path: &str, buf: &mut [u8]) -> io::Result<()> {
+    match kept_file(path) {
+        Some(file) => file.read_exact_at(buf, 0),
+        None => File::open(path)?.read_exact(buf),
+    }
+}
+
+/// Retry policy of a collector for transient read failures
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// max retries after the first attempt
+    pub max_retries: u32,
+    /// backoff before the first retry, doubled for every retry
+    pub initial_backoff: Duration,
+    /// upper bound of the backoff
+    pub max_backoff: Duration,
+    /// treat ENOENT as transient, e.g. a task being created or reaped
+    pub retry_not_found: bool,
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub const NONE: RetryPolicy = RetryPolicy {
+        max_retries: 0,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+        retry_not_found: false,
+    };
+
+    /// Retry `max_retries` times, starting with `initial_backoff`
+    pub const fn new(max_retries: u32, initial_backoff: Duration, max_backoff: Duration,
+            retry_not_found: bool) -> RetryPolicy {
+        RetryPolicy { max_retries, initial_backoff, max_backoff, retry_not_found }
+    }
+
+    /// Whether an error is worth another attempt
+    pub fn is_transient(&self, err: &io::Error) -> bool {
+        match err.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => true,
+            io::ErrorKind::NotFound => self.retry_not_found,
+            _ => false,
+        }
+    }
+
+    /// Backoff before the retry `attempt`, which starts at 0
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+                .checked_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+                .unwrap_or(self.max_backoff)
+                .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3, Duration::from_millis(1), Duration::from_millis(20), false)
+    }
+}
+
+/// Run `op` with retries, `retries` is increased by every retry done
+pub fn with_retry<T>(policy: &RetryPolicy, retries: &mut usize,
+        mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < policy.max_retries && policy.is_transient(&e) => {
+                sleep(policy.backoff(attempt));
+                attempt += 1;
+                *retries += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// read a node with `read_node`, retry transient failures according to `policy`
+pub fn read_path_retry(path: &str, policy: &RetryPolicy, retries: &mut usize) -> io::Result<String> {
+    with_retry(policy, retries, || read_node(path))
+}
@@ -0,0 +1,124 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use std::fmt::Write;
+
+/// Cpusets Android moves the app the user sees into
+pub const FOREGROUND_CPUSETS: &[&str] = &["/top-app"];
+
+/// State of the device and the target a sample interval is counted in,
+/// the split battery reviews use
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppWindow {
+    /// screen on and the target in a foreground cpuset
+    Foreground,
+    /// screen on and the target in another cpuset
+    Background,
+    /// screen off, whatever the cpuset
+    ScreenOff,
+    /// no cpuset read, e.g. without the cgroup metric group
+    Unknown,
+}
+
+/// Windows in the order they are reported
+pub const APP_WINDOWS: [AppWindow; 4] =
+        [AppWindow::Foreground, AppWindow::Background, AppWindow::ScreenOff, AppWindow::Unknown];
+
+impl AppWindow {
+    /// Window of a sample from the cpuset of the target and the screen state,
+    /// an unknown screen state counts as on
+    pub fn classify(cpuset: &str, screen_on: Option<bool>) -> AppWindow {
+        if cpuset.is_empty() {
+            AppWindow::Unknown
+        } else if screen_on == Some(false) {
+            AppWindow::ScreenOff
+        } else if FOREGROUND_CPUSETS.contains(&cpuset) {
+            AppWindow::Foreground
+        } else {
+            AppWindow::Background
+        }
+    }
+
+    /// Name used in reports and events
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppWindow::Foreground => "foreground",
+            AppWindow::Background => "background",
+            AppWindow::ScreenOff => "screen_off",
+            AppWindow::Unknown => "unknown",
+        }
+    }
+
+    fn index(&self) -> usize {
+        APP_WINDOWS.iter().position(|window| window == self).unwrap_or(APP_WINDOWS.len() - 1)
+    }
+}
+
+/// Time spent in one window and the CPU used in it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WindowTime {
+    /// CPU seconds of the target
+    pub cpu_seconds: f64,
+    /// seconds traced
+    pub seconds: i64,
+}
+
+/// CPU of a target split by the window every sample interval ended in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CpuSplit {
+    /// time per window, in the order of `APP_WINDOWS`
+    pub windows: [WindowTime; APP_WINDOWS.len()],
+}
+
+impl CpuSplit {
+    /// Count an interval of `seconds` in which the target used `cpu_seconds`
+    pub fn add(&mut self, window: AppWindow, cpu_seconds: f64, seconds: i64) {
+        let time = &mut self.windows[window.index()];
+        if cpu_seconds.is_finite() {
+            time.cpu_seconds += cpu_seconds;
+        }
+        time.seconds += seconds;
+    }
+
+    /// Time of one window
+    pub fn window(&self, window: AppWindow) -> WindowTime {
+        self.windows[window.index()]
+    }
+
+    /// CPU seconds of all windows
+    pub fn cpu_seconds(&self) -> f64 {
+        self.windows.iter().map(|time| time.cpu_seconds).sum()
+    }
+
+    /// Render the split as a table, a window's share of the CPU and its
+    /// CPU per second traced in it
+    pub fn format(&self, name: &str) -> String {
+        let total = self.cpu_seconds();
+        let mut out = format!("cpu of {} by window: {:.2}s\n", name, total);
+        let _ = writeln!(out, "  {:<12}{:>10}{:>8}{:>10}{:>10}", "window", "cpu_s", "share", "traced_s", "cpu/s");
+        for window in APP_WINDOWS {
+            let time = self.window(window);
+            if time.seconds == 0 {
+                continue;
+            }
+            let share = if total > 0.0 { time.cpu_seconds / total * 100.0 } else { 0.0 };
+            let _ = writeln!(out, "  {:<12}{:>10.2}{:>7.1}%{:>10}{:>10.3}", window.name(), time.cpu_seconds, share,
+                    time.seconds, time.cpu_seconds / time.seconds as f64);
+        }
+        out
+    }
+
+    /// Detail of the `cpu_split` event, e.g. `foreground_cpu_s=1.20 foreground_s=60 ...`
+    pub fn detail(&self) -> String {
+        APP_WINDOWS.iter()
+                .map(|window| {
+                    let time = self.window(*window);
+                    format!("{}_cpu_s={:.3} {}_s={}", window.name(), time.cpu_seconds, window.name(), time.seconds)
+                })
+                .collect::<Vec<String>>()
+                .join(" ")
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::pid_t;
+use crate::file_utils::read_node;
+use std::path::Path;
+
+// cgroup v2 is mounted here on Android, and under `unified` on hybrid hosts
+const CGROUP_V2_MOUNTS: &[&str] = &["/sys/fs/cgroup", "/sys/fs/cgroup/unified"];
+// Controllers of the cgroup v2 line of /proc/<pid>/cgroup, `0::<path>`
+const CGROUP_V2_CONTROLLERS: &str = "";
+// Controllers of the cgroup v1 freezer line, `<id>:freezer:<path>`
+const CGROUP_V1_FREEZER_CONTROLLERS: &str = "freezer";
+const CGROUP_V1_FREEZER_MOUNT: &str = "/sys/fs/cgroup/freezer";
+// `frozen 1` once every process of a v2 group is frozen
+const CGROUP_EVENTS_FILE: &str = "cgroup.events";
+const CGROUP_FROZEN_KEY: &str = "frozen";
+const FREEZER_STATE_FILE: &str = "freezer.state";
+const FREEZER_FROZEN: &str = "FROZEN";
+
+// Group of `pid` in the hierarchy of `controllers`, lines are `<id>:<controllers>:<path>`
+fn cgroup_group(pid: pid_t, controllers: &str) -> Option<String> {
+    let content = read_node(&format!(crate::TASK_CGROUP_TEMPLATE!(), pid)).ok()?;
+    let group = content.lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, ':').skip(1);
+                Some((fields.next()?, fields.next()?))
+            })
+            .find_map(|(names, path)| (names == controllers).then_some(path))?;
+    Some(group.trim().trim_end_matches('/').to_string())
+}
+
+/// Directory of the cgroup v2 group of `pid` which has `file`, e.g. `memory.events`
+pub(crate) fn cgroup_v2_file(pid: pid_t, file: &str) -> Option<String> {
+    let group = cgroup_group(pid, CGROUP_V2_CONTROLLERS)?;
+    CGROUP_V2_MOUNTS.iter()
+            .map(|mount| format!("{}{}/{}", mount, group, file))
+            .find(|path| Path::new(path).exists())
+}
+
+/// Freezer state file of one process, looked up once
+///
+/// Android freezes cached apps through the cgroup v2 freezer, the v1
+/// `freezer.state` is used where the v2 group has no `frozen` key.
+#[derive(Clone, Debug)]
+pub struct FreezerWatch {
+    /// process watched
+    pub pid: pid_t,
+    // `cgroup.events` or `freezer.state`, None when neither is there
+    path: Option<String>,
+}
+
+impl FreezerWatch {
+    /// Find the freezer state of `pid`
+    pub fn open(pid: pid_t) -> FreezerWatch {
+        let v2 = cgroup_v2_file(pid, CGROUP_EVENTS_FILE)
+                .filter(|path| read_node(path).map(|events| parse_frozen(&events).is_some()).unwrap_or(false));
+        let path = v2.or_else(|| {
+            let group = cgroup_group(pid, CGROUP_V1_FREEZER_CONTROLLERS)?;
+            Some(format!("{}{}/{}", CGROUP_V1_FREEZER_MOUNT, group, FREEZER_STATE_FILE))
+                    .filter(|path| Path::new(path).exists())
+        });
+        FreezerWatch { pid, path }
+    }
+
+    /// Whether the freezer froze the process, None without a freezer
+    pub fn frozen(&self) -> Option<bool> {
+        let content = read_node(self.path.as_ref()?).ok()?;
+        parse_frozen(&content).or_else(|| Some(content.trim() == FREEZER_FROZEN))
+    }
+}
+
+// The `frozen` key of cgroup.events, None in freezer.state or before Linux 5.2
+fn parse_frozen(events: &str) -> Option<bool> {
+    events.lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(key, _)| *key == CGROUP_FROZEN_KEY)
+            .map(|(_, value)| value.trim() == "1")
+}
+
+/// Whether the cgroup freezer froze `pid`, the v2 `cgroup.events` or the v1
+/// `freezer.state` of its group, None when neither is there
+pub fn cgroup_frozen(pid: pid_t) -> Option<bool> {
+    FreezerWatch::open(pid).frozen()
+}
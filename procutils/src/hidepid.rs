@@ -0,0 +1,148 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::privilege::is_root;
+use libc::{gid_t, uid_t};
+use std::fs;
+use std::sync::OnceLock;
+
+// Mounts seen by the tracer
+const MOUNTS_PATH: &str = "/proc/self/mounts";
+
+/// Who may see the /proc/<pid> entries of other users
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HidePid {
+    /// everyone sees every process
+    #[default]
+    Off,
+    /// the directories are listed but their nodes can not be read, hidepid=1
+    NoAccess,
+    /// the directories are not listed either, hidepid=2, the Android default
+    Invisible,
+    /// only processes the caller may ptrace are listed, hidepid=4
+    Ptraceable,
+}
+
+impl HidePid {
+    /// Parse the value of the mount option, numeric or by name
+    pub fn parse(value: &str) -> Option<HidePid> {
+        match value {
+            "0" | "off" => Some(HidePid::Off),
+            "1" | "noaccess" => Some(HidePid::NoAccess),
+            "2" | "invisible" => Some(HidePid::Invisible),
+            "4" | "ptraceable" => Some(HidePid::Ptraceable),
+            _ => None,
+        }
+    }
+
+    /// Name of the mode as newer kernels show it
+    pub fn name(&self) -> &'static str {
+        match self {
+            HidePid::Off => "off",
+            HidePid::NoAccess => "noaccess",
+            HidePid::Invisible => "invisible",
+            HidePid::Ptraceable => "ptraceable",
+        }
+    }
+}
+
+/// Options of the /proc mount which limit what the tracer sees
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcMount {
+    /// hidepid mode
+    pub hidepid: HidePid,
+    /// group exempt from hidepid, `readproc` (3009) on Android
+    pub gid: Option<gid_t>,
+}
+
+impl ProcMount {
+    /// Options of the /proc line of a mounts table, the defaults without one.
+    /// Of stacked mounts the last one is on top
+    pub fn parse(mounts: &str) -> ProcMount {
+        let mut mount = ProcMount::default();
+        let options = mounts.lines()
+                .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+                .rev()
+                .find(|fields| fields.len() > 3 && fields[1] == "/proc" && fields[2] == "proc")
+                .map(|fields| fields[3].to_string())
+                .unwrap_or_default();
+        for option in options.split(',') {
+            match option.split_once('=') {
+                Some(("hidepid", value)) => mount.hidepid = HidePid::parse(value).unwrap_or(mount.hidepid),
+                Some(("gid", value)) => mount.gid = value.parse().ok(),
+                _ => {},
+            }
+        }
+        mount
+    }
+
+    /// Options of the /proc the tracer sees, read once
+    pub fn current() -> ProcMount {
+        static MOUNT: OnceLock<ProcMount> = OnceLock::new();
+        *MOUNT.get_or_init(|| fs::read_to_string(MOUNTS_PATH).map(|mounts| ProcMount::parse(&mounts)).unwrap_or_default())
+    }
+
+    /// Whether the tracer sees only some processes: hidepid is on and it is
+    /// neither root nor in the exempt group. Checked every time, the
+    /// identity changes when privileges are dropped
+    pub fn restricted(&self) -> bool {
+        self.hidepid != HidePid::Off && !is_root() && !self.gid.is_some_and(in_group)
+    }
+
+    /// Mount options as printed in reports, e.g. `hidepid=invisible gid=3009`
+    pub fn describe(&self) -> String {
+        match self.gid {
+            Some(gid) => format!("hidepid={} gid={}", self.hidepid.name(), gid),
+            None => format!("hidepid={}", self.hidepid.name()),
+        }
+    }
+}
+
+// Whether the tracer has `gid` as its effective or a supplementary group
+fn in_group(gid: gid_t) -> bool {
+    // SAFETY:
+    // Safe because getegid has no side effects and can not fail, getgroups
+    // writes at most the count it is given into a buffer of that size
+    unsafe {
+        if libc::getegid() == gid {
+            return true;
+        }
+        let count = libc::getgroups(0, std::ptr::null_mut());
+        if count <= 0 {
+            return false;
+        }
+        let mut groups = vec![0 as gid_t; count as usize];
+        let count = libc::getgroups(count, groups.as_mut_ptr());
+        count > 0 && groups[..count as usize].contains(&gid)
+    }
+}
+
+/// Why processes may be missing from /proc, None when the tracer sees all of them
+pub fn restriction() -> Option<String> {
+    let mount = ProcMount::current();
+    if !mount.restricted() {
+        return None;
+    }
+    let exempt = match mount.gid {
+        Some(gid) => format!(", run as root or in group {} to see the others", gid),
+        None => ", run as root to see the others".to_string(),
+    };
+    Some(format!("/proc is mounted with {}, only processes of uid {} are visible{}",
+            mount.describe(), effective_uid(), exempt))
+}
+
+fn effective_uid() -> uid_t {
+    // SAFETY:
+    // Safe because geteuid has no side effects and can not fail
+    unsafe { libc::geteuid() }
+}
+
+/// The only uid whose /proc/<pid> entries discovery should look at, None
+/// when it sees every process. Under hidepid the entries of other users
+/// are skipped rather than failing one read after the other
+pub fn visible_uid() -> Option<uid_t> {
+    ProcMount::current().restricted().then(effective_uid)
+}
@@ -0,0 +1,118 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::analysis::{summarize_values, TraceData};
+use std::fmt::Write as _;
+
+/// File the joint summary of a session is written to
+pub const JOINT_SUMMARY_FILE: &str = "joint_summary.txt";
+
+/// One target of a joint summary, over the window all targets were traced in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JointRow {
+    /// target label
+    pub target: String,
+    /// samples in the window
+    pub samples: usize,
+    /// CPU seconds used in the window
+    pub cpu_seconds: f64,
+    /// share of the CPU time all targets used in the window, 0 ~ 1
+    pub cpu_share: f64,
+    /// mean PSS in kB
+    pub pss_mean: f64,
+    /// max PSS in kB
+    pub pss_max: f64,
+}
+
+/// Comparison of targets traced side by side
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JointSummary {
+    /// first and last trace second every target has samples for, None when
+    /// the targets were never traced at the same time
+    pub window: Option<(f64, f64)>,
+    /// targets in the order given, most CPU first
+    pub rows: Vec<JointRow>,
+}
+
+impl JointSummary {
+    /// Target which used the most CPU in the window
+    pub fn most_cpu(&self) -> Option<&JointRow> {
+        self.rows.iter().filter(|row| row.cpu_seconds > 0.0).max_by(|a, b| a.cpu_seconds.total_cmp(&b.cpu_seconds))
+    }
+
+    /// Target with the highest mean PSS in the window
+    pub fn most_pss(&self) -> Option<&JointRow> {
+        self.rows.iter().filter(|row| row.pss_mean.is_finite()).max_by(|a, b| a.pss_mean.total_cmp(&b.pss_mean))
+    }
+}
+
+// Values of `column` in the rows whose time is within `window`
+fn window_values(data: &TraceData, column: &str, window: (f64, f64)) -> Vec<f64> {
+    let (time, index) = match (data.column_index("time"), data.column_index(column)) {
+        (Some(time), Some(index)) => (time, index),
+        _ => return Vec::new(),
+    };
+    data.rows.iter()
+            .filter(|row| row[time] >= window.0 && row[time] <= window.1)
+            .map(|row| row[index])
+            .collect()
+}
+
+/// Compare the traces of `(target, data)` over the window they share. Times
+/// of one session count from the same start, so the windows line up
+pub fn joint_summary(traces: &[(String, TraceData)]) -> JointSummary {
+    let bounds = |data: &TraceData| {
+        let times = data.column(data.column_index("time")?);
+        Some((times.iter().copied().fold(f64::INFINITY, f64::min), times.iter().copied().fold(f64::NEG_INFINITY, f64::max)))
+    };
+    let window = traces.iter()
+            .map(|(_, data)| bounds(data).filter(|(first, last)| first <= last))
+            .try_fold((f64::NEG_INFINITY, f64::INFINITY), |(start, end), bounds| {
+                let (first, last) = bounds?;
+                Some((start.max(first), end.min(last)))
+            })
+            .filter(|(start, end)| start <= end && !traces.is_empty());
+    let mut rows: Vec<JointRow> = traces.iter().map(|(target, data)| {
+        let window = match window {
+            Some(window) => window,
+            None => return JointRow { target: target.clone(), pss_mean: f64::NAN, pss_max: f64::NAN, ..Default::default() },
+        };
+        let cpu = window_values(data, "totalcputime", window);
+        let pss = summarize_values("pss", &window_values(data, "pss", window));
+        JointRow {
+            target: target.clone(),
+            samples: cpu.len(),
+            cpu_seconds: cpu.iter().filter(|value| value.is_finite()).sum(),
+            cpu_share: 0.0,
+            pss_mean: pss.mean,
+            pss_max: pss.max,
+        }
+    }).collect();
+    let total: f64 = rows.iter().map(|row| row.cpu_seconds).sum();
+    for row in rows.iter_mut().filter(|_| total > 0.0) {
+        row.cpu_share = row.cpu_seconds / total;
+    }
+    rows.sort_by(|a, b| b.cpu_seconds.total_cmp(&a.cpu_seconds));
+    JointSummary { window, rows }
+}
+
+/// Render a joint summary as a text table
+pub fn format_joint_summary(summary: &JointSummary) -> String {
+    let mut out = match summary.window {
+        Some((start, end)) => format!("joint summary of {} targets over {:.0}s ~ {:.0}s\n", summary.rows.len(), start, end),
+        None => return format!("joint summary of {} targets: never traced at the same time\n", summary.rows.len()),
+    };
+    let _ = writeln!(out, "{:<26}{:>8}{:>14}{:>10}{:>14}{:>14}", "target", "count", "cpu_seconds", "cpu%",
+            "pss_mean", "pss_max");
+    for row in &summary.rows {
+        let _ = writeln!(out, "{:<26}{:>8}{:>14.3}{:>9.1}%{:>14.0}{:>14.0}", row.target, row.samples,
+                row.cpu_seconds, row.cpu_share * 100.0, row.pss_mean, row.pss_max);
+    }
+    if let (Some(cpu), Some(pss)) = (summary.most_cpu(), summary.most_pss()) {
+        let _ = writeln!(out, "most cpu: {}, most pss: {}", cpu.target, pss.target);
+    }
+    out
+}
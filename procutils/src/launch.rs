@@ -0,0 +1,84 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use libc::{c_int, c_void};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+/// File name of the rollup of the children of a launched command, in its session directory
+pub const CHILDREN_ROLLUP_FILE: &str = "children_rollup.csv";
+
+/// Holds a spawned command before it runs until it is opened
+///
+/// Dropping the gate without opening it makes the command exit with 127
+/// instead of running untraced.
+pub struct Gate {
+    fd: c_int,
+}
+
+impl Gate {
+    /// Let the command exec
+    pub fn open(self) -> io::Result<()> {
+        let byte = b'\n';
+        // SAFETY:
+        // Safe because the buffer outlives the call and its length is 1
+        let written = unsafe { libc::write(self.fd, &byte as *const u8 as *const c_void, 1) };
+        if written != 1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Gate {
+    fn drop(&mut self) {
+        // SAFETY:
+        // Safe because the gate owns the descriptor
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+// Shell which waits for the gate on descriptor 3, then becomes the command.
+// std only returns from spawn after the exec, so the wait can not happen
+// before it
+const GATE_SCRIPT: &str = "read -r _ <&3 || exit 127; exec 3<&-; exec \"$@\"";
+
+/// Descriptor the gate is read from in the launched shell
+const GATE_FD: c_int = 3;
+
+/// Spawn `program` with `args` held before it runs anything, so a trace can
+/// attach to its pid first. The pid stays the same once the gate opens
+pub fn spawn_gated(program: &str, args: &[String]) -> io::Result<(Child, Gate)> {
+    let mut fds = [0 as c_int; 2];
+    // SAFETY:
+    // Safe because `fds` has room for both descriptors
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read_fd, write_fd] = fds;
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(GATE_SCRIPT).arg("sh").arg(program).args(args);
+    // SAFETY:
+    // Safe because the hook only calls async-signal-safe functions between
+    // fork and exec
+    let spawned = unsafe {
+        command.pre_exec(move || {
+            if read_fd == GATE_FD {
+                libc::fcntl(GATE_FD, libc::F_SETFD, 0);
+            } else if libc::dup2(read_fd, GATE_FD) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }).spawn()
+    };
+    // SAFETY:
+    // Safe because the read end is only used by the child
+    unsafe { libc::close(read_fd) };
+    let gate = Gate { fd: write_fd };
+    Ok((spawned?, gate))
+}
@@ -6,9 +6,86 @@
 
 //! The `processutils` library provides utilities for process analysis.
 //!
-//! It includes two modules:
+//! It includes these modules:
 //! - The `file_utils` module, used for file operations.
 //! - The `proc_analysis` module, provides utilities for analyzing the process.
+//! - The `loki` module, exports trace data as JSON logs for Loki.
+//! - The `session` module, persists trace sessions so they can be resumed.
+//! - The `validate` module, sanity checks samples against device limits.
+//! - The `analysis` module, analyzes CSV outputs of finished traces.
+//! - The `error` module, errors returned by the tracer.
+//! - The `trace` module, a library API which returns the samples to the caller.
+//! - The `abi` module, detects the ABI of traced processes.
+//! - The `output` module, writes samples as CSV, JSON lines or Perfetto traces.
+//! - The `selinux` module, captures SELinux contexts and avc denials.
+//! - The `privilege` module, drops root after the setup of a trace.
+//! - The `pattern` module, a small regular expression matcher.
+//! - The `discover` module, finds processes by name in procfs.
+//! - The `audit` module, runs external commands with an audit log and strict mode.
+//! - The `sampling` module, histograms of sampling durations and jitter.
+//! - The `stats` module, confidence intervals and significance tests.
+//! - The `top` module, samples every process and reports the top ones.
+//! - The `resolve` module, finds the processes of target specs by scheme.
+//! - The `bus` module, broadcasts samples to live analyzers.
+//! - The `alert` module, threshold alerts as a live analyzer.
+//! - The `power` module, power states of the device and sampling profiles for them.
+//! - The `trend` module, an append-only store of run summaries across builds.
+//! - The `watch` module, traces every occurrence of a short-lived process.
+//! - The `rollup` module, totals of short-lived processes by name.
+//! - The `simpleperf` module, captures CPU hotspots when an alert fires.
+//! - The `binder` module, pairs binder thread CPU with the calling processes.
+//! - The `launch` module, starts commands held before their exec for tracing.
+//! - The `sandbox` module, runs workloads in a cgroup with limits.
+//! - The `baseline` module, measures idle rates and subtracts them from traces.
+//! - The `noise` module, stores the noise floor of a device for comparisons.
+//! - The `transform` module, rescales and smooths columns before they are written.
+//! - The `selftest` module, checks every collector against the running kernel.
+//! - The `progress` module, reports how far a long trace has come.
+//! - The `arena` module, keeps the samples of long traces in compact columns.
+//! - The `loadgen` module, generates a known CPU and memory load to check a device against.
+//! - The `crosscheck` module, compares parsed values against independent sources.
+//! - The `memwatch` module, notices memory changes between the ticks of a trace.
+//! - The `freezer` module, tells whether the cgroup freezer froze a process.
+//! - The `score` module, weighs CPU, memory and fault rates into one health score.
+//! - The `template` module, expands tokens like `{name}` and `{date}` in output paths.
+//! - The `shutdown` module, stops every target of a trace together on SIGINT or SIGTERM.
+//! - The `joint` module, compares targets traced side by side over their shared window.
+//! - The `contention` module, finds targets whose CPU spikes delay another target.
+//! - The `binderprio` module, catches short priority boosts of binder threads.
+//! - The `soak` module, records targets for weeks with daily rollups and retention.
+//! - The `codes` module, gives diagnostic events stable codes like `E_SMAPS_EACCES`.
+//! - The `foreground` module, splits the CPU of a target into foreground, background and screen off.
+//! - The `deps` module, snapshots the parent chain and the bound services of a target at attach.
+//! - The `smaps` module, parses only the changed mappings of smaps on kernels without smaps_rollup.
+//! - The `outfile` module, writes outputs in batches without evicting the pages of the workload.
+//! - The `numeric` module, formats and parses numbers the same way whatever the locale.
+//! - The `hidepid` module, detects a /proc which hides the processes of other users.
+//! - The `envelope` module, estimates the range of sampled levels between the samples.
+//! - The `align` module, resamples traces of different intervals onto a common time base.
+//! - The `manifest` module, checksums the files of a finished session.
+//!
+//! The sampling core builds without features, for recovery and early boot
+//! images which have room for little more than a CPU and RSS tracer writing
+//! CSV. The rest needs one of these features:
+//! - `json`: the JSON lines and Perfetto outputs.
+//! - `analysis`: the `analysis`, `stats`, `noise`, `baseline`, `trend`, `score`, `joint`,
+//!   `contention` and `soak` modules.
+//! - `top`: the `top`, `watch`, `rollup` and `binder` modules.
+//! - `alert`: the `alert` and `simpleperf` modules.
+//! - `run`: the `launch` and `sandbox` modules.
+
+/// Version of the library and the tracer
+pub const VERSION: &str = "0.2.0";
+
+/// Version of the output schema, bumped whenever columns change
+///
+/// - 1: the original 22 columns, outputs had no version stamp
+/// - 2: adds retries, qualityFlags, collectorsOk, carriedForward and confidence
+/// - 3: adds readBytes, writeBytes, syscr, syscw, fdCount and oomScoreAdj
+/// - 4: adds runDelay, sleepTime and offCpuTime
+/// - 5: adds seq and missed
+/// - 6: adds vmHwm
+pub const SCHEMA_VERSION: u32 = 6;
 
 /// This module is used for file operate.
 /// 
@@ -20,3 +97,347 @@ pub mod file_utils;
 /// It provides various utilities to analyze the process running on a computer,
 /// such as CPU usage, memory consumption and etc.
 pub mod proc_analysis;
+
+/// This module is used for Loki-style log export.
+///
+/// It writes samples and events as structured JSON lines labeled with
+/// device, process and run id.
+pub mod loki;
+
+/// This module is used for trace sessions.
+///
+/// It persists the progress of a trace so a killed tracer can resume it.
+pub mod session;
+
+/// This module is used for sample validation.
+///
+/// It flags or clamps physically impossible values with quality bits.
+pub mod validate;
+
+/// This module is used for trace analysis.
+///
+/// It loads CSV outputs back and summarizes, compares, merges, verifies
+/// and reports them, so it also works on the host against pulled files.
+#[cfg(feature = "analysis")]
+pub mod analysis;
+
+/// This module is used for errors.
+///
+/// It defines `TraceError`, returned instead of panicking.
+pub mod error;
+
+/// This module is used for tracing as a library.
+///
+/// It provides `TraceConfig` and `TraceSession`, which return the collected
+/// samples to the caller instead of writing files.
+pub mod trace;
+
+/// This module is used for ABI detection.
+///
+/// It reads the ELF class and machine of `/proc/<pid>/exe`, so 32-bit
+/// processes traced by a 64-bit tracer are recorded as such.
+pub mod abi;
+
+/// This module is used for output formats.
+///
+/// It defines the `RecordSink` trait and its CSV, JSON lines and Perfetto
+/// writers, which can write at the end of a trace or stream every sample.
+pub mod output;
+
+/// This module is used for SELinux diagnostics.
+///
+/// It reads the contexts of the target and the tracer, and finds the avc
+/// denials behind a collector failing with EACCES.
+pub mod selinux;
+
+/// This module is used for dropping privileges.
+///
+/// It switches a root tracer to a lower uid, optionally keeping only the
+/// capabilities needed to read procfs, for the long sampling phase.
+pub mod privilege;
+
+/// This module is used for pattern matching.
+///
+/// It compiles the small regular expression syntax accepted for target names.
+pub mod pattern;
+
+/// This module is used for process discovery.
+///
+/// It walks procfs and matches comm and command line of every process
+/// by exact name, substring or pattern.
+pub mod discover;
+
+/// This module is used for auditing the side effects of the tracer.
+///
+/// Every external command runs through it, so it can be logged and, in
+/// strict read-only mode, refused when it changes the system.
+pub mod audit;
+
+/// This module is used for sampling statistics.
+///
+/// It keeps histograms of how long each sample took and how late the
+/// tracer woke up for it.
+pub mod sampling;
+
+/// This module is used for statistics of compared runs.
+///
+/// It has the Welch confidence interval and the Mann-Whitney U test
+/// used to tell real changes from device noise.
+#[cfg(feature = "analysis")]
+pub mod stats;
+
+/// This module is used for system wide traces.
+///
+/// It samples every process each interval and passes the ones using the
+/// most CPU or memory to an observer.
+#[cfg(feature = "top")]
+pub mod top;
+
+/// This module is used for resolving targets.
+///
+/// Every target spec scheme, like `uid:` or `package:`, has a resolver and
+/// downstream users can register their own.
+pub mod resolve;
+
+/// This module is used for live analysis.
+///
+/// Monitors publish samples and events to a bus, analyzers subscribe to it
+/// and run on their own threads without slowing the sampling loop.
+pub mod bus;
+
+/// This module is used for threshold alerts.
+///
+/// It reports samples crossing configured limits while a trace runs.
+#[cfg(feature = "alert")]
+pub mod alert;
+
+/// This module is used for power state aware sampling.
+///
+/// It tells whether the screen is on and the device is charging, and holds
+/// the sampling settings traces switch to in each state.
+pub mod power;
+
+/// This module is used for tracking metrics across builds.
+///
+/// Summaries of finished runs are appended to a small text store on the
+/// device, keyed by build, and read back as a history per metric.
+#[cfg(feature = "analysis")]
+pub mod trend;
+
+/// This module is used for watching short-lived processes.
+///
+/// It waits for a target to start, traces it until it exits in a session of
+/// its own and waits for the next occurrence.
+#[cfg(feature = "top")]
+pub mod watch;
+
+/// This module is used for accounting short-lived processes.
+///
+/// It sums the lifetimes, CPU times and peak RSS of every process of a target
+/// by name instead of tracing each one.
+#[cfg(feature = "top")]
+pub mod rollup;
+
+/// This module is used for capturing hotspots.
+///
+/// A CPU alert starts a short simpleperf recording of the process, whose
+/// perf.data path is added to the events of the trace.
+#[cfg(feature = "alert")]
+pub mod simpleperf;
+
+/// This module is used for binder analysis.
+///
+/// It reads the binder transaction log to tell which processes called into
+/// a target and splits the CPU of its binder threads between them.
+#[cfg(feature = "top")]
+pub mod binder;
+
+/// This module is used for launching commands under trace.
+///
+/// A command is spawned held before its exec, so the tracer attaches to its
+/// pid first and no name has to be resolved.
+#[cfg(feature = "run")]
+pub mod launch;
+
+/// This module is used for running a workload in a cgroup of its own.
+///
+/// Limits like memory.max are set before the workload starts and the cgroup
+/// counters then cover exactly the workload.
+#[cfg(feature = "run")]
+pub mod sandbox;
+
+/// This module is used for idle baselines.
+///
+/// The CPU and RSS drift of the targets while nothing runs are measured
+/// before the scenario, so noisy devices can be corrected for.
+#[cfg(feature = "analysis")]
+pub mod baseline;
+
+/// This module is used for the noise floor of a device.
+///
+/// A target is traced in several idle windows and the spread of the window
+/// means tells compare which deltas the device produces on its own.
+#[cfg(feature = "analysis")]
+pub mod noise;
+
+/// This module is used for transforming samples before the outputs.
+///
+/// Rates, scaling, moving averages and clamping are set per column, so
+/// dashboards get clean series without post-processing. Smoothed
+/// companions keep the raw column and add its moving average next to it.
+pub mod transform;
+
+/// This module is used for self tests.
+///
+/// It runs every collector once against one process and tells which work,
+/// which need privileges and whether their values make sense on this kernel.
+pub mod selftest;
+
+/// This module is used for progress reports.
+///
+/// Unattended traces report the time traced, samples and output size on
+/// stderr and the bus, so a stuck session shows before its end.
+pub mod progress;
+
+/// This module is used for storing samples in memory.
+///
+/// Traces which write their outputs at the end keep the samples in
+/// pre-allocated columns instead of one `RecordItem` each, so day long
+/// traces at short intervals cost a fraction of the memory and allocations.
+pub mod arena;
+
+/// This module is used for synthetic load.
+///
+/// A process spins at a set CPU share and ramps up touched memory, so the
+/// numbers of a trace can be checked against ground truth on a new device.
+pub mod loadgen;
+
+/// This module is used for cross checks.
+///
+/// Values the tracer parses are compared against other sources of the same
+/// number, e.g. smaps_rollup against the summed smaps, so a parser which
+/// drifted on a new kernel shows as a discrepancy instead of wrong traces.
+pub mod crosscheck;
+
+/// This module is used for memory triggered samples.
+///
+/// Between the ticks, the memory.events notification of the target's cgroup
+/// and its RSS are watched, so a transient spike gets a sample of its own.
+pub mod memwatch;
+
+/// This module is used for frozen processes.
+///
+/// Ticks of a frozen target repeat the last sample exactly, the freezer state
+/// of its cgroup tells them from an idle process. Frozen periods become
+/// events and the collectors pause while Android keeps a cached app frozen.
+pub mod freezer;
+
+/// This module is used for composite health scores.
+///
+/// A weighted formula over normalized columns, e.g. CPU share, PSS and the
+/// major fault rate, scores every sample from 0 to 100, so runs and builds
+/// are compared by one number.
+#[cfg(feature = "analysis")]
+pub mod score;
+
+/// This module is used for output path templates.
+///
+/// Tokens like `{name}`, `{pid}`, `{date}` and `{tag:build}` in output
+/// directories and session names are expanded once at session start.
+pub mod template;
+
+/// This module is used for stopping traces gracefully.
+///
+/// The first SIGINT or SIGTERM asks the scheduler to stop, every target
+/// records why and flushes its outputs before the summaries are written.
+pub mod shutdown;
+
+/// This module is used for comparing targets traced together.
+///
+/// Over the window every target of a session was traced in, it shows which
+/// process used the most CPU and PSS.
+#[cfg(feature = "analysis")]
+pub mod joint;
+
+/// This module is used for contention between traced processes.
+///
+/// Samples where one target's CPU spikes while the run delay of another one
+/// spikes point at likely contention, e.g. system_server starving an app.
+#[cfg(feature = "analysis")]
+pub mod contention;
+
+/// This module is used for binder priority inheritance.
+///
+/// The priority of the binder threads of a target is read far more often
+/// than it is sampled, so the boost a thread inherits from a caller for one
+/// transaction shows up as events.
+pub mod binderprio;
+
+/// This module is used for long-term soak tests.
+///
+/// Targets are sampled every few minutes for weeks, in one session a day
+/// which is rolled up into a trend store before old days are pruned.
+#[cfg(feature = "analysis")]
+pub mod soak;
+
+/// This module is used for the codes of diagnostic events.
+///
+/// Errors and warnings in the event stream start their detail with a stable
+/// `code=`, so automation can alert on conditions instead of messages.
+pub mod codes;
+
+/// This module is used for splitting CPU time by app state.
+///
+/// Every sample interval counts in the window it ended in: the target in the
+/// top-app cpuset, in another one, or the screen off.
+pub mod foreground;
+
+/// This module is used for the dependencies of a traced process.
+///
+/// At attach the parent chain is walked and, for apps, the services and
+/// providers they bind are read from `dumpsys activity`.
+pub mod deps;
+
+/// This module is used for parsing smaps incrementally.
+///
+/// Kernels without smaps_rollup list thousands of mappings, those whose
+/// header did not change since the last pass keep their Pss.
+pub mod smaps;
+
+/// This module is used for writing output files considerately.
+///
+/// Outputs are opened without access time updates, written in batches and
+/// on low-RAM devices dropped from the page cache once written back.
+pub mod outfile;
+
+/// This module is used for locale independent numbers.
+///
+/// Outputs always use a `.` decimal point with a precision per column, and
+/// traces saved again by spreadsheets with decimal commas still load.
+pub mod numeric;
+
+/// This module is used for /proc mounts with hidepid.
+///
+/// Android mounts /proc so that other users' processes are hidden, discovery
+/// then sticks to the processes of the own uid and errors say why.
+pub mod hidepid;
+
+/// This module is used for the uncertainty of sampled levels.
+///
+/// Memory and other levels are only known at the ticks, the envelope bounds
+/// every interval by its samples and lifts it where VmHWM shows a peak.
+#[cfg(feature = "analysis")]
+pub mod envelope;
+
+/// This module is used for putting traces on a common time base.
+///
+/// Traces recorded with other intervals or ticks are resampled before they
+/// are compared or correlated sample by sample.
+#[cfg(feature = "analysis")]
+pub mod align;
+
+/// This module is used for checksumming finished sessions.
+///
+/// A session which finishes writes the checksums of its files with a
+/// footer, so a copy pulled to a host can be checked to be complete.
+pub mod manifest;
@@ -0,0 +1,145 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{clock_gettime, timespec, CLOCK_PROCESS_CPUTIME_ID};
+use crate::sampling::thread_cpu_time;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+// Busy and idle parts of one duty cycle add up to this
+const DUTY_PERIOD: Duration = Duration::from_millis(100);
+// Memory is allocated and touched in chunks of this size
+const CHUNK_BYTES: usize = 1 << 20;
+// How often the memory ramp is advanced
+const RAMP_STEP: Duration = Duration::from_millis(100);
+
+/// Load a `run_load` generates, its numbers are the ground truth a trace of
+/// the process is compared against
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadPattern {
+    /// busy share of one core per thread in percent, 0 ~ 100
+    pub cpu_percent: f64,
+    /// threads spinning at `cpu_percent`
+    pub threads: usize,
+    /// memory allocated and touched in MB
+    pub memory_mb: u64,
+    /// seconds to ramp the memory up linearly, 0 allocates it at once
+    pub ramp: u64,
+    /// seconds to run, 0 runs until the process is killed
+    pub duration: u64,
+}
+
+impl Default for LoadPattern {
+    fn default() -> Self {
+        LoadPattern {
+            cpu_percent: 50.0,
+            threads: 1,
+            memory_mb: 0,
+            ramp: 0,
+            duration: 60,
+        }
+    }
+}
+
+impl LoadPattern {
+    /// CPU time the threads should use per second, in percent of one core
+    pub fn total_cpu_percent(&self) -> f64 {
+        self.cpu_percent * self.threads as f64
+    }
+
+    /// Memory in kB the pattern holds `elapsed` seconds after the start
+    pub fn memory_kb(&self, elapsed: f64) -> u64 {
+        let share = if self.ramp == 0 { 1.0 } else { (elapsed / self.ramp as f64).min(1.0) };
+        (self.memory_mb as f64 * share) as u64 * 1024
+    }
+}
+
+/// What a running load did so far, reported once a second
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoadStatus {
+    /// seconds since the start
+    pub elapsed: f64,
+    /// CPU seconds of the whole process
+    pub cpu_time: f64,
+    /// CPU time of the last second in percent of one core
+    pub cpu_percent: f64,
+    /// memory allocated and touched in kB
+    pub memory_kb: u64,
+}
+
+impl LoadStatus {
+    /// One `load key=value ...` line next to the target of `pattern`
+    pub fn format(&self, pattern: &LoadPattern) -> String {
+        format!("load elapsed={:.0} cpu_time={:.2} cpu_percent={:.1} cpu_target={:.1} memory_kb={} memory_target_kb={}",
+                self.elapsed, self.cpu_time, self.cpu_percent, pattern.total_cpu_percent(), self.memory_kb,
+                pattern.memory_kb(self.elapsed))
+    }
+}
+
+// CPU seconds of the whole process
+fn process_cpu_time() -> f64 {
+    let mut now = timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY:
+    // Safe because `now` is a valid timespec, on failure it stays 0
+    unsafe { clock_gettime(CLOCK_PROCESS_CPUTIME_ID, &mut now) };
+    now.tv_sec as f64 + now.tv_nsec as f64 / 1e9
+}
+
+// Spin for the busy part of every duty cycle and sleep for the rest. The busy
+// part is CPU time of the thread, so threads sharing a core still reach it
+fn spin(cpu_percent: f64, stop: &AtomicBool) {
+    let busy = DUTY_PERIOD.mul_f64(cpu_percent.clamp(0.0, 100.0) / 100.0);
+    let mut cycle = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        let until = thread_cpu_time() + busy;
+        while thread_cpu_time() < until && cycle.elapsed() < DUTY_PERIOD {
+            std::hint::spin_loop();
+        }
+        cycle += DUTY_PERIOD;
+        sleep(cycle.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Generate `pattern` in this process and call `report` once a second, blocks
+/// until the duration is over
+pub fn run_load(pattern: &LoadPattern, mut report: impl FnMut(&LoadStatus)) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let spinners: Vec<_> = (0..pattern.threads).map(|i| {
+        let stop = stop.clone();
+        let cpu_percent = pattern.cpu_percent;
+        thread::Builder::new().name(format!("load-{}", i))
+                .spawn(move || spin(cpu_percent, &stop))
+    }).filter_map(Result::ok).collect();
+    let start = Instant::now();
+    let mut chunks: Vec<Vec<u8>> = Vec::new();
+    let mut last = (0.0, process_cpu_time());
+    let mut next_report = Duration::from_secs(1);
+    while pattern.duration == 0 || start.elapsed() < Duration::from_secs(pattern.duration) {
+        // Non zero bytes, so every page is touched and counts to the RSS
+        let chunks_wanted = (pattern.memory_kb(start.elapsed().as_secs_f64()) as usize * 1024) / CHUNK_BYTES;
+        while chunks.len() < chunks_wanted {
+            chunks.push(vec![1; CHUNK_BYTES]);
+        }
+        if start.elapsed() >= next_report {
+            let (elapsed, cpu_time) = (start.elapsed().as_secs_f64(), process_cpu_time());
+            report(&LoadStatus {
+                elapsed,
+                cpu_time,
+                cpu_percent: (cpu_time - last.1) / (elapsed - last.0) * 100.0,
+                memory_kb: (chunks.len() * CHUNK_BYTES / 1024) as u64,
+            });
+            last = (elapsed, cpu_time);
+            next_report += Duration::from_secs(1);
+        }
+        sleep(RAMP_STEP);
+    }
+    stop.store(true, Ordering::Relaxed);
+    for spinner in spinners {
+        let _ = spinner.join();
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+#[cfg(feature = "json")]
+use crate::{SCHEMA_VERSION, VERSION};
+#[cfg(feature = "json")]
+use crate::abi::ProcessAbi;
+use crate::audit::{self, Effect};
+#[cfg(feature = "json")]
+use crate::codes::detail_level;
+use crate::file_utils::read_path;
+#[cfg(feature = "json")]
+use crate::proc_analysis::{format_birth, RecordItem, RecordProcess, ThreadItem};
+#[cfg(feature = "json")]
+use crate::selinux::tracer_context;
+#[cfg(feature = "json")]
+use std::io::{self, Write};
+use std::process::Command;
+use std::str::from_utf8;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Device identity sources, the property is preferred on Android
+const DEVICE_SERIAL_PROPERTY: &str = "ro.serialno";
+const HOSTNAME_PATH: &str = "/proc/sys/kernel/hostname";
+
+/// Labels attached to every Loki log line
+#[derive(Default, Clone)]
+pub struct LokiLabels {
+    /// device serial or hostname
+    pub device: String,
+    /// traced process name
+    pub process: String,
+    /// unique id of this trace run
+    pub run_id: String,
+}
+
+impl LokiLabels {
+    /// Build labels for a process, device and run id are detected automatically
+    pub fn new(process_name: &str) -> LokiLabels {
+        LokiLabels::with_run_id(process_name, &new_run_id())
+    }
+
+    /// Build labels for a process which belongs to an existing run
+    pub fn with_run_id(process_name: &str, run_id: &str) -> LokiLabels {
+        LokiLabels {
+            device: get_device_name(),
+            process: process_name.to_string(),
+            run_id: run_id.to_string(),
+        }
+    }
+
+    // Tags are extra labels, a tag named like a label replaces it
+    #[cfg(feature = "json")]
+    fn to_json(&self, tags: &[(String, String)]) -> String {
+        let tag = |name: &str| tags.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        let mut labels = vec![
+            ("device", tag("device").unwrap_or(&self.device)),
+            ("process", tag("process").unwrap_or(&self.process)),
+            ("run_id", tag("run_id").unwrap_or(&self.run_id)),
+        ];
+        labels.extend(tags.iter()
+                .filter(|(key, _)| !matches!(key.as_str(), "device" | "process" | "run_id"))
+                .map(|(key, value)| (key.as_str(), value.as_str())));
+        let fields: Vec<String> = labels.iter()
+                .map(|(key, value)| format!("\"{}\":\"{}\"", key, escape_json(value)))
+                .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// Get the name used as `device` label
+pub fn get_device_name() -> String {
+    if let Ok(output) = audit::run(Command::new("getprop").arg(DEVICE_SERIAL_PROPERTY), Effect::ReadOnly) {
+        if output.status.success() {
+            let serial = from_utf8(&output.stdout).unwrap_or("").trim().to_string();
+            if !serial.is_empty() {
+                return serial;
+            }
+        }
+    }
+    match read_path(HOSTNAME_PATH) {
+        Ok(name) if !name.trim().is_empty() => name.trim().to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// Generate a run id from the wall clock and the tracer pid, unique per invocation
+pub fn new_run_id() -> String {
+    // Later runs of one process get a counter, they may start in the same second
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    match RUNS.fetch_add(1, Ordering::Relaxed) {
+        0 => format!("{}-{}", now.as_secs(), std::process::id()),
+        run => format!("{}-{}-{}", now.as_secs(), std::process::id(), run),
+    }
+}
+
+/// Escape a string to be embedded in a JSON string literal
+pub fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// JSON has no NaN or infinity
+#[cfg(feature = "json")]
+pub(crate) fn json_number(v: f64) -> String {
+    if v.is_finite() { format!("{:.6}", v) } else { "null".to_string() }
+}
+
+// Loki wants timestamps as nanosecond epoch strings
+#[cfg(feature = "json")]
+pub(crate) fn epoch_ns(epoch_secs: u64, offset_secs: i64) -> String {
+    format!("{}", (epoch_secs as i64 + offset_secs) as i128 * 1_000_000_000)
+}
+
+/// Detail of the `trace_start` event, it carries the version stamp, the comm,
+/// birth and binary of the process, the ABIs, the SELinux contexts and the
+/// dependencies of the process
+#[cfg(feature = "json")]
+pub(crate) fn start_detail(record: &RecordProcess) -> String {
+    let context = |context: &str| if context.is_empty() { "unknown".to_string() } else { context.to_string() };
+    let exe = if record.exe.is_empty() { "unknown" } else { &record.exe };
+    format!("pid={} comm={} birth={} exe={} abi={} tracer_abi={} selinux={} tracer_selinux={} version={} schema={} {}",
+            record.pid, record.comm.replace(' ', "_"), format_birth(record.birth), exe, record.abi.name(),
+            ProcessAbi::current().name(),
+            context(&record.selinux_context), context(&tracer_context().unwrap_or_default()),
+            VERSION, SCHEMA_VERSION, record.dependencies.detail())
+}
+
+/// Write one event line
+#[cfg(feature = "json")]
+pub(crate) fn write_event(out: &mut impl Write, labels: &LokiLabels, tags: &[(String, String)],
+        ts: &str, event: &str, detail: &str) -> io::Result<()> {
+    writeln!(out, "{{\"ts\":\"{}\",\"level\":\"{}\",\"labels\":{},\"kind\":\"event\",\"event\":\"{}\",\"detail\":\"{}\"}}",
+            ts, detail_level(detail), labels.to_json(tags), escape_json(event), escape_json(detail))
+}
+
+/// Columns which rarely change, compact outputs only write them at change points
+#[cfg(feature = "json")]
+pub const RLE_COLUMNS: &[&str] = &[
+    "priority", "nice", "numThreads", "startTime", "fdCount", "oomScoreAdj", "cgroup", "cpuset",
+];
+
+/// Write one sample line of `record`
+///
+/// With the `previous` sample of a compact output the columns of
+/// `RLE_COLUMNS` are left out when they did not change, a missing column
+/// keeps its last value.
+#[cfg(feature = "json")]
+pub(crate) fn write_sample(out: &mut impl Write, labels: &LokiLabels, record: &RecordProcess,
+        item: &RecordItem, previous: Option<&RecordItem>) -> io::Result<()> {
+    writeln!(out, "{{\"ts\":\"{}\",\"level\":\"info\",\"labels\":{},\"kind\":\"sample\",\"pid\":{},\
+            \"time\":{},\"pss\":{},\"vmRss\":{},\"vmAnon\":{},\"vmFile\":{},\"vmShmem\":{},\"vmSwap\":{},\
+            \"voluntaryCtxtSwitches\":{},\"nonvoluntaryCtxtSwitches\":{},\"minflt\":{},\"majflt\":{},\
+            \"utime\":{:.3},\"stime\":{:.3},\"totalcputime\":{:.3},\"gutime\":{:.3},\"gstime\":{:.3},\
+            \"gtotalcputime\":{:.3},\"cpuOccupancyRate\":{},\"retries\":{},\"qualityFlags\":{},\
+            \"collectorsOk\":{},\"carriedForward\":{},\"confidence\":{:.2},\"readBytes\":{},\
+            \"writeBytes\":{},\"syscr\":{},\"syscw\":{},\
+            \"runDelay\":{:.3},\"sleepTime\":{:.3},\"offCpuTime\":{:.3},\"seq\":{},\"missed\":{},\"vmHwm\":{}{}{}}}",
+            epoch_ns(record.start_epoch, item.timestamp), labels.to_json(&record.tags), record.pid,
+            item.timestamp, item.pss, item.vm_rss, item.vm_anon, item.vm_file, item.vm_shmem,
+            item.vm_swap, item.voluntary_ctxt_switches, item.nonvoluntary_ctxt_switches,
+            item.minflt, item.majflt, item.utime, item.stime, item.totalcputime, item.global_utime,
+            item.global_stime, item.global_total_cpu_time, json_number(item.cpu_occupancy_rate),
+            item.retries, item.quality_flags, item.collectors_ok, item.carried_forward, item.confidence,
+            item.read_bytes, item.write_bytes, item.syscr, item.syscw,
+            item.run_delay, item.sleep_time, item.off_cpu_time, item.seq, item.missed, item.vm_hwm,
+            rle_fields(item, previous),
+            smoothed_fields(record, item))
+}
+
+// `,"<column>":<value>` for the columns of `RLE_COLUMNS` which changed since `previous`
+#[cfg(feature = "json")]
+fn rle_fields(item: &RecordItem, previous: Option<&RecordItem>) -> String {
+    let fields = [
+        (item.priority.to_string(), previous.map(|p| p.priority.to_string())),
+        (item.nice.to_string(), previous.map(|p| p.nice.to_string())),
+        (item.num_threads.to_string(), previous.map(|p| p.num_threads.to_string())),
+        (item.start_time.to_string(), previous.map(|p| p.start_time.to_string())),
+        (item.fd_count.to_string(), previous.map(|p| p.fd_count.to_string())),
+        (item.oom_score_adj.to_string(), previous.map(|p| p.oom_score_adj.to_string())),
+        (format!("\"{}\"", escape_json(&item.cgroup)), previous.map(|p| format!("\"{}\"", escape_json(&p.cgroup)))),
+        (format!("\"{}\"", escape_json(&item.cpuset)), previous.map(|p| format!("\"{}\"", escape_json(&p.cpuset)))),
+    ];
+    RLE_COLUMNS.iter().zip(fields)
+            .filter(|(_, (value, previous))| previous.as_ref() != Some(value))
+            .map(|(column, (value, _))| format!(",\"{}\":{}", column, value))
+            .collect()
+}
+
+// `,"<column>":<value>` for every smoothed companion value
+#[cfg(feature = "json")]
+fn smoothed_fields(record: &RecordProcess, item: &RecordItem) -> String {
+    record.smoothed_columns.iter().zip(&item.smoothed)
+            .map(|(column, value)| format!(",\"{}\":{}", column, json_number(*value)))
+            .collect()
+}
+
+/// Write one thread sample line
+#[cfg(feature = "json")]
+pub(crate) fn write_thread_sample(out: &mut impl Write, labels: &LokiLabels, record: &RecordProcess,
+        thread: &ThreadItem) -> io::Result<()> {
+    let item = &thread.item;
+    writeln!(out, "{{\"ts\":\"{}\",\"level\":\"info\",\"labels\":{},\"kind\":\"thread_sample\",\"pid\":{},\
+            \"tid\":{},\"name\":\"{}\",\"time\":{},\"utime\":{:.3},\"stime\":{:.3},\"totalcputime\":{:.3},\
+            \"cpuOccupancyRate\":{},\"minflt\":{},\"majflt\":{},\"voluntaryCtxtSwitches\":{},\
+            \"nonvoluntaryCtxtSwitches\":{},\"priority\":{},\"nice\":{},\"qualityFlags\":{},\
+            \"runDelay\":{:.3},\"sleepTime\":{:.3}}}",
+            epoch_ns(record.start_epoch, item.timestamp), labels.to_json(&record.tags), record.pid, thread.tid,
+            escape_json(&thread.name), item.timestamp, item.utime, item.stime, item.totalcputime,
+            json_number(item.cpu_occupancy_rate), item.minflt, item.majflt,
+            item.voluntary_ctxt_switches, item.nonvoluntary_ctxt_switches, item.priority, item.nice,
+            item.quality_flags, item.run_delay, item.sleep_time)
+}
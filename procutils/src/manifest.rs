@@ -0,0 +1,108 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::session::SESSION_LOCK_FILE;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Checksums of the files of a finished session, inside of its directory
+pub const MANIFEST_FILE: &str = "session.sum";
+
+// Start of the last line, which checksums the lines before it
+const FOOTER_PREFIX: &str = "# footer";
+
+// FNV-1a, 64 bit
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// FNV-1a checksum and size of a file
+pub fn file_checksum(path: &Path) -> io::Result<(u64, u64)> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let (mut hash, mut size) = (FNV_OFFSET, 0u64);
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok((hash, size));
+        }
+        hash = fnv1a(hash, &buffer[..read]);
+        size += read as u64;
+    }
+}
+
+// Files under `dir` as paths relative to `root`, the manifest and the lock left out
+fn session_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            session_files(root, &path, files)?;
+            continue;
+        }
+        let name = path.strip_prefix(root).map_err(io::Error::other)?.to_string_lossy().to_string();
+        if name != MANIFEST_FILE && name != SESSION_LOCK_FILE {
+            files.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Write the manifest of a session directory: `<checksum> <size> <file>`
+/// for every file, then a footer with the count and the checksum of those
+/// lines. A manifest whose footer is missing or wrong is incomplete
+pub fn write_manifest(dir: &str) -> io::Result<usize> {
+    let root = Path::new(dir);
+    let mut files = Vec::new();
+    session_files(root, root, &mut files)?;
+    files.sort();
+    let mut content = String::new();
+    for name in &files {
+        let (hash, size) = file_checksum(&root.join(name))?;
+        let _ = writeln!(content, "{:016x} {} {}", hash, size, name);
+    }
+    let _ = writeln!(content, "{} files={} fnv1a64={:016x}", FOOTER_PREFIX, files.len(),
+            fnv1a(FNV_OFFSET, content.as_bytes()));
+    fs::write(root.join(MANIFEST_FILE), content)?;
+    Ok(files.len())
+}
+
+/// Check a session directory against its manifest, returns the files
+/// checked or the problems found
+pub fn verify_manifest(dir: &str) -> io::Result<Result<usize, Vec<String>>> {
+    let root = Path::new(dir);
+    let content = fs::read_to_string(root.join(MANIFEST_FILE))?;
+    let Some(footer_at) = content.rfind(FOOTER_PREFIX) else {
+        return Ok(Err(vec![format!("{} has no footer", MANIFEST_FILE)]));
+    };
+    let (lines, footer) = content.split_at(footer_at);
+    let expected = format!("{} files={} fnv1a64={:016x}", FOOTER_PREFIX, lines.lines().count(),
+            fnv1a(FNV_OFFSET, lines.as_bytes()));
+    if footer.trim_end() != expected {
+        return Ok(Err(vec![format!("{} footer does not match its lines, it is truncated or changed", MANIFEST_FILE)]));
+    }
+    let mut problems = Vec::new();
+    for line in lines.lines() {
+        let mut fields = line.splitn(3, ' ');
+        let hash = fields.next().and_then(|hash| u64::from_str_radix(hash, 16).ok());
+        let size = fields.next().and_then(|size| size.parse::<u64>().ok());
+        let (Some(hash), Some(size), Some(name)) = (hash, size, fields.next()) else {
+            problems.push(format!("broken line {:?}", line));
+            continue;
+        };
+        match file_checksum(&root.join(name)) {
+            Ok(actual) if actual == (hash, size) => {},
+            Ok((_, actual_size)) => problems.push(format!("{}: {} bytes, checksum differs from the manifest ({} bytes)",
+                    name, actual_size, size)),
+            Err(e) => problems.push(format!("{}: {}", name, e)),
+        }
+    }
+    Ok(if problems.is_empty() { Ok(lines.lines().count()) } else { Err(problems) })
+}
@@ -0,0 +1,93 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{pid_t, poll, pollfd, sysconf, POLLPRI, _SC_PAGESIZE};
+use crate::file_utils::read_node;
+use crate::freezer::cgroup_v2_file;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+// Only non-root cgroup v2 groups have it
+const MEMORY_EVENTS_FILE: &str = "memory.events";
+
+/// Watch on the memory of one process between the ticks
+///
+/// The `memory.events` file of its cgroup v2 group notifies when the group
+/// hits memory.high or memory.max, without it only the RSS is checked.
+#[derive(Debug)]
+pub struct MemoryWatch {
+    /// process watched
+    pub pid: pid_t,
+    // memory.events of the group and its last content
+    events: Option<(File, String)>,
+}
+
+impl MemoryWatch {
+    /// Watch `pid`, the notification is used where its group has one
+    pub fn open(pid: pid_t) -> MemoryWatch {
+        let events = cgroup_v2_file(pid, MEMORY_EVENTS_FILE).and_then(|path| File::open(path).ok()).map(|mut file| {
+            let content = read_events(&mut file).unwrap_or_default();
+            (file, content)
+        });
+        MemoryWatch { pid, events }
+    }
+
+    /// Whether the group notifies, otherwise only the RSS is checked
+    pub fn notifies(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// The counters of memory.events which changed since the last call, as
+    /// `key=value` pairs, None when nothing changed. Reading rearms the notification
+    pub fn changed_events(&mut self) -> Option<String> {
+        let (file, last) = self.events.as_mut()?;
+        let content = read_events(file).ok()?;
+        let changed: Vec<String> = content.lines()
+                .filter(|line| !last.lines().any(|old| old == *line))
+                .map(|line| line.replacen(' ', "=", 1))
+                .collect();
+        *last = content;
+        (!changed.is_empty()).then(|| changed.join(" "))
+    }
+}
+
+fn read_events(file: &mut File) -> std::io::Result<String> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Resident memory of `pid` in kB from /proc/<pid>/statm, cheaper than status
+pub fn statm_rss_kb(pid: pid_t) -> Option<isize> {
+    let content = read_node(&format!(crate::TASK_STATM_TEMPLATE!(), pid)).ok()?;
+    let pages: isize = content.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY:
+    // Safe because sysconf has no side effects
+    Some(pages * unsafe { sysconf(_SC_PAGESIZE) } as isize / 1024)
+}
+
+/// Wait up to `timeout` for a notification of any of `watches`, true for the
+/// ones which notified
+pub fn wait_events(watches: &[Option<&MemoryWatch>], timeout: Duration) -> Vec<bool> {
+    let mut fds: Vec<pollfd> = watches.iter()
+            .map(|watch| pollfd {
+                fd: watch.and_then(|watch| watch.events.as_ref()).map(|(file, _)| file.as_raw_fd()).unwrap_or(-1),
+                events: POLLPRI,
+                revents: 0,
+            })
+            .collect();
+    // SAFETY:
+    // Safe because `fds` holds `fds.len()` valid pollfds, negative fds are ignored
+    let ready = unsafe { poll(fds.as_mut_ptr(), fds.len() as _, timeout.as_millis() as i32) };
+    if ready < 0 {
+        // Interrupted, the caller waits again with the time left
+        return vec![false; watches.len()];
+    }
+    fds.iter().map(|fd| fd.revents & POLLPRI != 0).collect()
+}
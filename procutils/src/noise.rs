@@ -0,0 +1,173 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use crate::analysis::{GroupComparison, TraceData};
+use crate::error::TraceError;
+use crate::file_utils::read_path;
+use crate::output::OutputFormat;
+use crate::proc_analysis::{trace_session_with_options, MonitorOptions, TraceTarget};
+use crate::session::SessionState;
+use crate::stats::{mean, variance};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+/// First line of a noise store
+pub const NOISE_STORE_STAMP: &str = "# process_trace noise 1";
+
+/// Idle windows of a calibration when none are asked for
+pub const DEFAULT_NOISE_WINDOWS: usize = 5;
+
+/// Seconds of an idle window when none are asked for
+pub const DEFAULT_NOISE_WINDOW: i64 = 10;
+
+/// Deltas up to this many standard deviations of the window means are noise
+pub const NOISE_FACTOR: f64 = 2.0;
+
+// Columns which differ between windows by design
+const NOISE_IGNORED_COLUMNS: &[&str] = &["time", "seq", "startTime"];
+
+/// Declare an string
+#[macro_export]
+macro_rules! NOISE_STORE_TEMPLATE { () => { "{}/noise_{}.csv" }; }
+
+/// Spread of one column of one target on one device while it idles
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NoiseFloor {
+    /// target label
+    pub target: String,
+    /// column name
+    pub column: String,
+    /// idle windows measured
+    pub windows: usize,
+    /// mean of the window means
+    pub mean: f64,
+    /// standard deviation of the window means
+    pub stddev: f64,
+}
+
+// Commas and new lines separate the fields of the store
+fn store_safe(value: &str) -> String {
+    value.replace([',', '\n', '\r'], "_")
+}
+
+/// Path of the noise store of a device in `dir`
+pub fn noise_store_path(dir: &str, device: &str) -> String {
+    format!(NOISE_STORE_TEMPLATE!(), dir, store_safe(device).replace('/', "_"))
+}
+
+/// Trace `target` in `windows` sessions of `seconds` each below `output_dir`,
+/// nothing may run the scenario meanwhile. Returns the csv output of every window
+pub fn measure_windows(target: &TraceTarget, output_dir: &str, windows: usize, seconds: i64, interval: i64,
+        options: MonitorOptions) -> Result<Vec<TraceData>, TraceError> {
+    let spec = target.spec();
+    let mut traces = Vec::with_capacity(windows);
+    for window in 1..=windows {
+        // Sessions are named by second, one directory per window keeps them apart
+        let dir = format!("{}/window_{}", output_dir, window);
+        fs::create_dir_all(&dir)?;
+        let state = SessionState::create(&dir, seconds, interval, &[spec.as_str()], &[OutputFormat::Csv])?;
+        let path = format!("{}/{}", state.dir, OutputFormat::Csv.file_name(&target.label()));
+        println!("idle window {}/{} of {}s", window, windows, seconds);
+        trace_session_with_options(state, options)?;
+        traces.push(TraceData::load_csv(&path)?);
+    }
+    Ok(traces)
+}
+
+/// Noise floor of every column from the idle windows of a target, every
+/// window counts as one observation, its mean
+pub fn noise_floor(target: &str, windows: &[TraceData]) -> Vec<NoiseFloor> {
+    let Some(first) = windows.first() else { return Vec::new() };
+    first.columns.iter()
+            .filter(|name| !NOISE_IGNORED_COLUMNS.contains(&name.as_str()))
+            .filter_map(|name| {
+                let means: Vec<f64> = windows.iter()
+                        .filter_map(|window| window.column_index(name))
+                        .zip(windows)
+                        .map(|(index, window)| {
+                            let values: Vec<f64> = window.column(index).into_iter().filter(|v| v.is_finite()).collect();
+                            mean(&values)
+                        })
+                        .filter(|mean| mean.is_finite())
+                        .collect();
+                let stddev = variance(&means).sqrt();
+                stddev.is_finite().then(|| NoiseFloor {
+                    target: target.to_string(),
+                    column: name.clone(),
+                    windows: means.len(),
+                    mean: mean(&means),
+                    stddev,
+                })
+            })
+            .collect()
+}
+
+/// Read a noise store
+pub fn load_noise_store(path: &str) -> io::Result<Vec<NoiseFloor>> {
+    let content = read_path(path)?;
+    if !content.starts_with(NOISE_STORE_STAMP) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a noise store", path)));
+    }
+    Ok(content.lines().skip(1).filter_map(|line| {
+        let fields: Vec<&str> = line.split(',').collect();
+        match fields.as_slice() {
+            [target, column, windows, mean, stddev] => Some(NoiseFloor {
+                target: target.to_string(),
+                column: column.to_string(),
+                windows: windows.parse().ok()?,
+                mean: mean.parse().ok()?,
+                stddev: stddev.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }).collect())
+}
+
+/// Store the floors of a target, replacing the ones it had, the floors of
+/// other targets are kept
+pub fn save_noise_floor(path: &str, floors: &[NoiseFloor]) -> io::Result<()> {
+    let mut kept = match load_noise_store(path) {
+        Ok(kept) => kept,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    kept.retain(|floor| !floors.iter().any(|new| new.target == floor.target));
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{}", NOISE_STORE_STAMP)?;
+    for floor in kept.iter().chain(floors) {
+        writeln!(out, "{},{},{},{},{}", store_safe(&floor.target), floor.column, floor.windows, floor.mean, floor.stddev)?;
+    }
+    out.flush()
+}
+
+/// Target of a store a csv output belongs to, by its file name. A store of a
+/// single target applies to any trace
+pub fn noise_target(floors: &[NoiseFloor], csv_path: &str) -> Option<String> {
+    let file_name = csv_path.rsplit('/').next().unwrap_or(csv_path);
+    let mut targets: Vec<&str> = floors.iter().map(|floor| floor.target.as_str()).collect();
+    targets.dedup();
+    targets.iter()
+            .find(|target| OutputFormat::Csv.file_name(target) == file_name)
+            .or(match targets.as_slice() {
+                [only] => Some(only),
+                _ => None,
+            })
+            .map(|target| target.to_string())
+}
+
+/// Set the noise of the compared columns from the floors of `target`,
+/// returns the columns which have one
+pub fn apply_noise_floor(comparison: &mut GroupComparison, floors: &[NoiseFloor], target: &str) -> usize {
+    let mut applied = 0;
+    for column in comparison.columns.iter_mut() {
+        if let Some(floor) = floors.iter().find(|floor| floor.target == target && floor.column == column.name) {
+            column.noise = floor.stddev;
+            applied += 1;
+        }
+    }
+    applied
+}
@@ -0,0 +1,117 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use std::sync::Mutex;
+
+/// Most digits a column can be written with after the decimal point
+pub const MAX_PRECISION: usize = 9;
+
+/// Digits after the decimal point of one output column
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnPrecision {
+    /// CSV column, e.g. `cpuOccupancyRate`
+    pub column: String,
+    /// digits after the decimal point, at most `MAX_PRECISION`
+    pub digits: usize,
+}
+
+impl ColumnPrecision {
+    /// Parse `<column>=<digits>`, e.g. `cpuOccupancyRate=6`
+    pub fn parse(spec: &str) -> Option<ColumnPrecision> {
+        let (column, digits) = spec.split_once('=')?;
+        let column = column.trim();
+        let digits = digits.trim().parse::<usize>().ok().filter(|digits| *digits <= MAX_PRECISION)?;
+        if column.is_empty() {
+            return None;
+        }
+        Some(ColumnPrecision { column: column.to_string(), digits })
+    }
+
+    /// Spec accepted by `parse`
+    pub fn spec(&self) -> String {
+        format!("{}={}", self.column, self.digits)
+    }
+}
+
+// Precision of the columns written from now on, the others keep their default
+static PRECISIONS: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+
+/// Set the precision of columns written from now on, a column set twice
+/// takes the last one
+pub fn set_precisions(precisions: &[ColumnPrecision]) {
+    let mut set = PRECISIONS.lock().unwrap();
+    for precision in precisions {
+        set.retain(|(column, _)| *column != precision.column);
+        set.push((precision.column.clone(), precision.digits));
+    }
+}
+
+// Precision set for a column
+fn precision(column: &str) -> Option<usize> {
+    PRECISIONS.lock().unwrap().iter()
+            .find(|(name, _)| name == column)
+            .map(|(_, digits)| *digits)
+}
+
+/// Format a value of `column` with its precision, `default` digits unless
+/// set. Always a `.` decimal point and no grouping, whatever the locale
+pub fn fixed(column: &str, value: f64, default: usize) -> String {
+    format!("{:.*}", precision(column).unwrap_or(default), value)
+}
+
+/// Format a value of `column` with its precision, the shortest form which
+/// reads back the same value unless set
+pub fn plain(column: &str, value: f64) -> String {
+    match precision(column) {
+        Some(digits) => format!("{:.*}", digits, value),
+        None => format!("{}", value),
+    }
+}
+
+/// Parse a number the way the C locale does: `.` decimal point, no
+/// grouping, optional sign and exponent
+pub fn parse_number(text: &str) -> Option<f64> {
+    text.trim().parse::<f64>().ok()
+}
+
+/// Field and decimal separators of a CSV file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvDialect {
+    /// `,` between fields and `.` decimal points, as the tracer writes
+    Standard,
+    /// `;` between fields and `,` decimal points, as spreadsheets in such
+    /// locales save a trace again
+    DecimalComma,
+}
+
+impl CsvDialect {
+    /// Dialect of a file by its header line
+    pub fn detect(header: &str) -> CsvDialect {
+        match !header.contains(',') && header.contains(';') {
+            true => CsvDialect::DecimalComma,
+            false => CsvDialect::Standard,
+        }
+    }
+
+    /// Trimmed fields of a line
+    pub fn split(self, line: &str) -> Vec<&str> {
+        let delimiter = match self {
+            CsvDialect::Standard => ',',
+            CsvDialect::DecimalComma => ';',
+        };
+        line.split(delimiter).map(|field| field.trim()).collect()
+    }
+
+    /// Parse a numeric field, see `parse_number`. Values with a grouping
+    /// separator do not parse in either dialect
+    pub fn number(self, field: &str) -> Option<f64> {
+        match self {
+            CsvDialect::Standard => parse_number(field),
+            CsvDialect::DecimalComma if field.contains('.') => None,
+            CsvDialect::DecimalComma => parse_number(&field.replacen(',', ".", 1)),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{posix_fadvise, sync_file_range, EPERM, O_NOATIME, POSIX_FADV_DONTNEED, POSIX_FADV_NOREUSE,
+        SYNC_FILE_RANGE_WAIT_AFTER, SYNC_FILE_RANGE_WAIT_BEFORE, SYNC_FILE_RANGE_WRITE};
+use crate::validate::get_mem_total;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+
+/// Bytes buffered before an output is written, when none is configured
+pub const DEFAULT_FLUSH_BYTES: usize = 64 * 1024;
+
+/// Names accepted by `CacheMode::parse`
+pub const CACHE_MODES: [&str; 3] = ["auto", "keep", "drop"];
+
+// Devices with at most this much memory drop the written pages in auto mode, in kB
+const LOW_RAM_KB: isize = 2 * 1024 * 1024;
+
+/// What happens to the pages of the outputs once they are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// drop them on low-RAM devices, keep them elsewhere
+    #[default]
+    Auto,
+    /// leave them in the page cache
+    Keep,
+    /// write them back and drop them, so the tracer does not evict the pages of the workload
+    Drop,
+}
+
+impl CacheMode {
+    /// Parse a mode name, see `CACHE_MODES`
+    pub fn parse(name: &str) -> Option<CacheMode> {
+        match name {
+            "auto" => Some(CacheMode::Auto),
+            "keep" => Some(CacheMode::Keep),
+            "drop" => Some(CacheMode::Drop),
+            _ => None,
+        }
+    }
+
+    /// Whether written pages are dropped on this device
+    pub fn drops(&self) -> bool {
+        match self {
+            CacheMode::Auto => (1..=LOW_RAM_KB).contains(&get_mem_total()),
+            CacheMode::Keep => false,
+            CacheMode::Drop => true,
+        }
+    }
+}
+
+/// How the outputs of a trace are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// bytes buffered before they are written, and written between two drops
+    /// of the page cache
+    pub flush_bytes: usize,
+    /// page cache use of the written pages
+    pub cache: CacheMode,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions { flush_bytes: DEFAULT_FLUSH_BYTES, cache: CacheMode::Auto }
+    }
+}
+
+// Options of the outputs opened from now on, resolved once for the process
+static WRITE_OPTIONS: Mutex<Option<(usize, bool)>> = Mutex::new(None);
+
+/// Set how the outputs opened from now on are written
+pub fn set_write_options(options: WriteOptions) {
+    *WRITE_OPTIONS.lock().unwrap() = Some((options.flush_bytes.max(1), options.cache.drops()));
+}
+
+// Flush size and whether pages are dropped, the defaults until set
+fn write_options() -> (usize, bool) {
+    *WRITE_OPTIONS.lock().unwrap().get_or_insert_with(|| {
+        let options = WriteOptions::default();
+        (options.flush_bytes, options.cache.drops())
+    })
+}
+
+/// Open an output with `options`, without access time updates where the
+/// tracer owns the file and with the hint that its pages are not reused
+pub fn open_output(options: &mut OpenOptions, path: &str) -> io::Result<File> {
+    let file = match options.custom_flags(O_NOATIME).open(path) {
+        // Only the owner may ask for O_NOATIME
+        Err(e) if e.raw_os_error() == Some(EPERM) => options.custom_flags(0).open(path)?,
+        result => result?,
+    };
+    // SAFETY:
+    // Safe because the fd is open for the call, the hint may be ignored
+    unsafe { posix_fadvise(file.as_raw_fd(), 0, 0, POSIX_FADV_NOREUSE) };
+    Ok(file)
+}
+
+/// Buffered writer of an output file. Writes are batched up to the flush
+/// size and, when pages are dropped, every flush size written is written
+/// back and dropped from the page cache
+pub struct OutputWriter {
+    file: File,
+    buffer: Vec<u8>,
+    flush_bytes: usize,
+    drop_cache: bool,
+    // offset in the file after the written bytes, the buffer follows
+    written: u64,
+    // pages before this offset are dropped
+    dropped: u64,
+}
+
+impl OutputWriter {
+    /// Write to `file` from `offset` on, with the options set last
+    pub fn new(file: File, offset: u64) -> OutputWriter {
+        let (flush_bytes, drop_cache) = write_options();
+        OutputWriter { file, buffer: Vec::with_capacity(flush_bytes), flush_bytes, drop_cache,
+                written: offset, dropped: offset }
+    }
+
+    /// Get the file
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.file.write_all(&self.buffer)?;
+            self.written += self.buffer.len() as u64;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    // Write back and drop the pages written since the last drop, once there are
+    // enough of them or when `all` of them go
+    fn release(&mut self, all: bool) {
+        let length = self.written - self.dropped;
+        if !self.drop_cache || length == 0 || (!all && length < self.flush_bytes as u64) {
+            return;
+        }
+        let fd = self.file.as_raw_fd();
+        // SAFETY:
+        // Safe because the fd is open for both calls, they only act on its pages
+        unsafe {
+            // Dirty pages are not dropped, write them back first
+            sync_file_range(fd, self.dropped as i64, length as i64,
+                    SYNC_FILE_RANGE_WAIT_BEFORE | SYNC_FILE_RANGE_WRITE | SYNC_FILE_RANGE_WAIT_AFTER);
+            posix_fadvise(fd, self.dropped as i64, length as i64, POSIX_FADV_DONTNEED);
+        }
+        self.dropped = self.written;
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() + buf.len() > self.flush_bytes {
+            self.write_buffer()?;
+            self.release(false);
+        }
+        if buf.len() >= self.flush_bytes {
+            self.file.write_all(buf)?;
+            self.written += buf.len() as u64;
+            self.release(false);
+        } else {
+            self.buffer.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.file.flush()?;
+        self.release(false);
+        Ok(())
+    }
+}
+
+impl Drop for OutputWriter {
+    fn drop(&mut self) {
+        if self.write_buffer().is_ok() {
+            self.release(true);
+        }
+    }
+}
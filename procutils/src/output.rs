@@ -0,0 +1,478 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::error::TraceError;
+use crate::loki::{new_run_id, LokiLabels};
+#[cfg(feature = "json")]
+use crate::loki::{epoch_ns, escape_json, json_number, start_detail, write_event, write_sample, write_thread_sample,
+        RLE_COLUMNS};
+use crate::numeric::{fixed, plain};
+use crate::outfile::{open_output, OutputWriter};
+use crate::proc_analysis::{write_csv_header, write_csv_row, RecordItem, RecordProcess, ThreadItem};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+macro_rules! CSV_OUTPUT_FILE_TEMPLATE { () => { "resource_trace_{}.csv" }; }
+#[cfg(feature = "json")]
+macro_rules! JSONL_OUTPUT_FILE_TEMPLATE { () => { "resource_trace_{}.loki.jsonl" }; }
+#[cfg(feature = "json")]
+macro_rules! PERFETTO_OUTPUT_FILE_TEMPLATE { () => { "resource_trace_{}.perfetto.json" }; }
+macro_rules! THREADS_OUTPUT_FILE_TEMPLATE { () => { "resource_trace_{}.threads.csv" }; }
+
+/// Columns of the per thread CSV output, in order
+pub const THREAD_CSV_COLUMNS: &[&str] = &[
+    "time", "tid", "name", "utime", "stime", "totalcputime", "cpuOccupancyRate", "minflt", "majflt",
+    "voluntaryCtxtSwitches", "nonvoluntaryCtxtSwitches", "priority", "nice", "qualityFlags",
+    "runDelay", "sleepTime",
+];
+
+/// Prefix of the CSV columns which carry the tags of a trace
+pub const TAG_COLUMN_PREFIX: &str = "tag:";
+
+/// Parse a `key=value` tag
+///
+/// Keys are label names, ASCII letters, digits and `_` not starting with a
+/// digit. Values are not empty and have no commas, quotes or control
+/// characters, so they can be written to every output unescaped.
+pub fn parse_tag(tag: &str) -> Result<(String, String), TraceError> {
+    let invalid = |reason: &str| TraceError::InvalidConfig(format!("tag {}: {}", tag, reason));
+    let (key, value) = tag.split_once('=').ok_or_else(|| invalid("not key=value"))?;
+    let mut chars = key.chars();
+    if !chars.next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false)
+            || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(invalid("bad key"));
+    }
+    if value.is_empty() || value.chars().any(|c| c == ',' || c == '"' || c.is_control()) {
+        return Err(invalid("bad value"));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Output formats of a trace
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// one CSV row per sample
+    Csv,
+    /// newline delimited JSON, Loki style samples and events
+    #[cfg(feature = "json")]
+    Jsonl,
+    /// Trace Event Format JSON, opened by Perfetto UI and chrome://tracing
+    /// as counter tracks
+    #[cfg(feature = "json")]
+    Perfetto,
+    /// one CSV row per thread and sample, only written when tracing per thread
+    Threads,
+}
+
+/// All formats, in the order of their names
+#[cfg(feature = "json")]
+pub const OUTPUT_FORMATS: &[OutputFormat] = &[
+    OutputFormat::Csv, OutputFormat::Jsonl, OutputFormat::Perfetto, OutputFormat::Threads,
+];
+
+/// All formats, in the order of their names
+#[cfg(not(feature = "json"))]
+pub const OUTPUT_FORMATS: &[OutputFormat] = &[OutputFormat::Csv, OutputFormat::Threads];
+
+/// Formats written when none are asked for
+#[cfg(feature = "json")]
+pub const DEFAULT_OUTPUT_FORMATS: &[OutputFormat] = &[OutputFormat::Csv, OutputFormat::Jsonl];
+
+/// Formats written when none are asked for
+#[cfg(not(feature = "json"))]
+pub const DEFAULT_OUTPUT_FORMATS: &[OutputFormat] = &[OutputFormat::Csv];
+
+impl OutputFormat {
+    /// Parse a format name
+    pub fn parse(name: &str) -> Option<OutputFormat> {
+        OUTPUT_FORMATS.iter().copied().find(|format| format.name() == name)
+    }
+
+    /// Name accepted by `parse`
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            #[cfg(feature = "json")]
+            OutputFormat::Jsonl => "jsonl",
+            #[cfg(feature = "json")]
+            OutputFormat::Perfetto => "perfetto",
+            OutputFormat::Threads => "threads",
+        }
+    }
+
+    /// Output file name of a target label
+    pub fn file_name(&self, label: &str) -> String {
+        match self {
+            OutputFormat::Csv => format!(CSV_OUTPUT_FILE_TEMPLATE!(), label),
+            #[cfg(feature = "json")]
+            OutputFormat::Jsonl => format!(JSONL_OUTPUT_FILE_TEMPLATE!(), label),
+            #[cfg(feature = "json")]
+            OutputFormat::Perfetto => format!(PERFETTO_OUTPUT_FILE_TEMPLATE!(), label),
+            OutputFormat::Threads => format!(THREADS_OUTPUT_FILE_TEMPLATE!(), label),
+        }
+    }
+}
+
+/// Where and how traces without a session are written
+#[derive(Clone, Debug)]
+pub struct OutputPlan {
+    /// output directory
+    pub dir: String,
+    /// formats, one file per format and target
+    pub formats: Vec<OutputFormat>,
+    /// write and flush every sample as it is collected instead of
+    /// buffering the whole run and writing it at the end
+    pub streaming: bool,
+    /// `key=value` tags written to every output, see `parse_tag`
+    pub tags: Vec<(String, String)>,
+    /// write the columns which rarely change only at change points in the
+    /// JSON outputs, see `loki::RLE_COLUMNS`
+    pub compact: bool,
+    /// run id in the file names and labels, so concurrent traces of one
+    /// target do not overwrite each other, empty for the bare names
+    pub run_id: String,
+}
+
+impl Default for OutputPlan {
+    fn default() -> Self {
+        OutputPlan {
+            dir: ".".to_string(),
+            formats: DEFAULT_OUTPUT_FORMATS.to_vec(),
+            streaming: false,
+            tags: Vec::new(),
+            compact: false,
+            run_id: new_run_id(),
+        }
+    }
+}
+
+/// Receiver of the samples of one traced process
+pub trait RecordSink: Send {
+    /// Called once before the first sample
+    fn begin(&mut self, record: &RecordProcess) -> io::Result<()>;
+
+    /// Called for every sample
+    fn sample(&mut self, record: &RecordProcess, item: &RecordItem) -> io::Result<()>;
+
+    /// Called for every thread sample when tracing per thread
+    fn thread_sample(&mut self, _record: &RecordProcess, _thread: &ThreadItem) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called for events like gaps, `time` is seconds since time 0
+    fn event(&mut self, _record: &RecordProcess, _time: i64, _event: &str, _detail: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once after the last sample
+    fn end(&mut self, _record: &RecordProcess) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Push everything written so far to the underlying writer
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Writes samples as CSV
+pub struct CsvSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Write to `out`
+    pub fn new(out: W) -> CsvSink<W> {
+        CsvSink { out }
+    }
+
+    /// Get the writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+}
+
+impl<W: Write + Send> RecordSink for CsvSink<W> {
+    fn begin(&mut self, record: &RecordProcess) -> io::Result<()> {
+        write_csv_header(&mut self.out, &record.smoothed_columns, &record.transforms, &record.tags)
+    }
+
+    fn sample(&mut self, record: &RecordProcess, item: &RecordItem) -> io::Result<()> {
+        write_csv_row(&mut self.out, item, &record.smoothed_columns, &record.tags)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Writes samples and events as Loki style JSON lines
+#[cfg(feature = "json")]
+pub struct JsonLinesSink<W: Write> {
+    out: W,
+    labels: LokiLabels,
+    samples: usize,
+    last_time: i64,
+    compact: bool,
+    previous: Option<RecordItem>,
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> JsonLinesSink<W> {
+    /// Write to `out`, every line carries `labels`
+    pub fn new(out: W, labels: LokiLabels) -> JsonLinesSink<W> {
+        JsonLinesSink { out, labels, samples: 0, last_time: 0, compact: false, previous: None }
+    }
+
+    /// Leave out the columns of `loki::RLE_COLUMNS` when they did not change,
+    /// the first sample written by a sink always has them
+    pub fn compact(mut self, compact: bool) -> JsonLinesSink<W> {
+        self.compact = compact;
+        self
+    }
+
+    /// Get the writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W: Write + Send> RecordSink for JsonLinesSink<W> {
+    fn begin(&mut self, record: &RecordProcess) -> io::Result<()> {
+        let mut detail = start_detail(record);
+        if self.compact {
+            detail.push_str(&format!(" rle={}", RLE_COLUMNS.join(",")));
+        }
+        self.event(record, 0, "trace_start", &detail)
+    }
+
+    fn sample(&mut self, record: &RecordProcess, item: &RecordItem) -> io::Result<()> {
+        self.samples += 1;
+        self.last_time = item.timestamp;
+        write_sample(&mut self.out, &self.labels, record, item, self.previous.as_ref())?;
+        if self.compact {
+            self.previous = Some(item.clone());
+        }
+        Ok(())
+    }
+
+    fn thread_sample(&mut self, record: &RecordProcess, thread: &ThreadItem) -> io::Result<()> {
+        write_thread_sample(&mut self.out, &self.labels, record, thread)
+    }
+
+    fn event(&mut self, record: &RecordProcess, time: i64, event: &str, detail: &str) -> io::Result<()> {
+        write_event(&mut self.out, &self.labels, &record.tags, &epoch_ns(record.start_epoch, time), event, detail)
+    }
+
+    fn end(&mut self, record: &RecordProcess) -> io::Result<()> {
+        let detail = format!("samples={}", self.samples);
+        self.event(record, self.last_time, "trace_end", &detail)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Writes samples as counter events of the Trace Event Format
+///
+/// The closing `]` is never written, the format allows it to be missing
+/// so the file stays valid when the tracer is killed or resumed.
+#[cfg(feature = "json")]
+pub struct PerfettoSink<W: Write> {
+    out: W,
+    compact: bool,
+    previous: Option<RecordItem>,
+}
+
+#[cfg(feature = "json")]
+impl<W: Write> PerfettoSink<W> {
+    /// Write to `out`
+    pub fn new(out: W) -> PerfettoSink<W> {
+        PerfettoSink { out, compact: false, previous: None }
+    }
+
+    /// Write the counters of rarely changing columns only when they change,
+    /// counter tracks keep their value until the next event
+    pub fn compact(mut self, compact: bool) -> PerfettoSink<W> {
+        self.compact = compact;
+        self
+    }
+
+    /// Get the writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    fn counter(&mut self, record: &RecordProcess, ts: i64, name: &str,
+            args: &[(&str, f64)]) -> io::Result<()> {
+        let args: Vec<String> = args.iter()
+                .map(|(arg, value)| format!("\"{}\":{}", arg, json_number(*value)))
+                .collect();
+        writeln!(self.out, "{{\"name\":\"{}\",\"ph\":\"C\",\"ts\":{},\"pid\":{},\"args\":{{{}}}}},",
+                name, ts, record.pid, args.join(","))
+    }
+}
+
+// Trace Event Format timestamps are microseconds
+#[cfg(feature = "json")]
+fn epoch_us(epoch_secs: u64, offset_secs: i64) -> i64 {
+    (epoch_secs as i64 + offset_secs) * 1_000_000
+}
+
+#[cfg(feature = "json")]
+impl<W: Write + Send> RecordSink for PerfettoSink<W> {
+    fn begin(&mut self, record: &RecordProcess) -> io::Result<()> {
+        writeln!(self.out, "[")?;
+        writeln!(self.out, "{{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":{},\"args\":{{\"name\":\"{}\"}}}},",
+                record.pid, escape_json(&record.name))?;
+        if record.tags.is_empty() {
+            return Ok(());
+        }
+        let labels: Vec<String> = record.tags.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        writeln!(self.out, "{{\"name\":\"process_labels\",\"ph\":\"M\",\"pid\":{},\"args\":{{\"labels\":\"{}\"}}}},",
+                record.pid, escape_json(&labels.join(",")))
+    }
+
+    fn sample(&mut self, record: &RecordProcess, item: &RecordItem) -> io::Result<()> {
+        let ts = epoch_us(record.start_epoch, item.timestamp);
+        self.counter(record, ts, "memory_kb", &[
+            ("pss", item.pss as f64), ("vmRss", item.vm_rss as f64), ("vmAnon", item.vm_anon as f64),
+            ("vmFile", item.vm_file as f64), ("vmShmem", item.vm_shmem as f64),
+            ("vmSwap", item.vm_swap as f64),
+        ])?;
+        self.counter(record, ts, "cpu_s", &[("utime", item.utime), ("stime", item.stime)])?;
+        if item.cpu_occupancy_rate.is_finite() {
+            self.counter(record, ts, "cpu_occupancy", &[("rate", item.cpu_occupancy_rate)])?;
+        }
+        self.counter(record, ts, "faults", &[("minflt", item.minflt as f64), ("majflt", item.majflt as f64)])?;
+        self.counter(record, ts, "ctxt_switches", &[
+            ("voluntary", item.voluntary_ctxt_switches as f64),
+            ("nonvoluntary", item.nonvoluntary_ctxt_switches as f64),
+        ])?;
+        self.counter(record, ts, "io_bytes", &[("read", item.read_bytes as f64), ("write", item.write_bytes as f64)])?;
+        self.counter(record, ts, "io_syscalls", &[("read", item.syscr as f64), ("write", item.syscw as f64)])?;
+        self.counter(record, ts, "off_cpu_s", &[("runDelay", item.run_delay), ("sleep", item.sleep_time)])?;
+        if !item.smoothed.is_empty() {
+            let smoothed: Vec<(&str, f64)> = record.smoothed_columns.iter()
+                    .map(|column| column.as_str())
+                    .zip(item.smoothed.iter().copied())
+                    .collect();
+            self.counter(record, ts, "smoothed", &smoothed)?;
+        }
+        let previous = self.previous.take();
+        let changed = |value: fn(&RecordItem) -> i64| previous.as_ref().map(value) != Some(value(item));
+        if changed(|item| item.fd_count as i64) {
+            self.counter(record, ts, "fds", &[("count", item.fd_count as f64)])?;
+        }
+        if changed(|item| item.oom_score_adj) {
+            self.counter(record, ts, "oom_score_adj", &[("value", item.oom_score_adj as f64)])?;
+        }
+        if changed(|item| item.num_threads) {
+            self.counter(record, ts, "threads", &[("count", item.num_threads as f64)])?;
+        }
+        if self.compact {
+            self.previous = Some(item.clone());
+        }
+        Ok(())
+    }
+
+    fn thread_sample(&mut self, record: &RecordProcess, thread: &ThreadItem) -> io::Result<()> {
+        let ts = epoch_us(record.start_epoch, thread.item.timestamp);
+        let name = format!("cpu_s {} {}", escape_json(&thread.name), thread.tid);
+        self.counter(record, ts, &name, &[("utime", thread.item.utime), ("stime", thread.item.stime)])
+    }
+
+    fn event(&mut self, record: &RecordProcess, time: i64, event: &str, detail: &str) -> io::Result<()> {
+        writeln!(self.out, "{{\"name\":\"{}\",\"ph\":\"i\",\"s\":\"p\",\"ts\":{},\"pid\":{},\"args\":{{\"detail\":\"{}\"}}}},",
+                escape_json(event), epoch_us(record.start_epoch, time), record.pid, escape_json(detail))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Writes thread samples as CSV, process samples are ignored
+pub struct ThreadCsvSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> ThreadCsvSink<W> {
+    /// Write to `out`
+    pub fn new(out: W) -> ThreadCsvSink<W> {
+        ThreadCsvSink { out }
+    }
+
+    /// Get the writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+}
+
+impl<W: Write + Send> RecordSink for ThreadCsvSink<W> {
+    fn begin(&mut self, record: &RecordProcess) -> io::Result<()> {
+        write!(self.out, "{}{} \r\n", THREAD_CSV_COLUMNS.join(","), tag_columns(&record.tags))
+    }
+
+    fn sample(&mut self, _record: &RecordProcess, _item: &RecordItem) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn thread_sample(&mut self, record: &RecordProcess, thread: &ThreadItem) -> io::Result<()> {
+        let item = &thread.item;
+        write!(self.out, "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}{} \r\n",
+                item.timestamp, thread.tid, thread.name.replace(',', " "), fixed("utime", item.utime, 3),
+                fixed("stime", item.stime, 3), fixed("totalcputime", item.totalcputime, 3),
+                plain("cpuOccupancyRate", item.cpu_occupancy_rate), item.minflt, item.majflt,
+                item.voluntary_ctxt_switches, item.nonvoluntary_ctxt_switches, item.priority,
+                item.nice, item.quality_flags, fixed("runDelay", item.run_delay, 3),
+                fixed("sleepTime", item.sleep_time, 3), tag_values(&record.tags))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// `,tag:<key>` for every tag, appended to CSV headers
+pub(crate) fn tag_columns(tags: &[(String, String)]) -> String {
+    tags.iter().map(|(key, _)| format!(",{}{}", TAG_COLUMN_PREFIX, key)).collect()
+}
+
+// `,<value>` for every tag, appended to CSV rows
+pub(crate) fn tag_values(tags: &[(String, String)]) -> String {
+    tags.iter().map(|(_, value)| format!(",{}", value)).collect()
+}
+
+/// Create the sinks of a target, `label` is its label, see `TraceTarget::label`
+///
+/// Files are named `resource_trace_<label>_<run id>` and never replace an
+/// existing file.
+#[cfg_attr(not(feature = "json"), allow(unused_variables))]
+pub fn open_sinks(plan: &OutputPlan, label: &str) -> io::Result<Vec<Box<dyn RecordSink>>> {
+    let mut sinks: Vec<Box<dyn RecordSink>> = Vec::new();
+    let (name, labels) = match plan.run_id.as_str() {
+        "" => (label.to_string(), LokiLabels::new(label)),
+        run_id => (format!("{}_{}", label, run_id), LokiLabels::with_run_id(label, run_id)),
+    };
+    for format in &plan.formats {
+        let path = format!("{}/{}", plan.dir, format.file_name(&name));
+        let file = match plan.run_id.is_empty() {
+            true => open_output(OpenOptions::new().write(true).create(true).truncate(true), &path)?,
+            false => open_output(OpenOptions::new().write(true).create_new(true), &path)?,
+        };
+        let out = OutputWriter::new(file, 0);
+        sinks.push(match format {
+            OutputFormat::Csv => Box::new(CsvSink::new(out)),
+            #[cfg(feature = "json")]
+            OutputFormat::Jsonl => Box::new(JsonLinesSink::new(out, labels.clone()).compact(plan.compact)),
+            #[cfg(feature = "json")]
+            OutputFormat::Perfetto => Box::new(PerfettoSink::new(out).compact(plan.compact)),
+            OutputFormat::Threads => Box::new(ThreadCsvSink::new(out)),
+        });
+    }
+    Ok(sinks)
+}
@@ -0,0 +1,270 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+//! A small regular expression matcher for process names.
+//!
+//! Supports literals, `.`, `[...]` classes with ranges and `^` negation,
+//! `\d \w \s` and escaped literals, `^ $` anchors, `( )` groups, `|`
+//! alternation and the `* + ? {n} {n,} {n,m}` quantifiers. Matching runs
+//! on sets of positions, so no pattern can backtrack exponentially.
+
+// Largest count of a `{n,m}` quantifier
+const MAX_REPEAT: usize = 1000;
+
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+/// A compiled pattern
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    alternatives: Vec<Vec<Node>>,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+fn class_escape(c: char) -> Option<(Vec<(char, char)>, bool)> {
+    match c {
+        'd' => Some((vec![('0', '9')], false)),
+        'D' => Some((vec![('0', '9')], true)),
+        'w' => Some((vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], false)),
+        'W' => Some((vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], true)),
+        's' => Some((vec![(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')], false)),
+        'S' => Some((vec![(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')], true)),
+        _ => None,
+    }
+}
+
+impl Parser<'_> {
+    fn alternatives(&mut self, in_group: bool) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![Vec::new()];
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                '|' => {
+                    self.chars.next();
+                    alternatives.push(Vec::new());
+                },
+                ')' if in_group => return Ok(alternatives),
+                ')' => return Err("unmatched )".to_string()),
+                _ => {
+                    let atom = self.atom()?;
+                    let node = self.quantifier(atom)?;
+                    alternatives.last_mut().unwrap().push(node);
+                },
+            }
+        }
+        if in_group {
+            return Err("unmatched (".to_string());
+        }
+        Ok(alternatives)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.chars.next() {
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('(') => {
+                let alternatives = self.alternatives(true)?;
+                self.chars.next();
+                Ok(Node::Group(alternatives))
+            },
+            Some('[') => self.class(),
+            Some('\\') => match self.chars.next() {
+                Some(c) => Ok(class_escape(c)
+                        .map(|(ranges, negated)| Node::Class(ranges, negated))
+                        .unwrap_or(Node::Char(c))),
+                None => Err("trailing \\".to_string()),
+            },
+            Some(c @ ('*' | '+' | '?' | '{')) => Err(format!("nothing to repeat before {}", c)),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.chars.next() {
+                Some(']') if !first => break,
+                Some('\\') => match self.chars.next() {
+                    Some(e) => match class_escape(e) {
+                        Some((escaped, false)) => {
+                            ranges.extend(escaped);
+                            first = false;
+                            continue;
+                        },
+                        Some((_, true)) => return Err(format!("\\{} inside of a class", e)),
+                        None => e,
+                    },
+                    None => return Err("trailing \\".to_string()),
+                },
+                Some(c) => c,
+                None => return Err("unmatched [".to_string()),
+            };
+            first = false;
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') && lookahead.peek().map(|&n| n != ']').unwrap_or(false) {
+                self.chars.next();
+                let end = self.chars.next().unwrap();
+                if end < c {
+                    return Err(format!("bad range {}-{}", c, end));
+                }
+                ranges.push((c, end));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+        Ok(Node::Class(ranges, negated))
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let mut digits = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            digits.push(c);
+            self.chars.next();
+        }
+        digits.parse().ok()
+    }
+
+    fn quantifier(&mut self, node: Node) -> Result<Node, String> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.chars.next();
+                let min = self.number().ok_or("bad {n,m}")?;
+                let max = if self.chars.peek() == Some(&',') {
+                    self.chars.next();
+                    self.number()
+                } else {
+                    Some(min)
+                };
+                if self.chars.next() != Some('}') || max.map(|max| max < min).unwrap_or(false)
+                        || min.max(max.unwrap_or(0)) > MAX_REPEAT {
+                    return Err("bad {n,m}".to_string());
+                }
+                return Ok(Node::Repeat(Box::new(node), min, max));
+            },
+            _ => return Ok(node),
+        };
+        self.chars.next();
+        Ok(Node::Repeat(Box::new(node), min, max))
+    }
+}
+
+// Add a position to a sorted set
+fn insert(positions: &mut Vec<usize>, pos: usize) {
+    if let Err(i) = positions.binary_search(&pos) {
+        positions.insert(i, pos);
+    }
+}
+
+// End positions of `node` matched at `pos`
+fn node_ends(node: &Node, text: &[char], pos: usize, ends: &mut Vec<usize>) {
+    match node {
+        Node::Char(c) => if text.get(pos) == Some(c) { insert(ends, pos + 1) },
+        Node::Any => if pos < text.len() { insert(ends, pos + 1) },
+        Node::Class(ranges, negated) => {
+            if let Some(c) = text.get(pos) {
+                if ranges.iter().any(|(low, high)| low <= c && c <= high) != *negated {
+                    insert(ends, pos + 1);
+                }
+            }
+        },
+        Node::Start => if pos == 0 { insert(ends, pos) },
+        Node::End => if pos == text.len() { insert(ends, pos) },
+        Node::Group(alternatives) => {
+            for alternative in alternatives {
+                for end in seq_ends(alternative, text, vec![pos]) {
+                    insert(ends, end);
+                }
+            }
+        },
+        Node::Repeat(inner, min, max) => {
+            let mut current = vec![pos];
+            let mut seen = Vec::new();
+            let mut count = 0;
+            loop {
+                if count >= *min {
+                    for &p in &current {
+                        insert(ends, p);
+                    }
+                }
+                if max.map(|max| count >= max).unwrap_or(false) {
+                    break;
+                }
+                let mut next = Vec::new();
+                for &p in &current {
+                    node_ends(inner, text, p, &mut next);
+                }
+                // Once past the minimum, positions seen before add nothing new
+                if count >= *min {
+                    next.retain(|p| seen.binary_search(p).is_err());
+                    for &p in &current {
+                        insert(&mut seen, p);
+                    }
+                }
+                if next.is_empty() {
+                    break;
+                }
+                current = next;
+                count += 1;
+            }
+        },
+    }
+}
+
+// End positions of a sequence of nodes starting at any of `starts`
+fn seq_ends(nodes: &[Node], text: &[char], starts: Vec<usize>) -> Vec<usize> {
+    let mut positions = starts;
+    for node in nodes {
+        let mut next = Vec::new();
+        for &pos in &positions {
+            node_ends(node, text, pos, &mut next);
+        }
+        if next.is_empty() {
+            return next;
+        }
+        positions = next;
+    }
+    positions
+}
+
+impl Pattern {
+    /// Compile a pattern
+    pub fn new(pattern: &str) -> Result<Pattern, String> {
+        let mut parser = Parser { chars: pattern.chars().peekable() };
+        let alternatives = parser.alternatives(false)
+                .map_err(|e| format!("bad pattern {}: {}", pattern, e))?;
+        Ok(Pattern { alternatives })
+    }
+
+    /// Whether the pattern matches anywhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let starts: Vec<usize> = (0..=text.len()).collect();
+        self.alternatives.iter().any(|alternative| !seq_ends(alternative, &text, starts.clone()).is_empty())
+    }
+}
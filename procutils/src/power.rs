@@ -0,0 +1,158 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use crate::file_utils::read_node;
+use crate::proc_analysis::MetricGroups;
+use std::fs;
+
+// Power supplies and display backlights of the device
+const POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+const BACKLIGHT_ROOTS: [&str; 2] = ["/sys/class/backlight", "/sys/class/leds"];
+// Only the display backlight of the leds class counts
+const LCD_BACKLIGHT_LED: &str = "lcd-backlight";
+
+/// Power state of the device, None where it can not be told
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PowerState {
+    /// a display backlight is lit
+    pub screen_on: Option<bool>,
+    /// an external supply is online or the battery is charging or full
+    pub charging: Option<bool>,
+}
+
+impl PowerState {
+    /// Read the current state from sysfs
+    pub fn read() -> PowerState {
+        PowerState { screen_on: screen_on(), charging: charging() }
+    }
+
+    /// `screen=on|off|unknown charging=1|0|unknown`
+    pub fn detail(&self) -> String {
+        let screen = match self.screen_on {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "unknown",
+        };
+        let charging = match self.charging {
+            Some(true) => "1",
+            Some(false) => "0",
+            None => "unknown",
+        };
+        format!("screen={} charging={}", screen, charging)
+    }
+}
+
+fn screen_on() -> Option<bool> {
+    let mut found = None;
+    for root in BACKLIGHT_ROOTS {
+        for entry in fs::read_dir(root).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if root.ends_with("leds") && name != LCD_BACKLIGHT_LED {
+                continue;
+            }
+            let brightness = read_node(&format!("{}/{}/brightness", root, name)).ok()
+                    .and_then(|value| value.trim().parse::<u64>().ok());
+            if let Some(brightness) = brightness {
+                found = Some(found.unwrap_or(false) || brightness > 0);
+            }
+        }
+    }
+    found
+}
+
+fn charging() -> Option<bool> {
+    let mut found = None;
+    for entry in fs::read_dir(POWER_SUPPLY_ROOT).ok()?.flatten() {
+        let dir = format!("{}/{}", POWER_SUPPLY_ROOT, entry.file_name().to_string_lossy());
+        let read = |file: &str| read_node(&format!("{}/{}", dir, file)).ok().map(|value| value.trim().to_string());
+        let supplying = match read("type").as_deref() {
+            Some("Battery") => matches!(read("status").as_deref(), Some("Charging") | Some("Full")),
+            Some(_) => read("online").as_deref() == Some("1"),
+            None => continue,
+        };
+        found = Some(found.unwrap_or(false) || supplying);
+    }
+    found
+}
+
+/// Sampling settings used while the device is in some power state
+///
+/// Written `<states>:<interval>[:<metrics>]`, states are `+` joined
+/// `screen-on`, `screen-off`, `charging` or `battery`, e.g.
+/// `screen-off+battery:60:none`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PowerProfile {
+    /// required screen state, None for any
+    pub screen_on: Option<bool>,
+    /// required charging state, None for any
+    pub charging: Option<bool>,
+    /// sampling interval in seconds
+    pub interval: i64,
+    /// metric groups, None keeps the ones of the trace
+    pub metrics: Option<MetricGroups>,
+}
+
+impl PowerProfile {
+    /// Parse a profile, None when it is malformed
+    pub fn parse(spec: &str) -> Option<PowerProfile> {
+        let mut fields = spec.split(':');
+        let mut profile = PowerProfile { screen_on: None, charging: None, interval: 0, metrics: None };
+        for state in fields.next()?.split('+') {
+            let (slot, value) = match state.trim() {
+                "screen-on" => (&mut profile.screen_on, true),
+                "screen-off" => (&mut profile.screen_on, false),
+                "charging" => (&mut profile.charging, true),
+                "battery" => (&mut profile.charging, false),
+                _ => return None,
+            };
+            if slot.replace(value).is_some() {
+                return None;
+            }
+        }
+        profile.interval = fields.next()?.trim().parse().ok().filter(|interval| *interval > 0)?;
+        if let Some(metrics) = fields.next() {
+            profile.metrics = Some(MetricGroups::parse(metrics)?);
+        }
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(profile)
+    }
+
+    /// Spec accepted by `parse`
+    pub fn spec(&self) -> String {
+        let mut states = Vec::new();
+        match self.screen_on {
+            Some(true) => states.push("screen-on"),
+            Some(false) => states.push("screen-off"),
+            None => {},
+        }
+        match self.charging {
+            Some(true) => states.push("charging"),
+            Some(false) => states.push("battery"),
+            None => {},
+        }
+        let mut spec = format!("{}:{}", states.join("+"), self.interval);
+        if let Some(metrics) = &self.metrics {
+            let names = metrics.names();
+            spec.push(':');
+            spec.push_str(&if names.is_empty() { "none".to_string() } else { names.join(",") });
+        }
+        spec
+    }
+
+    /// Whether the profile applies, unknown states never match a required one
+    pub fn matches(&self, state: &PowerState) -> bool {
+        let fits = |required: Option<bool>, actual: Option<bool>| required.is_none() || required == actual;
+        fits(self.screen_on, state.screen_on) && fits(self.charging, state.charging)
+    }
+}
+
+/// The first profile matching `state`
+pub fn select_profile<'a>(profiles: &'a [PowerProfile], state: &PowerState) -> Option<&'a PowerProfile> {
+    profiles.iter().find(|profile| profile.matches(state))
+}
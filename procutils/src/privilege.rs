@@ -0,0 +1,151 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{c_int, gid_t, uid_t};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::ptr;
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+const CAPABILITY_WORDS: usize = 2;
+
+/// Bypass read permission checks of files and directories
+pub const CAP_DAC_READ_SEARCH: u32 = 2;
+/// Read `/proc/<pid>/smaps` and `exe` of processes of other users
+pub const CAP_SYS_PTRACE: u32 = 19;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: c_int,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn check(ret: c_int) -> io::Result<()> {
+    if ret < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+/// Whether the tracer runs as root
+pub fn is_root() -> bool {
+    // SAFETY:
+    // Safe because geteuid has no side effects and can not fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Identity the tracer drops to once its files are open
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DropPrivileges {
+    /// uid of the sampling phase
+    pub uid: uid_t,
+    /// gid of the sampling phase
+    pub gid: gid_t,
+    /// keep CAP_SYS_PTRACE and CAP_DAC_READ_SEARCH, so threads and files
+    /// opened later can still be read
+    pub keep_ptrace: bool,
+}
+
+pub(crate) fn lookup_user(name: &str) -> Option<(uid_t, gid_t)> {
+    if let Ok(uid) = name.parse::<uid_t>() {
+        return Some((uid, uid));
+    }
+    let name = CString::new(name).ok()?;
+    // SAFETY:
+    // Safe because the name is a valid C string and the result is checked
+    // for null before it is read
+    unsafe {
+        let passwd = libc::getpwnam(name.as_ptr());
+        if passwd.is_null() { None } else { Some(((*passwd).pw_uid, (*passwd).pw_gid)) }
+    }
+}
+
+fn lookup_group(name: &str) -> Option<gid_t> {
+    if let Ok(gid) = name.parse::<gid_t>() {
+        return Some(gid);
+    }
+    let name = CString::new(name).ok()?;
+    // SAFETY:
+    // Safe because the name is a valid C string and the result is checked
+    // for null before it is read
+    unsafe {
+        let group = libc::getgrnam(name.as_ptr());
+        if group.is_null() { None } else { Some((*group).gr_gid) }
+    }
+}
+
+impl DropPrivileges {
+    /// Parse `<user>[:<group>]`, names or numbers, the group defaults to
+    /// the group of the user
+    pub fn parse(spec: &str, keep_ptrace: bool) -> Option<DropPrivileges> {
+        let (user, group) = match spec.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (spec, None),
+        };
+        let (uid, user_gid) = lookup_user(user)?;
+        let gid = match group {
+            Some(group) => lookup_group(group)?,
+            None => user_gid,
+        };
+        Some(DropPrivileges { uid, gid, keep_ptrace })
+    }
+
+    /// Give the files of the sampling phase to the new identity, `dir` and
+    /// the files directly inside of it
+    pub fn chown_dir(&self, dir: &str) -> io::Result<()> {
+        let mut paths = vec![dir.to_string()];
+        for entry in fs::read_dir(dir)? {
+            paths.push(entry?.path().to_string_lossy().to_string());
+        }
+        for path in paths {
+            let path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            // SAFETY:
+            // Safe because the path is a valid C string, errors are checked
+            check(unsafe { libc::chown(path.as_ptr(), self.uid, self.gid) })?;
+        }
+        Ok(())
+    }
+
+    /// Drop to the identity for good, every thread of the tracer is affected
+    pub fn apply(&self) -> io::Result<()> {
+        if !is_root() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "dropping privileges needs root"));
+        }
+        let keep: &[u32] = if self.keep_ptrace { &[CAP_DAC_READ_SEARCH, CAP_SYS_PTRACE] } else { &[] };
+        let mut data = [CapData::default(); CAPABILITY_WORDS];
+        for cap in keep {
+            let word = &mut data[(cap / 32) as usize];
+            word.effective |= 1 << (cap % 32);
+            word.permitted |= 1 << (cap % 32);
+        }
+        let mut header = CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+        // SAFETY:
+        // Safe because all pointers are valid for the calls and every
+        // result is checked
+        unsafe {
+            check(libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0))?;
+            check(libc::setgroups(0, ptr::null()))?;
+            check(libc::setresgid(self.gid, self.gid, self.gid))?;
+            check(libc::setresuid(self.uid, self.uid, self.uid))?;
+            // Only the kept capabilities stay, the rest is gone with root
+            check(libc::syscall(libc::SYS_capset, &mut header as *mut CapHeader,
+                    data.as_ptr()) as c_int)?;
+            check(libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0))?;
+            check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            // Root must not come back
+            if self.uid != 0 && libc::setresuid(0, 0, 0) == 0 {
+                return Err(io::Error::other("uid 0 is still reachable"));
+            }
+        }
+        Ok(())
+    }
+}
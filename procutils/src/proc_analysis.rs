@@ -4,14 +4,42 @@
 // This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
 // See the LICENSE file at the root directory of this project for more details.
 
-use libc::{pid_t, sysconf, time_t, _SC_CLK_TCK};
-use crate::file_utils::read_path;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Write};
-use std::thread::{self, sleep};
-use std::process::{Command, Output};
-use std::str::from_utf8;
-use std::time::Duration;
+use libc::{pid_t, sysconf, time_t, ESRCH, _SC_CLK_TCK};
+use crate::{SCHEMA_VERSION, VERSION};
+use crate::abi::ProcessAbi;
+use crate::arena::SampleArena;
+use crate::audit::{self, AUDIT_LOG_FILE};
+use crate::binderprio::BinderPrioritySampler;
+use crate::bus::{clear_posted, publish, take_posted, BusMessage};
+use crate::codes::coded_detail;
+use crate::discover::{process_info, MatchMode};
+use crate::error::TraceError;
+use crate::memwatch::{statm_rss_kb, wait_events, MemoryWatch};
+use crate::numeric::{fixed, plain};
+use crate::freezer::FreezerWatch;
+use crate::deps::DependencySnapshot;
+use crate::smaps::{smaps_rollup_available, SmapsCache};
+use crate::foreground::{AppWindow, CpuSplit};
+use crate::file_utils::{self, keep_open, read_node, with_retry, OpenFiles, RetryPolicy, QUARANTINE_TIMEOUTS};
+use crate::output::{open_sinks, tag_columns, tag_values, OutputPlan, RecordSink};
+use crate::power::{select_profile, PowerProfile, PowerState};
+use crate::privilege::DropPrivileges;
+use crate::progress::{dir_bytes, Progress};
+use crate::resolve::resolver;
+use crate::sampling::{thread_cpu_time, SamplingStats};
+use crate::selinux::{denial_detail, process_context};
+use crate::session::{now_epoch, SessionOutput, SessionState};
+use crate::shutdown::{sleep_until, stop_requested};
+use crate::transform::TransformPipeline;
+use crate::validate::{confidence, delta_count, ValidationMode, Validator};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 // Procfs some path
 const GLOBAL_SYSTEM_INFO: &str = "/proc/stat";
@@ -41,18 +69,87 @@ macro_rules! TASK_STAT_TID_TEMPLATE { () => { "/proc/{}/task/{}/stat" } }
 #[macro_export]
 macro_rules! TASK_SMAPS_PID_TEMPLATE { () => { "/proc/{}/smaps" }; }
 
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_SMAPS_ROLLUP_PID_TEMPLATE { () => { "/proc/{}/smaps_rollup" }; }
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_STATM_TEMPLATE { () => { "/proc/{}/statm" }; }
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_IO_TEMPLATE { () => { "/proc/{}/io" }; }
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_FD_TEMPLATE { () => { "/proc/{}/fd" }; }
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_OOM_SCORE_ADJ_TEMPLATE { () => { "/proc/{}/oom_score_adj" }; }
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_CGROUP_TEMPLATE { () => { "/proc/{}/cgroup" }; }
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_CPUSET_TEMPLATE { () => { "/proc/{}/cpuset" }; }
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_SCHEDSTAT_TID_TEMPLATE { () => { "/proc/{}/task/{}/schedstat" }; }
+
 // procfs status some data type
-const TASK_VM_RSS_PREFIX: &str = "VmRSS:\t";
-const TASK_RSS_ANON_PREFIX: &str = "RssAnon:\t";
-const TASK_RSS_FILE_PREFIX: &str = "RssFile:\t";
-const TASK_RSS_SHMEM_PREFIX: &str = "RssShmem:\t";
-const TASK_VM_SWAP_PREFIX: &str = "VmSwap:\t";
-const TASK_PSS_PREFIX: &str = "Pss:\t";
-const TASK_VOLUNTARY_SWITCH_PREFIX: &str = "voluntary_ctxt_switches:\t";
-const TASK_NONVOLUNTARY_SWITCH_PREFIX: &str = "nonvoluntary_ctxt_switches:\t";
+// Keys of /proc/<pid>/status and smaps, the part before the `:`
+const TASK_VM_RSS_KEY: &str = "VmRSS";
+const TASK_VM_HWM_KEY: &str = "VmHWM";
+const TASK_RSS_ANON_KEY: &str = "RssAnon";
+const TASK_RSS_FILE_KEY: &str = "RssFile";
+const TASK_RSS_SHMEM_KEY: &str = "RssShmem";
+const TASK_VM_SWAP_KEY: &str = "VmSwap";
+const TASK_PSS_KEY: &str = "Pss";
+const TASK_VOLUNTARY_SWITCH_KEY: &str = "voluntary_ctxt_switches";
+const TASK_NONVOLUNTARY_SWITCH_KEY: &str = "nonvoluntary_ctxt_switches";
 const GLOBAL_CPU_STAT_PREFIX: &str = "cpu "; // static mark global lifecycle
+const GLOBAL_BOOT_TIME_PREFIX: &str = "btime ";
+const EXE_DELETED_SUFFIX: &str = " (deleted)";
+
+// procfs io keys
+const TASK_IO_READ_BYTES: &str = "read_bytes";
+const TASK_IO_WRITE_BYTES: &str = "write_bytes";
+const TASK_IO_SYSCR: &str = "syscr";
+const TASK_IO_SYSCW: &str = "syscw";
+// Controller of the cpuset line of /proc/<pid>/cgroup
+const CGROUP_CPUSET_CONTROLLER: &str = "cpuset";
+// Seconds since boot, the ages of threads are counted from it
+const UPTIME_PATH: &str = "/proc/uptime";
 
-macro_rules! OUTPUT_FILE_TEMPLATE { () => { "resource_trace_{}.csv" }; }
+/// Collectors of a sample, each one reads one kind of procfs node
+pub const COLLECTORS: &[(&str, &str)] = &[
+    ("global_cpu", "/proc/stat"),
+    ("pss", "/proc/<pid>/smaps_rollup"),
+    ("task_status", "/proc/<pid>/task/<tid>/status"),
+    ("task_stat", "/proc/<pid>/stat"),
+    ("io", "/proc/<pid>/io"),
+    ("fd", "/proc/<pid>/fd"),
+    ("oom_score_adj", "/proc/<pid>/oom_score_adj"),
+    ("cgroup", "/proc/<pid>/cgroup"),
+    ("sched", "/proc/<pid>/task/<tid>/schedstat"),
+];
+// The first collectors always run, the others belong to a `MetricGroups` group
+pub(crate) const BASE_COLLECTOR_COUNT: u32 = 4;
+
+/// Columns of the CSV output, in order
+pub const CSV_COLUMNS: &[&str] = &[
+    "time", "pss", "vmRss", "vmAnon", "vmFile", "vmShmem", "vmSwap", "voluntaryCtxtSwitches",
+    "nonvoluntaryCtxtSwitches", "minflt", "majflt", "utime", "stime", "totalcputime", "gutime",
+    "gstime", "gtotalcputime", "cpuOccupancyRate", "priority", "nice", "numThreads", "startTime",
+    "retries", "qualityFlags", "collectorsOk", "carriedForward", "confidence",
+    "readBytes", "writeBytes", "syscr", "syscw", "fdCount", "oomScoreAdj",
+    "runDelay", "sleepTime", "offCpuTime", "seq", "missed", "vmHwm",
+];
 
 // /proc/pid/stat shift
 const PROCESS_STAT_MINFLT_SHIFT: usize = 9;
@@ -62,113 +159,747 @@ const PROCESS_STAT_STIME_SHIFT: usize = 14;
 const PROCESS_STAT_PRIORITY_SHIFT: usize = 17;
 const PROCESS_STAT_NICE_SHIFT: usize = 18;
 const PROCESS_STAT_NUM_THREADS_SHIFT: usize = 19;
-const PROCESS_STAT_STARTTIME_SHIFT: usize = 21;
+pub(crate) const PROCESS_STAT_STARTTIME_SHIFT: usize = 21;
 
 // /proc/stat
 const SYSTEM_GLOBAL_USER_TIME_SHIFT: usize = 0;
 const SYSTEM_GLOBAL_SYSTEM_TIME_SHIFT: usize = 2;
 
-// Record process info of each piece
-#[derive(Default, Clone)]
-struct RecordItem {
-    timestamp: i64,
-    pss: isize,
-    vm_rss: isize,
-    vm_anon: isize,
-    vm_file: isize,
-    vm_shmem: isize,
-    vm_swap: isize,
-    voluntary_ctxt_switches: usize,
-    nonvoluntary_ctxt_switches: usize,
-    minflt: usize,
-    majflt: usize,
-    utime: f64,
-    stime: f64,
-    totalcputime: f64,
-    global_utime: f64,
-    global_stime: f64,
-    global_total_cpu_time: f64,
-    cpu_occupancy_rate: f64,
-    priority: i64,
-    nice: i64,
-    num_threads: i64,
-    start_time: i64,
+/// Record process info of each piece
+///
+/// Counters and times are deltas to the previous sample, memory is in kB
+/// and times are in seconds.
+#[derive(Default, Clone, Debug)]
+pub struct RecordItem {
+    /// seconds since the trace started
+    pub timestamp: i64,
+    /// sum of Pss of all mappings
+    pub pss: isize,
+    /// VmRSS
+    pub vm_rss: isize,
+    /// RssAnon
+    pub vm_anon: isize,
+    /// RssFile
+    pub vm_file: isize,
+    /// RssShmem
+    pub vm_shmem: isize,
+    /// VmSwap
+    pub vm_swap: isize,
+    /// voluntary context switches of all threads
+    pub voluntary_ctxt_switches: usize,
+    /// nonvoluntary context switches of all threads
+    pub nonvoluntary_ctxt_switches: usize,
+    /// minor faults of all threads
+    pub minflt: usize,
+    /// major faults of all threads
+    pub majflt: usize,
+    /// user time of all threads
+    pub utime: f64,
+    /// system time of all threads
+    pub stime: f64,
+    /// utime + stime
+    pub totalcputime: f64,
+    /// user time of the whole system
+    pub global_utime: f64,
+    /// system time of the whole system
+    pub global_stime: f64,
+    /// global_utime + global_stime
+    pub global_total_cpu_time: f64,
+    /// totalcputime / global_total_cpu_time
+    pub cpu_occupancy_rate: f64,
+    /// scheduling priority
+    pub priority: i64,
+    /// nice value
+    pub nice: i64,
+    /// number of threads
+    pub num_threads: i64,
+    /// start time of the process in clock ticks since boot
+    pub start_time: i64,
+    /// retries of transient read failures needed by this sample
+    pub retries: usize,
+    /// QUALITY_* bits set by the validator
+    pub quality_flags: u32,
+    /// collectors which read their source successfully
+    pub collectors_ok: u32,
+    /// some values are copied from the last sample
+    pub carried_forward: bool,
+    /// 0.0 ~ 1.0, how much the sample can be trusted
+    pub confidence: f64,
+    /// bytes fetched from storage
+    pub read_bytes: usize,
+    /// bytes sent to storage
+    pub write_bytes: usize,
+    /// read syscalls
+    pub syscr: usize,
+    /// write syscalls
+    pub syscw: usize,
+    /// open file descriptors
+    pub fd_count: usize,
+    /// oom_score_adj used by lmkd, -1000 ~ 1000
+    pub oom_score_adj: i64,
+    /// `controllers:path` of every cgroup hierarchy joined by `;`
+    pub cgroup: String,
+    /// cpuset path, e.g. `/top-app`
+    pub cpuset: String,
+    /// comm of the process from its stat node, empty when it was not read
+    pub comm: String,
+    /// seconds the threads were runnable but waited for a CPU
+    pub run_delay: f64,
+    /// seconds the threads were neither running nor runnable, estimated from
+    /// their age, CPU time and run delay
+    pub sleep_time: f64,
+    /// run_delay + sleep_time
+    pub off_cpu_time: f64,
+    /// number of the sample in its target, counted from 0 and kept across resumes
+    pub seq: u64,
+    /// ticks without a sample right before this one, see the `gap` event
+    pub missed: u64,
+    /// peak resident set size in KB since the process started, VmHWM. It
+    /// bounds the RSS between two samples, see `envelope`
+    pub vm_hwm: isize,
+    /// smoothed companion values, named by `RecordProcess::smoothed_columns`
+    pub smoothed: Vec<f64>,
+}
+
+impl RecordItem {
+    /// Value of a numeric CSV column, see `CSV_COLUMNS`
+    pub fn value(&self, column: &str) -> Option<f64> {
+        Some(match column {
+            "time" => self.timestamp as f64,
+            "pss" => self.pss as f64,
+            "vmRss" => self.vm_rss as f64,
+            "vmAnon" => self.vm_anon as f64,
+            "vmFile" => self.vm_file as f64,
+            "vmShmem" => self.vm_shmem as f64,
+            "vmSwap" => self.vm_swap as f64,
+            "voluntaryCtxtSwitches" => self.voluntary_ctxt_switches as f64,
+            "nonvoluntaryCtxtSwitches" => self.nonvoluntary_ctxt_switches as f64,
+            "minflt" => self.minflt as f64,
+            "majflt" => self.majflt as f64,
+            "utime" => self.utime,
+            "stime" => self.stime,
+            "totalcputime" => self.totalcputime,
+            "gutime" => self.global_utime,
+            "gstime" => self.global_stime,
+            "gtotalcputime" => self.global_total_cpu_time,
+            "cpuOccupancyRate" => self.cpu_occupancy_rate,
+            "priority" => self.priority as f64,
+            "nice" => self.nice as f64,
+            "numThreads" => self.num_threads as f64,
+            "startTime" => self.start_time as f64,
+            "retries" => self.retries as f64,
+            "qualityFlags" => self.quality_flags as f64,
+            "collectorsOk" => self.collectors_ok as f64,
+            "carriedForward" => self.carried_forward as u8 as f64,
+            "confidence" => self.confidence,
+            "readBytes" => self.read_bytes as f64,
+            "writeBytes" => self.write_bytes as f64,
+            "syscr" => self.syscr as f64,
+            "syscw" => self.syscw as f64,
+            "fdCount" => self.fd_count as f64,
+            "oomScoreAdj" => self.oom_score_adj as f64,
+            "runDelay" => self.run_delay,
+            "sleepTime" => self.sleep_time,
+            "offCpuTime" => self.off_cpu_time,
+            "seq" => self.seq as f64,
+            "missed" => self.missed as f64,
+            "vmHwm" => self.vm_hwm as f64,
+            _ => return None,
+        })
+    }
+
+    /// Set a measured column, see `value`. Columns of whole numbers are
+    /// rounded, false for the time, quality and unknown columns
+    pub fn set_value(&mut self, column: &str, value: f64) -> bool {
+        let whole = value.round();
+        match column {
+            "pss" => self.pss = whole as isize,
+            "vmRss" => self.vm_rss = whole as isize,
+            "vmAnon" => self.vm_anon = whole as isize,
+            "vmFile" => self.vm_file = whole as isize,
+            "vmShmem" => self.vm_shmem = whole as isize,
+            "vmSwap" => self.vm_swap = whole as isize,
+            "voluntaryCtxtSwitches" => self.voluntary_ctxt_switches = whole as usize,
+            "nonvoluntaryCtxtSwitches" => self.nonvoluntary_ctxt_switches = whole as usize,
+            "minflt" => self.minflt = whole as usize,
+            "majflt" => self.majflt = whole as usize,
+            "utime" => self.utime = value,
+            "stime" => self.stime = value,
+            "totalcputime" => self.totalcputime = value,
+            "gutime" => self.global_utime = value,
+            "gstime" => self.global_stime = value,
+            "gtotalcputime" => self.global_total_cpu_time = value,
+            "cpuOccupancyRate" => self.cpu_occupancy_rate = value,
+            "priority" => self.priority = whole as i64,
+            "nice" => self.nice = whole as i64,
+            "numThreads" => self.num_threads = whole as i64,
+            "readBytes" => self.read_bytes = whole as usize,
+            "writeBytes" => self.write_bytes = whole as usize,
+            "syscr" => self.syscr = whole as usize,
+            "syscw" => self.syscw = whole as usize,
+            "fdCount" => self.fd_count = whole as usize,
+            "oomScoreAdj" => self.oom_score_adj = whole as i64,
+            "runDelay" => self.run_delay = value,
+            "sleepTime" => self.sleep_time = value,
+            "offCpuTime" => self.off_cpu_time = value,
+            "vmHwm" => self.vm_hwm = whole as isize,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// One sample of one thread
+///
+/// The item carries the values a thread has on its own: times, faults,
+/// context switches, priority and nice. Like process samples, counters and
+/// times are deltas and `cpu_occupancy_rate` is relative to the whole system.
+#[derive(Default, Clone, Debug)]
+pub struct ThreadItem {
+    /// thread id
+    pub tid: pid_t,
+    /// thread name
+    pub name: String,
+    /// values of the thread
+    pub item: RecordItem,
+}
+
+/// Retry policy of every collector
+#[derive(Clone, Copy, Debug)]
+pub struct CollectorPolicies {
+    /// `/proc/stat`
+    pub global_cpu: RetryPolicy,
+    /// `/proc/<pid>/smaps_rollup`, or `smaps` on kernels without it
+    pub pss: RetryPolicy,
+    /// `/proc/<pid>/task` and `/proc/<pid>/task/<tid>/status`
+    pub task_status: RetryPolicy,
+    /// `/proc/<pid>/stat` and `/proc/<pid>/task/<tid>/stat`
+    pub task_stat: RetryPolicy,
+    /// nodes of the optional `MetricGroups`
+    pub metrics: RetryPolicy,
+}
+
+impl Default for CollectorPolicies {
+    fn default() -> Self {
+        // Threads come and go, a short ENOENT retry is enough for them
+        let task = RetryPolicy::new(1, Duration::from_millis(1), Duration::from_millis(5), true);
+        CollectorPolicies {
+            global_cpu: RetryPolicy::default(),
+            pss: RetryPolicy::new(2, Duration::from_millis(1), Duration::from_millis(10), false),
+            task_status: task,
+            task_stat: task,
+            metrics: RetryPolicy::default(),
+        }
+    }
+}
+
+// Pressure stall information of the memory, `some avg10=.. avg60=.. avg300=.. total=..`
+const PSI_MEMORY_PATH: &str = "/proc/pressure/memory";
+const PSI_SOME_PREFIX: &str = "some ";
+const PSI_AVG10_PREFIX: &str = "avg10=";
+
+/// Collector read timeout of `MonitorOptions`, vendor sysfs and debugfs nodes
+/// may block forever
+pub const DEFAULT_READ_TIMEOUT: Duration = file_utils::DEFAULT_NODE_TIMEOUT;
+
+/// Prefix of a target given by pid, e.g. `pid:1234`
+pub const TARGET_PID_PREFIX: &str = "pid:";
+
+/// A process to trace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceTarget {
+    /// resolved by exact process name
+    Name(String),
+    /// resolved by substring or pattern, see `MatchMode`
+    Match(MatchMode, String),
+    /// attached by pid
+    Pid(pid_t),
+    /// resolved by the `TargetResolver` of a scheme, as (scheme, query)
+    Scheme(String, String),
+}
+
+impl TraceTarget {
+    /// Parse a target spec
+    ///
+    /// `pid:<pid>` is a pid, `substring:<text>` and `regex:<pattern>` match
+    /// names loosely, `<scheme>:<query>` goes to the resolver of a scheme,
+    /// e.g. `uid:1000`, `exact:<name>` or anything else is an exact name.
+    pub fn parse(spec: &str) -> TraceTarget {
+        if let Some(pid) = spec.strip_prefix(TARGET_PID_PREFIX).and_then(|pid| pid.parse::<pid_t>().ok()) {
+            return TraceTarget::Pid(pid);
+        }
+        if let Some((mode, name)) = spec.split_once(':') {
+            match MatchMode::parse(mode) {
+                Some(MatchMode::Exact) => return TraceTarget::Name(name.to_string()),
+                Some(mode) => return TraceTarget::Match(mode, name.to_string()),
+                None if resolver(mode).is_some() => return TraceTarget::Scheme(mode.to_string(), name.to_string()),
+                None => {},
+            }
+        }
+        TraceTarget::Name(spec.to_string())
+    }
+
+    /// Target of a name matched with `mode`
+    pub fn matching(name: &str, mode: MatchMode) -> TraceTarget {
+        match mode {
+            MatchMode::Exact => TraceTarget::Name(name.to_string()),
+            _ => TraceTarget::Match(mode, name.to_string()),
+        }
+    }
+
+    /// Spec string accepted by `parse`
+    pub fn spec(&self) -> String {
+        match self {
+            TraceTarget::Name(name) if name.contains(':') => format!("{}:{}", MatchMode::Exact.name(), name),
+            TraceTarget::Name(name) => name.clone(),
+            TraceTarget::Match(mode, name) => format!("{}:{}", mode.name(), name),
+            TraceTarget::Pid(pid) => format!("{}{}", TARGET_PID_PREFIX, pid),
+            TraceTarget::Scheme(scheme, query) => format!("{}:{}", scheme, query),
+        }
+    }
+
+    /// Name used in output file names and labels
+    pub fn label(&self) -> String {
+        match self {
+            TraceTarget::Name(name) => name.clone(),
+            TraceTarget::Match(mode, name) => format!("{}_{}", mode.name(), label_safe(name)),
+            TraceTarget::Pid(pid) => format!("pid_{}", pid),
+            TraceTarget::Scheme(scheme, query) => format!("{}_{}", label_safe(scheme), label_safe(query)),
+        }
+    }
+
+    /// Find the pids of every process matching the target, oldest first
+    pub fn resolve_pids(&self) -> Result<Vec<pid_t>, TraceError> {
+        let (scheme, query) = match self {
+            TraceTarget::Name(name) => (MatchMode::Exact.name().to_string(), name.clone()),
+            TraceTarget::Match(mode, name) => (mode.name().to_string(), name.clone()),
+            TraceTarget::Pid(pid) => ("pid".to_string(), pid.to_string()),
+            TraceTarget::Scheme(scheme, query) => (scheme.clone(), query.clone()),
+        };
+        let pids = resolver(&scheme)
+                .ok_or_else(|| TraceError::InvalidConfig(format!("no resolver of {}", scheme)))?
+                .resolve(&query)?;
+        if pids.is_empty() {
+            return Err(TraceError::ProcessNotFound(self.spec()));
+        }
+        Ok(pids)
+    }
+
+    /// Find the pid of the target, the oldest one when several processes match
+    pub fn resolve_pid(&self) -> Result<pid_t, TraceError> {
+        let pids = self.resolve_pids()?;
+        if pids.len() > 1 {
+            println!("{} processes match {}, tracing the oldest one {}", pids.len(), self.spec(), pids[0]);
+        }
+        Ok(pids[0])
+    }
+}
+
+// Keep file names and labels portable
+fn label_safe(name: &str) -> String {
+    name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' { c } else { '_' })
+            .collect()
+}
+
+/// Birth of a process as written to event details, wall clock seconds or `unknown`
+pub fn format_birth(birth: Option<f64>) -> String {
+    birth.map(|birth| format!("{:.2}", birth)).unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Whether a process still exists
+pub fn process_alive(pid: pid_t) -> bool {
+    Path::new(&format!(TASK_STAT_TEMPLATE!(), pid)).exists()
+}
+
+/// Start time of a process in clock ticks after boot, None once it exited.
+/// A pid reused by a new process has another start time
+pub fn process_start_time(pid: pid_t) -> Option<i64> {
+    let content = read_node(&format!(TASK_STAT_TEMPLATE!(), pid)).ok()?;
+    split_stat(&content).get(PROCESS_STAT_STARTTIME_SHIFT)?.parse().ok()
+}
+
+/// Binary a process runs, the target of /proc/<pid>/exe. None once it
+/// exited or without permission to follow the link
+///
+/// A binary replaced on disk keeps its path, the ` (deleted)` mark is dropped.
+pub fn process_exe(pid: pid_t) -> Option<String> {
+    let exe = fs::read_link(format!(crate::TASK_EXE_TEMPLATE!(), pid)).ok()?;
+    let exe = exe.to_string_lossy();
+    Some(exe.strip_suffix(EXE_DELETED_SUFFIX).unwrap_or(&exe).to_string())
+}
+
+/// Wall clock seconds of the boot, `btime` of /proc/stat
+pub fn boot_time() -> Result<u64, TraceError> {
+    let content = read_node(GLOBAL_SYSTEM_INFO)?;
+    let btime = content.lines().find_map(|line| line.strip_prefix(GLOBAL_BOOT_TIME_PREFIX));
+    btime.and_then(|btime| btime.trim().parse().ok())
+            .ok_or(TraceError::Parse { field: "btime", value: btime.unwrap_or_default().to_string() })
+}
+
+/// Wall clock seconds of a start time in clock ticks after boot, like the
+/// starttime of /proc/<pid>/stat
+///
+/// btime is whole seconds, so the result is as exact as one second.
+pub fn start_time_to_epoch(start_time: i64) -> Result<f64, TraceError> {
+    // SAFETY:
+    // Safe because sysconf has no side effects
+    let clock_ticks = unsafe { sysconf(_SC_CLK_TCK) as f64 };
+    Ok(boot_time()? as f64 + start_time as f64 / clock_ticks)
+}
+
+/// Wall clock seconds when a process started, None once it exited
+pub fn process_birth(pid: pid_t) -> Option<f64> {
+    start_time_to_epoch(process_start_time(pid)?).ok()
+}
+
+/// Names of the metric groups accepted by `MetricGroups::parse`
+pub const METRIC_GROUPS: [&str; 5] = ["io", "fd", "oom", "cgroup", "sched"];
+
+/// Optional metric groups, reading io and fd of another process needs the
+/// same access as ptrace
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetricGroups {
+    /// storage bytes and syscalls of `/proc/<pid>/io`
+    pub io: bool,
+    /// open fds of `/proc/<pid>/fd`
+    pub fd: bool,
+    /// `/proc/<pid>/oom_score_adj`
+    pub oom: bool,
+    /// `/proc/<pid>/cgroup` and `/proc/<pid>/cpuset`
+    pub cgroup: bool,
+    /// off-CPU time of `/proc/<pid>/task/<tid>/schedstat`
+    pub sched: bool,
+}
+
+impl Default for MetricGroups {
+    fn default() -> Self {
+        MetricGroups { io: true, fd: true, oom: true, cgroup: true, sched: true }
+    }
+}
+
+impl MetricGroups {
+    /// No optional metrics, their columns stay 0
+    pub fn none() -> MetricGroups {
+        MetricGroups { io: false, fd: false, oom: false, cgroup: false, sched: false }
+    }
+
+    /// Parse a comma separated list of `METRIC_GROUPS`, `none` or empty for none
+    pub fn parse(list: &str) -> Option<MetricGroups> {
+        let mut groups = MetricGroups::none();
+        for name in list.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            match name {
+                "io" => groups.io = true,
+                "fd" => groups.fd = true,
+                "oom" => groups.oom = true,
+                "cgroup" => groups.cgroup = true,
+                "sched" => groups.sched = true,
+                "none" => {},
+                _ => return None,
+            }
+        }
+        Some(groups)
+    }
+
+    /// Names of the enabled groups
+    pub fn names(&self) -> Vec<&'static str> {
+        [self.io, self.fd, self.oom, self.cgroup, self.sched].iter().zip(METRIC_GROUPS)
+                .filter(|(enabled, _)| **enabled)
+                .map(|(_, name)| name)
+                .collect()
+    }
+
+    /// Collectors run for every sample
+    pub fn collector_count(&self) -> u32 {
+        BASE_COLLECTOR_COUNT + self.names().len() as u32
+    }
+}
+
+/// Options of the monitor threads
+#[derive(Clone, Copy, Debug)]
+pub struct MonitorOptions {
+    /// retry policies of the collectors
+    pub policies: CollectorPolicies,
+    /// how impossible values are handled
+    pub validation: ValidationMode,
+    /// also record a sample of every thread
+    pub per_thread: bool,
+    /// identity to drop to once the files of the targets are open
+    pub drop_privileges: Option<DropPrivileges>,
+    /// wait for targets which have not started yet, and re-attach to a
+    /// target resolved by name when it exits and starts again
+    pub follow: bool,
+    /// optional metric groups to collect
+    pub metrics: MetricGroups,
+    /// warn when one sample takes longer than this fraction of the interval
+    pub slow_sample_fraction: f64,
+    /// memory PSI `some avg10` in percent at which traces buffering their
+    /// samples write them out and stream from then on, 0 never does
+    pub memory_pressure_limit: f64,
+    /// a collector read taking longer fails and its node is skipped while it
+    /// hangs, None reads in the monitor thread without a timeout
+    pub read_timeout: Option<Duration>,
+    /// take a new baseline when a target execs another binary, e.g. a
+    /// wrapper script exec'ing the real one, instead of a delta across the exec
+    pub rebaseline_on_exec: bool,
+    /// report the progress of the trace on stderr and the bus this often,
+    /// None never does
+    pub progress: Option<Duration>,
+    /// return the samples of traces without session in `RecordProcess::record_infos`,
+    /// otherwise they are only written to the outputs
+    pub keep_samples: bool,
+    /// take an extra sample between the ticks when the RSS changed by this
+    /// fraction since the last sample or the cgroup of the target reports
+    /// memory.events, None samples at the ticks only
+    pub memory_trigger: Option<f64>,
+    /// read the priority of the binder threads of the targets this often and
+    /// record every change as an event, None does not
+    pub binder_priority: Option<Duration>,
+    /// split the CPU of the targets into foreground, background and screen
+    /// off windows, the cpuset comes from the cgroup metric group
+    pub cpu_split: bool,
+    /// on kernels without smaps_rollup, parse only the mappings of smaps whose
+    /// header changed and all of them every this many samples, None parses
+    /// all of them every sample
+    pub smaps_full_every: Option<u32>,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        MonitorOptions {
+            policies: CollectorPolicies::default(),
+            validation: ValidationMode::default(),
+            per_thread: false,
+            drop_privileges: None,
+            follow: false,
+            metrics: MetricGroups::default(),
+            slow_sample_fraction: 0.5,
+            memory_pressure_limit: 10.0,
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            rebaseline_on_exec: false,
+            progress: None,
+            keep_samples: true,
+            memory_trigger: None,
+            binder_priority: None,
+            cpu_split: false,
+            smaps_full_every: None,
+        }
+    }
+}
+
+/// Samples of one traced process
+#[derive(Default, Clone, Debug)]
+pub struct RecordProcess {
+    /// target label, see `TraceTarget::label`
+    pub name: String,
+    /// pid of the process
+    pub pid: pid_t,
+    /// ABI of the process binary
+    pub abi: ProcessAbi,
+    /// SELinux context of the process, empty when unknown
+    pub selinux_context: String,
+    /// wall clock seconds when the process started, see `process_birth`
+    pub birth: Option<f64>,
+    /// binary of the process, see `process_exe`, empty when unknown
+    pub exe: String,
+    /// parent chain and bound services of the process at attach
+    pub dependencies: DependencySnapshot,
+    /// comm of the process, from attach on it follows the renames
+    pub comm: String,
+    /// samples in time order, empty for session traces which write them as they go
+    pub record_infos: Vec<RecordItem>,
+    /// samples of every thread in time order, only when tracing per thread
+    pub thread_infos: Vec<ThreadItem>,
+    /// diagnostic events as (time, event, detail), empty for session and streaming traces
+    pub events: Vec<(i64, String, String)>,
+    /// wall clock seconds when the trace started
+    pub start_epoch: u64,
+    /// durations and wake up jitter of the samples
+    pub sampling: SamplingStats,
+    /// CPU by foreground, background and screen off window, only with `MonitorOptions::cpu_split`
+    pub cpu_split: CpuSplit,
+    /// `key=value` tags of the trace, written to every output
+    pub tags: Vec<(String, String)>,
+    /// smoothed companion columns written after the others, see `transform::SmoothSpec`
+    pub smoothed_columns: Vec<String>,
+    /// specs of the transforms applied before the samples are written, see `transform::ColumnTransform`
+    pub transforms: Vec<String>,
+}
+
+/// Prefix of the version stamp line of CSV outputs
+pub const CSV_STAMP_PREFIX: &str = "# process_trace";
+
+/// Word of the stamp line after which the transforms of the columns follow
+pub const CSV_STAMP_TRANSFORMS: &str = "transforms";
+
+/// Version stamp line of CSV outputs, without line ending, the transforms
+/// tell which columns hold transformed instead of measured values
+pub fn csv_stamp(schema: u32, transforms: &[String]) -> String {
+    match transforms.is_empty() {
+        true => format!("{} {} schema {}", CSV_STAMP_PREFIX, VERSION, schema),
+        false => format!("{} {} schema {} {} {}", CSV_STAMP_PREFIX, VERSION, schema,
+                CSV_STAMP_TRANSFORMS, transforms.join(" ")),
+    }
+}
+
+// Tags are extra `tag:<key>` columns after the columns of the schema
+pub(crate) fn write_csv_header(out: &mut impl Write, smoothed: &[String], transforms: &[String],
+        tags: &[(String, String)]) -> io::Result<()> {
+    let smoothed: String = smoothed.iter().map(|column| format!(",{}", column)).collect();
+    write!(out, "{} \r\n{}{}{} \r\n", csv_stamp(SCHEMA_VERSION, transforms), CSV_COLUMNS.join(","), smoothed,
+            tag_columns(tags))
+}
+
+pub(crate) fn write_csv_row(out: &mut impl Write, item: &RecordItem, smoothed: &[String],
+        tags: &[(String, String)]) -> io::Result<()> {
+    write!(out, "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}{}{} \r\n",
+            item.timestamp, item.pss, item.vm_rss, item.vm_anon, item.vm_file, item.vm_shmem,
+            item.vm_swap, item.voluntary_ctxt_switches, item.nonvoluntary_ctxt_switches,
+            item.minflt, item.majflt, plain("utime", item.utime), plain("stime", item.stime),
+            fixed("totalcputime", item.totalcputime, 3), fixed("gutime", item.global_utime, 3),
+            fixed("gstime", item.global_stime, 3), fixed("gtotalcputime", item.global_total_cpu_time, 3),
+            plain("cpuOccupancyRate", item.cpu_occupancy_rate),
+            item.priority, item.nice, item.num_threads, item.start_time, item.retries,
+            item.quality_flags, item.collectors_ok, item.carried_forward as u8,
+            fixed("confidence", item.confidence, 2),
+            item.read_bytes, item.write_bytes, item.syscr, item.syscw, item.fd_count, item.oom_score_adj,
+            fixed("runDelay", item.run_delay, 3), fixed("sleepTime", item.sleep_time, 3),
+            fixed("offCpuTime", item.off_cpu_time, 3), item.seq, item.missed, item.vm_hwm,
+            smoothed_values(smoothed, &item.smoothed), tag_values(tags))
+}
+
+// `,<value>` for every smoothed companion value, appended to CSV rows
+fn smoothed_values(columns: &[String], values: &[f64]) -> String {
+    columns.iter().zip(values).map(|(column, value)| format!(",{}", fixed(column, *value, 3))).collect()
 }
 
+// Parse one value of a procfs node
+pub(crate) fn parse_field<T: FromStr>(value: &str, field: &'static str) -> Result<T, TraceError> {
+    value.trim().parse::<T>().map_err(|_| TraceError::Parse { field, value: value.to_string() })
+}
+
+/// Key and value of a `<key>:<value> [kB]` line of a status or smaps node
+///
+/// Kernels pad the value with a tab, some vendor kernels with spaces or a
+/// tab and spaces, so both are trimmed and the unit is dropped.
+pub fn status_field(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    let value = value.trim();
+    Some((key.trim(), value.strip_suffix("kB").map(|value| value.trim_end()).unwrap_or(value)))
+}
+
+// Cumulative scheduler times of one thread
+#[derive(Clone, Copy, Debug, Default)]
+struct SchedTimes {
+    // seconds after boot the thread started
+    start: f64,
+    // seconds runnable but waiting for a CPU
+    run_delay: f64,
+    // seconds neither running nor runnable
+    sleep: f64,
+}
+
+// Open files and buffers of the collectors of one process, reused by all of its samples
 #[derive(Default)]
-struct RecordProcess {
-    pid: pid_t,
-    record_infos: Vec<RecordItem>,
+pub(crate) struct Sampler {
+    files: OpenFiles,
+    // content of the node read last
+    content: String,
+    // paths failing with EACCES in the last sample
+    denied: Vec<String>,
+    // scheduler times of the threads in the last sample, by tid
+    sched: HashMap<pid_t, SchedTimes>,
+    // Pss of the mappings of the last smaps pass, None parses all of them every time
+    smaps: Option<SmapsCache>,
 }
 
-fn dump_csv_info(record: &RecordProcess, process_name: &str) {
-    let out_path = format!(OUTPUT_FILE_TEMPLATE!(), process_name);
-    let mut out = File::create(&out_path).unwrap_or_else(|_| panic!("Open file {} failed!", out_path));
-    match write!(out, "time,pss,vmRss,vmAnon,vmFile,vmShmem,vmSwap,voluntaryCtxtSwitches,nonvoluntaryCtxtSwitches,minflt,\
-            majflt,utime,stime,totalcputime,gutime,gstime,gtotalcputime,cpuOccupancyRate,priority,nice,numThreads,startTime \r\n") {
-        Ok(_) => {},
-        Err(_) => {
-            panic!("dump_csv_info failed!");
-        },
+impl Sampler {
+    // Sampler whose reads fail after `timeout`, see `OpenFiles`, and which
+    // parses smaps incrementally with a full pass every `smaps_full_every` samples
+    fn with_timeout(timeout: Option<Duration>, smaps_full_every: Option<u32>) -> Sampler {
+        let mut sampler = Sampler::default();
+        sampler.files.set_timeout(timeout);
+        sampler.smaps = smaps_full_every.map(SmapsCache::new);
+        sampler
     }
-    for item in &record.record_infos {
-        match write!(out, "{},{},{},{},{},{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{},{},{},{},{} \r\n",
-                item.timestamp, item.pss, item.vm_rss, item.vm_anon, item.vm_file, item.vm_shmem,
-                item.vm_swap, item.voluntary_ctxt_switches, item.nonvoluntary_ctxt_switches,
-                item.minflt, item.majflt, item.utime, item.stime, item.totalcputime, item.global_utime,
-                item.global_stime, item.global_total_cpu_time, item.cpu_occupancy_rate,
-                item.priority, item.nice, item.num_threads, item.start_time) {
-            Ok(_) => {},
-            Err(_) => {
-                panic!("dump_csv_info failed!");
+
+    // Read a node into the content buffer, retry transient failures according to `policy`
+    pub(crate) fn read(&mut self, path: &str, policy: &RetryPolicy, retries: &mut usize) -> io::Result<&str> {
+        let Sampler { files, content, .. } = self;
+        with_retry(policy, retries, || files.read_into(path, content))?;
+        Ok(content)
+    }
+
+    // Read a node of a collector, a path failing with EACCES is added to `denied`
+    fn read_collector(&mut self, path: String, policy: &RetryPolicy,
+            retries: &mut usize) -> Result<&str, TraceError> {
+        let Sampler { files, content, denied, .. } = self;
+        match with_retry(policy, retries, || files.read_into(&path, content)) {
+            Ok(_) => Ok(content),
+            Err(e) => {
+                note_denied(denied, path, &e);
+                Err(e.into())
             },
         }
     }
-}
 
-fn get_process_pid(chr: &str) -> pid_t {
-    let mut pid: pid_t = -1;
-    let output: Output = Command::new("sh")
-            .arg("-c")
-            .arg(format!("ps -ef | grep {} | grep -v grep | awk '{{print $2}}'", chr))
-            .output()
-            .expect("Failed to execute command");
-    if output.status.success() {
-        pid = from_utf8(&output.stdout)
-                .unwrap()
-                .trim()
-                .parse::<pid_t>()
-                .unwrap_or(-1);
+    // Close the files of the last process, e.g. when the target restarted
+    fn reset(&mut self) {
+        self.files.clear();
+        self.sched.clear();
+        if let Some(smaps) = self.smaps.as_mut() {
+            *smaps = SmapsCache::new(smaps.full_every);
+        }
+    }
+
+    // Close the files not read since the last sweep
+    pub(crate) fn sweep(&mut self) {
+        self.files.sweep();
     }
-    if pid == -1 {
-        panic!("error pid: {}!", pid);
+
+    // Release the buffers, they grow again with the next sample
+    fn shrink(&mut self) {
+        self.files.shrink();
+        self.content = String::new();
+        self.sched.shrink_to_fit();
     }
-    pid
 }
 
-fn get_pss_info(item: &mut RecordItem, pid: pid_t) {
-    let path = format!(TASK_SMAPS_PID_TEMPLATE!(), pid);
-    let content = read_path(&path)
-            .unwrap_or_else(|_| panic!("Read path {} failed!", path));
+// smaps_rollup sums the mappings up in the kernel, it is missing before Linux 4.14
+pub(crate) fn pss_path(pid: pid_t) -> String {
+    if smaps_rollup_available() {
+        format!(TASK_SMAPS_ROLLUP_PID_TEMPLATE!(), pid)
+    } else {
+        format!(TASK_SMAPS_PID_TEMPLATE!(), pid)
+    }
+}
+
+// Sum the `Pss:` lines up, smaps has one for every mapping and smaps_rollup only one.
+// A sampler with a smaps cache skips the mappings which did not change
+pub(crate) fn get_pss_info(item: &mut RecordItem, pid: pid_t, sampler: &mut Sampler, policy: &RetryPolicy) -> io::Result<()> {
+    let Sampler { files, content, smaps, .. } = sampler;
+    match with_retry(policy, &mut item.retries, || files.read_into(&pss_path(pid), content)) {
+        Ok(_) => {},
+        // Kernel threads and zombies have no memory, smaps is empty but smaps_rollup fails
+        Err(e) if e.raw_os_error() == Some(ESRCH) => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    if let (false, Some(smaps)) = (smaps_rollup_available(), smaps.as_mut()) {
+        item.pss += smaps.sum(pid, content);
+        return Ok(());
+    }
     let lines = content.lines();
 
-    for line in lines {
-        if !line.starts_with(TASK_PSS_PREFIX) {
-            continue;
-        }
-        let number_part = line
-                .trim_start_matches(TASK_PSS_PREFIX)
-                .trim_end_matches(" kB")
-                .trim();
-        if let Ok(number) = number_part.parse::<isize>() {
+    for (_, value) in lines.filter_map(status_field).filter(|(key, _)| *key == TASK_PSS_KEY) {
+        if let Ok(number) = value.parse::<isize>() {
             item.pss += number;
         }
     }
+    Ok(())
 }
 
-fn get_global_cpu_info(item: &mut RecordItem) {
-    let content = read_path(GLOBAL_SYSTEM_INFO)
-            .unwrap_or_else(|_| panic!("Read path {} failed!", GLOBAL_SYSTEM_INFO));
+pub(crate) fn get_global_cpu_info(item: &mut RecordItem, sampler: &mut Sampler, policy: &RetryPolicy) -> io::Result<()> {
+    let content = sampler.read(GLOBAL_SYSTEM_INFO, policy, &mut item.retries)?;
     let lines = content.lines();
     for line in lines {
         if !line.starts_with(GLOBAL_CPU_STAT_PREFIX) {
@@ -180,173 +911,1634 @@ fn get_global_cpu_info(item: &mut RecordItem) {
         // SAFETY:
         // Safe because we've verified that the system call returns correctly
         let clock_ticks = unsafe { sysconf(_SC_CLK_TCK) as f64 };
-        item.global_utime += process_stat_strs[SYSTEM_GLOBAL_USER_TIME_SHIFT]
-                .parse::<f64>()
-                .unwrap_or(0.0) / clock_ticks;
-        item.global_stime += process_stat_strs[SYSTEM_GLOBAL_SYSTEM_TIME_SHIFT]
-                .parse::<f64>()
-                .unwrap_or(0.0) / clock_ticks;
+        let get = |shift: usize| process_stat_strs.get(shift)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(0.0);
+        item.global_utime += get(SYSTEM_GLOBAL_USER_TIME_SHIFT) / clock_ticks;
+        item.global_stime += get(SYSTEM_GLOBAL_SYSTEM_TIME_SHIFT) / clock_ticks;
     }
     item.global_total_cpu_time = item.global_stime + item.global_utime;
+    Ok(())
+}
+
+// Keep the values of a failed collector from the last sample
+fn carry_forward_global_cpu(item: &mut RecordItem, last: &RecordItem) {
+    item.global_utime = last.global_utime;
+    item.global_stime = last.global_stime;
+    item.global_total_cpu_time = last.global_total_cpu_time;
+    item.carried_forward = true;
+}
+
+fn carry_forward_task_status(item: &mut RecordItem, last: &RecordItem) {
+    item.vm_rss = last.vm_rss;
+    item.vm_hwm = last.vm_hwm;
+    item.vm_anon = last.vm_anon;
+    item.vm_file = last.vm_file;
+    item.vm_shmem = last.vm_shmem;
+    item.vm_swap = last.vm_swap;
+    item.voluntary_ctxt_switches = last.voluntary_ctxt_switches;
+    item.nonvoluntary_ctxt_switches = last.nonvoluntary_ctxt_switches;
+    item.carried_forward = true;
+}
+
+fn carry_forward_task_stat(item: &mut RecordItem, last: &RecordItem) {
+    item.minflt = last.minflt;
+    item.majflt = last.majflt;
+    item.utime = last.utime;
+    item.stime = last.stime;
+    item.totalcputime = last.totalcputime;
+    item.priority = last.priority;
+    item.nice = last.nice;
+    item.num_threads = last.num_threads;
+    item.start_time = last.start_time;
+    item.carried_forward = true;
+}
+
+fn carry_forward_io(item: &mut RecordItem, last: &RecordItem) {
+    item.read_bytes = last.read_bytes;
+    item.write_bytes = last.write_bytes;
+    item.syscr = last.syscr;
+    item.syscw = last.syscw;
+    item.carried_forward = true;
+}
+
+// Read the storage counters of a process
+pub(crate) fn parse_task_io(item: &mut RecordItem, content: &str) -> Result<(), TraceError> {
+    for line in content.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        match key {
+            TASK_IO_READ_BYTES => item.read_bytes = parse_field(value, "read_bytes")?,
+            TASK_IO_WRITE_BYTES => item.write_bytes = parse_field(value, "write_bytes")?,
+            TASK_IO_SYSCR => item.syscr = parse_field(value, "syscr")?,
+            TASK_IO_SYSCW => item.syscw = parse_field(value, "syscw")?,
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+// Read the cgroup paths of a process, lines are `hierarchy:controllers:path`
+pub(crate) fn parse_task_cgroup(item: &mut RecordItem, content: &str) {
+    let mut cgroups = Vec::new();
+    for line in content.lines() {
+        let mut fields = line.splitn(3, ':');
+        if let (Some(_), Some(controllers), Some(path)) = (fields.next(), fields.next(), fields.next()) {
+            if controllers.split(',').any(|controller| controller == CGROUP_CPUSET_CONTROLLER) {
+                item.cpuset = path.to_string();
+            }
+            cgroups.push(format!("{}:{}", controllers, path));
+        }
+    }
+    item.cgroup = cgroups.join(";");
+}
+
+// Read the optional metric groups of a process into a cumulative sample
+fn collect_metrics(record_item: &mut RecordItem, last_record_item: &RecordItem, pid: pid_t,
+        sampler: &mut Sampler, policy: &RetryPolicy, metrics: MetricGroups) {
+    if metrics.io {
+        let io = sampler.read_collector(format!(TASK_IO_TEMPLATE!(), pid), policy, &mut record_item.retries)
+                .and_then(|content| parse_task_io(record_item, content));
+        match io {
+            Ok(_) => record_item.collectors_ok += 1,
+            Err(e) => {
+                println!("read io of {} failed: {}", pid, e);
+                carry_forward_io(record_item, last_record_item);
+            },
+        }
+    }
+    if metrics.fd {
+        let fd_dir = format!(TASK_FD_TEMPLATE!(), pid);
+        match with_retry(policy, &mut record_item.retries, || fs::read_dir(&fd_dir)) {
+            Ok(entries) => {
+                record_item.fd_count = entries.filter(|entry| entry.is_ok()).count();
+                record_item.collectors_ok += 1;
+            },
+            Err(e) => {
+                println!("List dir {} failed!", fd_dir);
+                note_denied(&mut sampler.denied, fd_dir, &e);
+                record_item.fd_count = last_record_item.fd_count;
+                record_item.carried_forward = true;
+            },
+        }
+    }
+    if metrics.oom {
+        let oom = sampler.read_collector(format!(TASK_OOM_SCORE_ADJ_TEMPLATE!(), pid), policy,
+                &mut record_item.retries)
+                .and_then(|content| parse_field(content, "oom_score_adj"));
+        match oom {
+            Ok(oom_score_adj) => {
+                record_item.oom_score_adj = oom_score_adj;
+                record_item.collectors_ok += 1;
+            },
+            Err(e) => {
+                println!("read oom_score_adj of {} failed: {}", pid, e);
+                record_item.oom_score_adj = last_record_item.oom_score_adj;
+                record_item.carried_forward = true;
+            },
+        }
+    }
+    if metrics.cgroup {
+        match sampler.read_collector(format!(TASK_CGROUP_TEMPLATE!(), pid), policy, &mut record_item.retries) {
+            Ok(content) => {
+                parse_task_cgroup(record_item, content);
+                // Kernels with cgroup v2 only have no cpuset line, the cpuset node still tells
+                if let Ok(cpuset) = sampler.read(&format!(TASK_CPUSET_TEMPLATE!(), pid),
+                        &RetryPolicy::NONE, &mut record_item.retries) {
+                    record_item.cpuset = cpuset.trim().to_string();
+                }
+                record_item.collectors_ok += 1;
+            },
+            Err(e) => {
+                println!("read cgroup of {} failed: {}", pid, e);
+                record_item.cgroup = last_record_item.cgroup.clone();
+                record_item.cpuset = last_record_item.cpuset.clone();
+                record_item.carried_forward = true;
+            },
+        }
+    }
+}
+
+// Read the status values of one thread
+pub(crate) fn parse_task_status(item: &mut RecordItem, status: &str) -> Result<(), TraceError> {
+    for (key, value) in status.lines().filter_map(status_field) {
+        match key {
+            TASK_RSS_ANON_KEY => item.vm_anon = parse_field(value, "vm_anon")?,
+            TASK_VM_RSS_KEY => item.vm_rss = parse_field(value, "vm_rss")?,
+            TASK_VM_HWM_KEY => item.vm_hwm = parse_field(value, "vm_hwm")?,
+            TASK_RSS_FILE_KEY => item.vm_file = parse_field(value, "vm_file")?,
+            TASK_RSS_SHMEM_KEY => item.vm_shmem = parse_field(value, "vm_shmem")?,
+            TASK_VM_SWAP_KEY => item.vm_swap = parse_field(value, "vm_swap")?,
+            TASK_NONVOLUNTARY_SWITCH_KEY => item.nonvoluntary_ctxt_switches = parse_field(value,
+                    "nonvoluntary_ctxt_switches")?,
+            TASK_VOLUNTARY_SWITCH_KEY => item.voluntary_ctxt_switches = parse_field(value,
+                    "voluntary_ctxt_switches")?,
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+// comm of a stat node without its parentheses, empty when it is broken
+fn stat_comm(content: &str) -> String {
+    split_stat(content).get(1)
+            .map(|comm| comm.trim_start_matches('(').trim_end_matches(')').to_string())
+            .unwrap_or_default()
+}
+
+// Split a stat node into fields, comm is one field even when it contains
+// spaces or parentheses
+pub(crate) fn split_stat(content: &str) -> Vec<&str> {
+    match (content.find('('), content.rfind(')')) {
+        (Some(open), Some(close)) if open < close => {
+            let mut fields = vec![content[..open].trim(), &content[open..=close]];
+            fields.extend(content[close + 1..].split_whitespace());
+            fields
+        },
+        _ => content.split_whitespace().collect(),
+    }
+}
+
+// Read the stat values of a process or thread, nothing is set when a value is broken
+pub(crate) fn parse_task_stat(item: &mut RecordItem, content: &str) -> Result<(), TraceError> {
+    let process_stat_strs = split_stat(content);
+    if process_stat_strs.len() <= PROCESS_STAT_STARTTIME_SHIFT {
+        return Err(TraceError::Parse { field: "stat", value: content.trim().to_string() });
+    }
+    let minflt: usize = parse_field(process_stat_strs[PROCESS_STAT_MINFLT_SHIFT], "minflt")?;
+    let majflt: usize = parse_field(process_stat_strs[PROCESS_STAT_MAJFLT_SHIFT], "majflt")?;
+    // SAFETY:
+    // Safe because we've verified that the system call returns correctly
+    let clock_ticks = unsafe { sysconf(_SC_CLK_TCK) as f64 };
+    let utime = parse_field::<f64>(process_stat_strs[PROCESS_STAT_UTIME_SHIFT], "utime")? / clock_ticks;
+    let stime = parse_field::<f64>(process_stat_strs[PROCESS_STAT_STIME_SHIFT], "stime")? / clock_ticks;
+    let priority = parse_field(process_stat_strs[PROCESS_STAT_PRIORITY_SHIFT], "priority")?;
+    let nice = parse_field(process_stat_strs[PROCESS_STAT_NICE_SHIFT], "nice")?;
+    let num_threads = parse_field(process_stat_strs[PROCESS_STAT_NUM_THREADS_SHIFT], "num_threads")?;
+    let start_time = parse_field(process_stat_strs[PROCESS_STAT_STARTTIME_SHIFT], "start_time")?;
+    item.minflt = minflt;
+    item.majflt = majflt;
+    item.utime = utime;
+    item.stime = stime;
+    item.totalcputime = utime + stime;
+    item.priority = priority;
+    item.nice = nice;
+    item.num_threads = num_threads;
+    item.start_time = start_time;
+    Ok(())
+}
+
+// Add the status values of one thread to the process sample, memory is
+// shared by all threads while context switches are counted per thread
+fn add_thread_status(item: &mut RecordItem, thread: &RecordItem) {
+    item.vm_rss = thread.vm_rss;
+    item.vm_hwm = thread.vm_hwm;
+    item.vm_anon = thread.vm_anon;
+    item.vm_file = thread.vm_file;
+    item.vm_shmem = thread.vm_shmem;
+    item.vm_swap = thread.vm_swap;
+    item.voluntary_ctxt_switches += thread.voluntary_ctxt_switches;
+    item.nonvoluntary_ctxt_switches += thread.nonvoluntary_ctxt_switches;
+}
+
+// Remember a path the tracer may not read
+fn note_denied(denied: &mut Vec<String>, path: String, err: &io::Error) {
+    if err.kind() == io::ErrorKind::PermissionDenied && !denied.contains(&path) {
+        denied.push(path);
+    }
+}
+
+// Read all collectors of a process and the enabled `metrics` into a cumulative
+// sample, a failed collector carries its values forward from `last_record_item`.
+// With `threads`, a cumulative sample of every thread is added to it.
+// Paths failing with EACCES are left in the `denied` of the sampler
+pub(crate) fn collect_record(record_item: &mut RecordItem, last_record_item: &RecordItem, pid: pid_t,
+        sampler: &mut Sampler, policies: &CollectorPolicies, metrics: MetricGroups,
+        mut threads: Option<&mut Vec<ThreadItem>>) {
+    sampler.denied.clear();
+    if let Err(e) = get_global_cpu_info(record_item, sampler, &policies.global_cpu) {
+        println!("read {} failed!", GLOBAL_SYSTEM_INFO);
+        note_denied(&mut sampler.denied, GLOBAL_SYSTEM_INFO.to_string(), &e);
+        carry_forward_global_cpu(record_item, last_record_item);
+    } else {
+        record_item.collectors_ok += 1;
+    }
+    if let Err(e) = get_pss_info(record_item, pid, sampler, &policies.pss) {
+        println!("read smaps of {} failed!", pid);
+        note_denied(&mut sampler.denied, pss_path(pid), &e);
+        record_item.pss = last_record_item.pss;
+        record_item.carried_forward = true;
+    } else {
+        record_item.collectors_ok += 1;
+    }
+    // Times and faults of the whole process, the kernel also counts exited threads
+    let stat_path = format!(TASK_STAT_TEMPLATE!(), pid);
+    let stat = sampler.read_collector(stat_path, &policies.task_stat, &mut record_item.retries)
+            .and_then(|content| {
+                record_item.comm = stat_comm(content);
+                parse_task_stat(record_item, content)
+            });
+    match stat {
+        Ok(_) => record_item.collectors_ok += 1,
+        Err(e) => {
+            println!("read stat of {} failed: {}", pid, e);
+            carry_forward_task_stat(record_item, last_record_item);
+        },
+    }
+    collect_metrics(record_item, last_record_item, pid, sampler, &policies.metrics, metrics);
+    // Off-CPU times are summed from the threads, which may exit between samples
+    let uptime = match metrics.sched {
+        true => sampler.read(UPTIME_PATH, &policies.metrics, &mut record_item.retries).ok()
+                .and_then(|content| content.split_whitespace().next()?.parse::<f64>().ok()),
+        false => None,
+    };
+    record_item.run_delay = last_record_item.run_delay;
+    record_item.sleep_time = last_record_item.sleep_time;
+    let mut sched = HashMap::new();
+    let task_dir = format!(SUBTASK_PATH_TEMPLATE!(), pid);
+    let task_entries = match with_retry(&policies.task_status, &mut record_item.retries,
+            || fs::read_dir(&task_dir)) {
+        Ok(entries) => Some(entries),
+        Err(e) => {
+            println!("List dir {} failed!", task_dir);
+            note_denied(&mut sampler.denied, task_dir.clone(), &e);
+            None
+        },
+    };
+    // Threads read successfully by the status collector
+    let mut status_ok_count = 0;
+    for entry in task_entries.into_iter().flatten() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => { 
+                println!("get dir entry failed");
+                continue;
+            },
+        };
+        let pid_dir_path = entry.file_name();
+        let status_path = format!(TASK_STATUS_TID_TEMPLATE!(), pid, pid_dir_path.to_string_lossy());
+        let status = sampler.read(&status_path, &policies.task_status, &mut record_item.retries);
+        let status = match status {
+            Ok(status) => status,
+            Err(e) => {
+                println!("open file {} failed!", pid_dir_path.to_string_lossy());
+                note_denied(&mut sampler.denied, status_path, &e);
+                continue;
+            }
+        };
+        let mut thread = ThreadItem::default();
+        if let Err(e) = parse_task_status(&mut thread.item, status) {
+            println!("{} of {}", e, pid_dir_path.to_string_lossy());
+            continue;
+        }
+        add_thread_status(record_item, &thread.item);
+        status_ok_count += 1;
+        let tid: pid_t = pid_dir_path.to_string_lossy().parse().unwrap_or(0);
+        if let Some(times) = uptime.and_then(|uptime| read_sched_times(sampler, pid, tid, uptime, &policies.metrics,
+                &mut record_item.retries)) {
+            // Threads new since the last sample spent their whole life in the interval,
+            // a tid reused by a new thread has less run delay
+            let last = sampler.sched.get(&tid)
+                    .filter(|last| last.run_delay <= times.run_delay)
+                    .copied()
+                    .unwrap_or_default();
+            record_item.run_delay += (times.run_delay - last.run_delay).max(0.0);
+            record_item.sleep_time += (times.sleep - last.sleep).max(0.0);
+            thread.item.run_delay = times.run_delay;
+            thread.item.sleep_time = times.sleep;
+            sched.insert(tid, times);
+        }
+        let threads = match threads.as_mut() {
+            Some(threads) => threads,
+            None => continue,
+        };
+        let content = sampler.read(&format!(TASK_STAT_TID_TEMPLATE!(),
+                pid, pid_dir_path.to_string_lossy()),
+                &policies.task_stat, &mut record_item.retries);
+        let content = match content {
+            Ok(content) => content,
+            Err(_) => {
+                // The thread exited after its status was read
+                println!("read stat of {} failed!", pid_dir_path.to_string_lossy());
+                continue;
+            }
+        };
+        if let Err(e) = parse_task_stat(&mut thread.item, content) {
+            println!("{} of {}", e, pid_dir_path.to_string_lossy());
+            continue;
+        }
+        thread.tid = tid;
+        // comm of the stat node, the same as /proc/<pid>/task/<tid>/comm
+        thread.name = stat_comm(content);
+        threads.push(thread);
+    }
+    if status_ok_count > 0 {
+        record_item.collectors_ok += 1;
+    } else {
+        carry_forward_task_status(record_item, last_record_item);
+    }
+    if metrics.sched {
+        if sched.is_empty() {
+            println!("read schedstat of {} failed!", pid);
+            record_item.carried_forward = true;
+        } else {
+            record_item.collectors_ok += 1;
+        }
+        sampler.sched = sched;
+    }
+    // Threads gone since the last sample close their files
+    sampler.sweep();
+}
+
+// Read the cumulative scheduler times of a thread, the start of a known
+// thread is not read again
+fn read_sched_times(sampler: &mut Sampler, pid: pid_t, tid: pid_t, uptime: f64, policy: &RetryPolicy,
+        retries: &mut usize) -> Option<SchedTimes> {
+    // SAFETY:
+    // Safe because sysconf has no side effects
+    let clock_ticks = unsafe { sysconf(_SC_CLK_TCK) as f64 };
+    let (run_ns, delay_ns) = {
+        let content = sampler.read(&format!(TASK_SCHEDSTAT_TID_TEMPLATE!(), pid, tid), policy, retries).ok()?;
+        let mut fields = content.split_whitespace().map(|field| field.parse::<f64>().ok());
+        (fields.next()??, fields.next()??)
+    };
+    let start = match sampler.sched.get(&tid) {
+        Some(last) => last.start,
+        None => {
+            let content = sampler.read(&format!(TASK_STAT_TID_TEMPLATE!(), pid, tid), policy, retries).ok()?;
+            split_stat(content).get(PROCESS_STAT_STARTTIME_SHIFT)?.parse::<f64>().ok()? / clock_ticks
+        },
+    };
+    let run_delay = delay_ns / 1e9;
+    Some(SchedTimes { start, run_delay, sleep: (uptime - start - run_ns / 1e9 - run_delay).max(0.0) })
+}
+
+// Delta of two cumulative samples, `delta` holds the current values on entry
+pub(crate) fn delta_record(delta: &mut RecordItem, current: &RecordItem, last: &RecordItem, validator: &Validator) {
+    let quality = &mut delta.quality_flags;
+    delta.majflt = delta_count(current.majflt, last.majflt, quality);
+    delta.minflt = delta_count(current.minflt, last.minflt, quality);
+    delta.nonvoluntary_ctxt_switches = delta_count(current.nonvoluntary_ctxt_switches,
+            last.nonvoluntary_ctxt_switches, quality);
+    delta.voluntary_ctxt_switches = delta_count(current.voluntary_ctxt_switches,
+            last.voluntary_ctxt_switches, quality);
+    delta.stime = validator.delta_time(current.stime, last.stime, quality);
+    delta.utime = validator.delta_time(current.utime, last.utime, quality);
+    delta.global_stime = validator.delta_time(current.global_stime, last.global_stime, quality);
+    delta.global_utime = validator.delta_time(current.global_utime, last.global_utime, quality);
+    delta.totalcputime = validator.delta_time(current.totalcputime, last.totalcputime, quality);
+    delta.global_total_cpu_time = validator.delta_time(current.global_total_cpu_time,
+            last.global_total_cpu_time, quality);
+    delta.read_bytes = delta_count(current.read_bytes, last.read_bytes, quality);
+    delta.write_bytes = delta_count(current.write_bytes, last.write_bytes, quality);
+    delta.syscr = delta_count(current.syscr, last.syscr, quality);
+    delta.syscw = delta_count(current.syscw, last.syscw, quality);
+    delta.run_delay = validator.delta_time(current.run_delay, last.run_delay, quality);
+    delta.sleep_time = validator.delta_time(current.sleep_time, last.sleep_time, quality);
+    delta.off_cpu_time = delta.run_delay + delta.sleep_time;
+    delta.cpu_occupancy_rate = cpu_share(delta.totalcputime, delta.global_total_cpu_time);
+}
+
+/// Share of `global` CPU seconds taken by `cpu`, 0 when no CPU time passed,
+/// e.g. two samples in one clock tick
+pub(crate) fn cpu_share(cpu: f64, global: f64) -> f64 {
+    if global > 0.0 { cpu / global } else { 0.0 }
+}
+
+// Names in event details have no spaces
+fn rename_value(name: &str) -> String {
+    name.replace(' ', "_")
+}
+
+// `tid= from= to=` of every thread whose name changed since the last sample,
+// the main thread `pid` renames with the process
+fn thread_renames(current: &[ThreadItem], last: &[ThreadItem], pid: pid_t) -> Vec<String> {
+    current.iter()
+            .filter(|thread| thread.tid != pid)
+            .filter_map(|thread| {
+                let last_thread = last.iter().find(|t| t.tid == thread.tid)?;
+                (!thread.name.is_empty() && thread.name != last_thread.name).then(|| format!("tid={} from={} to={}",
+                        thread.tid, rename_value(&last_thread.name), rename_value(&thread.name)))
+            })
+            .collect()
+}
+
+// Deltas of the threads seen in both samples, new threads start at their next sample
+fn delta_threads(current: &[ThreadItem], last: &[ThreadItem], process: &RecordItem,
+        validator: &Validator) -> Vec<ThreadItem> {
+    let mut deltas = Vec::new();
+    for thread in current {
+        let last_thread = match last.iter().find(|t| t.tid == thread.tid) {
+            Some(last_thread) => last_thread,
+            None => continue,
+        };
+        let mut delta = thread.clone();
+        delta_record(&mut delta.item, &thread.item, &last_thread.item, validator);
+        delta.item.timestamp = process.timestamp;
+        // Threads see the same system wide CPU time as the process
+        delta.item.global_utime = process.global_utime;
+        delta.item.global_stime = process.global_stime;
+        delta.item.global_total_cpu_time = process.global_total_cpu_time;
+        delta.item.cpu_occupancy_rate = cpu_share(delta.item.totalcputime, process.global_total_cpu_time);
+        deltas.push(delta);
+    }
+    deltas
+}
+
+// Sinks of a target traced without a session
+#[derive(Default)]
+struct TargetSinks {
+    sinks: Vec<Box<dyn RecordSink>>,
+    // write every sample as it is collected
+    streaming: bool,
+    tags: Vec<(String, String)>,
+}
+
+// Write buffered or streamed samples to every sink
+fn sink_all(sinks: &mut [Box<dyn RecordSink>],
+        mut op: impl FnMut(&mut dyn RecordSink) -> io::Result<()>) -> io::Result<()> {
+    for sink in sinks.iter_mut() {
+        op(sink.as_mut())?;
+    }
+    Ok(())
+}
+
+// Record an event the way the samples of the target are recorded
+fn record_event(record_process: &mut RecordProcess, session_output: Option<&mut SessionOutput>,
+        sinks: &mut [Box<dyn RecordSink>], streaming: bool, time: i64, event: &str,
+        detail: &str) -> io::Result<()> {
+    match session_output {
+        Some(output) => output.event(record_process, time, event, detail),
+        None if streaming => sink_all(sinks, |sink| sink.event(record_process, time, event, detail)
+                .and_then(|_| sink.flush())),
+        None => {
+            record_process.events.push((time, event.to_string(), detail.to_string()));
+            Ok(())
+        },
+    }
+}
+
+// Point the record at a new process of the target
+fn attach(record_process: &mut RecordProcess, pid: pid_t) {
+    record_process.pid = pid;
+    publish(|| BusMessage::Attach { target: record_process.name.clone(), pid });
+    record_process.abi = ProcessAbi::detect(pid);
+    record_process.selinux_context = process_context(pid).unwrap_or_default();
+    record_process.birth = process_birth(pid);
+    record_process.exe = process_exe(pid).unwrap_or_default();
+    record_process.comm = process_info(pid).map(|info| info.comm).unwrap_or_default();
+    record_process.dependencies = DependencySnapshot::take(pid);
+    println!("process {} ({}) {}", record_process.name, pid, record_process.dependencies.detail());
+    if record_process.abi != ProcessAbi::Unknown
+            && record_process.abi.bits() != ProcessAbi::current().bits() {
+        println!("process {} ({}) is {}, tracer is {}", record_process.name, pid,
+                record_process.abi.name(), ProcessAbi::current().name());
+    }
+}
+
+// Persist the outputs before the progress, so a kill never loses a committed sample
+fn commit_progress(session: &Mutex<SessionState>, output: &mut SessionOutput, monitor_target: &str,
+        time_count: i64, sequence: &SampleSequence) -> io::Result<()> {
+    let offsets = output.flush()?;
+    let mut state = session.lock().unwrap();
+    state.commit(monitor_target, time_count, sequence.seq, sequence.last_time, offsets);
+    if let Err(e) = state.save() {
+        println!("save session {} failed: {}", state.dir, e);
+    }
+    Ok(())
+}
+
+// Numbering of the written samples of a target and the cause of the ticks
+// missed since the last one
+#[derive(Clone, Debug, Default)]
+struct SampleSequence {
+    seq: u64,
+    last_time: Option<i64>,
+    gap_reason: Option<&'static str>,
+}
+
+impl SampleSequence {
+    // Remember why ticks are skipped, the first cause of a gap is kept
+    fn skip(&mut self, reason: &'static str) {
+        if self.last_time.is_some() {
+            self.gap_reason.get_or_insert(reason);
+        }
+    }
+
+    // Number a sample about to be written, returns the detail of its gap event
+    fn number(&mut self, item: &mut RecordItem, interval: i64) -> Option<String> {
+        item.seq = self.seq;
+        self.seq += 1;
+        let last = self.last_time.replace(item.timestamp);
+        let reason = self.gap_reason.take();
+        let elapsed = item.timestamp - last?;
+        if interval <= 0 || elapsed <= interval {
+            return None;
+        }
+        item.missed = (elapsed / interval).saturating_sub(1) as u64;
+        Some(format!("from={} to={} missed={} reason={}", last?, item.timestamp, item.missed,
+                reason.unwrap_or("unknown")))
+    }
+}
+
+// State of one traced target between the ticks of the scheduler
+struct TargetMonitor {
+    monitor_time: i64,
+    monitor_iterval: i64,
+    monitor_target: String,
+    target: TraceTarget,
+    // Only targets resolved by name can start again under another pid
+    follow: bool,
+    options: MonitorOptions,
+    validator: Validator,
+    session: Option<Arc<Mutex<SessionState>>>,
+    session_output: Option<SessionOutput>,
+    sinks: Vec<Box<dyn RecordSink>>,
+    streaming: bool,
+    record_process: RecordProcess,
+    // Samples of a trace without session which are written when it ends
+    samples: SampleArena,
+    record_item: RecordItem,
+    last_record_item: RecordItem,
+    // Cumulative samples of the threads, empty unless tracing per thread
+    threads: Vec<ThreadItem>,
+    last_threads: Vec<ThreadItem>,
+    sampler: Sampler,
+    // column transforms of the session, applied before the samples are written
+    transforms: TransformPipeline,
+    reported_denials: HashSet<String>,
+    first_sample: bool,
+    time_count: time_t,
+    // Wall clock seconds lost while the tracer was not running
+    time_offset: time_t,
+    start_time: Option<i64>,
+    // Last attached pid, 0 before the first one
+    last_pid: pid_t,
+    sequence: SampleSequence,
+    // Time of the first frozen tick while the target is frozen
+    frozen_since: Option<i64>,
+    // Freezer state of the attached process, None before the first tick
+    freezer: Option<FreezerWatch>,
+    // /proc/uptime of the last two samples, a stalled clock does not move it
+    uptimes: (f64, f64),
+    // Micro-sampling of the binder thread priorities of the attached process
+    binder_priority: Option<BinderPrioritySampler>,
+    // Screen state of the tick, read once for all targets, only with `MonitorOptions::cpu_split`
+    screen_on: Option<bool>,
+}
+
+impl TargetMonitor {
+    // pid 0 waits for the target to start
+    fn new(monitor_time: i64, monitor_iterval: i64, monitor_target: String, pid: pid_t,
+            session: Option<Arc<Mutex<SessionState>>>, target_sinks: TargetSinks,
+            options: MonitorOptions) -> Result<TargetMonitor, TraceError> {
+        let TargetSinks { mut sinks, streaming, tags } = target_sinks;
+        let target = TraceTarget::parse(&monitor_target);
+        let mut record_process = RecordProcess {
+            name: target.label(),
+            start_epoch: now_epoch(),
+            tags,
+            ..RecordProcess::default()
+        };
+        if pid > 0 {
+            attach(&mut record_process, pid);
+        }
+        let mut time_count = 0;
+        let mut time_offset = 0;
+        let mut session_output = None;
+        let mut transforms = TransformPipeline::default();
+        let mut sequence = SampleSequence::default();
+        if let Some(session) = &session {
+            let state = session.lock().unwrap();
+            transforms = TransformPipeline::new(&state.transforms, &state.smooths);
+            record_process.smoothed_columns = transforms.smoothed_columns();
+            record_process.transforms = state.transforms.iter().map(|transform| transform.spec()).collect();
+            let elapsed = state.target(&monitor_target)
+                    .map(|target| target.elapsed)
+                    .unwrap_or(0);
+            if let Some(target) = state.target(&monitor_target) {
+                sequence.seq = target.seq;
+                sequence.last_time = target.last_sample;
+            }
+            let mut output = SessionOutput::open(&state, &monitor_target)?;
+            record_process.start_epoch = state.start_epoch;
+            record_process.tags = state.tags.clone();
+            if output.start(&record_process).is_err() {
+                println!("record start event of {} failed", record_process.name);
+            }
+            if elapsed > 0 {
+                // Resumed, the samples between elapsed and now are lost, the
+                // gap is recorded with the next sample
+                time_count = elapsed;
+                time_offset = now_epoch().saturating_sub(state.start_epoch) as time_t - elapsed;
+                sequence.skip("resume");
+            }
+            session_output = Some(output);
+        } else if streaming {
+            sink_all(&mut sinks, |sink| sink.begin(&record_process).and_then(|_| sink.flush()))?;
+        }
+        let samples = match session.is_none() && !streaming {
+            true => SampleArena::for_trace(monitor_time, monitor_iterval),
+            false => SampleArena::default(),
+        };
+        Ok(TargetMonitor {
+            monitor_time,
+            monitor_iterval,
+            follow: options.follow && !matches!(target, TraceTarget::Pid(_)),
+            monitor_target,
+            target,
+            validator: Validator::new(options.validation),
+            options,
+            session,
+            session_output,
+            sinks,
+            streaming,
+            record_process,
+            samples,
+            record_item: RecordItem::default(),
+            last_record_item: RecordItem::default(),
+            threads: Vec::new(),
+            last_threads: Vec::new(),
+            sampler: Sampler::with_timeout(options.read_timeout, options.smaps_full_every),
+            transforms,
+            reported_denials: HashSet::new(),
+            first_sample: true,
+            time_count,
+            time_offset,
+            start_time: process_start_time(pid),
+            last_pid: pid,
+            sequence,
+            frozen_since: None,
+            freezer: None,
+            uptimes: (0.0, 0.0),
+            binder_priority: None,
+            screen_on: None,
+        })
+    }
+
+    // Record an event of the target, `session_event` also adds it to the session state
+    fn event(&mut self, time: i64, event: &str, detail: &str, session_event: bool) -> io::Result<()> {
+        // Diagnostics carry their code in every sink, the bus and the session log
+        let detail = &coded_detail(event, detail);
+        publish(|| BusMessage::Event {
+            target: self.record_process.name.clone(),
+            time,
+            event: event.to_string(),
+            detail: detail.to_string(),
+        });
+        record_event(&mut self.record_process, self.session_output.as_mut(), &mut self.sinks,
+                self.streaming, time, event, detail)?;
+        if let (true, Some(session)) = (session_event, &self.session) {
+            let _ = session.lock().unwrap().append_event(&self.monitor_target, event, detail);
+        }
+        Ok(())
+    }
+
+    // Attach a started process of a waiting target, false while it is not running
+    fn resolve(&mut self) -> Result<bool, TraceError> {
+        let pid = match self.target.resolve_pid() {
+            Ok(pid) => pid,
+            Err(_) => return Ok(false),
+        };
+        attach(&mut self.record_process, pid);
+        self.start_time = process_start_time(pid);
+        let (event, detail) = match self.last_pid {
+            0 => ("process_start", format!("pid={} birth={} {}", pid, format_birth(self.record_process.birth),
+                    self.record_process.dependencies.detail())),
+            _ => ("process_restart", format!("pid={} old_pid={} birth={} {}", pid, self.last_pid,
+                    format_birth(self.record_process.birth), self.record_process.dependencies.detail())),
+        };
+        println!("process {} {}", self.record_process.name, detail);
+        self.last_pid = pid;
+        self.event(self.time_count + self.time_offset, event, &detail, true)?;
+        Ok(true)
+    }
+
+    // An exec keeps the pid and the start time, only the binary tells it
+    fn check_exec(&mut self) -> io::Result<()> {
+        let pid = self.record_process.pid;
+        let exe = match process_exe(pid) {
+            Some(exe) if exe != self.record_process.exe => exe,
+            _ => return Ok(()),
+        };
+        if self.record_process.exe.is_empty() {
+            self.record_process.exe = exe;
+            return Ok(());
+        }
+        let detail = format!("pid={} from={} to={}", pid, self.record_process.exe, exe);
+        println!("process {} exec: {}", self.record_process.name, detail);
+        self.record_process.exe = exe;
+        self.record_process.abi = ProcessAbi::detect(pid);
+        self.event(self.time_count + self.time_offset, "exec", &detail, true)?;
+        if self.options.rebaseline_on_exec {
+            self.first_sample = true;
+            self.threads.clear();
+            self.sequence.skip("exec");
+        }
+        Ok(())
+    }
+
+    // Take the sample of one interval, false once the target is done
+    fn tick(&mut self) -> Result<bool, TraceError> {
+        // monitor_time <= 0 traces until the process exits
+        if self.monitor_time > 0 && self.time_count >= self.monitor_time {
+            return Ok(false);
+        }
+        loop {
+            if self.record_process.pid == 0 && !self.resolve()? {
+                self.sequence.skip("not_running");
+                self.time_count += self.monitor_iterval;
+                self.commit()?;
+                return Ok(true);
+            }
+            // The pid is gone, or was reused by another process
+            if self.start_time.is_some() && process_start_time(self.record_process.pid) == self.start_time {
+                self.check_exec()?;
+                if self.pause_frozen()? {
+                    return Ok(true);
+                }
+                break;
+            }
+            println!("process {} ({}) exited", self.record_process.name, self.record_process.pid);
+            if !self.follow {
+                return Ok(false);
+            }
+            let detail = format!("pid={}", self.record_process.pid);
+            self.event(self.time_count + self.time_offset, "process_exit", &detail, true)?;
+            self.sequence.skip("process_exit");
+            // Samples of the next process start from a new baseline
+            self.record_process.pid = 0;
+            self.record_item = RecordItem::default();
+            self.threads.clear();
+            self.sampler.reset();
+            self.first_sample = true;
+        }
+        let tick_start = Instant::now();
+        self.sample()?;
+        self.record_binder_priority()?;
+        let interval = Duration::from_secs(self.monitor_iterval as u64);
+        let duration = tick_start.elapsed();
+        self.record_process.sampling.duration.add(duration);
+        if duration.as_secs_f64() > interval.as_secs_f64() * self.options.slow_sample_fraction {
+            self.record_process.sampling.slow += 1;
+            let detail = format!("duration_us={} interval_us={}", duration.as_micros(), interval.as_micros());
+            println!("{} slow sample: {}", self.record_process.name, detail);
+            self.event(self.time_count + self.time_offset, "slow_sample", &detail, false)?;
+        }
+        self.time_count += self.monitor_iterval;
+        Ok(true)
+    }
+
+    // Why the sample repeats the last one, None while the target runs or just idles.
+    // Identical counters alone are an idle process, the freezer or a clock
+    // which did not move either tell a frozen one
+    fn frozen_reason(&mut self) -> Option<&'static str> {
+        let (item, last) = (&self.record_item, &self.last_record_item);
+        if item.carried_forward || item.totalcputime != last.totalcputime
+                || item.voluntary_ctxt_switches != last.voluntary_ctxt_switches
+                || item.nonvoluntary_ctxt_switches != last.nonvoluntary_ctxt_switches
+                || item.minflt != last.minflt || item.majflt != last.majflt || item.vm_rss != last.vm_rss {
+            return None;
+        }
+        if self.uptimes.1 > 0.0 && self.uptimes.1 <= self.uptimes.0 {
+            return Some("stalled_clock");
+        }
+        self.freezer_frozen().then_some("cgroup_freezer")
+    }
+
+    // Record the priority changes of the binder threads since the last tick, at
+    // the second they were seen. The sampling follows the target to a new process
+    fn record_binder_priority(&mut self) -> io::Result<()> {
+        let (interval, pid) = match self.options.binder_priority {
+            Some(interval) => (interval, self.record_process.pid),
+            None => return Ok(()),
+        };
+        let (changes, dropped) = match self.binder_priority.as_ref() {
+            Some(sampler) if sampler.pid == pid => sampler.drain(),
+            _ => {
+                self.binder_priority = Some(BinderPrioritySampler::spawn(pid, interval));
+                return Ok(());
+            },
+        };
+        let now = self.time_count + self.time_offset;
+        for change in &changes {
+            self.event(now - change.seen.elapsed().as_secs() as i64, "binder_priority", &change.detail(), false)?;
+        }
+        if dropped > 0 {
+            self.event(now, "binder_priority", &format!("dropped={}", dropped), false)?;
+        }
+        Ok(())
+    }
+
+    // Whether the cgroup freezer froze the attached process
+    fn freezer_frozen(&mut self) -> bool {
+        let pid = self.record_process.pid;
+        if self.freezer.as_ref().map(|freezer| freezer.pid) != Some(pid) {
+            self.freezer = Some(FreezerWatch::open(pid));
+        }
+        self.freezer.as_ref().and_then(FreezerWatch::frozen).unwrap_or(false)
+    }
+
+    // Skip the collectors for the tick while the freezer holds the target, the
+    // samples go on with the delta over the frozen period once it is thawed
+    fn pause_frozen(&mut self) -> Result<bool, TraceError> {
+        if !self.freezer_frozen() {
+            return Ok(false);
+        }
+        if self.frozen_since.is_none() {
+            let time = self.time_count + self.time_offset;
+            self.frozen_since = Some(time);
+            println!("{} frozen: reason=cgroup_freezer", self.record_process.name);
+            self.event(time, "frozen", "reason=cgroup_freezer", true)?;
+        }
+        self.sequence.skip("frozen");
+        self.time_count += self.monitor_iterval;
+        self.commit()?;
+        Ok(true)
+    }
+
+    // Record why the trace ends before its duration and persist the progress,
+    // a resumed session continues from here
+    fn stop(&mut self, reason: &str) -> io::Result<()> {
+        let detail = format!("reason={} elapsed={}", reason, self.time_count);
+        self.event(self.time_count + self.time_offset, "stopped", &detail, true)?;
+        self.commit()
+    }
+
+    // Sample `elapsed` after the last tick because the memory changed, at most once
+    // a second, not before the baseline and not after the duration
+    fn extra_sample(&mut self, elapsed: Duration, detail: &str) -> Result<(), TraceError> {
+        let seconds = elapsed.as_secs() as i64;
+        let timestamp = self.time_count - self.monitor_iterval + seconds;
+        if self.first_sample || self.frozen_since.is_some() || self.record_process.pid == 0
+                || seconds >= self.monitor_iterval
+                || (self.monitor_time > 0 && timestamp >= self.monitor_time)
+                || timestamp + self.time_offset <= self.record_item.timestamp
+                || process_start_time(self.record_process.pid) != self.start_time {
+            return Ok(());
+        }
+        println!("{} memory trigger: {}", self.record_process.name, detail);
+        self.event(timestamp + self.time_offset, "memory_trigger", detail, false)?;
+        let time_count = std::mem::replace(&mut self.time_count, timestamp);
+        let result = self.sample();
+        self.time_count = time_count;
+        result
+    }
+
+    // Collect a cumulative sample and write its delta to the last one
+    fn sample(&mut self) -> Result<(), TraceError> {
+        // The buffers of the sample before the last one are reused
+        std::mem::swap(&mut self.last_record_item, &mut self.record_item);
+        self.record_item = RecordItem::default();
+        self.record_item.timestamp = self.time_count + self.time_offset;
+        std::mem::swap(&mut self.last_threads, &mut self.threads);
+        self.threads.clear();
+        let uptime = self.sampler.read(UPTIME_PATH, &RetryPolicy::NONE, &mut self.record_item.retries).ok()
+                .and_then(|content| content.split_whitespace().next()?.parse::<f64>().ok());
+        self.uptimes = (self.uptimes.1, uptime.unwrap_or(0.0));
+        collect_record(&mut self.record_item, &self.last_record_item, self.record_process.pid,
+                &mut self.sampler, &self.options.policies, self.options.metrics,
+                if self.options.per_thread { Some(&mut self.threads) } else { None });
+        let (record_item, last_record_item) = (&self.record_item, &self.last_record_item);
+        let timestamp = record_item.timestamp;
+        // Moves between cgroups, e.g. top-app to background, are events
+        if self.options.metrics.cgroup && !record_item.cgroup.is_empty()
+                && (record_item.cgroup != last_record_item.cgroup || record_item.cpuset != last_record_item.cpuset) {
+            let detail = format!("cpuset={} cgroup={}", record_item.cpuset, record_item.cgroup);
+            self.event(timestamp, "cgroup", &detail, false)?;
+        }
+        // Processes and threads rename themselves, e.g. to `Jit thread pool`, the
+        // events tell which name the samples of a time belong to
+        if !self.record_item.comm.is_empty() && self.record_item.comm != self.record_process.comm {
+            let detail = format!("pid={} from={} to={}", self.record_process.pid,
+                    rename_value(&self.record_process.comm), rename_value(&self.record_item.comm));
+            println!("{} renamed: {}", self.record_process.name, detail);
+            self.record_process.comm = self.record_item.comm.clone();
+            self.event(timestamp, "comm_rename", &detail, true)?;
+        }
+        for detail in thread_renames(&self.threads, &self.last_threads, self.record_process.pid) {
+            self.event(timestamp, "thread_rename", &detail, false)?;
+        }
+        // Diagnose every denied path once, the denial does not change between samples
+        for path in std::mem::take(&mut self.sampler.denied) {
+            if !self.reported_denials.insert(path.clone()) {
+                continue;
+            }
+            let detail = denial_detail(&path);
+            println!("{} access denied: {}", self.record_process.name, detail);
+            self.event(timestamp, "access_denied", &detail, true)?;
+        }
+        // A hung node is skipped until its read returns, and dropped when it keeps hanging
+        for (path, timeouts) in self.sampler.files.take_timeouts().into_iter().chain(file_utils::take_node_timeouts()) {
+            let event = match timeouts >= QUARANTINE_TIMEOUTS {
+                true => "collector_quarantined",
+                false => "collector_timeout",
+            };
+            let detail = format!("path={} timeouts={}", path, timeouts);
+            println!("{} {}: {}", self.record_process.name, event, detail);
+            self.event(timestamp, event, &detail, true)?;
+        }
+        if self.first_sample {
+            self.first_sample = false;
+            return Ok(());
+        }
+        // A frozen target has ticks without samples instead of samples without activity
+        if let Some(reason) = self.frozen_reason() {
+            if self.frozen_since.is_none() {
+                self.frozen_since = Some(timestamp);
+                let detail = format!("reason={}", reason);
+                println!("{} frozen: {}", self.record_process.name, detail);
+                self.event(timestamp, "frozen", &detail, true)?;
+            }
+            self.sequence.skip("frozen");
+            return Ok(());
+        }
+        if let Some(since) = self.frozen_since.take() {
+            let detail = format!("frozen_for={}", timestamp - since);
+            println!("{} thawed: {}", self.record_process.name, detail);
+            self.event(timestamp, "thawed", &detail, true)?;
+        }
+        let record_item = &self.record_item;
+        let mut tmp_record_item = record_item.clone();
+        println!("{},{},{},{},{},{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{},{},{},{},{}",
+                tmp_record_item.timestamp, tmp_record_item.pss, tmp_record_item.vm_rss, tmp_record_item.vm_anon, tmp_record_item.vm_file, tmp_record_item.vm_shmem,
+                tmp_record_item.vm_swap, tmp_record_item.voluntary_ctxt_switches, tmp_record_item.nonvoluntary_ctxt_switches,
+                tmp_record_item.minflt, tmp_record_item.majflt, tmp_record_item.utime, tmp_record_item.stime, tmp_record_item.totalcputime, tmp_record_item.global_utime,
+                tmp_record_item.global_stime, tmp_record_item.global_total_cpu_time, tmp_record_item.cpu_occupancy_rate,
+                tmp_record_item.priority, tmp_record_item.nice, tmp_record_item.num_threads, tmp_record_item.start_time);
+        // Record difference, over less than an interval next to extra samples
+        let interval = match record_item.timestamp - self.last_record_item.timestamp {
+            elapsed if elapsed > 0 => elapsed.min(self.monitor_iterval),
+            _ => self.monitor_iterval,
+        };
+        delta_record(&mut tmp_record_item, record_item, &self.last_record_item, &self.validator);
+        self.validator.validate(&mut tmp_record_item, interval);
+        tmp_record_item.confidence = confidence(&tmp_record_item, self.options.metrics.collector_count());
+        // The interval counts in the window the sample sees, the cgroup events tell when it changed
+        if self.options.cpu_split {
+            let window = AppWindow::classify(&tmp_record_item.cpuset, self.screen_on);
+            self.record_process.cpu_split.add(window, tmp_record_item.totalcputime, interval);
+        }
+        if let Some(detail) = self.sequence.number(&mut tmp_record_item, self.monitor_iterval) {
+            println!("{} gap: {}", self.record_process.name, detail);
+            self.event(tmp_record_item.timestamp, "gap", &detail, true)?;
+        }
+        let thread_items = delta_threads(&self.threads, &self.last_threads, &tmp_record_item, &self.validator);
+        // Last, the CPU split and the thread rollups use the measured values
+        self.transforms.apply(&mut tmp_record_item, interval);
+        let record_process = &mut self.record_process;
+        publish(|| BusMessage::Sample {
+            target: record_process.name.clone(),
+            pid: record_process.pid,
+            item: Box::new(tmp_record_item.clone()),
+        });
+        match (&self.session, self.session_output.as_mut()) {
+            (Some(session), Some(output)) => {
+                output.append(record_process, &tmp_record_item)?;
+                for thread in &thread_items {
+                    output.append_thread(record_process, thread)?;
+                }
+                commit_progress(session, output, &self.monitor_target, self.time_count, &self.sequence)?;
+            },
+            _ if self.streaming => {
+                sink_all(&mut self.sinks, |sink| {
+                    sink.sample(record_process, &tmp_record_item)?;
+                    for thread in &thread_items {
+                        sink.thread_sample(record_process, thread)?;
+                    }
+                    sink.flush()
+                })?;
+            },
+            _ => {
+                self.samples.push(&tmp_record_item);
+                record_process.thread_infos.extend(thread_items);
+            },
+        }
+        Ok(())
+    }
+
+    // Save the progress of a session trace
+    fn commit(&mut self) -> io::Result<()> {
+        if let (Some(session), Some(output)) = (&self.session, self.session_output.as_mut()) {
+            commit_progress(session, output, &self.monitor_target, self.time_count, &self.sequence)?;
+        }
+        Ok(())
+    }
+
+    // Sample with the settings of another power profile from the next tick on.
+    // The next sample is a new baseline when the metric groups change
+    fn switch_profile(&mut self, interval: i64, metrics: MetricGroups, detail: &str) -> io::Result<()> {
+        let time = self.time_count + self.time_offset - self.monitor_iterval;
+        self.event(time, "power_state", detail, true)?;
+        self.time_count += interval - self.monitor_iterval;
+        self.monitor_iterval = interval;
+        if metrics != self.options.metrics {
+            self.options.metrics = metrics;
+            self.first_sample = true;
+            self.sequence.skip("metrics_change");
+        }
+        Ok(())
+    }
+
+    // Ticks passed while the tracer did not run, e.g. the device suspended
+    fn skip_ticks(&mut self, ticks: i64) {
+        self.sequence.skip("late");
+        self.time_count += ticks * self.monitor_iterval;
+    }
+
+    // Record the events analyzers posted for the target since the last tick
+    fn record_posted(&mut self) -> io::Result<()> {
+        let time = self.time_count + self.time_offset - self.monitor_iterval;
+        for (event, detail) in take_posted(&self.record_process.name) {
+            self.event(time, &event, &detail, true)?;
+        }
+        Ok(())
+    }
+
+    // Whether the samples are kept in memory until the end, only traces with sinks can stop
+    fn buffering(&self) -> bool {
+        self.session.is_none() && !self.streaming && !self.sinks.is_empty()
+    }
+
+    // Write the buffered samples out, free their memory and stream from now on
+    fn relieve_pressure(&mut self, pressure: f64) -> Result<(), TraceError> {
+        let record_process = &mut self.record_process;
+        let sinks = &mut self.sinks;
+        sink_all(sinks, |sink| sink.begin(record_process))?;
+        for item in self.samples.iter() {
+            sink_all(sinks, |sink| sink.sample(record_process, &item))?;
+        }
+        for (time, event, detail) in &record_process.events {
+            sink_all(sinks, |sink| sink.event(record_process, *time, event, detail))?;
+        }
+        for thread in &record_process.thread_infos {
+            sink_all(sinks, |sink| sink.thread_sample(record_process, thread))?;
+        }
+        sink_all(sinks, |sink| sink.flush())?;
+        let samples = self.samples.len();
+        self.samples = SampleArena::default();
+        record_process.thread_infos = Vec::new();
+        record_process.events = Vec::new();
+        self.sampler.shrink();
+        self.streaming = true;
+        println!("memory pressure {:.2}%, {} streams its samples from now on", pressure, record_process.name);
+        let detail = format!("some_avg10={:.2} flushed={}", pressure, samples);
+        self.event(self.time_count + self.time_offset, "memory_pressure", &detail, false)?;
+        Ok(())
+    }
+
+    // Summarize the sampling and close the sinks of a trace without session
+    fn finish(mut self) -> Result<RecordProcess, TraceError> {
+        print!("{}", self.record_process.sampling.format(&self.record_process.name));
+        let detail = self.record_process.sampling.detail();
+        self.event(self.time_count + self.time_offset, "sampling", &detail, false)?;
+        if self.options.cpu_split {
+            print!("{}", self.record_process.cpu_split.format(&self.record_process.name));
+            let detail = self.record_process.cpu_split.detail();
+            self.event(self.time_count + self.time_offset, "cpu_split", &detail, false)?;
+        }
+        publish(|| BusMessage::End { target: self.record_process.name.clone() });
+
+        let record_process = &self.record_process;
+        let sinks = &mut self.sinks;
+        if self.session.is_none() {
+            if !self.streaming {
+                sink_all(sinks, |sink| sink.begin(record_process))?;
+                for item in self.samples.iter() {
+                    sink_all(sinks, |sink| sink.sample(record_process, &item))?;
+                }
+                for (time, event, detail) in &record_process.events {
+                    sink_all(sinks, |sink| sink.event(record_process, *time, event, detail))?;
+                }
+                for thread in &record_process.thread_infos {
+                    sink_all(sinks, |sink| sink.thread_sample(record_process, thread))?;
+                }
+            }
+            sink_all(sinks, |sink| sink.end(record_process).and_then(|_| sink.flush()))?;
+        }
+        if self.options.keep_samples {
+            self.record_process.record_infos = self.samples.to_records();
+        }
+        Ok(self.record_process)
+    }
+}
+
+/// Samples taken by a calibration
+pub const CALIBRATION_SAMPLES: u32 = 5;
+
+/// Cost of sampling the targets, measured by `calibrate`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Calibration {
+    /// ticks sampled
+    pub samples: u32,
+    /// CPU time of the tracer to sample every target once
+    pub cpu_per_tick: Duration,
+    /// wall time to sample every target once
+    pub wall_per_tick: Duration,
+}
+
+/// Sample every target `samples` times back to back with the metric groups and
+/// per thread setting of `options`, to measure what one tick costs
+pub fn calibrate(names: &[String], options: &MonitorOptions, samples: u32) -> Result<Calibration, TraceError> {
+    let pids = names.iter()
+            .map(|name| TraceTarget::parse(name).resolve_pid())
+            .collect::<Result<Vec<pid_t>, TraceError>>()?;
+    let mut lasts = vec![RecordItem::default(); pids.len()];
+    let mut samplers: Vec<Sampler> = pids.iter().map(|_| Sampler::default()).collect();
+    let mut threads = Vec::new();
+    let cpu_start = thread_cpu_time();
+    let wall_start = Instant::now();
+    for _ in 0..samples {
+        for ((pid, last), sampler) in pids.iter().zip(lasts.iter_mut()).zip(samplers.iter_mut()) {
+            let mut item = RecordItem::default();
+            threads.clear();
+            collect_record(&mut item, last, *pid, sampler, &options.policies, options.metrics,
+                    if options.per_thread { Some(&mut threads) } else { None });
+            *last = item;
+        }
+    }
+    let samples = samples.max(1);
+    Ok(Calibration {
+        samples,
+        cpu_per_tick: thread_cpu_time().saturating_sub(cpu_start) / samples,
+        wall_per_tick: wall_start.elapsed() / samples,
+    })
 }
 
-fn monitor_thread(monitor_time: i64, monitor_iterval: i64,
-        monitor_process_name: String) {
-    let mut frist_flag: bool = true;
-    let mut time_count: time_t = 0;
-    let mut record_process = RecordProcess::default();
-    let mut record_item: RecordItem = RecordItem::default();
-    let mut last_record_item: RecordItem;
-    let mut tmp_record_item: RecordItem;
-
-    record_process.pid = get_process_pid(&monitor_process_name);
-
-    while time_count < monitor_time {
-        last_record_item = record_item;
-        record_item = RecordItem::default();
-        record_item.timestamp = time_count;
-        get_global_cpu_info(&mut record_item);
-        get_pss_info(&mut record_item, record_process.pid);
-        for entry in fs::read_dir(format!(SUBTASK_PATH_TEMPLATE!(), record_process.pid))
-                .unwrap_or_else(|_| panic!("List dir {} failed!", record_process.pid)) {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(_) => { 
-                    println!("get dir entry failed");
-                    continue;
+/// Take one sample of a process and write its cumulative values as CSV
+pub fn snapshot_process(process_name: &str, out: &mut impl Write) -> Result<(), TraceError> {
+    let pid = TraceTarget::parse(process_name).resolve_pid()?;
+    let mut item = RecordItem::default();
+    let metrics = MetricGroups::default();
+    collect_record(&mut item, &RecordItem::default(), pid, &mut Sampler::default(),
+            &CollectorPolicies::default(), metrics, None);
+    item.cpu_occupancy_rate = cpu_share(item.totalcputime, item.global_total_cpu_time);
+    item.confidence = confidence(&item, metrics.collector_count());
+    write_csv_header(out, &[], &[], &[])?;
+    write_csv_row(out, &item, &[], &[])?;
+    Ok(())
+}
+
+/// trace process, `lists` are target specs, see `TraceTarget::parse`
+///
+/// Samples are written to `resource_trace_<name>_<run id>.csv` and
+/// `.loki.jsonl` when the trace ends, use `TraceConfig` to get them back instead.
+pub fn trace_process(monitor_time: i64, monitor_iterval: i64,
+        lists: &Vec<&str>) -> Result<(), TraceError> {
+    trace_process_to(monitor_time, monitor_iterval, lists, &OutputPlan::default())
+}
+
+/// trace process and write the samples as `plan` says
+pub fn trace_process_to(monitor_time: i64, monitor_iterval: i64,
+        lists: &[&str], plan: &OutputPlan) -> Result<(), TraceError> {
+    let names: Vec<String> = lists.iter().map(|s| s.to_string()).collect();
+    let options = MonitorOptions { keep_samples: false, ..MonitorOptions::default() };
+    first_error(run_monitors(monitor_time, monitor_iterval, names, None, Some(plan), options, &[])?)
+}
+
+/// trace process inside of a session, samples are appended to the session
+/// directory as they are collected so the trace can be resumed
+pub fn trace_session(state: SessionState) -> Result<(), TraceError> {
+    trace_session_with_options(state, MonitorOptions::default())
+}
+
+/// trace process inside of a session with custom monitor options
+///
+/// A strict session logs its external commands to `audit.log` in the session
+/// directory, unless an audit log is already open.
+pub fn trace_session_with_options(state: SessionState, mut options: MonitorOptions) -> Result<(), TraceError> {
+    options.per_thread |= state.per_thread;
+    options.follow |= state.follow;
+    options.metrics = state.metrics;
+    if state.strict {
+        audit::set_strict(true);
+        audit::open_log_unless_open(&format!("{}/{}", state.dir, AUDIT_LOG_FILE))?;
+    }
+    // Held until the trace ends, a second tracer of the session fails here
+    let _lock = state.lock()?;
+    let names: Vec<String> = state.targets.iter().map(|t| t.name.clone()).collect();
+    let (monitor_time, monitor_iterval) = (state.monitor_time, state.monitor_interval);
+    let profiles = state.profiles.clone();
+    first_error(run_monitors(monitor_time, monitor_iterval, names, Some(Arc::new(Mutex::new(state))), None, options,
+            &profiles)?)
+}
+
+// The outputs of every target are complete, the first failure is reported
+fn first_error(results: Vec<Result<RecordProcess, TraceError>>) -> Result<(), TraceError> {
+    match results.into_iter().find_map(Result::err) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Open the nodes only a privileged tracer can read, then drop the privileges.
+// The session directory is given to the new identity so it can still write it
+fn drop_privileges(drop: &DropPrivileges, pids: &[pid_t],
+        session: Option<&Arc<Mutex<SessionState>>>) -> Result<(), TraceError> {
+    // Processes started later are read with the dropped privileges only
+    for pid in pids.iter().filter(|pid| **pid > 0) {
+        for path in [pss_path(*pid), format!(TASK_STAT_TEMPLATE!(), pid),
+                format!(crate::TASK_EXE_TEMPLATE!(), pid), format!(crate::TASK_ATTR_CURRENT_TEMPLATE!(), pid)] {
+            if let Err(e) = keep_open(&path) {
+                println!("keep {} open failed: {}", path, e);
+            }
+        }
+    }
+    keep_open(GLOBAL_SYSTEM_INFO)?;
+    if let Some(session) = session {
+        drop.chown_dir(&session.lock().unwrap().dir)?;
+    }
+    drop.apply()?;
+    println!("Dropped privileges to uid {} gid {}{}", drop.uid, drop.gid,
+            if drop.keep_ptrace { ", kept CAP_SYS_PTRACE" } else { "" });
+    Ok(())
+}
+
+/// `some avg10` of the memory pressure stall information in percent, None
+/// on kernels without PSI
+pub fn memory_pressure() -> Option<f64> {
+    read_node(PSI_MEMORY_PATH).ok()?
+            .lines()
+            .find(|line| line.starts_with(PSI_SOME_PREFIX))?
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix(PSI_AVG10_PREFIX))?
+            .parse()
+            .ok()
+}
+
+// How often the RSS is checked between the ticks
+const MEMORY_WATCH_STEP: Duration = Duration::from_secs(1);
+
+// Wait for the next tick at `until`, sampling the targets in between whose memory
+// notifies or whose RSS changed by `change` since their last sample
+fn watch_memory(monitors: &mut [Option<TargetMonitor>], active: &mut [bool], watches: &mut [Option<MemoryWatch>],
+        tick: Instant, until: Instant, change: f64, errors: &mut [Option<TraceError>]) {
+    loop {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() || stop_requested() {
+            return;
+        }
+        // A target followed to a new process is watched in that one
+        for (watch, (monitor, active)) in watches.iter_mut().zip(monitors.iter().zip(active.iter())) {
+            let pid = monitor.as_ref().filter(|_| *active).map(|m| m.record_process.pid).unwrap_or(0);
+            if watch.as_ref().map(|watch| watch.pid) != Some(pid) {
+                *watch = (pid > 0).then(|| MemoryWatch::open(pid));
+            }
+        }
+        let notified = wait_events(&watches.iter().map(|watch| watch.as_ref()).collect::<Vec<_>>(),
+                left.min(MEMORY_WATCH_STEP));
+        for (i, ((slot, active), (watch, notified))) in monitors.iter_mut().zip(active.iter_mut())
+                .zip(watches.iter_mut().zip(notified)).enumerate() {
+            let (monitor, watch) = match (slot.as_mut(), watch.as_mut()) {
+                (Some(monitor), Some(watch)) if *active => (monitor, watch),
+                _ => continue,
+            };
+            let last = monitor.record_item.vm_rss;
+            let detail = match notified.then(|| watch.changed_events()).flatten() {
+                Some(events) => format!("reason=memory_events {}", events),
+                None => match statm_rss_kb(watch.pid) {
+                    // An exiting process has no RSS left, it is not a change
+                    Some(rss) if rss > 0 && last > 0 && (rss - last).abs() as f64 >= last as f64 * change =>
+                        format!("reason=rss_change from={} to={}", last, rss),
+                    _ => continue,
                 },
             };
-            let pid_dir_path = entry.file_name();
-            let file = fs::File::open(format!(TASK_STATUS_TID_TEMPLATE!(),
-                    record_process.pid, pid_dir_path.to_string_lossy()));
-            let file = match file {
-                Ok(file) => file,
-                Err(_) => {
-                    println!("open file {} failed!", pid_dir_path.to_string_lossy());
-                    continue;
+            if let Err(e) = monitor.extra_sample(tick.elapsed(), &detail) {
+                println!("Monitor {} error: {}", i, e);
+                errors[i].get_or_insert(e);
+                *slot = None;
+                *active = false;
+            }
+        }
+    }
+}
+
+// Progress of the targets still traced, stalled ones have the samples of the last report
+fn trace_progress(monitor_time: i64, monitors: &[Option<TargetMonitor>], active: &[bool], reported: &[u64],
+        session: Option<&Arc<Mutex<SessionState>>>) -> Progress {
+    let traced = || monitors.iter().zip(active).zip(reported)
+            .filter_map(|((monitor, active), reported)| Some((monitor.as_ref().filter(|_| *active)?, *reported)));
+    Progress {
+        elapsed: traced().map(|(monitor, _)| monitor.time_count).max().unwrap_or(0),
+        duration: monitor_time.max(0),
+        samples: monitors.iter().flatten().map(|monitor| monitor.sequence.seq).sum(),
+        targets: traced().count(),
+        stalled: traced().filter(|(monitor, reported)| monitor.sequence.seq == *reported).count(),
+        output_bytes: session.map(|session| dir_bytes(&session.lock().unwrap().dir)),
+    }
+}
+
+// Trace all targets from one scheduler loop, the record or the first error of
+// every target is returned in the order of `names`, a failed target does not
+// lose the records of the others. Without a session, samples are written to the files of `plan` if any
+pub(crate) fn run_monitors(monitor_time: i64, monitor_iterval: i64, names: Vec<String>,
+        session: Option<Arc<Mutex<SessionState>>>, plan: Option<&OutputPlan>,
+        options: MonitorOptions, profiles: &[PowerProfile]) -> Result<Vec<Result<RecordProcess, TraceError>>, TraceError> {
+    // Nodes read outside of the samplers time out alike, in this thread only
+    // as traces may run side by side
+    file_utils::set_node_timeout(options.read_timeout);
+    // Resolve all targets first, a missing process fails before anything is traced
+    // unless it is followed, then its monitor waits for it with pid 0
+    let pids = names.iter()
+            .map(|name| {
+                let target = TraceTarget::parse(name);
+                match target.resolve_pid() {
+                    Err(TraceError::ProcessNotFound(_)) if options.follow
+                            && !matches!(target, TraceTarget::Pid(_)) => {
+                        println!("waiting for {} to start", name);
+                        Ok(0)
+                    },
+                    result => result,
+                }
+            })
+            .collect::<Result<Vec<pid_t>, TraceError>>()?;
+    let mut target_sinks = Vec::new();
+    for name in &names {
+        target_sinks.push(match plan {
+            Some(plan) => TargetSinks {
+                sinks: open_sinks(plan, &TraceTarget::parse(name).label())?,
+                streaming: plan.streaming,
+                tags: plan.tags.clone(),
+            },
+            None => TargetSinks::default(),
+        });
+    }
+    if let Some(drop) = &options.drop_privileges {
+        drop_privileges(drop, &pids, session.as_ref())?;
+    }
+    let mut monitors = Vec::new();
+    let labels = names.clone();
+    for ((process_name, pid), sinks) in names.into_iter().zip(pids).zip(target_sinks) {
+        monitors.push(Some(TargetMonitor::new(monitor_time, monitor_iterval, process_name, pid,
+                session.clone(), sinks, options)?));
+    }
+    // Every tick samples all targets, a failed target stops while the others go on
+    let mut active = vec![true; monitors.len()];
+    let mut errors: Vec<Option<TraceError>> = monitors.iter().map(|_| None).collect();
+    let mut interval = Duration::from_secs(monitor_iterval as u64);
+    let mut profile = None;
+    let mut deadline = Instant::now();
+    let mut reported = (Instant::now(), vec![0; monitors.len()]);
+    let mut watches: Vec<Option<MemoryWatch>> = monitors.iter().map(|_| None).collect();
+    loop {
+        // All targets stop at the same tick, so their outputs cover the same window
+        if stop_requested() {
+            println!("Stop requested, flushing {} targets", active.iter().filter(|active| **active).count());
+            for (i, (slot, active)) in monitors.iter_mut().zip(active.iter_mut()).enumerate() {
+                let monitor = match slot.as_mut() {
+                    Some(monitor) if *active => monitor,
+                    _ => continue,
+                };
+                if let Err(e) = monitor.stop("signal") {
+                    println!("Monitor {} error: {}", i, e);
+                    errors[i].get_or_insert(e.into());
+                    *slot = None;
                 }
+                *active = false;
+            }
+            break;
+        }
+        // The sysfs nodes of the power state are read once a tick, not once a
+        // target, and the profile of the next tick follows the same state
+        let state = match options.cpu_split || !profiles.is_empty() {
+            true => PowerState::read(),
+            false => PowerState::default(),
+        };
+        let screen_on = state.screen_on.filter(|_| options.cpu_split);
+        for (i, (slot, active)) in monitors.iter_mut().zip(active.iter_mut()).enumerate() {
+            let monitor = match slot.as_mut() {
+                Some(monitor) if *active => monitor,
+                _ => continue,
+            };
+            monitor.screen_on = screen_on;
+            match panic::catch_unwind(AssertUnwindSafe(|| monitor.tick())) {
+                Ok(Ok(true)) => continue,
+                Ok(Ok(false)) => {},
+                Ok(Err(e)) => {
+                    println!("Monitor {} error: {}", i, e);
+                    errors[i].get_or_insert(e);
+                    *slot = None;
+                },
+                Err(_) => {
+                    println!("Monitor {} error!", i);
+                    errors[i].get_or_insert(TraceError::MonitorPanicked(monitor.monitor_target.clone()));
+                    *slot = None;
+                },
+            }
+            *active = false;
+        }
+        if !active.contains(&true) {
+            break;
+        }
+        for (i, (slot, active)) in monitors.iter_mut().zip(active.iter_mut()).enumerate() {
+            let monitor = match slot.as_mut() {
+                Some(monitor) if *active => monitor,
+                _ => continue,
             };
-            let reader = BufReader::new(file);
-            for line in reader.lines() {
-                let line = match line {
-                    Ok(line) => line,
-                    Err(_) => { continue; }
+            if let Err(e) = monitor.record_posted() {
+                println!("Monitor {} error: {}", i, e);
+                errors[i].get_or_insert(e.into());
+                *slot = None;
+                *active = false;
+            }
+        }
+        // Switched after a tick, so the next one already uses the interval of the profile
+        let selected = select_profile(profiles, &state);
+        if selected != profile {
+            profile = selected;
+            let seconds = profile.map(|profile| profile.interval).unwrap_or(monitor_iterval);
+            let metrics = profile.and_then(|profile| profile.metrics).unwrap_or(options.metrics);
+            let names = metrics.names();
+            let detail = format!("{} profile={} interval={} metrics={}", state.detail(),
+                    profile.map(|profile| profile.spec()).unwrap_or_else(|| "default".to_string()),
+                    seconds, if names.is_empty() { "none".to_string() } else { names.join(",") });
+            println!("power {}", detail);
+            interval = Duration::from_secs(seconds as u64);
+            for (i, (slot, active)) in monitors.iter_mut().zip(active.iter_mut()).enumerate() {
+                let monitor = match slot.as_mut() {
+                    Some(monitor) if *active => monitor,
+                    _ => continue,
                 };
-                if line.starts_with(TASK_RSS_ANON_PREFIX) {
-                    let t = line
-                            .trim_start_matches(TASK_RSS_ANON_PREFIX)
-                            .trim_end_matches(" kB")
-                            .trim();
-                    record_item.vm_anon = t.parse::<isize>()
-                            .expect("parse vm_anon failed!");
-                } else if line.starts_with(TASK_VM_RSS_PREFIX) {
-                    let t = line
-                            .trim_start_matches(TASK_VM_RSS_PREFIX)
-                            .trim_end_matches(" kB")
-                            .trim();
-                    record_item.vm_rss = t.parse::<isize>()
-                        .expect("parse vm_rss failed!");
-                } else if line.starts_with(TASK_RSS_FILE_PREFIX) {
-                    let t = line
-                            .trim_start_matches(TASK_RSS_FILE_PREFIX)
-                            .trim_end_matches(" kB")
-                            .trim();
-                    record_item.vm_file = t.parse::<isize>()
-                        .expect("parse vm_file failed!");
-                } else if line.starts_with(TASK_RSS_SHMEM_PREFIX) {
-                    let t = line
-                            .trim_start_matches(TASK_RSS_SHMEM_PREFIX)
-                            .trim_end_matches(" kB")
-                            .trim();
-                    record_item.vm_shmem = t.parse::<isize>()
-                        .expect("parse vm_shmem failed!");
-                } else if line.starts_with(TASK_VM_SWAP_PREFIX) {
-                    let t = line
-                            .trim_start_matches(TASK_VM_SWAP_PREFIX)
-                            .trim_end_matches(" kB")
-                            .trim();
-                    record_item.vm_swap = t.parse::<isize>()
-                        .expect("parse vm_swap failed!");
-                } else if line.starts_with(TASK_NONVOLUNTARY_SWITCH_PREFIX) {
-                    let t = line
-                            .trim_start_matches(TASK_NONVOLUNTARY_SWITCH_PREFIX)
-                            .trim();
-                    record_item.nonvoluntary_ctxt_switches = t.parse::<usize>().expect("nonvoluntary_ctxt_switches failed");
-                } else if line.starts_with(TASK_VOLUNTARY_SWITCH_PREFIX) {
-                    let t = line
-                            .trim_start_matches(TASK_VOLUNTARY_SWITCH_PREFIX)
-                            .trim();
-                    record_item.voluntary_ctxt_switches = t.parse::<usize>().expect("voluntary_ctxt_switches failed");
+                if let Err(e) = monitor.switch_profile(seconds, metrics, &detail) {
+                    println!("Monitor {} error: {}", i, e);
+                    errors[i].get_or_insert(e.into());
+                    *slot = None;
+                    *active = false;
                 }
             }
-            let content = read_path(&format!(TASK_STAT_TID_TEMPLATE!(),
-                    record_process.pid, pid_dir_path.to_string_lossy()))
-                    .expect("read TASK_STAT_TID_TEMPLATE failed!");
-            let process_stat_strs: Vec<&str> = content.split_whitespace().collect();
-            if process_stat_strs.len() > PROCESS_STAT_STIME_SHIFT {
-                let minflt = process_stat_strs[PROCESS_STAT_MINFLT_SHIFT].parse::<usize>().expect("minflt");
-                let majflt = process_stat_strs[PROCESS_STAT_MAJFLT_SHIFT].parse::<usize>().expect("majflt");
-                // SAFETY:
-                // Safe because we've verified that the system call returns correctly
-                let clock_ticks = unsafe { sysconf(_SC_CLK_TCK) as f64 };
-                let utime = process_stat_strs[PROCESS_STAT_UTIME_SHIFT].parse::<f64>().expect("utime") / clock_ticks;
-                let stime = process_stat_strs[PROCESS_STAT_STIME_SHIFT].parse::<f64>().expect("stime") / clock_ticks;
-                record_item.minflt += minflt;
-                record_item.majflt += majflt;
-                record_item.utime += utime;
-                record_item.stime += stime;
-                record_item.totalcputime += utime + stime;
-                record_item.priority = process_stat_strs[PROCESS_STAT_PRIORITY_SHIFT].parse::<i64>().expect("priority");
-                record_item.nice = process_stat_strs[PROCESS_STAT_NICE_SHIFT].parse::<i64>().expect("nice");
-                record_item.num_threads = process_stat_strs[PROCESS_STAT_NUM_THREADS_SHIFT].parse::<i64>().expect("num_threads");
-                record_item.start_time = process_stat_strs[PROCESS_STAT_STARTTIME_SHIFT].parse::<i64>().expect("start_time");
+        }
+        // Buffered samples must not add to the memory pressure the trace observes
+        let buffering = monitors.iter().zip(&active)
+                .any(|(monitor, active)| *active && monitor.as_ref().map(|m| m.buffering()).unwrap_or(false));
+        if buffering && options.memory_pressure_limit > 0.0 {
+            let pressure = memory_pressure().unwrap_or(0.0);
+            for (i, (slot, active)) in monitors.iter_mut().zip(active.iter_mut()).enumerate() {
+                let monitor = match slot.as_mut() {
+                    Some(monitor) if *active && pressure >= options.memory_pressure_limit
+                            && monitor.buffering() => monitor,
+                    _ => continue,
+                };
+                if let Err(e) = monitor.relieve_pressure(pressure) {
+                    println!("Monitor {} error: {}", i, e);
+                    errors[i].get_or_insert(e);
+                    *slot = None;
+                    *active = false;
+                }
             }
         }
-        if !frist_flag {
-            tmp_record_item = record_item.clone();
-            println!("{},{},{},{},{},{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{},{},{},{},{}",
-                    tmp_record_item.timestamp, tmp_record_item.pss, tmp_record_item.vm_rss, tmp_record_item.vm_anon, tmp_record_item.vm_file, tmp_record_item.vm_shmem,
-                    tmp_record_item.vm_swap, tmp_record_item.voluntary_ctxt_switches, tmp_record_item.nonvoluntary_ctxt_switches,
-                    tmp_record_item.minflt, tmp_record_item.majflt, tmp_record_item.utime, tmp_record_item.stime, tmp_record_item.totalcputime, tmp_record_item.global_utime,
-                    tmp_record_item.global_stime, tmp_record_item.global_total_cpu_time, tmp_record_item.cpu_occupancy_rate,
-                    tmp_record_item.priority, tmp_record_item.nice, tmp_record_item.num_threads, tmp_record_item.start_time);
-            // Record difference
-            tmp_record_item.majflt = record_item.majflt - last_record_item.majflt;
-            tmp_record_item.minflt = record_item.minflt - last_record_item.minflt;
-            tmp_record_item.nonvoluntary_ctxt_switches = record_item.nonvoluntary_ctxt_switches - last_record_item.nonvoluntary_ctxt_switches;
-            tmp_record_item.stime = record_item.stime - last_record_item.stime;
-            tmp_record_item.utime = record_item.utime - last_record_item.utime;
-            tmp_record_item.global_stime = record_item.global_stime - last_record_item.global_stime;
-            tmp_record_item.global_utime = record_item.global_utime - last_record_item.global_utime;
-            tmp_record_item.voluntary_ctxt_switches = record_item.voluntary_ctxt_switches - last_record_item.voluntary_ctxt_switches;
-            tmp_record_item.totalcputime = record_item.totalcputime - last_record_item.totalcputime;
-            tmp_record_item.global_total_cpu_time = record_item.global_total_cpu_time - last_record_item.global_total_cpu_time;
-            tmp_record_item.cpu_occupancy_rate = tmp_record_item.totalcputime / tmp_record_item.global_total_cpu_time;
-            record_process.record_infos.push(tmp_record_item);
-        }
-        frist_flag = false;
-        sleep(Duration::from_secs(monitor_iterval as u64));
-        time_count += monitor_iterval;
-    }
-
-    dump_csv_info(&record_process, &monitor_process_name);
-}
-
-/// trace process
-pub fn trace_process(monitor_time: i64, monitor_iterval: i64,
-        lists: &Vec<&str>) {
-    let mut works: Vec<thread::JoinHandle<_>> = Vec::new();
-    // Start thread to monitor process
-    for &s in lists {
-        let process_name = s.to_string();
-        works.push(thread::spawn(move || monitor_thread(monitor_time, monitor_iterval,
-            process_name)));
-    }
-    // Wait sub thread finish
-    for (i, t) in (0_i32..).zip(works.into_iter()) {
-        match t.join() {
-            Ok(_) => { println!("Thread {} finish.", i) },
-            Err(_) => { println!("Thread {} error!", i) },   
-        }
-    }
-}
\ No newline at end of file
+        if options.progress.is_some_and(|every| reported.0.elapsed() >= every) {
+            let progress = trace_progress(monitor_time, &monitors, &active, &reported.1, session.as_ref());
+            eprintln!("{}", progress.format());
+            publish(|| BusMessage::Progress(progress.clone()));
+            reported = (Instant::now(), monitors.iter()
+                    .map(|monitor| monitor.as_ref().map(|m| m.sequence.seq).unwrap_or(0)).collect());
+        }
+        // Deadlines are counted from the first tick, so the time spent sampling
+        // does not drift the ticks
+        deadline += interval;
+        match options.memory_trigger {
+            Some(change) => watch_memory(&mut monitors, &mut active, &mut watches, deadline - interval, deadline,
+                    change, &mut errors),
+            None => {
+                sleep_until(deadline);
+            },
+        }
+        let late = Instant::now().saturating_duration_since(deadline);
+        // Whole intervals late are ticks which never happened, they are skipped
+        let missed = (late.as_secs_f64() / interval.as_secs_f64().max(1.0)) as u32;
+        deadline += interval * missed;
+        for (monitor, _) in monitors.iter_mut().zip(&active).filter(|(_, active)| **active) {
+            if let Some(monitor) = monitor {
+                monitor.record_process.sampling.jitter.add(late);
+                if missed > 0 {
+                    monitor.skip_ticks(missed as i64);
+                }
+            }
+        }
+    }
+    clear_posted(&labels.iter().map(|name| TraceTarget::parse(name).label()).collect::<Vec<String>>());
+    // A failed target keeps its first error, the others their records
+    let mut results = Vec::new();
+    for (((i, monitor), error), label) in (0_i32..).zip(monitors).zip(errors).zip(labels) {
+        results.push(match (monitor.map(TargetMonitor::finish), error) {
+            (_, Some(e)) => Err(e),
+            (Some(Ok(record)), None) => {
+                println!("Monitor {} finish.", i);
+                Ok(record)
+            },
+            (Some(Err(e)), None) => {
+                println!("Monitor {} error: {}", i, e);
+                Err(e)
+            },
+            // Every stopped monitor has its error, a monitor is never lost without one
+            (None, None) => Err(TraceError::MonitorPanicked(label)),
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mainline kernels pad with a tab, some vendor kernels with spaces
+    const TAB_PADDED: &str = "Name:\tsystem_server\nVmHWM:\t  310820 kB\nVmRSS:\t  298764 kB\n\
+            RssAnon:\t  120004 kB\nRssFile:\t  170560 kB\nRssShmem:\t    8200 kB\nVmSwap:\t   12044 kB\n\
+            voluntary_ctxt_switches:\t52031\nnonvoluntary_ctxt_switches:\t7120\n";
+    const SPACE_PADDED: &str = "Name:   system_server\nVmHWM:     310820 kB\nVmRSS:     298764 kB\n\
+            RssAnon:     120004 kB\nRssFile:     170560 kB\nRssShmem:       8200 kB\nVmSwap:      12044 kB\n\
+            voluntary_ctxt_switches:        52031\nnonvoluntary_ctxt_switches:     7120\n";
+    const MIXED_PADDED: &str = "Name:\tsystem_server\nVmHWM: \t310820 kB\nVmRSS:\t 298764 kB\n\
+            RssAnon:    \t120004 kB\nRssFile:\t170560  kB\nRssShmem: 8200 kB\nVmSwap:\t \t12044 kB\n\
+            voluntary_ctxt_switches: \t52031\nnonvoluntary_ctxt_switches:\t 7120\n";
+
+    fn parsed(status: &str) -> RecordItem {
+        let mut item = RecordItem::default();
+        parse_task_status(&mut item, status).unwrap();
+        item
+    }
+
+    #[test]
+    fn status_field_trims_padding_and_unit() {
+        assert_eq!(status_field("VmRSS:\t  298764 kB"), Some(("VmRSS", "298764")));
+        assert_eq!(status_field("VmRSS:     298764 kB"), Some(("VmRSS", "298764")));
+        assert_eq!(status_field("VmRSS: \t298764\tkB"), Some(("VmRSS", "298764")));
+        assert_eq!(status_field("Threads:\t12"), Some(("Threads", "12")));
+        assert_eq!(status_field("no separator"), None);
+    }
+
+    #[test]
+    fn status_paddings_parse_alike() {
+        for status in [TAB_PADDED, SPACE_PADDED, MIXED_PADDED] {
+            let item = parsed(status);
+            assert_eq!(item.vm_hwm, 310820);
+            assert_eq!(item.vm_rss, 298764);
+            assert_eq!(item.vm_anon, 120004);
+            assert_eq!(item.vm_file, 170560);
+            assert_eq!(item.vm_shmem, 8200);
+            assert_eq!(item.vm_swap, 12044);
+            assert_eq!(item.voluntary_ctxt_switches, 52031);
+            assert_eq!(item.nonvoluntary_ctxt_switches, 7120);
+        }
+    }
+
+    #[test]
+    fn status_keys_match_whole() {
+        // Keys which are a prefix or a suffix of another never take its value
+        let item = parsed("Vm:\t1 kB\nVmRSSx:\t2 kB\nVmRSS:\t3 kB\nRss:\t4 kB\n\
+                nonvoluntary_ctxt_switches:\t5\nvoluntary_ctxt_switches:\t6\n");
+        assert_eq!(item.vm_rss, 3);
+        assert_eq!(item.vm_anon, 0);
+        assert_eq!(item.nonvoluntary_ctxt_switches, 5);
+        assert_eq!(item.voluntary_ctxt_switches, 6);
+    }
+
+    #[test]
+    fn cpu_share_of_no_cpu_time_is_zero() {
+        assert_eq!(cpu_share(0.5, 2.0), 0.25);
+        assert_eq!(cpu_share(0.0, 0.0), 0.0);
+        assert_eq!(cpu_share(0.01, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use std::fs;
+
+/// Progress of a running trace, reported every `MonitorOptions::progress`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Progress {
+    /// seconds traced, including the ones before a resume
+    pub elapsed: i64,
+    /// seconds to trace, 0 traces until the targets exit
+    pub duration: i64,
+    /// samples of all targets
+    pub samples: u64,
+    /// targets still traced
+    pub targets: usize,
+    /// targets still traced without a sample since the last report, e.g.
+    /// a followed process which is not running
+    pub stalled: usize,
+    /// bytes in the session directory, None without a session
+    pub output_bytes: Option<u64>,
+}
+
+impl Progress {
+    /// Percent of the duration traced, None until the targets exit
+    pub fn percent(&self) -> Option<f64> {
+        (self.duration > 0).then(|| (self.elapsed as f64 / self.duration as f64 * 100.0).min(100.0))
+    }
+
+    /// Seconds left, None until the targets exit
+    pub fn eta(&self) -> Option<i64> {
+        (self.duration > 0).then(|| (self.duration - self.elapsed).max(0))
+    }
+
+    /// One `progress key=value ...` line, unknown values are `unknown`
+    pub fn format(&self) -> String {
+        let unknown = || "unknown".to_string();
+        format!("progress elapsed={} duration={} percent={} samples={} targets={} stalled={} output_bytes={} eta={}",
+                self.elapsed, self.duration, self.percent().map(|p| format!("{:.1}", p)).unwrap_or_else(unknown),
+                self.samples, self.targets, self.stalled,
+                self.output_bytes.map(|bytes| bytes.to_string()).unwrap_or_else(unknown),
+                self.eta().map(|eta| eta.to_string()).unwrap_or_else(unknown))
+    }
+}
+
+/// Bytes of the files in `dir`, subdirectories are not counted
+pub fn dir_bytes(dir: &str) -> u64 {
+    fs::read_dir(dir).into_iter().flatten()
+            .filter_map(|entry| entry.ok()?.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .sum()
+}
@@ -0,0 +1,227 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use crate::audit::{self, Effect};
+use crate::discover::{find_processes, find_processes_by, MatchMode, MATCH_MODES, PROC_ROOT};
+use crate::error::TraceError;
+use crate::privilege::lookup_user;
+use crate::proc_analysis::{process_alive, split_stat, status_field};
+use libc::{pid_t, uid_t};
+use std::fs;
+use std::process::Command;
+use std::str::from_utf8;
+use std::sync::{Arc, OnceLock, RwLock};
+
+// Pid of a running init service, only set on debuggable builds
+macro_rules! SERVICE_PID_PROPERTY_TEMPLATE { () => { "init.svc_debug_pid.{}" }; }
+
+// Real uid, the first field of the `Uid:` line of /proc/<pid>/status
+const STATUS_UID_KEY: &str = "Uid";
+
+// /proc/pid/stat shift of the parent pid
+const PROCESS_STAT_PPID_SHIFT: usize = 3;
+
+/// Finds the processes of target specs `<scheme>:<query>`
+///
+/// Implement it for targets the tracer does not know, e.g. vendor HAL
+/// instance names, and add it with `register_resolver`.
+pub trait TargetResolver: Send + Sync {
+    /// Scheme of the specs this resolver handles
+    fn scheme(&self) -> &str;
+
+    /// Find the pids of every process matching `query`, oldest first.
+    /// No process is an empty list, not an error
+    fn resolve(&self, query: &str) -> Result<Vec<pid_t>, TraceError>;
+}
+
+/// Processes whose names match, see `MatchMode`
+pub struct NameResolver(pub MatchMode);
+
+impl TargetResolver for NameResolver {
+    fn scheme(&self) -> &str {
+        self.0.name()
+    }
+
+    fn resolve(&self, query: &str) -> Result<Vec<pid_t>, TraceError> {
+        find_processes(query, self.0)
+    }
+}
+
+/// One process by pid
+pub struct PidResolver;
+
+impl TargetResolver for PidResolver {
+    fn scheme(&self) -> &str {
+        "pid"
+    }
+
+    fn resolve(&self, query: &str) -> Result<Vec<pid_t>, TraceError> {
+        let pid = query.parse::<pid_t>()
+                .map_err(|_| TraceError::InvalidConfig(format!("bad pid {}", query)))?;
+        Ok(if pid > 0 && process_alive(pid) { vec![pid] } else { Vec::new() })
+    }
+}
+
+/// Processes of a user, by uid or user name
+pub struct UidResolver;
+
+impl TargetResolver for UidResolver {
+    fn scheme(&self) -> &str {
+        "uid"
+    }
+
+    fn resolve(&self, query: &str) -> Result<Vec<pid_t>, TraceError> {
+        let (uid, _) = lookup_user(query)
+                .ok_or_else(|| TraceError::InvalidConfig(format!("unknown user {}", query)))?;
+        find_processes_by(|process| process_uid(process.pid) == Some(uid))
+    }
+}
+
+fn process_uid(pid: pid_t) -> Option<uid_t> {
+    let status = fs::read_to_string(format!(crate::TASK_STATUS_TEMPLATE!(), pid)).ok()?;
+    status.lines()
+            .filter_map(status_field)
+            .find(|(key, _)| *key == STATUS_UID_KEY)?
+            .1
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+}
+
+/// Processes of an Android package, its main process is named like the
+/// package and the others `<package>:<process>`
+pub struct PackageResolver;
+
+impl TargetResolver for PackageResolver {
+    fn scheme(&self) -> &str {
+        "package"
+    }
+
+    fn resolve(&self, query: &str) -> Result<Vec<pid_t>, TraceError> {
+        find_processes_by(|process| {
+            let argv0 = process.cmdline.first().map(|arg| arg.as_str()).unwrap_or("");
+            argv0 == query || argv0.strip_prefix(query).map(|rest| rest.starts_with(':')).unwrap_or(false)
+        })
+    }
+}
+
+/// Processes in a cgroup or below it, e.g. `/top-app`, in any hierarchy
+pub struct CgroupResolver;
+
+impl TargetResolver for CgroupResolver {
+    fn scheme(&self) -> &str {
+        "cgroup"
+    }
+
+    fn resolve(&self, query: &str) -> Result<Vec<pid_t>, TraceError> {
+        let query = format!("/{}", query.trim_matches('/'));
+        let below = format!("{}/", query.trim_end_matches('/'));
+        find_processes_by(|process| {
+            let content = fs::read_to_string(format!(crate::TASK_CGROUP_TEMPLATE!(), process.pid)).unwrap_or_default();
+            // Lines are `hierarchy:controllers:path`
+            content.lines()
+                    .filter_map(|line| line.splitn(3, ':').nth(2))
+                    .any(|path| path == query || path.starts_with(&below))
+        })
+    }
+}
+
+/// An Android init service, by the pid init publishes on debuggable
+/// builds or else by its process name
+pub struct ServiceResolver;
+
+impl TargetResolver for ServiceResolver {
+    fn scheme(&self) -> &str {
+        "service"
+    }
+
+    fn resolve(&self, query: &str) -> Result<Vec<pid_t>, TraceError> {
+        let property = format!(SERVICE_PID_PROPERTY_TEMPLATE!(), query);
+        if let Ok(output) = audit::run(Command::new("getprop").arg(&property), Effect::ReadOnly) {
+            let pid = from_utf8(&output.stdout).unwrap_or("").trim().parse::<pid_t>().unwrap_or(0);
+            if output.status.success() && pid > 0 && process_alive(pid) {
+                return Ok(vec![pid]);
+            }
+        }
+        find_processes(query, MatchMode::Exact)
+    }
+}
+
+/// Every descendant of a process by pid, the process itself excluded
+pub struct ChildrenResolver;
+
+impl TargetResolver for ChildrenResolver {
+    fn scheme(&self) -> &str {
+        "children"
+    }
+
+    fn resolve(&self, query: &str) -> Result<Vec<pid_t>, TraceError> {
+        let root = query.parse::<pid_t>()
+                .map_err(|_| TraceError::InvalidConfig(format!("bad pid {}", query)))?;
+        let parents: Vec<(pid_t, pid_t)> = fs::read_dir(PROC_ROOT)?
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<pid_t>().ok())
+                .filter_map(|pid| Some((pid, process_ppid(pid)?)))
+                .collect();
+        let mut descendants = vec![root];
+        let mut i = 0;
+        while i < descendants.len() {
+            let parent = descendants[i];
+            descendants.extend(parents.iter().filter(|(_, ppid)| *ppid == parent).map(|(pid, _)| *pid));
+            i += 1;
+        }
+        descendants.remove(0);
+        find_processes_by(|process| descendants.contains(&process.pid))
+    }
+}
+
+// Parent pid, the field after the state of /proc/<pid>/stat
+fn process_ppid(pid: pid_t) -> Option<pid_t> {
+    let stat = fs::read_to_string(format!(crate::TASK_STAT_TEMPLATE!(), pid)).ok()?;
+    split_stat(&stat).get(PROCESS_STAT_PPID_SHIFT)?.parse().ok()
+}
+
+fn resolvers() -> &'static RwLock<Vec<Arc<dyn TargetResolver>>> {
+    static RESOLVERS: OnceLock<RwLock<Vec<Arc<dyn TargetResolver>>>> = OnceLock::new();
+    RESOLVERS.get_or_init(|| {
+        let mut builtin: Vec<Arc<dyn TargetResolver>> = MATCH_MODES.iter()
+                .filter_map(|name| MatchMode::parse(name))
+                .map(|mode| Arc::new(NameResolver(mode)) as Arc<dyn TargetResolver>)
+                .collect();
+        builtin.push(Arc::new(PidResolver));
+        builtin.push(Arc::new(UidResolver));
+        builtin.push(Arc::new(PackageResolver));
+        builtin.push(Arc::new(CgroupResolver));
+        builtin.push(Arc::new(ServiceResolver));
+        builtin.push(Arc::new(ChildrenResolver));
+        RwLock::new(builtin)
+    })
+}
+
+/// Add a resolver, its scheme must be new and must not contain `:`
+pub fn register_resolver(resolver: impl TargetResolver + 'static) -> Result<(), TraceError> {
+    let scheme = resolver.scheme().to_string();
+    if scheme.is_empty() || scheme.contains(':') {
+        return Err(TraceError::InvalidConfig(format!("bad resolver scheme {:?}", scheme)));
+    }
+    let mut resolvers = resolvers().write().unwrap();
+    if resolvers.iter().any(|other| other.scheme() == scheme) {
+        return Err(TraceError::InvalidConfig(format!("resolver of {} already registered", scheme)));
+    }
+    resolvers.push(Arc::new(resolver));
+    Ok(())
+}
+
+/// Get the resolver of a scheme
+pub fn resolver(scheme: &str) -> Option<Arc<dyn TargetResolver>> {
+    resolvers().read().unwrap().iter().find(|resolver| resolver.scheme() == scheme).cloned()
+}
+
+/// Schemes of every registered resolver, built-in ones first
+pub fn resolver_schemes() -> Vec<String> {
+    resolvers().read().unwrap().iter().map(|resolver| resolver.scheme().to_string()).collect()
+}
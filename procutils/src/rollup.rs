@@ -0,0 +1,216 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use libc::{pid_t, sysconf, _SC_CLK_TCK};
+use crate::discover::PROC_ROOT;
+use crate::error::TraceError;
+use crate::file_utils::read_node;
+use crate::proc_analysis::{parse_task_stat, split_stat, status_field, RecordItem, TraceTarget};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// Peak resident set size of /proc/<pid>/status
+const TASK_VM_HWM_KEY: &str = "VmHWM";
+
+/// Header of a rollup CSV
+pub const ROLLUP_CSV_HEADER: &str = "name,processes,running,lifetimeTotal,lifetimeMax,cpuTotal,cpuMax,peakRssMax,peakRssMean";
+
+/// Totals of the processes sharing a name
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NameRollup {
+    /// comm of the processes
+    pub name: String,
+    /// processes seen
+    pub processes: usize,
+    /// processes still running when the rollup was taken
+    pub running: usize,
+    /// seconds from start to the last time each process was seen, summed
+    pub lifetime_total: f64,
+    /// longest lifetime in seconds
+    pub lifetime_max: f64,
+    /// CPU seconds, summed
+    pub cpu_total: f64,
+    /// CPU seconds of the busiest process
+    pub cpu_max: f64,
+    /// largest peak RSS in KB
+    pub peak_rss_max: usize,
+    /// peak RSS in KB, summed, see `peak_rss_mean`
+    pub peak_rss_total: usize,
+}
+
+impl NameRollup {
+    /// Mean peak RSS of the processes in KB
+    pub fn peak_rss_mean(&self) -> f64 {
+        match self.processes {
+            0 => 0.0,
+            processes => self.peak_rss_total as f64 / processes as f64,
+        }
+    }
+
+    fn add(&mut self, process: &Tracked, running: bool) {
+        self.processes += 1;
+        self.running += running as usize;
+        self.lifetime_total += process.lifetime;
+        self.lifetime_max = self.lifetime_max.max(process.lifetime);
+        self.cpu_total += process.cpu;
+        self.cpu_max = self.cpu_max.max(process.cpu);
+        self.peak_rss_max = self.peak_rss_max.max(process.peak_rss);
+        self.peak_rss_total += process.peak_rss;
+    }
+}
+
+// Last values of a running process, the final ones are lost at its exit
+#[derive(Clone, Debug, Default)]
+struct Tracked {
+    name: String,
+    start_time: u64,
+    lifetime: f64,
+    cpu: f64,
+    peak_rss: usize,
+}
+
+/// Accounts the processes of a target by name, for workloads which start
+/// many short-lived processes, e.g. dex2oat or installd workers
+///
+/// Processes are polled, so values are as of the last poll before a process
+/// exited and processes living shorter than one poll may be missed.
+#[derive(Debug)]
+pub struct RollupTracker {
+    target: TraceTarget,
+    live: HashMap<pid_t, Tracked>,
+    exited: HashMap<String, NameRollup>,
+}
+
+impl RollupTracker {
+    /// Track the processes of `target`
+    pub fn new(target: TraceTarget) -> RollupTracker {
+        RollupTracker { target, live: HashMap::new(), exited: HashMap::new() }
+    }
+
+    /// Read every process of the target and fold the exited ones into the totals
+    pub fn poll(&mut self) -> Result<(), TraceError> {
+        let uptime = read_uptime()?;
+        let pids = self.target.resolve_pids().unwrap_or_default();
+        let mut live = HashMap::with_capacity(pids.len());
+        for pid in pids {
+            let Some(mut current) = read_process(pid, uptime) else { continue };
+            // A reused pid is a new process
+            match self.live.remove(&pid) {
+                Some(last) if last.start_time == current.start_time => {
+                    current.peak_rss = current.peak_rss.max(last.peak_rss);
+                },
+                Some(last) => Self::fold_into(&mut self.exited, &last, false),
+                None => {},
+            }
+            live.insert(pid, current);
+        }
+        for (_, last) in self.live.drain() {
+            Self::fold_into(&mut self.exited, &last, false);
+        }
+        self.live = live;
+        Ok(())
+    }
+
+    // Add a process to the totals of its name
+    fn fold_into(totals: &mut HashMap<String, NameRollup>, process: &Tracked, running: bool) {
+        totals.entry(process.name.clone())
+                .or_insert_with(|| NameRollup { name: process.name.clone(), ..Default::default() })
+                .add(process, running);
+    }
+
+    /// Totals by name of the exited and the running processes, by CPU highest first
+    pub fn rollup(&self) -> Vec<NameRollup> {
+        let mut totals = self.exited.clone();
+        for process in self.live.values() {
+            Self::fold_into(&mut totals, process, true);
+        }
+        let mut rollup: Vec<NameRollup> = totals.into_values().collect();
+        rollup.sort_by(|a, b| b.cpu_total.total_cmp(&a.cpu_total).then_with(|| a.name.cmp(&b.name)));
+        rollup
+    }
+
+    /// Poll every `poll` for `duration`, `observer` gets the rollup after every
+    /// poll and returning false stops early. <= 0 seconds polls until the
+    /// observer stops
+    pub fn run(&mut self, poll: Duration, duration: i64,
+            observer: &mut impl FnMut(&[NameRollup]) -> bool) -> Result<Vec<NameRollup>, TraceError> {
+        let start = Instant::now();
+        loop {
+            self.poll()?;
+            if !observer(&self.rollup()) {
+                break;
+            }
+            if duration > 0 && start.elapsed() >= Duration::from_secs(duration as u64) {
+                break;
+            }
+            sleep(poll);
+        }
+        Ok(self.rollup())
+    }
+}
+
+// Seconds since boot
+fn read_uptime() -> Result<f64, TraceError> {
+    let content = read_node(&format!("{}/uptime", PROC_ROOT))?;
+    content.split_whitespace().next()
+            .and_then(|uptime| uptime.parse().ok())
+            .ok_or(TraceError::Parse { field: "uptime", value: content.trim().to_string() })
+}
+
+// Read one process, None once it exited
+fn read_process(pid: pid_t, uptime: f64) -> Option<Tracked> {
+    let stat = read_node(&format!(crate::TASK_STAT_TEMPLATE!(), pid)).ok()?;
+    let mut item = RecordItem::default();
+    parse_task_stat(&mut item, &stat).ok()?;
+    let name = split_stat(&stat).get(1)?.trim_start_matches('(').trim_end_matches(')').to_string();
+    // SAFETY:
+    // Safe because sysconf has no side effects
+    let clock_ticks = unsafe { sysconf(_SC_CLK_TCK) as f64 };
+    let start_time = item.start_time as u64;
+    let peak_rss = read_node(&format!(crate::TASK_STATUS_TEMPLATE!(), pid)).ok()
+            .and_then(|status| status.lines()
+                    .filter_map(status_field)
+                    .find(|(key, _)| *key == TASK_VM_HWM_KEY)
+                    .and_then(|(_, value)| value.parse().ok()))
+            .unwrap_or(0);
+    Some(Tracked {
+        name,
+        start_time,
+        lifetime: (uptime - start_time as f64 / clock_ticks).max(0.0),
+        cpu: item.totalcputime,
+        peak_rss,
+    })
+}
+
+/// Rollup as a table
+pub fn format_rollup(rollup: &[NameRollup]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:<16}{:>8}{:>8}{:>14}{:>12}{:>12}{:>12}{:>12}",
+            "name", "procs", "running", "lifetime(s)", "max(s)", "cpu(s)", "peakRss", "meanRss");
+    for name in rollup {
+        let _ = writeln!(out, "{:<16}{:>8}{:>8}{:>14.1}{:>12.1}{:>12.2}{:>12}{:>12.0}",
+                name.name, name.processes, name.running, name.lifetime_total, name.lifetime_max,
+                name.cpu_total, name.peak_rss_max, name.peak_rss_mean());
+    }
+    out
+}
+
+/// Write a rollup as CSV, sizes in KB and times in seconds
+pub fn write_rollup_csv(path: &str, rollup: &[NameRollup]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{}", ROLLUP_CSV_HEADER)?;
+    for name in rollup {
+        writeln!(out, "{},{},{},{:.3},{:.3},{:.3},{:.3},{},{:.0}", name.name, name.processes, name.running,
+                name.lifetime_total, name.lifetime_max, name.cpu_total, name.cpu_max, name.peak_rss_max,
+                name.peak_rss_mean())?;
+    }
+    out.flush()
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{clock_gettime, timespec, CLOCK_THREAD_CPUTIME_ID};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Default share of one core the tracer may spend sampling
+pub const DEFAULT_OVERHEAD_BUDGET: f64 = 0.01;
+
+/// Upper bounds of the histogram buckets in microseconds, one more bucket
+/// holds everything above the last bound
+pub const HISTOGRAM_BOUNDS_US: [u64; 13] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+];
+
+// Shortest interval recommended, in seconds
+const MIN_INTERVAL: i64 = 1;
+
+// Width of the longest bar of a rendered histogram
+const HISTOGRAM_BAR_WIDTH: u64 = 40;
+
+/// Distribution of durations in fixed buckets
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    /// counts per bucket, see `HISTOGRAM_BOUNDS_US`
+    pub buckets: [u64; HISTOGRAM_BOUNDS_US.len() + 1],
+    /// durations added
+    pub count: u64,
+    /// sum of all durations in microseconds
+    pub sum_us: u64,
+    /// longest duration in microseconds
+    pub max_us: u64,
+}
+
+// `<= 2.5ms`, `> 1000ms`
+fn bucket_name(index: usize) -> String {
+    match HISTOGRAM_BOUNDS_US.get(index) {
+        Some(bound) => format!("<= {}ms", *bound as f64 / 1000.0),
+        None => format!("> {}ms", HISTOGRAM_BOUNDS_US[HISTOGRAM_BOUNDS_US.len() - 1] as f64 / 1000.0),
+    }
+}
+
+impl Histogram {
+    /// Add one duration
+    pub fn add(&mut self, duration: Duration) {
+        let us = duration.as_micros() as u64;
+        let index = HISTOGRAM_BOUNDS_US.iter().position(|bound| us <= *bound).unwrap_or(HISTOGRAM_BOUNDS_US.len());
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.sum_us += us;
+        self.max_us = self.max_us.max(us);
+    }
+
+    /// Mean duration in microseconds, 0 when empty
+    pub fn mean_us(&self) -> u64 {
+        self.sum_us.checked_div(self.count).unwrap_or(0)
+    }
+
+    /// Name of the bucket holding the `p` (0.0 ~ 1.0) percentile, None when empty
+    pub fn percentile_bucket(&self, p: f64) -> Option<String> {
+        let wanted = (self.count as f64 * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= wanted && self.count > 0 {
+                return Some(bucket_name(index));
+            }
+        }
+        None
+    }
+
+    /// Render the non empty buckets as text bars
+    pub fn render(&self, out: &mut String) {
+        let top = self.buckets.iter().copied().max().unwrap_or(0).max(1);
+        for (index, count) in self.buckets.iter().enumerate().filter(|(_, count)| **count > 0) {
+            let bar = "#".repeat(((count * HISTOGRAM_BAR_WIDTH).div_ceil(top)) as usize);
+            let _ = writeln!(out, "    {:>10} {:>8} {}", bucket_name(index), count, bar);
+        }
+    }
+
+    /// Non empty buckets as `le<bound>us=<count>` joined by `,`, for event details
+    pub fn compact(&self) -> String {
+        let buckets: Vec<String> = self.buckets.iter().enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(index, count)| match HISTOGRAM_BOUNDS_US.get(index) {
+                    Some(bound) => format!("le{}us={}", bound, count),
+                    None => format!("inf={}", count),
+                })
+                .collect();
+        buckets.join(",")
+    }
+}
+
+/// Timing of the sampling ticks of one target
+#[derive(Clone, Debug, Default)]
+pub struct SamplingStats {
+    /// time spent collecting and writing each sample
+    pub duration: Histogram,
+    /// how much later than asked each sleep between samples woke up
+    pub jitter: Histogram,
+    /// samples which took longer than the slow sample fraction of the interval
+    pub slow: u64,
+}
+
+impl SamplingStats {
+    /// Render the summary with both histograms
+    pub fn format(&self, name: &str) -> String {
+        let mut out = format!("sampling of {}: {} samples, {} slow\n", name, self.duration.count, self.slow);
+        for (title, histogram) in [("duration", &self.duration), ("jitter", &self.jitter)] {
+            let _ = writeln!(out, "  {} mean {:.3}ms p95 {} max {:.3}ms", title,
+                    histogram.mean_us() as f64 / 1000.0,
+                    histogram.percentile_bucket(0.95).unwrap_or_else(|| "-".to_string()),
+                    histogram.max_us as f64 / 1000.0);
+            histogram.render(&mut out);
+        }
+        out
+    }
+
+    /// Detail of the `sampling` event
+    pub fn detail(&self) -> String {
+        format!("samples={} slow={} duration_mean_us={} duration_max_us={} duration=[{}] \
+                jitter_mean_us={} jitter_max_us={} jitter=[{}]",
+                self.duration.count, self.slow, self.duration.mean_us(), self.duration.max_us,
+                self.duration.compact(), self.jitter.mean_us(), self.jitter.max_us, self.jitter.compact())
+    }
+}
+
+/// CPU time consumed by the calling thread
+pub fn thread_cpu_time() -> Duration {
+    let mut now = timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY:
+    // Safe because `now` is a valid timespec, on failure it stays 0
+    unsafe { clock_gettime(CLOCK_THREAD_CPUTIME_ID, &mut now) };
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
+
+/// Smallest interval in seconds which keeps `cost_per_tick` of CPU time
+/// under `budget`, a share of one core
+pub fn recommend_interval(cost_per_tick: Duration, budget: f64) -> i64 {
+    ((cost_per_tick.as_secs_f64() / budget).ceil() as i64).max(MIN_INTERVAL)
+}
@@ -0,0 +1,249 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use libc::pid_t;
+use crate::audit;
+use crate::file_utils::{read_node, read_path};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Mount point of the cgroup v2 hierarchy
+pub const CGROUP_V2_ROOT: &str = "/sys/fs/cgroup";
+
+/// File name of the samples of a sandbox, in the session directory
+pub const SANDBOX_STATS_FILE: &str = "sandbox.csv";
+
+/// Header of the sandbox samples
+pub const SANDBOX_CSV_HEADER: &str =
+        "time,memoryCurrent,memoryPeak,cpuUsage,cpuThrottled,nrThrottled,pidsCurrent,oomKill";
+
+macro_rules! SANDBOX_DIR_TEMPLATE { () => { "{}/process_trace_{}" }; }
+
+// cpu.max period in microseconds, a quota of the same length is one CPU
+const CPU_MAX_PERIOD: u64 = 100_000;
+
+/// Parse a size in bytes, e.g. `512M`, the suffixes are K, M and G in 1024 steps
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, shift) = match text.chars().last()?.to_ascii_uppercase() {
+        'K' => (&text[..text.len() - 1], 10),
+        'M' => (&text[..text.len() - 1], 20),
+        'G' => (&text[..text.len() - 1], 30),
+        _ => (text, 0),
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// Counters of a sandbox, zero when the controller is not enabled
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SandboxStats {
+    /// memory.current in bytes
+    pub memory_current: u64,
+    /// memory.peak in bytes, kernels before 5.19 do not have it
+    pub memory_peak: u64,
+    /// usage_usec of cpu.stat
+    pub cpu_usage_usec: u64,
+    /// throttled_usec of cpu.stat
+    pub throttled_usec: u64,
+    /// nr_throttled of cpu.stat
+    pub nr_throttled: u64,
+    /// pids.current
+    pub pids_current: u64,
+    /// oom_kill of memory.events
+    pub oom_kill: u64,
+}
+
+/// Limits of a sandbox cgroup, built with chained setters
+///
+/// ```ignore
+/// let sandbox = SandboxConfig::new()
+///         .memory_max(512 << 20)
+///         .cpu_max(50)
+///         .build(pid)?;
+/// sandbox.add(pid)?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct SandboxConfig {
+    root: String,
+    memory_max: Option<u64>,
+    cpu_max: Option<u64>,
+    pids_max: Option<u64>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig::new()
+    }
+}
+
+impl SandboxConfig {
+    /// A cgroup below the cgroup v2 root without limits
+    pub fn new() -> SandboxConfig {
+        SandboxConfig {
+            root: CGROUP_V2_ROOT.to_string(),
+            memory_max: None,
+            cpu_max: None,
+            pids_max: None,
+        }
+    }
+
+    /// Cgroup the sandbox is created in, it must be a cgroup v2 one
+    pub fn root(mut self, root: &str) -> SandboxConfig {
+        self.root = root.to_string();
+        self
+    }
+
+    /// memory.max in bytes, the workload is reclaimed and then OOM killed above it
+    pub fn memory_max(mut self, bytes: u64) -> SandboxConfig {
+        self.memory_max = Some(bytes);
+        self
+    }
+
+    /// cpu.max in percent of one CPU, 200 is two CPUs
+    pub fn cpu_max(mut self, percent: u64) -> SandboxConfig {
+        self.cpu_max = Some(percent);
+        self
+    }
+
+    /// pids.max, forks fail above it
+    pub fn pids_max(mut self, count: u64) -> SandboxConfig {
+        self.pids_max = Some(count);
+        self
+    }
+
+    /// Create the cgroup, `id` names it, e.g. the pid of the workload.
+    /// The controllers of the limits are enabled in the root first, the memory
+    /// and pids ones for the counters too if the root allows it
+    pub fn build(&self, id: pid_t) -> io::Result<Sandbox> {
+        let available = read_path(&format!("{}/cgroup.controllers", self.root))
+                .map_err(|e| io::Error::new(e.kind(), format!("{} is not a cgroup v2 root: {}", self.root, e)))?;
+        let required = [("memory", self.memory_max.is_some()), ("cpu", self.cpu_max.is_some()),
+                ("pids", self.pids_max.is_some())];
+        for (controller, limited) in required {
+            if !available.split_whitespace().any(|c| c == controller) {
+                if limited {
+                    return Err(io::Error::new(io::ErrorKind::Unsupported,
+                            format!("{} has no {} controller", self.root, controller)));
+                }
+                continue;
+            }
+            let enabled = audit::write_system_file(&format!("{}/cgroup.subtree_control", self.root),
+                    &format!("+{}", controller));
+            if let (Err(e), true) = (enabled, limited) {
+                return Err(io::Error::new(e.kind(), format!("enable {} in {} failed: {}", controller, self.root, e)));
+            }
+        }
+        let sandbox = Sandbox { path: format!(SANDBOX_DIR_TEMPLATE!(), self.root, id) };
+        audit::create_system_dir(&sandbox.path)?;
+        // Dropping the sandbox removes the cgroup again if a limit is refused
+        if let Some(bytes) = self.memory_max {
+            sandbox.write("memory.max", &bytes.to_string())?;
+        }
+        if let Some(percent) = self.cpu_max {
+            sandbox.write("cpu.max", &format!("{} {}", percent * CPU_MAX_PERIOD / 100, CPU_MAX_PERIOD))?;
+        }
+        if let Some(count) = self.pids_max {
+            sandbox.write("pids.max", &count.to_string())?;
+        }
+        Ok(sandbox)
+    }
+}
+
+/// A cgroup created for one workload, removed again when dropped
+///
+/// Processes still in it when it is dropped keep it alive, it is reported and
+/// left behind then.
+#[derive(Debug)]
+pub struct Sandbox {
+    path: String,
+}
+
+impl Sandbox {
+    /// Directory of the cgroup
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        audit::write_system_file(&format!("{}/{}", self.path, file), value)
+                .map_err(|e| io::Error::new(e.kind(), format!("write {} to {}/{} failed: {}", value, self.path, file, e)))
+    }
+
+    // Value of a key of a flat keyed file like cpu.stat, 0 when it is missing
+    fn keyed(&self, file: &str, key: &str) -> u64 {
+        read_node(&format!("{}/{}", self.path, file))
+                .ok()
+                .and_then(|content| content.lines()
+                        .filter_map(|line| line.split_once(' '))
+                        .find(|(k, _)| *k == key)
+                        .and_then(|(_, v)| v.trim().parse().ok()))
+                .unwrap_or(0)
+    }
+
+    fn single(&self, file: &str) -> u64 {
+        read_node(&format!("{}/{}", self.path, file))
+                .ok()
+                .and_then(|content| content.trim().parse().ok())
+                .unwrap_or(0)
+    }
+
+    /// Move a process into the sandbox, its later children are born in it
+    pub fn add(&self, pid: pid_t) -> io::Result<()> {
+        self.write("cgroup.procs", &pid.to_string())
+    }
+
+    /// Read the counters
+    pub fn stats(&self) -> SandboxStats {
+        SandboxStats {
+            memory_current: self.single("memory.current"),
+            memory_peak: self.single("memory.peak"),
+            cpu_usage_usec: self.keyed("cpu.stat", "usage_usec"),
+            throttled_usec: self.keyed("cpu.stat", "throttled_usec"),
+            nr_throttled: self.keyed("cpu.stat", "nr_throttled"),
+            pids_current: self.single("pids.current"),
+            oom_kill: self.keyed("memory.events", "oom_kill"),
+        }
+    }
+
+    /// Sample the counters to a csv file every `interval` until `done` is set,
+    /// one more sample is taken then. Returns the last sample
+    pub fn record(&self, path: &str, interval: Duration, done: &AtomicBool) -> io::Result<SandboxStats> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", SANDBOX_CSV_HEADER)?;
+        let start = Instant::now();
+        loop {
+            let finished = done.load(Ordering::Relaxed);
+            let stats = self.stats();
+            writeln!(out, "{:.3},{},{},{},{},{},{},{}", start.elapsed().as_secs_f64(), stats.memory_current,
+                    stats.memory_peak, stats.cpu_usage_usec, stats.throttled_usec, stats.nr_throttled,
+                    stats.pids_current, stats.oom_kill)?;
+            out.flush()?;
+            if finished {
+                return Ok(stats);
+            }
+            // Wake up often so the last sample follows the end closely
+            let next = start.elapsed() + interval;
+            while start.elapsed() < next && !done.load(Ordering::Relaxed) {
+                sleep(Duration::from_millis(50));
+            }
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if Path::new(&self.path).exists() {
+            if let Err(e) = audit::remove_system_dir(&self.path) {
+                println!("remove cgroup {} failed: {}", self.path, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::analysis::TraceData;
+
+/// Column the score of every sample is written to
+pub const HEALTH_SCORE_COLUMN: &str = "healthScore";
+
+/// Formula used when none is given: CPU share, PSS, major fault and
+/// voluntary context switch rates
+pub const DEFAULT_HEALTH_SCORE: &str =
+        "cpuOccupancyRate:0.4@0.25,pss:0.3@500000,majflt/s:0.2@20,voluntaryCtxtSwitches/s:0.1@1000";
+
+// Suffix of a term which divides the delta of a sample by its interval
+const PER_SECOND_SUFFIX: &str = "/s";
+
+/// One weighted term of a health score, written `<column>[/s]:<weight>@<full>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreTerm {
+    /// CSV column, e.g. `pss`
+    pub column: String,
+    /// divide by the seconds since the last sample, for counter deltas
+    pub per_second: bool,
+    /// share of the term in the score, relative to the other weights
+    pub weight: f64,
+    /// value at which the term costs its whole weight, values are normalized
+    /// to 0 ~ 1 against it
+    pub full: f64,
+}
+
+impl ScoreTerm {
+    /// Parse `<column>[/s]:<weight>@<full>`, e.g. `majflt/s:0.2@20`
+    pub fn parse(spec: &str) -> Option<ScoreTerm> {
+        let (column, rest) = spec.trim().split_once(':')?;
+        let (weight, full) = rest.split_once('@')?;
+        let (column, per_second) = match column.strip_suffix(PER_SECOND_SUFFIX) {
+            Some(column) => (column, true),
+            None => (column, false),
+        };
+        let weight: f64 = weight.trim().parse().ok().filter(|weight: &f64| *weight > 0.0 && weight.is_finite())?;
+        let full: f64 = full.trim().parse().ok().filter(|full: &f64| *full > 0.0 && full.is_finite())?;
+        (!column.is_empty()).then(|| ScoreTerm { column: column.to_string(), per_second, weight, full })
+    }
+
+    /// Spec accepted by `parse`
+    pub fn spec(&self) -> String {
+        format!("{}{}:{}@{}", self.column, if self.per_second { PER_SECOND_SUFFIX } else { "" },
+                self.weight, self.full)
+    }
+}
+
+/// Composite score of a sample, 100 when every term is 0 and 0 when every
+/// term is at or over its full value
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthScore {
+    /// weighted terms
+    pub terms: Vec<ScoreTerm>,
+}
+
+impl Default for HealthScore {
+    fn default() -> Self {
+        HealthScore::parse(DEFAULT_HEALTH_SCORE).unwrap_or(HealthScore { terms: Vec::new() })
+    }
+}
+
+impl HealthScore {
+    /// Parse terms separated by commas, None when one is malformed
+    pub fn parse(spec: &str) -> Option<HealthScore> {
+        let terms = spec.split(',').map(ScoreTerm::parse).collect::<Option<Vec<ScoreTerm>>>()?;
+        (!terms.is_empty()).then_some(HealthScore { terms })
+    }
+
+    /// Spec accepted by `parse`
+    pub fn spec(&self) -> String {
+        self.terms.iter().map(ScoreTerm::spec).collect::<Vec<String>>().join(",")
+    }
+
+    /// Score of every row of `data`. Terms whose column is missing or not a
+    /// number in a row are left out and the other weights count for them
+    pub fn sample_scores(&self, data: &TraceData) -> Vec<f64> {
+        let time = data.column_index("time").map(|i| data.column(i)).unwrap_or_default();
+        let columns: Vec<(&ScoreTerm, Vec<f64>)> = self.terms.iter()
+                .filter_map(|term| Some((term, data.column(data.column_index(&term.column)?))))
+                .collect();
+        (0..data.rows.len()).map(|row| {
+            // The first sample has no previous one, it covers the interval of the next
+            let seconds = match (row.checked_sub(1), row + 1 < time.len()) {
+                (Some(previous), _) => time.get(row).zip(time.get(previous)).map(|(now, last)| now - last),
+                (None, true) => Some(time[row + 1] - time[row]),
+                (None, false) => None,
+            }.filter(|seconds| *seconds > 0.0).unwrap_or(1.0);
+            let (mut cost, mut weights) = (0.0, 0.0);
+            for (term, values) in &columns {
+                let value = match term.per_second {
+                    true => values[row] / seconds,
+                    false => values[row],
+                };
+                if value.is_finite() {
+                    cost += term.weight * (value / term.full).clamp(0.0, 1.0);
+                    weights += term.weight;
+                }
+            }
+            match weights > 0.0 {
+                true => 100.0 * (1.0 - cost / weights),
+                false => f64::NAN,
+            }
+        }).collect()
+    }
+
+    /// Add the score of every row as `healthScore`, replacing an older one
+    pub fn add_column(&self, data: &mut TraceData) {
+        let scores = self.sample_scores(data);
+        match data.column_index(HEALTH_SCORE_COLUMN) {
+            Some(index) => {
+                for (row, score) in data.rows.iter_mut().zip(scores) {
+                    row[index] = score;
+                }
+            },
+            None => {
+                data.columns.push(HEALTH_SCORE_COLUMN.to_string());
+                for (row, score) in data.rows.iter_mut().zip(scores) {
+                    row.push(score);
+                }
+            },
+        }
+    }
+}
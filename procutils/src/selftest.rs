@@ -0,0 +1,181 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{pid_t, EACCES, ENOENT, EPERM};
+use crate::error::TraceError;
+use crate::file_utils::{read_path, RetryPolicy};
+use crate::hidepid::{visible_uid, ProcMount};
+use crate::proc_analysis::{get_global_cpu_info, get_pss_info, parse_field, parse_task_cgroup, parse_task_io,
+        parse_task_stat, parse_task_status, process_alive, pss_path, RecordItem, Sampler, COLLECTORS};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+
+// Release of the running kernel
+const KERNEL_RELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+
+/// Outcome of the self test of a collector
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// read and parsed, the values make sense
+    Ok,
+    /// the node exists but the tracer may not read it
+    NeedsPrivileges,
+    /// the kernel has no such node
+    Unsupported,
+    /// the node is broken or its values make no sense
+    Failed,
+}
+
+impl CheckStatus {
+    /// Name printed in the report
+    pub fn name(&self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::NeedsPrivileges => "needs privileges",
+            CheckStatus::Unsupported => "unsupported",
+            CheckStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Self test of one collector
+#[derive(Clone, Debug)]
+pub struct CollectorCheck {
+    /// name of the collector, see `COLLECTORS`
+    pub name: &'static str,
+    /// node the collector read
+    pub path: String,
+    /// outcome
+    pub status: CheckStatus,
+    /// values read, or why the collector failed
+    pub detail: String,
+}
+
+/// Release of the running kernel, e.g. `5.10.198-android12-9`
+pub fn kernel_release() -> String {
+    read_path(KERNEL_RELEASE_PATH).map(|release| release.trim().to_string())
+            .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Run every collector of `COLLECTORS` once against `pid`, its main thread
+/// stands in for the per thread nodes
+///
+/// A pid hidden by hidepid fails its nodes as missing, they are reported as
+/// needing privileges instead.
+pub fn run_selftest(pid: pid_t) -> Vec<CollectorCheck> {
+    let mut sampler = Sampler::default();
+    let hidden = visible_uid().is_some() && !process_alive(pid);
+    COLLECTORS.iter().map(|(name, source)| {
+        let path = match *name {
+            "pss" => pss_path(pid),
+            _ => source.replace("<pid>", &pid.to_string()).replace("<tid>", &pid.to_string()),
+        };
+        let (status, detail) = match check_collector(name, &path, pid, &mut sampler) {
+            Ok(detail) => (CheckStatus::Ok, detail),
+            Err(_) if hidden => (CheckStatus::NeedsPrivileges,
+                    format!("pid {} is hidden by {}", pid, ProcMount::current().describe())),
+            Err(failure) => failure,
+        };
+        CollectorCheck { name, path, status, detail }
+    }).collect()
+}
+
+// A read failure, EACCES and EPERM are SELinux or ptrace access checks
+fn read_failure(e: io::Error) -> (CheckStatus, String) {
+    let status = match e.raw_os_error() {
+        Some(EACCES) | Some(EPERM) => CheckStatus::NeedsPrivileges,
+        Some(ENOENT) => CheckStatus::Unsupported,
+        _ => CheckStatus::Failed,
+    };
+    (status, e.to_string())
+}
+
+fn parse_failure(e: TraceError) -> (CheckStatus, String) {
+    match e {
+        TraceError::Io(e) => read_failure(e),
+        e => (CheckStatus::Failed, e.to_string()),
+    }
+}
+
+// The values read, or a failure when they make no sense
+fn sane(sane: bool, values: String) -> Result<String, (CheckStatus, String)> {
+    match sane {
+        true => Ok(values),
+        false => Err((CheckStatus::Failed, format!("implausible {}", values))),
+    }
+}
+
+fn check_collector(name: &str, path: &str, pid: pid_t, sampler: &mut Sampler) -> Result<String, (CheckStatus, String)> {
+    let mut item = RecordItem::default();
+    let mut retries = 0;
+    match name {
+        "global_cpu" => {
+            get_global_cpu_info(&mut item, sampler, &RetryPolicy::NONE).map_err(read_failure)?;
+            sane(item.global_total_cpu_time > 0.0, format!("gtotalcputime={:.2}s", item.global_total_cpu_time))
+        },
+        "pss" => {
+            get_pss_info(&mut item, pid, sampler, &RetryPolicy::NONE).map_err(read_failure)?;
+            sane(item.pss > 0, format!("pss={}KB", item.pss))
+        },
+        "task_status" => {
+            let content = sampler.read(path, &RetryPolicy::NONE, &mut retries).map_err(read_failure)?;
+            parse_task_status(&mut item, content).map_err(parse_failure)?;
+            sane(item.vm_rss > 0, format!("vmRss={}KB voluntaryCtxtSwitches={}", item.vm_rss,
+                    item.voluntary_ctxt_switches))
+        },
+        "task_stat" => {
+            let content = sampler.read(path, &RetryPolicy::NONE, &mut retries).map_err(read_failure)?;
+            parse_task_stat(&mut item, content).map_err(parse_failure)?;
+            sane(item.num_threads > 0 && item.totalcputime >= 0.0, format!("totalcputime={:.2}s numThreads={}",
+                    item.totalcputime, item.num_threads))
+        },
+        "io" => {
+            let content = sampler.read(path, &RetryPolicy::NONE, &mut retries).map_err(read_failure)?;
+            parse_task_io(&mut item, content).map_err(parse_failure)?;
+            sane(item.syscr > 0, format!("readBytes={} writeBytes={} syscr={} syscw={}", item.read_bytes,
+                    item.write_bytes, item.syscr, item.syscw))
+        },
+        "fd" => {
+            let count = fs::read_dir(path).map_err(read_failure)?.filter(|entry| entry.is_ok()).count();
+            sane(count > 0, format!("fdCount={}", count))
+        },
+        "oom_score_adj" => {
+            let content = sampler.read(path, &RetryPolicy::NONE, &mut retries).map_err(read_failure)?;
+            let oom_score_adj: i32 = parse_field(content, "oom_score_adj").map_err(parse_failure)?;
+            sane((-1000..=1000).contains(&oom_score_adj), format!("oomScoreAdj={}", oom_score_adj))
+        },
+        "cgroup" => {
+            let content = sampler.read(path, &RetryPolicy::NONE, &mut retries).map_err(read_failure)?;
+            parse_task_cgroup(&mut item, content);
+            sane(!item.cgroup.is_empty(), format!("cgroup={}", item.cgroup))
+        },
+        "sched" => {
+            let content = sampler.read(path, &RetryPolicy::NONE, &mut retries).map_err(read_failure)?;
+            let times: Vec<f64> = content.split_whitespace().filter_map(|field| field.parse().ok()).collect();
+            match times.as_slice() {
+                [run, delay, ..] => sane(*run >= 0.0 && *delay >= 0.0,
+                        format!("runTime={:.3}s runDelay={:.3}s", run / 1e9, delay / 1e9)),
+                _ => Err((CheckStatus::Failed, format!("parse schedstat failed: {:?}", content.trim()))),
+            }
+        },
+        _ => Err((CheckStatus::Failed, "no self test".to_string())),
+    }
+}
+
+/// Format a self test as a table with a summary line
+pub fn format_selftest(checks: &[CollectorCheck]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:<16}{:<18}{:<34}detail", "collector", "result", "node");
+    for check in checks {
+        let _ = writeln!(out, "{:<16}{:<18}{:<34}{}", check.name, check.status.name(), check.path, check.detail);
+    }
+    let count = |status: CheckStatus| checks.iter().filter(|check| check.status == status).count();
+    let _ = writeln!(out, "{} collectors: {} ok, {} need privileges, {} unsupported, {} failed", checks.len(),
+            count(CheckStatus::Ok), count(CheckStatus::NeedsPrivileges), count(CheckStatus::Unsupported),
+            count(CheckStatus::Failed));
+    out
+}
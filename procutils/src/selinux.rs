@@ -0,0 +1,82 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::pid_t;
+use crate::audit::{self, Effect};
+use crate::file_utils::read_node;
+use std::process::{self, Command};
+
+/// Declare an string
+#[macro_export]
+macro_rules! TASK_ATTR_CURRENT_TEMPLATE { () => { "/proc/{}/attr/current" }; }
+
+const SELF_ATTR_CURRENT: &str = "/proc/self/attr/current";
+
+// Commands whose output may hold avc denials, tried in order
+const DENIAL_SOURCES: &[&[&str]] = &[
+    &["dmesg"],
+    &["logcat", "-d", "-b", "all"],
+];
+
+// avc denials attached to one diagnostic
+const MAX_DENIALS: usize = 3;
+
+fn read_context(path: &str) -> Option<String> {
+    let context = read_node(path).ok()?;
+    let context = context.trim_end_matches(['\0', '\n']).to_string();
+    if context.is_empty() { None } else { Some(context) }
+}
+
+/// SELinux context of a process, None when SELinux is off or the node is hidden
+pub fn process_context(pid: pid_t) -> Option<String> {
+    read_context(&format!(TASK_ATTR_CURRENT_TEMPLATE!(), pid))
+}
+
+/// SELinux context of the tracer
+pub fn tracer_context() -> Option<String> {
+    read_context(SELF_ATTR_CURRENT)
+}
+
+/// Recent avc denials of the tracer, newest last
+///
+/// Lines naming the tracer pid are preferred, then lines with the tracer
+/// context as source. Empty when neither dmesg nor logcat can be read.
+pub fn recent_denials(max: usize) -> Vec<String> {
+    let pid_key = format!("pid={} ", process::id());
+    let scontext_key = tracer_context().map(|context| format!("scontext={} ", context));
+    for source in DENIAL_SOURCES {
+        let output = match audit::run(Command::new(source[0]).args(&source[1..]), Effect::ReadOnly) {
+            Ok(output) if output.status.success() => output,
+            _ => continue,
+        };
+        let text = String::from_utf8_lossy(&output.stdout);
+        let denials: Vec<&str> = text.lines()
+                .filter(|line| line.contains("avc:") && line.contains("denied"))
+                .collect();
+        let mut matched: Vec<&str> = denials.iter().copied().filter(|line| line.contains(&pid_key)).collect();
+        if matched.is_empty() {
+            if let Some(key) = &scontext_key {
+                matched = denials.iter().copied().filter(|line| line.contains(key.as_str())).collect();
+            }
+        }
+        if !matched.is_empty() {
+            let skip = matched.len().saturating_sub(max);
+            return matched[skip..].iter().map(|line| line.trim().to_string()).collect();
+        }
+    }
+    Vec::new()
+}
+
+/// Detail of an `access_denied` event for `path`, with the avc denials found
+pub fn denial_detail(path: &str) -> String {
+    let tracer = tracer_context().unwrap_or_else(|| "unknown".to_string());
+    let denials = recent_denials(MAX_DENIALS);
+    if denials.is_empty() {
+        format!("path={} tracer_selinux={} denial=not found in dmesg or logcat", path, tracer)
+    } else {
+        format!("path={} tracer_selinux={} denial={}", path, tracer, denials.join(" | "))
+    }
+}
@@ -0,0 +1,518 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{flock, LOCK_EX, LOCK_NB};
+use crate::file_utils::read_path;
+use crate::loki::new_run_id;
+#[cfg(feature = "json")]
+use crate::loki::LokiLabels;
+use crate::outfile::{open_output, OutputWriter};
+use crate::output::{CsvSink, OutputFormat, RecordSink, ThreadCsvSink, DEFAULT_OUTPUT_FORMATS};
+#[cfg(feature = "json")]
+use crate::output::{JsonLinesSink, PerfettoSink};
+use crate::power::PowerProfile;
+use crate::proc_analysis::{MetricGroups, RecordItem, RecordProcess, ThreadItem, TraceTarget};
+use crate::transform::{ColumnTransform, SmoothSpec};
+use crate::SCHEMA_VERSION;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Files inside of a session directory
+pub(crate) const SESSION_STATE_FILE: &str = "session.state";
+const SESSION_STATE_TMP_FILE: &str = "session.state.tmp";
+const SESSION_EVENTS_FILE: &str = "events.log";
+pub(crate) const SESSION_LOCK_FILE: &str = "session.lock";
+// Bumped when the format of session.state changes, the output schema of the
+// rows is recorded apart so a resume never appends rows of another schema
+const SESSION_STATE_VERSION: u32 = 2;
+
+macro_rules! SESSION_DIR_TEMPLATE { () => { "trace_session_{}" }; }
+
+// session.state keys
+const KEY_VERSION: &str = "version";
+const KEY_SCHEMA: &str = "schema";
+const KEY_SESSION_ID: &str = "session_id";
+const KEY_START_EPOCH: &str = "start_epoch";
+const KEY_MONITOR_TIME: &str = "monitor_time";
+const KEY_MONITOR_INTERVAL: &str = "monitor_interval";
+const KEY_FORMATS: &str = "formats";
+const KEY_PER_THREAD: &str = "per_thread";
+const KEY_FOLLOW: &str = "follow";
+const KEY_STRICT: &str = "strict";
+const KEY_COMPACT: &str = "compact";
+const KEY_METRICS: &str = "metrics";
+const KEY_TAG: &str = "tag";
+const KEY_PROFILE: &str = "profile";
+const KEY_TRANSFORM: &str = "transform";
+const KEY_SMOOTH: &str = "smooth";
+const KEY_TARGET: &str = "target";
+const KEY_ELAPSED: &str = "elapsed";
+const KEY_SEQ: &str = "seq";
+const KEY_LAST_SAMPLE: &str = "last_sample";
+const KEY_OFFSET: &str = "offset";
+
+/// Advisory lock of a session directory, released when dropped
+///
+/// The lock file holds the pid of the tracer. The kernel releases the lock
+/// when the tracer dies, so a killed trace never blocks its resume.
+#[derive(Debug)]
+pub struct SessionLock {
+    _file: File,
+}
+
+/// Progress of one traced target
+#[derive(Default, Clone)]
+pub struct TargetState {
+    /// target spec, see `TraceTarget::parse`
+    pub name: String,
+    /// traced seconds, samples up to this time are on disk
+    pub elapsed: i64,
+    /// number of the next sample, see `RecordItem::seq`
+    pub seq: u64,
+    /// time of the last sample on disk, None before the first one
+    pub last_sample: Option<i64>,
+    /// committed length of every output file, keyed by file name
+    pub offsets: Vec<(String, u64)>,
+}
+
+/// Minimal state of a trace session, persisted to `session.state`
+#[derive(Clone)]
+pub struct SessionState {
+    /// session directory
+    pub dir: String,
+    /// session id, used as run id of the outputs
+    pub session_id: String,
+    /// wall clock seconds of time 0
+    pub start_epoch: u64,
+    /// planned trace duration in seconds, <= 0 until the targets exit
+    pub monitor_time: i64,
+    /// sampling interval in seconds
+    pub monitor_interval: i64,
+    /// output formats of every target
+    pub formats: Vec<OutputFormat>,
+    /// also record a sample of every thread
+    pub per_thread: bool,
+    /// follow the targets across restarts, see `MonitorOptions::follow`
+    pub follow: bool,
+    /// strict read-only mode, see `audit::set_strict`
+    pub strict: bool,
+    /// run-length encode rarely changing columns in the JSON outputs, see `OutputPlan::compact`
+    pub compact: bool,
+    /// optional metric groups to collect
+    pub metrics: MetricGroups,
+    /// `key=value` tags written to every output, see `output::parse_tag`
+    pub tags: Vec<(String, String)>,
+    /// sampling settings per power state, see `PowerProfile`
+    pub profiles: Vec<PowerProfile>,
+    /// transforms of the process samples before they are written, see `ColumnTransform`
+    pub transforms: Vec<ColumnTransform>,
+    /// smoothed companion columns of the process samples, see `SmoothSpec`
+    pub smooths: Vec<SmoothSpec>,
+    /// traced targets
+    pub targets: Vec<TargetState>,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        SessionState {
+            dir: String::new(),
+            session_id: String::new(),
+            start_epoch: 0,
+            monitor_time: 0,
+            monitor_interval: 0,
+            // Sessions written before formats were configurable have these
+            formats: DEFAULT_OUTPUT_FORMATS.to_vec(),
+            per_thread: false,
+            follow: false,
+            strict: false,
+            compact: false,
+            metrics: MetricGroups::default(),
+            tags: Vec::new(),
+            profiles: Vec::new(),
+            transforms: Vec::new(),
+            smooths: Vec::new(),
+            targets: Vec::new(),
+        }
+    }
+}
+
+/// Get wall clock seconds
+pub fn now_epoch() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl SessionState {
+    /// Create a new session directory under `output_dir` and persist the initial state
+    pub fn create(output_dir: &str, monitor_time: i64, monitor_iterval: i64,
+            lists: &[&str], formats: &[OutputFormat]) -> io::Result<SessionState> {
+        let session_id = new_run_id();
+        let dir = format!(concat!("{}/", SESSION_DIR_TEMPLATE!()), output_dir.trim_end_matches('/'), session_id);
+        SessionState::create_named(dir, session_id, monitor_time, monitor_iterval, lists, formats)
+    }
+
+    /// Create a new session in `dir`, e.g. one named by an output template.
+    /// An existing `dir` is an error, sessions never share a directory
+    pub fn create_named(dir: String, session_id: String, monitor_time: i64, monitor_iterval: i64,
+            lists: &[&str], formats: &[OutputFormat]) -> io::Result<SessionState> {
+        if Path::new(&dir).exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", dir)));
+        }
+        let state = SessionState {
+            dir,
+            session_id,
+            start_epoch: now_epoch(),
+            monitor_time,
+            monitor_interval: monitor_iterval,
+            formats: formats.to_vec(),
+            per_thread: false,
+            follow: false,
+            strict: false,
+            compact: false,
+            metrics: MetricGroups::default(),
+            tags: Vec::new(),
+            profiles: Vec::new(),
+            transforms: Vec::new(),
+            smooths: Vec::new(),
+            targets: lists.iter().map(|&name| TargetState {
+                name: name.to_string(),
+                ..Default::default()
+            }).collect(),
+        };
+        fs::create_dir_all(&state.dir)?;
+        state.save()?;
+        Ok(state)
+    }
+
+    /// Load a session, `session` is the session directory or the session id
+    pub fn load(session: &str) -> io::Result<SessionState> {
+        let dir = if Path::new(session).is_dir() {
+            session.to_string()
+        } else {
+            format!(SESSION_DIR_TEMPLATE!(), session)
+        };
+        let content = read_path(&format!("{}/{}", dir, SESSION_STATE_FILE))?;
+        let mut state = SessionState { dir, ..Default::default() };
+        let mut schema = None;
+        for line in content.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let parse_err = |_| invalid_data(format!("bad value of {}: {}", key, value));
+            let mut keys = key.splitn(3, '.');
+            match (keys.next(), keys.next(), keys.next()) {
+                (Some(KEY_VERSION), None, None) => {
+                    let version = value.parse::<u32>().map_err(parse_err)?;
+                    if version != SESSION_STATE_VERSION {
+                        return Err(invalid_data(format!("unsupported session version {}", version)));
+                    }
+                },
+                (Some(KEY_SCHEMA), None, None) => schema = Some(value.parse::<u32>().map_err(parse_err)?),
+                (Some(KEY_SESSION_ID), None, None) => state.session_id = value.to_string(),
+                (Some(KEY_START_EPOCH), None, None) => state.start_epoch = value.parse().map_err(parse_err)?,
+                (Some(KEY_MONITOR_TIME), None, None) => state.monitor_time = value.parse().map_err(parse_err)?,
+                (Some(KEY_MONITOR_INTERVAL), None, None) => state.monitor_interval = value.parse().map_err(parse_err)?,
+                (Some(KEY_FORMATS), None, None) => {
+                    state.formats = value.split(',')
+                            .map(|name| OutputFormat::parse(name)
+                                    .ok_or_else(|| invalid_data(format!("unknown format {}", name))))
+                            .collect::<io::Result<Vec<OutputFormat>>>()?;
+                },
+                (Some(KEY_PER_THREAD), None, None) => state.per_thread = value == "1",
+                (Some(KEY_FOLLOW), None, None) => state.follow = value == "1",
+                (Some(KEY_STRICT), None, None) => state.strict = value == "1",
+                (Some(KEY_COMPACT), None, None) => state.compact = value == "1",
+                (Some(KEY_METRICS), None, None) => {
+                    state.metrics = MetricGroups::parse(value)
+                            .ok_or_else(|| invalid_data(format!("unknown metric groups {}", value)))?;
+                },
+                (Some(KEY_TAG), Some(name), None) => state.tags.push((name.to_string(), value.to_string())),
+                (Some(KEY_PROFILE), Some(_), None) => {
+                    state.profiles.push(PowerProfile::parse(value)
+                            .ok_or_else(|| invalid_data(format!("bad power profile {}", value)))?);
+                },
+                (Some(KEY_TRANSFORM), Some(_), None) => {
+                    state.transforms.push(ColumnTransform::parse(value)
+                            .ok_or_else(|| invalid_data(format!("bad transform {}", value)))?);
+                },
+                (Some(KEY_SMOOTH), Some(_), None) => {
+                    state.smooths.push(SmoothSpec::parse(value)
+                            .ok_or_else(|| invalid_data(format!("bad smoothing {}", value)))?);
+                },
+                (Some(KEY_TARGET), Some(index), field) => {
+                    let index = index.parse::<usize>().map_err(parse_err)?;
+                    if state.targets.len() <= index {
+                        state.targets.resize(index + 1, TargetState::default());
+                    }
+                    let target = &mut state.targets[index];
+                    match field {
+                        None => target.name = value.to_string(),
+                        Some(KEY_ELAPSED) => target.elapsed = value.parse().map_err(parse_err)?,
+                        Some(KEY_SEQ) => target.seq = value.parse().map_err(parse_err)?,
+                        Some(KEY_LAST_SAMPLE) => target.last_sample = Some(value.parse().map_err(parse_err)?),
+                        Some(f) if f.starts_with(KEY_OFFSET) => {
+                            let file = f.trim_start_matches(KEY_OFFSET).trim_start_matches(':');
+                            target.offsets.push((file.to_string(), value.parse().map_err(parse_err)?));
+                        },
+                        Some(_) => {},
+                    }
+                },
+                _ => {},
+            }
+        }
+        if state.targets.is_empty() || state.monitor_interval <= 0 || state.formats.is_empty() {
+            return Err(invalid_data(format!("incomplete session state in {}", state.dir)));
+        }
+        // Resumed rows go under the header already written
+        match schema {
+            Some(SCHEMA_VERSION) => {},
+            Some(schema) => return Err(invalid_data(format!(
+                    "session {} has output schema {}, this tracer writes schema {}, start a new session",
+                    state.dir, schema, SCHEMA_VERSION))),
+            None => return Err(invalid_data(format!(
+                    "session {} has no output schema, it was written by an older tracer, start a new session",
+                    state.dir))),
+        }
+        Ok(state)
+    }
+
+    /// Persist the state, the file is replaced atomically
+    pub fn save(&self) -> io::Result<()> {
+        let formats: Vec<&str> = self.formats.iter().map(|format| format.name()).collect();
+        let mut content = format!("{}={}\n{}={}\n{}={}\n{}={}\n{}={}\n{}={}\n{}={}\n{}={}\n{}={}\n{}={}\n{}={}\n",
+                KEY_VERSION, SESSION_STATE_VERSION, KEY_SCHEMA, SCHEMA_VERSION, KEY_SESSION_ID, self.session_id,
+                KEY_START_EPOCH, self.start_epoch, KEY_MONITOR_TIME, self.monitor_time,
+                KEY_MONITOR_INTERVAL, self.monitor_interval, KEY_FORMATS, formats.join(","),
+                KEY_PER_THREAD, self.per_thread as u8, KEY_FOLLOW, self.follow as u8,
+                KEY_STRICT, self.strict as u8, KEY_METRICS, self.metrics.names().join(","));
+        if self.compact {
+            content.push_str(&format!("{}=1\n", KEY_COMPACT));
+        }
+        for (name, value) in &self.tags {
+            content.push_str(&format!("{}.{}={}\n", KEY_TAG, name, value));
+        }
+        for (i, profile) in self.profiles.iter().enumerate() {
+            content.push_str(&format!("{}.{}={}\n", KEY_PROFILE, i, profile.spec()));
+        }
+        for (i, transform) in self.transforms.iter().enumerate() {
+            content.push_str(&format!("{}.{}={}\n", KEY_TRANSFORM, i, transform.spec()));
+        }
+        for (i, smooth) in self.smooths.iter().enumerate() {
+            content.push_str(&format!("{}.{}={}\n", KEY_SMOOTH, i, smooth.spec()));
+        }
+        for (i, target) in self.targets.iter().enumerate() {
+            content.push_str(&format!("{}.{}={}\n", KEY_TARGET, i, target.name));
+            content.push_str(&format!("{}.{}.{}={}\n", KEY_TARGET, i, KEY_ELAPSED, target.elapsed));
+            content.push_str(&format!("{}.{}.{}={}\n", KEY_TARGET, i, KEY_SEQ, target.seq));
+            if let Some(last_sample) = target.last_sample {
+                content.push_str(&format!("{}.{}.{}={}\n", KEY_TARGET, i, KEY_LAST_SAMPLE, last_sample));
+            }
+            for (file, offset) in &target.offsets {
+                content.push_str(&format!("{}.{}.{}:{}={}\n", KEY_TARGET, i, KEY_OFFSET, file, offset));
+            }
+        }
+        let tmp_path = format!("{}/{}", self.dir, SESSION_STATE_TMP_FILE);
+        let mut out = File::create(&tmp_path)?;
+        out.write_all(content.as_bytes())?;
+        out.sync_all()?;
+        fs::rename(&tmp_path, format!("{}/{}", self.dir, SESSION_STATE_FILE))
+    }
+
+    /// Lock the session directory against other tracers, fails while
+    /// another process holds it
+    pub fn lock(&self) -> io::Result<SessionLock> {
+        let path = format!("{}/{}", self.dir, SESSION_LOCK_FILE);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        // SAFETY:
+        // Safe because the fd is owned by `file` and stays open during the call
+        if unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(e);
+            }
+            let holder = read_path(&path).unwrap_or_default();
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, format!(
+                    "session {} is traced by process {}", self.dir, holder.trim())));
+        }
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        Ok(SessionLock { _file: file })
+    }
+
+    /// Get the state of a target
+    pub fn target(&self, name: &str) -> Option<&TargetState> {
+        self.targets.iter().find(|t| t.name == name)
+    }
+
+    /// Record the progress of a target after its outputs were flushed
+    pub fn commit(&mut self, name: &str, elapsed: i64, seq: u64, last_sample: Option<i64>,
+            offsets: Vec<(String, u64)>) {
+        if let Some(target) = self.targets.iter_mut().find(|t| t.name == name) {
+            target.elapsed = elapsed;
+            target.seq = seq;
+            target.last_sample = last_sample;
+            target.offsets = offsets;
+        }
+    }
+
+    /// Append an event line to `events.log`
+    pub fn append_event(&self, name: &str, event: &str, detail: &str) -> io::Result<()> {
+        let mut out = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(format!("{}/{}", self.dir, SESSION_EVENTS_FILE))?;
+        writeln!(out, "{},{},{},{}", now_epoch(), name, event, detail)
+    }
+}
+
+// Output file opened for appending at its committed offset
+struct AppendFile {
+    name: String,
+    file: OutputWriter,
+    offset: u64,
+}
+
+impl AppendFile {
+    fn open(state: &SessionState, target: &TargetState, name: String) -> io::Result<AppendFile> {
+        let offset = target.offsets.iter()
+                .find(|(file, _)| *file == name)
+                .map(|(_, offset)| *offset)
+                .unwrap_or(0);
+        let mut file = open_output(OpenOptions::new().create(true).write(true).truncate(false),
+                &format!("{}/{}", state.dir, name))?;
+        // Drop anything written after the last commit, it may be a partial line
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(AppendFile { name, file: OutputWriter::new(file, offset), offset })
+    }
+}
+
+impl Write for AppendFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.offset += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// One output file of a target and the sink writing it
+enum SessionSink {
+    Csv(CsvSink<AppendFile>),
+    #[cfg(feature = "json")]
+    Jsonl(JsonLinesSink<AppendFile>),
+    #[cfg(feature = "json")]
+    Perfetto(PerfettoSink<AppendFile>),
+    Threads(ThreadCsvSink<AppendFile>),
+}
+
+impl SessionSink {
+    fn sink(&mut self) -> &mut dyn RecordSink {
+        match self {
+            SessionSink::Csv(sink) => sink,
+            #[cfg(feature = "json")]
+            SessionSink::Jsonl(sink) => sink,
+            #[cfg(feature = "json")]
+            SessionSink::Perfetto(sink) => sink,
+            SessionSink::Threads(sink) => sink,
+        }
+    }
+
+    fn file(&mut self) -> &mut AppendFile {
+        match self {
+            SessionSink::Csv(sink) => sink.get_mut(),
+            #[cfg(feature = "json")]
+            SessionSink::Jsonl(sink) => sink.get_mut(),
+            #[cfg(feature = "json")]
+            SessionSink::Perfetto(sink) => sink.get_mut(),
+            SessionSink::Threads(sink) => sink.get_mut(),
+        }
+    }
+}
+
+/// Outputs of one target inside of a session directory
+pub struct SessionOutput {
+    sinks: Vec<SessionSink>,
+}
+
+impl SessionOutput {
+    /// Open the outputs of a target, continuing where the last commit stopped
+    pub fn open(state: &SessionState, spec: &str) -> io::Result<SessionOutput> {
+        let target = state.target(spec)
+                .ok_or_else(|| invalid_data(format!("{} is not a target of the session", spec)))?;
+        let name = &TraceTarget::parse(spec).label();
+        let mut sinks = Vec::new();
+        for format in &state.formats {
+            let file = AppendFile::open(state, target, format.file_name(name))?;
+            sinks.push(match format {
+                OutputFormat::Csv => SessionSink::Csv(CsvSink::new(file)),
+                #[cfg(feature = "json")]
+                OutputFormat::Jsonl => SessionSink::Jsonl(JsonLinesSink::new(file,
+                        LokiLabels::with_run_id(name, &state.session_id)).compact(state.compact)),
+                #[cfg(feature = "json")]
+                OutputFormat::Perfetto => SessionSink::Perfetto(PerfettoSink::new(file).compact(state.compact)),
+                OutputFormat::Threads => SessionSink::Threads(ThreadCsvSink::new(file)),
+            });
+        }
+        Ok(SessionOutput { sinks })
+    }
+
+    /// Begin the outputs which are new, e.g. the CSV header and the `trace_start` event
+    pub fn start(&mut self, record: &RecordProcess) -> io::Result<()> {
+        for sink in self.sinks.iter_mut() {
+            if sink.file().offset == 0 {
+                sink.sink().begin(record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append one sample to all outputs
+    pub fn append(&mut self, record: &RecordProcess, item: &RecordItem) -> io::Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.sink().sample(record, item)?;
+        }
+        Ok(())
+    }
+
+    /// Append one thread sample to all outputs
+    pub fn append_thread(&mut self, record: &RecordProcess, thread: &ThreadItem) -> io::Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.sink().thread_sample(record, thread)?;
+        }
+        Ok(())
+    }
+
+    /// Append one event, `time` is seconds since time 0
+    pub fn event(&mut self, record: &RecordProcess, time: i64, event: &str, detail: &str) -> io::Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.sink().event(record, time, event, detail)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the outputs and get the offsets to commit
+    pub fn flush(&mut self) -> io::Result<Vec<(String, u64)>> {
+        let mut offsets = Vec::new();
+        for sink in self.sinks.iter_mut() {
+            sink.sink().flush()?;
+            let file = sink.file();
+            file.file.get_ref().sync_data()?;
+            offsets.push((file.name.clone(), file.offset));
+        }
+        Ok(offsets)
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{c_int, sighandler_t, signal, _exit, SIGINT, SIGTERM};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// Set by the first SIGINT or SIGTERM, the traces stop at their next check
+static STOP: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+// Longest sleep between two checks of the stop request
+const STOP_CHECK_STEP: Duration = Duration::from_millis(200);
+
+extern "C" fn on_signal(signum: c_int) {
+    // A second signal does not wait for the flush
+    if STOP.swap(true, Ordering::SeqCst) {
+        // SAFETY:
+        // Safe because _exit is async signal safe
+        unsafe { _exit(128 + signum) };
+    }
+}
+
+/// Stop the traces on the first SIGINT or SIGTERM instead of dying, so every
+/// target flushes its outputs before the summaries are written. The second
+/// signal exits right away
+pub fn install_stop_handler() {
+    INSTALL.call_once(|| {
+        for signum in [SIGINT, SIGTERM] {
+            // SAFETY:
+            // Safe because the handler only touches an atomic and calls _exit
+            unsafe { signal(signum, on_signal as extern "C" fn(c_int) as sighandler_t) };
+        }
+    });
+}
+
+/// Ask the running traces to stop, as the first signal does
+pub fn request_stop() {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Whether the traces were asked to stop
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+/// Sleep until `until` or until a stop is requested, false when stopped
+pub fn sleep_until(until: Instant) -> bool {
+    loop {
+        if stop_requested() {
+            return false;
+        }
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        sleep(left.min(STOP_CHECK_STEP));
+    }
+}
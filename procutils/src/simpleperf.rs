@@ -0,0 +1,95 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use crate::alert::Alert;
+use crate::audit::{self, Effect};
+use crate::bus::post_event;
+use libc::pid_t;
+use std::collections::HashSet;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Profiler started for a capture, looked up in PATH
+pub const SIMPLEPERF_BINARY: &str = "simpleperf";
+
+/// Event posted when a capture starts, its detail has the perf.data path
+pub const SIMPLEPERF_EVENT: &str = "simpleperf";
+
+/// Event posted when a capture failed
+pub const SIMPLEPERF_FAILED_EVENT: &str = "simpleperf_failed";
+
+/// Alert columns which start a capture
+pub const CPU_ALERT_COLUMNS: [&str; 4] = ["cpuOccupancyRate", "utime", "stime", "totalcputime"];
+
+/// Captures started per trace unless set with `max_captures`
+pub const DEFAULT_MAX_CAPTURES: usize = 3;
+
+/// Starts a bounded `simpleperf record` of a process when a CPU alert fires,
+/// instead of profiling all the time
+///
+/// A process is captured once at a time and every capture is posted as an
+/// event of its target, so the spike and its perf.data sit side by side.
+pub struct HotspotCapture {
+    output_dir: String,
+    duration: u64,
+    max_captures: usize,
+    captures: usize,
+    // pids with a running capture
+    running: Arc<Mutex<HashSet<pid_t>>>,
+}
+
+impl HotspotCapture {
+    /// Record `duration` seconds into perf.data files under `output_dir`
+    pub fn new(output_dir: &str, duration: u64) -> HotspotCapture {
+        HotspotCapture {
+            output_dir: output_dir.trim_end_matches('/').to_string(),
+            duration,
+            max_captures: DEFAULT_MAX_CAPTURES,
+            captures: 0,
+            running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Stop starting captures after `max` of them
+    pub fn max_captures(mut self, max: usize) -> HotspotCapture {
+        self.max_captures = max;
+        self
+    }
+
+    /// Start a capture for a firing CPU alert, returns the perf.data path of
+    /// a started capture
+    pub fn on_alert(&mut self, alert: &Alert) -> Option<String> {
+        if !alert.firing || alert.pid <= 0 || !CPU_ALERT_COLUMNS.contains(&alert.rule.column.as_str())
+                || self.captures >= self.max_captures || !self.running.lock().unwrap().insert(alert.pid) {
+            return None;
+        }
+        self.captures += 1;
+        let path = format!("{}/perf_{}_{}_{}.data", self.output_dir, alert.target, alert.pid, alert.time);
+        post_event(&alert.target, SIMPLEPERF_EVENT, &format!("path={} pid={} duration={}s rule={}",
+                path, alert.pid, self.duration, alert.rule.spec()));
+        let (target, pid, duration, running) = (alert.target.clone(), alert.pid, self.duration, self.running.clone());
+        let output = path.clone();
+        thread::spawn(move || {
+            // Writes perf.data and perturbs the target, strict mode refuses it
+            let result = audit::run(Command::new(SIMPLEPERF_BINARY)
+                    .args(["record", "-p", &pid.to_string(), "--duration", &duration.to_string(), "-o", &output]),
+                    Effect::Writes);
+            let failure = match result {
+                Ok(result) if result.status.success() => None,
+                Ok(result) => Some(String::from_utf8_lossy(&result.stderr).trim().replace('\n', " ")),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(failure) = failure {
+                println!("simpleperf of {} failed: {}", pid, failure);
+                post_event(&target, SIMPLEPERF_FAILED_EVENT, &format!("path={} pid={} {}", output, pid, failure));
+            }
+            running.lock().unwrap().remove(&pid);
+        });
+        Some(path)
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::pid_t;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Field of a mapping summed up
+const PSS_KEY: &str = "Pss:";
+
+/// Whether the kernel sums smaps up itself, Linux 4.14 and later. The
+/// incremental parse only helps where it does not
+pub fn smaps_rollup_available() -> bool {
+    static SMAPS_ROLLUP: OnceLock<bool> = OnceLock::new();
+    *SMAPS_ROLLUP.get_or_init(|| Path::new("/proc/self/smaps_rollup").exists())
+}
+
+// Start address of a mapping header, e.g. `7f3a2c000000-7f3a2c021000 rw-p ...`.
+// Field lines start with their key in upper case, addresses are lower case hex
+fn header_start(line: &str) -> Option<u64> {
+    let first = *line.as_bytes().first()?;
+    if !(first.is_ascii_digit() || (b'a'..=b'f').contains(&first)) {
+        return None;
+    }
+    u64::from_str_radix(line.split_once('-')?.0, 16).ok()
+}
+
+// One mapping of the last pass
+#[derive(Clone, Debug, Default)]
+struct Region {
+    start: u64,
+    header: String,
+    pss: isize,
+}
+
+/// Pss of the mappings of one process from the last pass over its smaps.
+///
+/// A mapping whose header line, its address range, offset, inode and path,
+/// did not change keeps its Pss and its field lines are skipped. Pages
+/// faulted into a mapping do not change its header, so every `full_every`
+/// passes all mappings are parsed again and the sum is exact
+#[derive(Clone, Debug, Default)]
+pub struct SmapsCache {
+    // process the regions belong to
+    pid: pid_t,
+    // mappings of the last pass in address order, like smaps lists them
+    regions: Vec<Region>,
+    // passes since the last full one
+    passes: u32,
+    /// parse every mapping every this many passes, at least 1
+    pub full_every: u32,
+    /// mappings whose Pss was reused
+    pub reused: u64,
+    /// mappings whose fields were parsed
+    pub parsed: u64,
+}
+
+impl SmapsCache {
+    /// Cache with a full pass every `full_every` passes
+    pub fn new(full_every: u32) -> SmapsCache {
+        SmapsCache { full_every: full_every.max(1), ..Default::default() }
+    }
+
+    /// Sum the Pss of the smaps `content` of `pid` up in kB
+    pub fn sum(&mut self, pid: pid_t, content: &str) -> isize {
+        let full = pid != self.pid || self.passes >= self.full_every;
+        self.pid = pid;
+        self.passes = if full { 1 } else { self.passes + 1 };
+        let mut last = std::mem::take(&mut self.regions).into_iter().peekable();
+        let mut regions = Vec::with_capacity(last.len());
+        let mut total = 0;
+        // Whether the fields of the current mapping are parsed
+        let mut parsing = false;
+        for line in content.lines() {
+            if let Some(start) = header_start(line) {
+                // Both passes are in address order, mappings gone since are dropped
+                while last.next_if(|region| region.start < start).is_some() {}
+                match last.next_if(|region| !full && region.start == start && region.header == line) {
+                    Some(region) => {
+                        total += region.pss;
+                        self.reused += 1;
+                        regions.push(region);
+                        parsing = false;
+                    },
+                    None => {
+                        self.parsed += 1;
+                        regions.push(Region { start, header: line.to_string(), pss: 0 });
+                        parsing = true;
+                    },
+                }
+                continue;
+            }
+            if !parsing {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix(PSS_KEY) {
+                if let (Ok(value), Some(region)) = (value.trim().trim_end_matches("kB").trim_end().parse::<isize>(),
+                        regions.last_mut()) {
+                    region.pss += value;
+                    total += value;
+                }
+            }
+        }
+        self.regions = regions;
+        total
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::analysis::TraceData;
+use crate::error::TraceError;
+use crate::output::OutputFormat;
+use crate::proc_analysis::{trace_session_with_options, MetricGroups, MonitorOptions, TraceTarget};
+use crate::score::HealthScore;
+use crate::session::{now_epoch, SessionState, SESSION_STATE_FILE};
+use crate::shutdown::stop_requested;
+use crate::template::format_utc;
+use crate::trend::append_run;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+/// Trend store in the output directory the day summaries are appended to,
+/// keyed by the start of the day
+pub const DAILY_ROLLUP_FILE: &str = "daily_rollup.csv";
+
+/// Prefix of the session directory of every day, followed by its UTC start
+pub const DAY_SESSION_PREFIX: &str = "day_";
+
+/// One traced day of a soak
+#[derive(Clone, Debug)]
+pub struct SoakDay {
+    /// 1 for the first day
+    pub index: u64,
+    /// session directory of the day
+    pub dir: String,
+    /// key of the day in the rollup store, its UTC start `YYYYmmdd-HHMMSS`
+    pub key: String,
+    /// targets whose summary was appended to the rollup store
+    pub rolled_up: usize,
+    /// day sessions removed as older than the retention
+    pub pruned: Vec<String>,
+    /// error of the trace or the rollup
+    pub error: Option<String>,
+}
+
+/// Configuration of a soak, which records the targets at a low rate for
+/// weeks in one session a day, rolls every day up into a trend store and
+/// prunes old days, built with chained setters
+///
+/// ```ignore
+/// SoakConfig::new(vec!["system_server".to_string()])
+///         .output_dir("/data/local/tmp/soak")
+///         .keep_days(7)
+///         .build()?
+///         .run(&mut |day: &SoakDay| {
+///             println!("day {} rolled up to {}", day.index, day.key);
+///             true
+///         })?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct SoakConfig {
+    targets: Vec<String>,
+    output_dir: String,
+    interval: i64,
+    day: i64,
+    days: u64,
+    keep_days: usize,
+    formats: Vec<OutputFormat>,
+    metrics: MetricGroups,
+    tags: Vec<(String, String)>,
+    score: HealthScore,
+    options: MonitorOptions,
+}
+
+impl SoakConfig {
+    /// Soak `targets`, specs as `TraceTarget::parse` takes them, with a sample
+    /// every 5 minutes, days of 24 hours and 14 days of raw samples kept
+    pub fn new(targets: Vec<String>) -> SoakConfig {
+        SoakConfig {
+            targets,
+            output_dir: ".".to_string(),
+            interval: 300,
+            day: 86400,
+            days: 0,
+            keep_days: 14,
+            formats: vec![OutputFormat::Csv],
+            metrics: MetricGroups::default(),
+            tags: Vec::new(),
+            score: HealthScore::default(),
+            options: MonitorOptions::default(),
+        }
+    }
+
+    /// Where the day sessions and the rollup store are created
+    pub fn output_dir(mut self, dir: &str) -> SoakConfig {
+        self.output_dir = dir.to_string();
+        self
+    }
+
+    /// Sampling interval in seconds
+    pub fn interval(mut self, seconds: i64) -> SoakConfig {
+        self.interval = seconds;
+        self
+    }
+
+    /// Seconds one day session and its rollup cover
+    pub fn day(mut self, seconds: i64) -> SoakConfig {
+        self.day = seconds;
+        self
+    }
+
+    /// Stop after this many days, 0 runs until interrupted
+    pub fn days(mut self, days: u64) -> SoakConfig {
+        self.days = days;
+        self
+    }
+
+    /// Day sessions kept, older ones are removed once a day is rolled up,
+    /// 0 keeps all. The rollups are never pruned
+    pub fn keep_days(mut self, days: usize) -> SoakConfig {
+        self.keep_days = days;
+        self
+    }
+
+    /// Output formats of every day, csv is needed for the rollups
+    pub fn formats(mut self, formats: &[OutputFormat]) -> SoakConfig {
+        self.formats = formats.to_vec();
+        self
+    }
+
+    /// Optional metric groups of every day
+    pub fn metrics(mut self, metrics: MetricGroups) -> SoakConfig {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Tags of every day session
+    pub fn tags(mut self, tags: Vec<(String, String)>) -> SoakConfig {
+        self.tags = tags;
+        self
+    }
+
+    /// Health score added to the rollups
+    pub fn score(mut self, score: HealthScore) -> SoakConfig {
+        self.score = score;
+        self
+    }
+
+    /// Monitor options of every day, targets are always followed
+    pub fn options(mut self, options: MonitorOptions) -> SoakConfig {
+        self.options = options;
+        self
+    }
+
+    /// Check the configuration and get a soak to run
+    pub fn build(mut self) -> Result<SoakTrace, TraceError> {
+        if self.targets.is_empty() {
+            return Err(TraceError::InvalidConfig("no targets".to_string()));
+        }
+        if self.interval <= 0 || self.day < self.interval {
+            return Err(TraceError::InvalidConfig(format!("day of {}s is shorter than the interval of {}s",
+                    self.day, self.interval)));
+        }
+        if !self.formats.contains(&OutputFormat::Csv) {
+            return Err(TraceError::InvalidConfig("the daily rollups need the csv format".to_string()));
+        }
+        self.options.follow = true;
+        Ok(SoakTrace { config: self })
+    }
+}
+
+/// A checked soak
+#[derive(Clone, Debug)]
+pub struct SoakTrace {
+    config: SoakConfig,
+}
+
+impl SoakTrace {
+    /// Trace day after day until the days are done, a stop is requested or the
+    /// observer returns false, returns the days traced. A day whose trace fails
+    /// before its first interval ends the soak, it would fail again right away
+    pub fn run(&self, observer: &mut impl FnMut(&SoakDay) -> bool) -> Result<u64, TraceError> {
+        let config = &self.config;
+        fs::create_dir_all(&config.output_dir)?;
+        let mut index = 0;
+        while (config.days == 0 || index < config.days) && !stop_requested() {
+            index += 1;
+            let start = Instant::now();
+            let mut day = self.trace_day(index)?;
+            let failed_early = day.error.is_some() && start.elapsed().as_secs() < config.interval as u64;
+            match self.roll_up(&day.dir, &day.key) {
+                Ok(rolled_up) => day.rolled_up = rolled_up,
+                Err(e) => {
+                    day.error.get_or_insert(format!("rollup failed: {}", e));
+                },
+            }
+            day.pruned = self.prune().unwrap_or_else(|e| {
+                println!("prune {} failed: {}", config.output_dir, e);
+                Vec::new()
+            });
+            if !observer(&day) || failed_early {
+                break;
+            }
+        }
+        Ok(index)
+    }
+
+    // Trace one day in a session of its own
+    fn trace_day(&self, index: u64) -> Result<SoakDay, TraceError> {
+        let config = &self.config;
+        let key = format_utc(now_epoch());
+        let dir = format!("{}/{}{}", config.output_dir.trim_end_matches('/'), DAY_SESSION_PREFIX, key);
+        let specs: Vec<&str> = config.targets.iter().map(|target| target.as_str()).collect();
+        let session_id = crate::loki::new_run_id();
+        let mut state = SessionState::create_named(dir.clone(), session_id, config.day, config.interval, &specs,
+                &config.formats)?;
+        state.metrics = config.metrics;
+        state.follow = true;
+        state.tags = config.tags.clone();
+        state.save()?;
+        println!("Soak day {} in {}", index, dir);
+        let error = trace_session_with_options(state, config.options).err().map(|e| e.to_string());
+        Ok(SoakDay { index, dir, key, rolled_up: 0, pruned: Vec::new(), error })
+    }
+
+    // Append the summary of every target of a day to the rollup store
+    fn roll_up(&self, dir: &str, key: &str) -> io::Result<usize> {
+        let config = &self.config;
+        let store = format!("{}/{}", config.output_dir.trim_end_matches('/'), DAILY_ROLLUP_FILE);
+        let mut rolled_up = 0;
+        for spec in &config.targets {
+            let label = TraceTarget::parse(spec).label();
+            let path = format!("{}/{}", dir, OutputFormat::Csv.file_name(&label));
+            // A target which never ran that day has no output
+            let mut data = match TraceData::load_csv(&path) {
+                Ok(data) if !data.rows.is_empty() => data,
+                _ => continue,
+            };
+            config.score.add_column(&mut data);
+            append_run(&store, key, &label, &data, now_epoch())?;
+            rolled_up += 1;
+        }
+        Ok(rolled_up)
+    }
+
+    // Remove the oldest day sessions beyond the retention, returns them. Only
+    // sessions a soak created count, the output directory may hold anything else
+    fn prune(&self) -> io::Result<Vec<String>> {
+        let config = &self.config;
+        if config.keep_days == 0 {
+            return Ok(Vec::new());
+        }
+        let mut days: Vec<String> = fs::read_dir(&config.output_dir)?
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| is_day_session(&config.output_dir, name))
+                .collect();
+        // UTC starts sort like the time
+        days.sort();
+        let old = days.len().saturating_sub(config.keep_days);
+        let mut pruned = Vec::new();
+        for name in days.into_iter().take(old) {
+            let dir = format!("{}/{}", config.output_dir.trim_end_matches('/'), name);
+            fs::remove_dir_all(&dir)?;
+            pruned.push(dir);
+        }
+        Ok(pruned)
+    }
+}
+
+// `day_YYYYmmdd-HHMMSS` holding a session state, the directory of a soak day
+fn is_day_session(output_dir: &str, name: &str) -> bool {
+    let Some(key) = name.strip_prefix(DAY_SESSION_PREFIX) else { return false };
+    let shape = key.len() == 15 && key.char_indices().all(|(i, c)| match i {
+        8 => c == '-',
+        _ => c.is_ascii_digit(),
+    });
+    shape && Path::new(&format!("{}/{}/{}", output_dir.trim_end_matches('/'), name, SESSION_STATE_FILE)).is_file()
+}
@@ -0,0 +1,281 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+/// Default significance level of the tests
+pub const DEFAULT_ALPHA: f64 = 0.05;
+
+/// Modified z-score above which a value is an outlier, after Iglewicz and Hoaglin
+pub const OUTLIER_Z: f64 = 3.5;
+
+// Groups up to this many observations in total get the exact Mann-Whitney
+// distribution, larger ones the normal approximation
+const EXACT_MANN_WHITNEY_MAX: usize = 30;
+
+// Iterations of the continued fraction of the incomplete beta function
+const BETA_CF_ITERATIONS: usize = 200;
+
+/// Mean of the values, NaN when there are none
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Sample variance of the values, NaN with less than two
+pub fn variance(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return f64::NAN;
+    }
+    let m = mean(values);
+    values.iter().map(|v| (v - m) * (v - m)).sum::<f64>() / (values.len() - 1) as f64
+}
+
+/// Median of the values, NaN when there are none
+pub fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    // The two middle values are the same one for odd lengths
+    (sorted[(sorted.len() - 1) / 2] + sorted[sorted.len() / 2]) / 2.0
+}
+
+/// Modified z-scores of the values, robust to the outliers they look for.
+///
+/// They scale the distance to the median by the median absolute deviation,
+/// or by the mean absolute deviation when more than half of the values are
+/// equal. Constant values all score 0.
+pub fn modified_z_scores(values: &[f64]) -> Vec<f64> {
+    let center = median(values);
+    let deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+    let mad = median(&deviations);
+    let scale = if mad > 0.0 { mad / 0.6745 } else { 1.253314 * mean(&deviations) };
+    values.iter()
+            .map(|v| if scale > 0.0 { (v - center) / scale } else { 0.0 })
+            .collect()
+}
+
+// Complementary error function, Chebyshev fit with an error below 1.2e-7
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418
+            + t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587
+            + t * (-0.82215223 + t * 0.17087277))))))))).exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+/// Cumulative distribution of the standard normal distribution
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+// Logarithm of the gamma function, Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [76.18009172947146, -86.50532032941677, 24.01409824083091,
+            -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000000000190015;
+    let mut y = x;
+    for c in COEFFICIENTS {
+        y += 1.0;
+        series += c / y;
+    }
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+// Continued fraction of the incomplete beta function, modified Lentz method
+fn beta_cf(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let (qab, qap, qam) = (a + b, a + 1.0, a - 1.0);
+    let mut c = 1.0;
+    let mut d = 1.0 - qab * x / qap;
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..=BETA_CF_ITERATIONS {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        for aa in [m * (b - m) * x / ((qam + m2) * (a + m2)),
+                -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2))] {
+            d = 1.0 + aa * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + aa / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+// Regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_cf(a, b, x) / a
+    } else {
+        1.0 - front * beta_cf(b, a, 1.0 - x) / b
+    }
+}
+
+/// Cumulative distribution of Student's t distribution with `df` degrees of freedom
+pub fn student_t_cdf(t: f64, df: f64) -> f64 {
+    let tail = 0.5 * incomplete_beta(df / 2.0, 0.5, df / (df + t * t));
+    if t > 0.0 { 1.0 - tail } else { tail }
+}
+
+/// Quantile of Student's t distribution, `p` is in (0, 1)
+pub fn student_t_quantile(p: f64, df: f64) -> f64 {
+    if p < 0.5 {
+        return -student_t_quantile(1.0 - p, df);
+    }
+    // The cdf is monotonic, bisect between 0 and a bound far in the tail
+    let (mut low, mut high) = (0.0, 1e6);
+    for _ in 0..100 {
+        let mid = (low + high) / 2.0;
+        if student_t_cdf(mid, df) < p {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Welch confidence interval of `mean(b) - mean(a)` at `confidence`, e.g. 0.95.
+/// NaN bounds when a group has less than two values
+pub fn welch_interval(a: &[f64], b: &[f64], confidence: f64) -> (f64, f64) {
+    if a.len() < 2 || b.len() < 2 {
+        return (f64::NAN, f64::NAN);
+    }
+    let delta = mean(b) - mean(a);
+    let (va, vb) = (variance(a) / a.len() as f64, variance(b) / b.len() as f64);
+    let se = (va + vb).sqrt();
+    if se == 0.0 {
+        return (delta, delta);
+    }
+    let df = (va + vb).powi(2) / (va * va / (a.len() - 1) as f64 + vb * vb / (b.len() - 1) as f64);
+    let q = student_t_quantile(1.0 - (1.0 - confidence) / 2.0, df);
+    (delta - q * se, delta + q * se)
+}
+
+// Ranks of the values, ties get their average rank
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        for k in i..=j {
+            ranks[order[k]] = (i + j) as f64 / 2.0 + 1.0;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+// Counts of every U of two groups without ties, index is U
+fn mann_whitney_counts(n1: usize, n2: usize) -> Vec<f64> {
+    // counts[j][u] of groups of i and j values, built up one value of the first group at a time
+    let mut counts: Vec<Vec<f64>> = (0..=n2).map(|_| vec![1.0]).collect();
+    for _ in 1..=n1 {
+        let mut next: Vec<Vec<f64>> = Vec::with_capacity(n2 + 1);
+        next.push(vec![1.0]);
+        for j in 1..=n2 {
+            // The largest value is either in the first group, above all j values
+            // of the second group, or in the second group
+            let (with_first, with_second) = (&counts[j], &next[j - 1]);
+            let mut row = vec![0.0; (with_first.len() + j).max(with_second.len())];
+            for (u, c) in with_first.iter().enumerate() {
+                row[u + j] += c;
+            }
+            for (u, c) in with_second.iter().enumerate() {
+                row[u] += c;
+            }
+            next.push(row);
+        }
+        counts = next;
+    }
+    counts.swap_remove(n2)
+}
+
+/// Two sided p value of the Mann-Whitney U test, non finite values are skipped
+/// and NaN is returned when a group is empty.
+///
+/// Small groups without ties use the exact distribution of U, others the
+/// normal approximation with tie and continuity correction.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> f64 {
+    // The values of a are first
+    let values: Vec<f64> = a.iter().chain(b).copied().filter(|v| v.is_finite()).collect();
+    let n1 = a.iter().filter(|v| v.is_finite()).count();
+    let n2 = values.len() - n1;
+    if n1 == 0 || n2 == 0 {
+        return f64::NAN;
+    }
+    let ranks = ranks(&values);
+    let u = ranks[..n1].iter().sum::<f64>() - (n1 * (n1 + 1)) as f64 / 2.0;
+    let mut sorted = values;
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    // t^3 - t of every run of t equal values
+    let mut tie_sum = 0.0;
+    let mut i = 0;
+    while i < sorted.len() {
+        let t = sorted[i..].iter().take_while(|v| **v == sorted[i]).count();
+        tie_sum += (t * t * t - t) as f64;
+        i += t;
+    }
+    if tie_sum == 0.0 && n1 + n2 <= EXACT_MANN_WHITNEY_MAX {
+        let counts = mann_whitney_counts(n1, n2);
+        let total: f64 = counts.iter().sum();
+        let u = u as usize;
+        let below: f64 = counts[..=u].iter().sum();
+        let above: f64 = counts[u..].iter().sum();
+        return (2.0 * below.min(above) / total).min(1.0);
+    }
+    let n = (n1 + n2) as f64;
+    let mu = (n1 * n2) as f64 / 2.0;
+    let sigma = ((n1 * n2) as f64 / 12.0 * ((n + 1.0) - tie_sum / (n * (n - 1.0)))).sqrt();
+    if sigma == 0.0 {
+        return 1.0;
+    }
+    let z = ((u - mu).abs() - 0.5).max(0.0) / sigma;
+    (2.0 * (1.0 - normal_cdf(z))).min(1.0)
+}
+
+/// Smallest two sided p value the Mann-Whitney test can give for groups of
+/// `n1` and `n2` values, reached when they do not overlap at all
+pub fn mann_whitney_min_p(n1: usize, n2: usize) -> f64 {
+    // 2 / C(n1 + n2, n1)
+    let mut combinations = 1.0;
+    for i in 0..n1.min(n2) {
+        combinations = combinations * (n1 + n2 - i) as f64 / (i + 1) as f64;
+    }
+    (2.0 / combinations).min(1.0)
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use libc::{gmtime_r, pid_t, time_t, tm};
+use crate::error::TraceError;
+use crate::loki::get_device_name;
+
+// `{tag:<key>}` takes the value of a --tag
+const TAG_TOKEN_PREFIX: &str = "tag:";
+
+/// Tokens an output template may use
+pub const TEMPLATE_TOKENS: &[&str] = &["{name}", "{pid}", "{date}", "{device_serial}", "{id}", "{tag:<key>}"];
+
+/// Values the tokens of output templates expand to, fixed at session start
+#[derive(Clone, Debug, Default)]
+pub struct TemplateValues {
+    /// target labels, `{name}` joins them with `+`
+    pub names: Vec<String>,
+    /// pids of the targets found at session start, `{pid}` joins them with `+`
+    pub pids: Vec<pid_t>,
+    /// wall clock seconds, `{date}` is its UTC `YYYYmmdd-HHMMSS`
+    pub epoch: u64,
+    /// `{id}`, the session id
+    pub session_id: String,
+    /// `--tag` values for `{tag:<key>}`
+    pub tags: Vec<(String, String)>,
+    // `{device_serial}`, looked up on first use as it may run getprop
+    device_serial: Option<String>,
+}
+
+impl TemplateValues {
+    /// Values of a session of `names` started at `epoch`
+    pub fn new(names: Vec<String>, pids: Vec<pid_t>, epoch: u64, session_id: &str,
+            tags: &[(String, String)]) -> TemplateValues {
+        TemplateValues { names, pids, epoch, session_id: session_id.to_string(), tags: tags.to_vec(),
+                device_serial: None }
+    }
+
+    fn value(&mut self, token: &str) -> Result<String, TraceError> {
+        let value = match token {
+            "name" if !self.names.is_empty() => self.names.join("+"),
+            "pid" if !self.pids.is_empty() =>
+                self.pids.iter().map(|pid| pid.to_string()).collect::<Vec<String>>().join("+"),
+            "pid" => return Err(TraceError::InvalidConfig("{pid} needs a running target".to_string())),
+            "date" => format_utc(self.epoch),
+            "device_serial" => self.device_serial.get_or_insert_with(get_device_name).clone(),
+            "id" if !self.session_id.is_empty() => self.session_id.clone(),
+            _ => match token.strip_prefix(TAG_TOKEN_PREFIX) {
+                Some(key) => self.tags.iter().find(|(tag, _)| tag == key).map(|(_, value)| value.clone())
+                        .ok_or_else(|| TraceError::InvalidConfig(format!("{{{}}} needs --tag {}=<value>", token, key)))?,
+                None => return Err(TraceError::InvalidConfig(format!("unknown token {{{}}}, use {}",
+                        token, TEMPLATE_TOKENS.join(" ")))),
+            },
+        };
+        Ok(path_safe(&value))
+    }
+}
+
+// Token values become single path components, `.` and `..` would name a directory
+fn path_safe(value: &str) -> String {
+    if !value.is_empty() && value.chars().all(|c| c == '.') {
+        return "_".to_string();
+    }
+    value.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+') { c } else { '_' })
+            .collect()
+}
+
+/// `YYYYmmdd-HHMMSS` of wall clock seconds in UTC, sorts like the time
+pub fn format_utc(epoch: u64) -> String {
+    let seconds = epoch as time_t;
+    // SAFETY:
+    // Safe because tm is plain data, all zero is a valid value
+    let mut utc: tm = unsafe { std::mem::zeroed() };
+    // SAFETY:
+    // Safe because both pointers are valid for the call, on failure `utc` stays zero
+    unsafe { gmtime_r(&seconds, &mut utc) };
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", utc.tm_year + 1900, utc.tm_mon + 1, utc.tm_mday,
+            utc.tm_hour, utc.tm_min, utc.tm_sec)
+}
+
+/// Expand the `{token}`s of `template`, e.g. `{tag:build}/{name}_{date}`.
+/// Unknown tokens and tokens without a value are errors, so a lab script
+/// never gets a name it did not ask for
+pub fn expand_template(template: &str, values: &mut TemplateValues) -> Result<String, TraceError> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}')
+                .ok_or_else(|| TraceError::InvalidConfig(format!("unclosed token in {}", template)))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&values.value(&rest[start + 1..start + end])?);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Whether `template` has tokens to expand
+pub fn has_tokens(template: &str) -> bool {
+    template.contains('{')
+}
@@ -0,0 +1,537 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use libc::{pid_t, sysconf, _SC_PAGESIZE};
+use crate::discover::PROC_ROOT;
+use crate::error::TraceError;
+use crate::file_utils::RetryPolicy;
+use crate::proc_analysis::{collect_record, cpu_share, delta_record, get_global_cpu_info, get_pss_info, parse_task_stat,
+        process_exe, split_stat, MetricGroups, MonitorOptions, RecordItem, Sampler};
+use crate::validate::{confidence, Validator};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// Resident pages of /proc/<pid>/stat
+const PROCESS_STAT_RSS_SHIFT: usize = 23;
+
+/// Names of the orders accepted by `TopSort::parse`
+pub const TOP_SORTS: [&str; 3] = ["cpu", "pss", "rss"];
+
+/// Order of the processes of a top trace, highest first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TopSort {
+    /// CPU time in the interval
+    #[default]
+    Cpu,
+    /// proportional set size, only read for the processes which may rank by their RSS
+    Pss,
+    /// resident set size
+    Rss,
+}
+
+impl TopSort {
+    /// Parse an order name
+    pub fn parse(name: &str) -> Option<TopSort> {
+        match name {
+            "cpu" => Some(TopSort::Cpu),
+            "pss" => Some(TopSort::Pss),
+            "rss" => Some(TopSort::Rss),
+            _ => None,
+        }
+    }
+
+    /// Name accepted by `parse`
+    pub fn name(&self) -> &'static str {
+        match self {
+            TopSort::Cpu => "cpu",
+            TopSort::Pss => "pss",
+            TopSort::Rss => "rss",
+        }
+    }
+}
+
+/// One process of a top interval
+#[derive(Default, Clone, Debug)]
+pub struct TopEntry {
+    /// pid of the process
+    pub pid: pid_t,
+    /// comm of the process
+    pub name: String,
+    /// delta sample of the interval, like the samples of a trace
+    pub item: RecordItem,
+    /// false in the first interval of the process in the list, its counters
+    /// other than the CPU times and faults are 0 then
+    pub baseline: bool,
+}
+
+/// Processes of one binary in a top interval, e.g. every app_process64
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct ExeGroup {
+    /// path of the binary, `[comm]` for processes without a readable one like kernel threads
+    pub exe: String,
+    /// processes running it
+    pub processes: usize,
+    /// CPU seconds of the processes in the interval
+    pub cpu: f64,
+    /// share of the CPU time of the system, like `RecordItem::cpu_occupancy_rate`
+    pub cpu_occupancy_rate: f64,
+    /// summed PSS in kB when sorting by PSS, otherwise 0 as walking the smaps
+    /// of every process each interval costs more than the trace itself.
+    /// Processes whose PSS can not be read are left out
+    pub pss: isize,
+    /// summed RSS in kB
+    pub rss: isize,
+}
+
+/// One interval of a top trace
+#[derive(Default, Clone, Debug)]
+pub struct TopSample {
+    /// seconds since the start of the trace
+    pub timestamp: i64,
+    /// order of the entries
+    pub sort: TopSort,
+    /// processes running in the interval
+    pub processes: usize,
+    /// the top processes, highest first
+    pub entries: Vec<TopEntry>,
+    /// the top binaries, highest first, empty unless grouping by binary
+    pub groups: Vec<ExeGroup>,
+}
+
+/// Receiver of the intervals of a top trace, e.g. a host agent alerting on thresholds
+///
+/// Closures taking a `&TopSample` and returning a bool are observers too.
+pub trait SampleObserver {
+    /// Called after every interval, returning false stops the trace
+    fn on_sample(&mut self, sample: &TopSample) -> bool;
+}
+
+impl<F: FnMut(&TopSample) -> bool> SampleObserver for F {
+    fn on_sample(&mut self, sample: &TopSample) -> bool {
+        self(sample)
+    }
+}
+
+/// Configuration of a system wide trace of the top processes, built with chained setters
+///
+/// ```ignore
+/// TopConfig::new()
+///         .sort(TopSort::Pss)
+///         .count(5)
+///         .build()?
+///         .run(&mut |sample: &TopSample| {
+///             sample.entries.iter().all(|entry| entry.item.pss < 500_000)
+///         })?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct TopConfig {
+    sort: TopSort,
+    count: usize,
+    duration: i64,
+    interval: i64,
+    options: MonitorOptions,
+    group_by_exe: bool,
+}
+
+impl Default for TopConfig {
+    fn default() -> Self {
+        TopConfig {
+            sort: TopSort::Cpu,
+            count: 10,
+            duration: 0,
+            interval: 3,
+            options: MonitorOptions { metrics: MetricGroups::none(), ..Default::default() },
+            group_by_exe: false,
+        }
+    }
+}
+
+impl TopConfig {
+    /// The 10 processes using the most CPU every 3 seconds, until the observer stops
+    pub fn new() -> TopConfig {
+        TopConfig::default()
+    }
+
+    /// Order of the processes
+    pub fn sort(mut self, sort: TopSort) -> TopConfig {
+        self.sort = sort;
+        self
+    }
+
+    /// Processes reported every interval
+    pub fn count(mut self, count: usize) -> TopConfig {
+        self.count = count;
+        self
+    }
+
+    /// Trace duration in seconds, <= 0 traces until the observer stops
+    pub fn duration(mut self, seconds: i64) -> TopConfig {
+        self.duration = seconds;
+        self
+    }
+
+    /// Sampling interval in seconds
+    pub fn interval(mut self, seconds: i64) -> TopConfig {
+        self.interval = seconds;
+        self
+    }
+
+    /// Retry and validation options and metric groups of the collectors,
+    /// no metric group by default
+    pub fn options(mut self, options: MonitorOptions) -> TopConfig {
+        self.options = options;
+        self
+    }
+
+    /// Also sum every process by its binary, see `TopSample::groups`. The
+    /// binary of every process is read each interval then, its PSS only when
+    /// sorting by PSS
+    pub fn group_by_exe(mut self, group_by_exe: bool) -> TopConfig {
+        self.group_by_exe = group_by_exe;
+        self
+    }
+
+    /// Check the configuration and get a trace to run
+    pub fn build(self) -> Result<TopTrace, TraceError> {
+        if self.count == 0 {
+            return Err(TraceError::InvalidConfig("no processes to report".to_string()));
+        }
+        if self.interval <= 0 {
+            return Err(TraceError::InvalidConfig(format!("interval {} is not positive", self.interval)));
+        }
+        Ok(TopTrace { config: self })
+    }
+}
+
+// Cumulative stat values of a process from a scan
+struct Scanned {
+    name: String,
+    rss_pages: isize,
+    item: RecordItem,
+    // CPU seconds since the last scan
+    cpu: f64,
+}
+
+// Collectors of a process in the top list, kept while it stays in the list
+#[derive(Default)]
+struct Ranked {
+    sampler: Sampler,
+    last: Option<RecordItem>,
+}
+
+/// A checked top trace
+#[derive(Clone, Debug)]
+pub struct TopTrace {
+    config: TopConfig,
+}
+
+impl TopTrace {
+    /// The configuration of the trace
+    pub fn config(&self) -> &TopConfig {
+        &self.config
+    }
+
+    /// Sample every process and pass the top ones to `observer` every interval,
+    /// blocks until the duration is over or the observer stops.
+    ///
+    /// The first interval has no CPU times to rank, the first sample comes
+    /// after one interval.
+    pub fn run(&self, observer: &mut impl SampleObserver) -> Result<(), TraceError> {
+        let config = &self.config;
+        let validator = Validator::new(config.options.validation);
+        let mut scan_sampler = Sampler::default();
+        let mut ranked: HashMap<pid_t, Ranked> = HashMap::new();
+        let mut last_scanned = HashMap::new();
+        let mut last_global = RecordItem::default();
+        let interval = Duration::from_secs(config.interval as u64);
+        let mut deadline = Instant::now();
+        let mut time_count = 0;
+        loop {
+            let mut global = RecordItem::default();
+            get_global_cpu_info(&mut global, &mut scan_sampler, &config.options.policies.global_cpu)?;
+            let scanned = scan(&mut scan_sampler, &last_scanned)?;
+            if time_count > 0 {
+                let top = rank(config, &scanned, &mut scan_sampler);
+                let mut entries = Vec::with_capacity(top.len());
+                let mut next_ranked = HashMap::with_capacity(top.len());
+                for pid in top {
+                    let process = &scanned[&pid];
+                    // A reused pid starts over
+                    let mut state = ranked.remove(&pid)
+                            .filter(|state| state.last.as_ref()
+                                    .is_some_and(|last| last.start_time == process.item.start_time))
+                            .unwrap_or_default();
+                    let mut current = RecordItem { timestamp: time_count, ..RecordItem::default() };
+                    collect_record(&mut current, state.last.as_ref().unwrap_or(&RecordItem::default()), pid,
+                            &mut state.sampler, &config.options.policies, config.options.metrics, None);
+                    let baseline = state.last.is_some();
+                    let last = state.last.take().unwrap_or_else(|| {
+                        scan_baseline(&current, last_scanned.get(&pid), &last_global)
+                    });
+                    let mut delta = current.clone();
+                    delta_record(&mut delta, &current, &last, &validator);
+                    validator.validate(&mut delta, config.interval);
+                    delta.confidence = confidence(&delta, config.options.metrics.collector_count());
+                    entries.push(TopEntry {
+                        pid,
+                        name: process.name.clone(),
+                        item: delta,
+                        baseline,
+                    });
+                    state.last = Some(current);
+                    next_ranked.insert(pid, state);
+                }
+                // Processes which left the list close their files
+                ranked = next_ranked;
+                let groups = match config.group_by_exe {
+                    true => group_by_exe(config, &scanned, &mut scan_sampler,
+                            global.global_total_cpu_time - last_global.global_total_cpu_time),
+                    false => Vec::new(),
+                };
+                let sample = TopSample {
+                    timestamp: time_count,
+                    sort: config.sort,
+                    processes: scanned.len(),
+                    entries,
+                    groups,
+                };
+                if !observer.on_sample(&sample) {
+                    return Ok(());
+                }
+            }
+            scan_sampler.sweep();
+            last_scanned = scanned;
+            last_global = global;
+            if config.duration > 0 && time_count >= config.duration {
+                return Ok(());
+            }
+            // Deadlines are counted from the first scan, like the ticks of a trace
+            deadline += interval;
+            sleep(deadline.saturating_duration_since(Instant::now()));
+            time_count += config.interval;
+        }
+    }
+}
+
+// Read the stat node of every process, CPU times are deltas to `last`
+fn scan(sampler: &mut Sampler, last: &HashMap<pid_t, Scanned>) -> Result<HashMap<pid_t, Scanned>, TraceError> {
+    let mut scanned = HashMap::with_capacity(last.len());
+    for entry in fs::read_dir(PROC_ROOT)? {
+        let pid: pid_t = match entry.ok().and_then(|entry| entry.file_name().to_str()?.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        let mut item = RecordItem::default();
+        // Processes exiting during the scan are skipped
+        let content = match sampler.read(&format!(crate::TASK_STAT_TEMPLATE!(), pid), &RetryPolicy::NONE,
+                &mut item.retries) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        if parse_task_stat(&mut item, content).is_err() {
+            continue;
+        }
+        let fields = split_stat(content);
+        let name = fields.get(1)
+                .map(|comm| comm.trim_start_matches('(').trim_end_matches(')').to_string())
+                .unwrap_or_default();
+        let rss_pages = fields.get(PROCESS_STAT_RSS_SHIFT)
+                .and_then(|rss| rss.parse().ok())
+                .unwrap_or(0);
+        // Processes started since the last scan spent all their CPU time in the interval
+        let cpu = match last.get(&pid) {
+            Some(last) if last.item.start_time == item.start_time => item.totalcputime - last.item.totalcputime,
+            _ => item.totalcputime,
+        };
+        scanned.insert(pid, Scanned { name, rss_pages, item, cpu });
+    }
+    Ok(scanned)
+}
+
+// Pids of the top processes, highest first
+fn rank(config: &TopConfig, scanned: &HashMap<pid_t, Scanned>, sampler: &mut Sampler) -> Vec<pid_t> {
+    let mut order: Vec<(pid_t, &Scanned)> = scanned.iter().map(|(pid, process)| (*pid, process)).collect();
+    match config.sort {
+        TopSort::Cpu => order.sort_by(|a, b| b.1.cpu.total_cmp(&a.1.cpu).then(a.0.cmp(&b.0))),
+        TopSort::Pss | TopSort::Rss => order.sort_by(|a, b| b.1.rss_pages.cmp(&a.1.rss_pages).then(a.0.cmp(&b.0))),
+    }
+    if config.sort != TopSort::Pss {
+        return order.into_iter().take(config.count).map(|(pid, _)| pid).collect();
+    }
+    // SAFETY:
+    // Safe because sysconf has no side effects
+    let page_kb = unsafe { sysconf(_SC_PAGESIZE) } as isize / 1024;
+    // PSS is at most RSS, the walk in RSS order stops once no process can beat the last one kept
+    let mut found: Vec<(isize, pid_t)> = Vec::with_capacity(config.count + 1);
+    for (pid, process) in order {
+        if found.len() >= config.count && process.rss_pages * page_kb < found[config.count - 1].0 {
+            break;
+        }
+        let mut item = RecordItem::default();
+        if get_pss_info(&mut item, pid, sampler, &config.options.policies.pss).is_err() {
+            continue;
+        }
+        found.push((item.pss, pid));
+        found.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        found.truncate(config.count);
+    }
+    found.into_iter().map(|(_, pid)| pid).collect()
+}
+
+// Sum the scanned processes by binary, the top groups in the order of the trace
+fn group_by_exe(config: &TopConfig, scanned: &HashMap<pid_t, Scanned>, sampler: &mut Sampler,
+        global_cpu: f64) -> Vec<ExeGroup> {
+    // SAFETY:
+    // Safe because sysconf has no side effects
+    let page_kb = unsafe { sysconf(_SC_PAGESIZE) } as isize / 1024;
+    let mut groups: HashMap<String, ExeGroup> = HashMap::new();
+    for (pid, process) in scanned {
+        let exe = process_exe(*pid).unwrap_or_else(|| format!("[{}]", process.name));
+        let mut item = RecordItem::default();
+        let read_pss = config.sort == TopSort::Pss;
+        let pss = match read_pss && get_pss_info(&mut item, *pid, sampler, &RetryPolicy::NONE).is_ok() {
+            true => item.pss,
+            false => 0,
+        };
+        let group = groups.entry(exe.clone()).or_insert_with(|| ExeGroup { exe, ..Default::default() });
+        group.processes += 1;
+        group.cpu += process.cpu;
+        group.pss += pss;
+        group.rss += process.rss_pages * page_kb;
+    }
+    let mut groups: Vec<ExeGroup> = groups.into_values()
+            .map(|group| ExeGroup { cpu_occupancy_rate: cpu_share(group.cpu, global_cpu), ..group })
+            .collect();
+    match config.sort {
+        TopSort::Cpu => groups.sort_by(|a, b| b.cpu.total_cmp(&a.cpu).then_with(|| a.exe.cmp(&b.exe))),
+        TopSort::Pss => groups.sort_by(|a, b| b.pss.cmp(&a.pss).then_with(|| a.exe.cmp(&b.exe))),
+        TopSort::Rss => groups.sort_by(|a, b| b.rss.cmp(&a.rss).then_with(|| a.exe.cmp(&b.exe))),
+    }
+    groups.truncate(config.count);
+    groups
+}
+
+// Baseline of a process entering the top list, the stat values of the last
+// scan and the counters of the current sample, whose deltas are then 0
+fn scan_baseline(current: &RecordItem, scanned: Option<&Scanned>, global: &RecordItem) -> RecordItem {
+    let mut baseline = current.clone();
+    let stat = scanned.map(|process| &process.item)
+            .filter(|item| item.start_time == current.start_time);
+    let empty = RecordItem::default();
+    let stat = stat.unwrap_or(&empty);
+    baseline.minflt = stat.minflt;
+    baseline.majflt = stat.majflt;
+    baseline.utime = stat.utime;
+    baseline.stime = stat.stime;
+    baseline.totalcputime = stat.totalcputime;
+    baseline.global_utime = global.global_utime;
+    baseline.global_stime = global.global_stime;
+    baseline.global_total_cpu_time = global.global_total_cpu_time;
+    baseline
+}
+
+// ANSI escapes of the highlighted values
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Thresholds which color the values of top tables, red for CPU over
+/// `cpu_percent` and yellow for an RSS rising in `rising_intervals` intervals
+/// in a row. It keeps the RSS of the last table to see the trend
+#[derive(Clone, Debug)]
+pub struct TopHighlight {
+    /// CPU in percent of all cores shown red
+    pub cpu_percent: f64,
+    /// intervals in a row the RSS has to grow in to be shown yellow
+    pub rising_intervals: usize,
+    // pid to the RSS in the last table and the intervals it grew in since
+    rss: HashMap<pid_t, (isize, usize)>,
+}
+
+impl TopHighlight {
+    /// Highlight CPU over `cpu_percent` and RSS rising for `rising_intervals`
+    pub fn new(cpu_percent: f64, rising_intervals: usize) -> TopHighlight {
+        TopHighlight { cpu_percent, rising_intervals: rising_intervals.max(1), rss: HashMap::new() }
+    }
+
+    // Follow the RSS of the processes of a table, processes not in it start over
+    fn observe(&mut self, sample: &TopSample) {
+        let rss = sample.entries.iter().map(|entry| {
+            let rising = match self.rss.get(&entry.pid) {
+                Some((last, rising)) if entry.item.vm_rss > *last => rising + 1,
+                _ => 0,
+            };
+            (entry.pid, (entry.item.vm_rss, rising))
+        }).collect();
+        self.rss = rss;
+    }
+
+    fn cpu_color(&self, cpu_occupancy_rate: f64) -> Option<&'static str> {
+        (cpu_occupancy_rate * 100.0 > self.cpu_percent).then_some(RED)
+    }
+
+    fn rss_color(&self, pid: pid_t) -> Option<&'static str> {
+        self.rss.get(&pid).filter(|(_, rising)| *rising >= self.rising_intervals).map(|_| YELLOW)
+    }
+}
+
+// A padded value in `color`, the escapes do not count for the width
+fn paint(value: String, color: Option<&str>) -> String {
+    match color {
+        Some(color) => format!("{}{}{}", color, value, RESET),
+        None => value,
+    }
+}
+
+/// Render a top interval as a text table, CPU in percent of all cores
+pub fn format_top(sample: &TopSample) -> String {
+    render_top(sample, None)
+}
+
+/// Render a top interval like `format_top` with the values over the
+/// thresholds of `highlight` in color, for terminals
+pub fn format_top_highlighted(sample: &TopSample, highlight: &mut TopHighlight) -> String {
+    highlight.observe(sample);
+    render_top(sample, Some(highlight))
+}
+
+fn render_top(sample: &TopSample, highlight: Option<&TopHighlight>) -> String {
+    let mut out = format!("time {}s, {} processes, top {} by {}\n",
+            sample.timestamp, sample.processes, sample.entries.len(), sample.sort.name());
+    let _ = writeln!(out, "{:>7}{:>8}{:>10}{:>10}{:>9}{:>6}{:>9}{:>7}{:>8}{:>8}  name",
+            "pid", "cpu%", "pss", "rss", "swap", "thr", "minflt", "majflt", "vcsw", "nvcsw");
+    for entry in &sample.entries {
+        let item = &entry.item;
+        let cpu = paint(format!("{:>8.2}", item.cpu_occupancy_rate * 100.0),
+                highlight.and_then(|h| h.cpu_color(item.cpu_occupancy_rate)));
+        let rss = paint(format!("{:>10}", item.vm_rss), highlight.and_then(|h| h.rss_color(entry.pid)));
+        let _ = writeln!(out, "{:>7}{}{:>10}{}{:>9}{:>6}{:>9}{:>7}{:>8}{:>8}  {}{}",
+                entry.pid, cpu, item.pss, rss, item.vm_swap,
+                item.num_threads, item.minflt, item.majflt, item.voluntary_ctxt_switches,
+                item.nonvoluntary_ctxt_switches, entry.name, if entry.baseline { "" } else { " *" });
+    }
+    if sample.entries.iter().any(|entry| !entry.baseline) {
+        out.push_str("* new in the list, context switches are counted from the next interval\n");
+    }
+    if !sample.groups.is_empty() {
+        let _ = writeln!(out, "\ntop {} binaries by {}", sample.groups.len(), sample.sort.name());
+        let _ = writeln!(out, "{:>6}{:>8}{:>11}{:>11}  exe", "procs", "cpu%", "pss", "rss");
+        for group in &sample.groups {
+            let cpu = paint(format!("{:>8.2}", group.cpu_occupancy_rate * 100.0),
+                    highlight.and_then(|h| h.cpu_color(group.cpu_occupancy_rate)));
+            let pss = match sample.sort {
+                TopSort::Pss => group.pss.to_string(),
+                _ => "-".to_string(),
+            };
+            let _ = writeln!(out, "{:>6}{}{:>11}{:>11}  {}", group.processes, cpu, pss, group.rss, group.exe);
+        }
+    }
+    out
+}
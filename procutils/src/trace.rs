@@ -0,0 +1,196 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::error::TraceError;
+use crate::loki::new_run_id;
+use crate::output::{parse_tag, OutputFormat, OutputPlan};
+use crate::power::PowerProfile;
+use crate::proc_analysis::{run_monitors, MonitorOptions, RecordProcess, TraceTarget};
+
+/// Configuration of a trace, built with chained setters
+///
+/// ```ignore
+/// let session = TraceConfig::new()
+///         .duration(120)
+///         .interval(5)
+///         .targets(vec![TraceTarget::Name("init".to_string())])
+///         .build()?;
+/// for record in session.run()? {
+///     match record {
+///         Ok(record) => println!("{}: {} samples", record.name, record.record_infos.len()),
+///         Err(e) => println!("target failed: {}", e),
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TraceConfig {
+    duration: i64,
+    interval: i64,
+    targets: Vec<TraceTarget>,
+    options: MonitorOptions,
+    output: Option<OutputPlan>,
+    tags: Vec<(String, String)>,
+    profiles: Vec<PowerProfile>,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        TraceConfig {
+            duration: 60,
+            interval: 10,
+            targets: Vec::new(),
+            options: MonitorOptions::default(),
+            output: None,
+            tags: Vec::new(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl TraceConfig {
+    /// Trace for 60 seconds with a 10 seconds interval
+    pub fn new() -> TraceConfig {
+        TraceConfig::default()
+    }
+
+    /// Trace duration in seconds, <= 0 traces until the targets exit
+    pub fn duration(mut self, seconds: i64) -> TraceConfig {
+        self.duration = seconds;
+        self
+    }
+
+    /// Trace until the targets exit
+    pub fn until_exit(self) -> TraceConfig {
+        self.duration(0)
+    }
+
+    /// Sampling interval in seconds
+    pub fn interval(mut self, seconds: i64) -> TraceConfig {
+        self.interval = seconds;
+        self
+    }
+
+    /// Add one target
+    pub fn target(mut self, target: TraceTarget) -> TraceConfig {
+        self.targets.push(target);
+        self
+    }
+
+    /// Add targets
+    pub fn targets(mut self, targets: impl IntoIterator<Item = TraceTarget>) -> TraceConfig {
+        self.targets.extend(targets);
+        self
+    }
+
+    /// Retry and validation options of the collectors
+    pub fn options(mut self, options: MonitorOptions) -> TraceConfig {
+        self.options = options;
+        self
+    }
+
+    /// Also write the samples of every target to `dir` in `formats`
+    pub fn output(mut self, dir: &str, formats: &[OutputFormat]) -> TraceConfig {
+        let streaming = self.output.as_ref().map(|plan| plan.streaming).unwrap_or(false);
+        let compact = self.output.as_ref().map(|plan| plan.compact).unwrap_or(false);
+        self.output = Some(OutputPlan {
+            dir: dir.to_string(),
+            formats: formats.to_vec(),
+            streaming,
+            tags: Vec::new(),
+            compact,
+            run_id: new_run_id(),
+        });
+        self
+    }
+
+    /// Write every sample to the outputs as it is collected instead of at the
+    /// end, the returned records then carry no samples
+    pub fn streaming(mut self, streaming: bool) -> TraceConfig {
+        if let Some(plan) = self.output.as_mut() {
+            plan.streaming = streaming;
+        }
+        self
+    }
+
+    /// Write the columns which rarely change only at change points in the
+    /// JSON outputs, see `OutputPlan::compact`
+    pub fn compact(mut self, compact: bool) -> TraceConfig {
+        if let Some(plan) = self.output.as_mut() {
+            plan.compact = compact;
+        }
+        self
+    }
+
+    /// Tag the trace, the tag is written to every output and to the
+    /// returned records, see `output::parse_tag`
+    pub fn tag(mut self, key: &str, value: &str) -> TraceConfig {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sample with other settings while the device is in a power state, the
+    /// first matching profile is used
+    pub fn profile(mut self, profile: PowerProfile) -> TraceConfig {
+        self.profiles.push(profile);
+        self
+    }
+
+    /// Check the configuration and get a session to run
+    pub fn build(self) -> Result<TraceSession, TraceError> {
+        if self.targets.is_empty() {
+            return Err(TraceError::InvalidConfig("no targets".to_string()));
+        }
+        if self.output.as_ref().map(|plan| plan.formats.is_empty()).unwrap_or(false) {
+            return Err(TraceError::InvalidConfig("no output formats".to_string()));
+        }
+        for (i, (key, value)) in self.tags.iter().enumerate() {
+            parse_tag(&format!("{}={}", key, value))?;
+            if self.tags[..i].iter().any(|(other, _)| other == key) {
+                return Err(TraceError::InvalidConfig(format!("duplicate tag {}", key)));
+            }
+        }
+        if self.profiles.iter().any(|profile| profile.interval <= 0) {
+            return Err(TraceError::InvalidConfig("power profile interval is not positive".to_string()));
+        }
+        if self.interval <= 0 {
+            return Err(TraceError::InvalidConfig(format!("interval {} is not positive", self.interval)));
+        }
+        if self.options.follow && self.duration <= 0 {
+            return Err(TraceError::InvalidConfig("followed targets need a duration".to_string()));
+        }
+        Ok(TraceSession { config: self })
+    }
+}
+
+/// A checked trace, its samples are returned to the caller instead of being written
+#[derive(Clone, Debug)]
+pub struct TraceSession {
+    config: TraceConfig,
+}
+
+impl TraceSession {
+    /// The configuration of the session
+    pub fn config(&self) -> &TraceConfig {
+        &self.config
+    }
+
+    /// Trace all targets, blocks until the duration is over or the targets exited
+    ///
+    /// Fails before sampling when a target has no process, unless it is
+    /// followed. The samples or the error of every target are returned in
+    /// the order of the targets, a target failing later keeps the others.
+    pub fn run(&self) -> Result<Vec<Result<RecordProcess, TraceError>>, TraceError> {
+        let names = self.config.targets.iter().map(|t| t.spec()).collect();
+        let plan = self.config.output.clone()
+                .map(|plan| OutputPlan { tags: self.config.tags.clone(), ..plan });
+        let mut records = run_monitors(self.config.duration, self.config.interval, names, None,
+                plan.as_ref(), self.config.options, &self.config.profiles)?;
+        for record in records.iter_mut().flatten() {
+            record.tags = self.config.tags.clone();
+        }
+        Ok(records)
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use crate::proc_analysis::RecordItem;
+use std::collections::VecDeque;
+
+/// One step of a column transform
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransformOp {
+    /// divide by the sample interval, a delta becomes a per second rate
+    Rate,
+    /// multiply by a factor, e.g. 1024 turns kB into bytes
+    Scale(f64),
+    /// exponential moving average with this weight of the new value, 0 < alpha <= 1
+    Ema(f64),
+    /// keep between the bounds, a missing bound is open
+    Clamp(Option<f64>, Option<f64>),
+}
+
+impl TransformOp {
+    /// Parse `rate`, `scale=<factor>`, `ema=<alpha>` or `clamp=<min>..<max>`
+    pub fn parse(spec: &str) -> Option<TransformOp> {
+        let (name, arg) = match spec.split_once('=') {
+            Some((name, arg)) => (name.trim(), Some(arg.trim())),
+            None => (spec.trim(), None),
+        };
+        let bound = |text: &str| match text {
+            "" => Some(None),
+            text => text.parse::<f64>().ok().filter(|v| v.is_finite()).map(Some),
+        };
+        match (name, arg) {
+            ("rate", None) => Some(TransformOp::Rate),
+            ("scale", Some(factor)) => factor.parse().ok().filter(|f: &f64| f.is_finite()).map(TransformOp::Scale),
+            ("ema", Some(alpha)) => alpha.parse().ok()
+                    .filter(|alpha: &f64| *alpha > 0.0 && *alpha <= 1.0)
+                    .map(TransformOp::Ema),
+            ("clamp", Some(range)) => {
+                let (min, max) = range.split_once("..")?;
+                let (min, max) = (bound(min)?, bound(max)?);
+                match (min, max) {
+                    (Some(min), Some(max)) if min > max => None,
+                    _ => Some(TransformOp::Clamp(min, max)),
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Spec accepted by `parse`
+    pub fn spec(&self) -> String {
+        let bound = |bound: Option<f64>| bound.map(|v| v.to_string()).unwrap_or_default();
+        match self {
+            TransformOp::Rate => "rate".to_string(),
+            TransformOp::Scale(factor) => format!("scale={}", factor),
+            TransformOp::Ema(alpha) => format!("ema={}", alpha),
+            TransformOp::Clamp(min, max) => format!("clamp={}..{}", bound(*min), bound(*max)),
+        }
+    }
+}
+
+/// Steps applied to one column of every sample in order, written
+/// `<column>:<op>[+<op>]...`, e.g. `minflt:rate+ema=0.3`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnTransform {
+    /// column name, see `RecordItem::set_value`
+    pub column: String,
+    /// steps in order
+    pub ops: Vec<TransformOp>,
+}
+
+impl ColumnTransform {
+    /// Parse a transform, None when it is malformed or the column can not be set
+    pub fn parse(spec: &str) -> Option<ColumnTransform> {
+        let (column, ops) = spec.split_once(':')?;
+        let column = column.trim();
+        if !RecordItem::default().set_value(column, 0.0) {
+            return None;
+        }
+        let ops = ops.split('+').map(TransformOp::parse).collect::<Option<Vec<TransformOp>>>()?;
+        Some(ColumnTransform { column: column.to_string(), ops })
+    }
+
+    /// Spec accepted by `parse`
+    pub fn spec(&self) -> String {
+        let ops: Vec<String> = self.ops.iter().map(|op| op.spec()).collect();
+        format!("{}:{}", self.column, ops.join("+"))
+    }
+}
+
+/// Moving averages of a smoothed companion column
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmoothKind {
+    /// exponential, alpha is 2 / (window + 1)
+    Ema,
+    /// simple, the mean of the last window samples
+    Sma,
+}
+
+/// A smoothed companion of a column, written `<column>:ema=<window>` or
+/// `<column>:sma=<window>` with the window in samples. The companion is
+/// named like `cpuOccupancyRateEma5` and follows the column transforms
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmoothSpec {
+    /// column name, see `RecordItem::value`
+    pub column: String,
+    /// kind of average
+    pub kind: SmoothKind,
+    /// samples averaged, at least 2
+    pub window: usize,
+}
+
+impl SmoothSpec {
+    /// Parse a smoothing, None when it is malformed or the column is unknown
+    pub fn parse(spec: &str) -> Option<SmoothSpec> {
+        let (column, average) = spec.split_once(':')?;
+        let column = column.trim();
+        RecordItem::default().value(column)?;
+        let (kind, window) = average.split_once('=')?;
+        let kind = match kind.trim() {
+            "ema" => SmoothKind::Ema,
+            "sma" => SmoothKind::Sma,
+            _ => return None,
+        };
+        let window = window.trim().parse().ok().filter(|window| *window >= 2)?;
+        Some(SmoothSpec { column: column.to_string(), kind, window })
+    }
+
+    /// Spec accepted by `parse`
+    pub fn spec(&self) -> String {
+        let kind = match self.kind {
+            SmoothKind::Ema => "ema",
+            SmoothKind::Sma => "sma",
+        };
+        format!("{}:{}={}", self.column, kind, self.window)
+    }
+
+    /// Name of the companion column
+    pub fn column_name(&self) -> String {
+        let kind = match self.kind {
+            SmoothKind::Ema => "Ema",
+            SmoothKind::Sma => "Sma",
+        };
+        format!("{}{}{}", self.column, kind, self.window)
+    }
+}
+
+// State of one smoothed companion, samples without a finite value are skipped
+#[derive(Clone, Debug)]
+struct Smoother {
+    spec: SmoothSpec,
+    average: Option<f64>,
+    window: VecDeque<f64>,
+}
+
+impl Smoother {
+    fn push(&mut self, value: f64) -> f64 {
+        if !value.is_finite() {
+            return self.average.unwrap_or(f64::NAN);
+        }
+        let average = match self.spec.kind {
+            SmoothKind::Ema => {
+                let alpha = 2.0 / (self.spec.window as f64 + 1.0);
+                self.average.map_or(value, |last| last + alpha * (value - last))
+            },
+            SmoothKind::Sma => {
+                if self.window.len() == self.spec.window {
+                    self.window.pop_front();
+                }
+                self.window.push_back(value);
+                self.window.iter().sum::<f64>() / self.window.len() as f64
+            },
+        };
+        self.average = Some(average);
+        average
+    }
+}
+
+/// Applies the transforms of a trace to its samples before they are written
+/// and adds the smoothed companions
+///
+/// Moving averages carry their state from sample to sample, a pipeline
+/// belongs to one target.
+#[derive(Clone, Debug, Default)]
+pub struct TransformPipeline {
+    transforms: Vec<ColumnTransform>,
+    // last average of every Ema step, by transform and step
+    averages: Vec<Vec<Option<f64>>>,
+    smoothers: Vec<Smoother>,
+}
+
+impl TransformPipeline {
+    /// Pipeline of `transforms`, applied in order, then `smooths`
+    pub fn new(transforms: &[ColumnTransform], smooths: &[SmoothSpec]) -> TransformPipeline {
+        TransformPipeline {
+            transforms: transforms.to_vec(),
+            averages: transforms.iter().map(|transform| vec![None; transform.ops.len()]).collect(),
+            smoothers: smooths.iter()
+                    .map(|spec| Smoother { spec: spec.clone(), average: None, window: VecDeque::new() })
+                    .collect(),
+        }
+    }
+
+    /// Whether there is nothing to apply
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty() && self.smoothers.is_empty()
+    }
+
+    /// Names of the smoothed companion columns, in the order of `RecordItem::smoothed`
+    pub fn smoothed_columns(&self) -> Vec<String> {
+        self.smoothers.iter().map(|smoother| smoother.spec.column_name()).collect()
+    }
+
+    /// Transform a sample taken `interval` seconds after the last one
+    pub fn apply(&mut self, item: &mut RecordItem, interval: i64) {
+        for (transform, averages) in self.transforms.iter().zip(self.averages.iter_mut()) {
+            let Some(mut value) = item.value(&transform.column) else { continue };
+            for (op, average) in transform.ops.iter().zip(averages.iter_mut()) {
+                value = match *op {
+                    TransformOp::Rate if interval > 0 => value / interval as f64,
+                    TransformOp::Rate => value,
+                    TransformOp::Scale(factor) => value * factor,
+                    TransformOp::Ema(alpha) => {
+                        let smoothed = match *average {
+                            Some(last) => last + alpha * (value - last),
+                            None => value,
+                        };
+                        *average = Some(smoothed);
+                        smoothed
+                    },
+                    TransformOp::Clamp(min, max) => {
+                        let value = min.map_or(value, |min| value.max(min));
+                        max.map_or(value, |max| value.min(max))
+                    },
+                };
+            }
+            item.set_value(&transform.column, value);
+        }
+        item.smoothed.clear();
+        for smoother in self.smoothers.iter_mut() {
+            let value = item.value(&smoother.spec.column).unwrap_or(f64::NAN);
+            item.smoothed.push(smoother.push(value));
+        }
+    }
+}
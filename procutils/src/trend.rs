@@ -0,0 +1,182 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use crate::analysis::{summarize, TraceData};
+use crate::file_utils::read_path;
+use std::fmt::Write as FmtWrite;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+/// First line of a trend store
+pub const TREND_STORE_STAMP: &str = "# process_trace trend 1";
+
+/// Tag whose value keys the runs when no key is given
+pub const TREND_KEY_TAG: &str = "build";
+
+/// Columns shown by `format_trend` when none are asked for
+pub const DEFAULT_TREND_COLUMNS: &[&str] = &["cpuOccupancyRate", "pss", "vmRss", "healthScore"];
+
+/// Summary of one column of one run
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrendEntry {
+    /// wall clock seconds when the run was appended
+    pub epoch: u64,
+    /// build, tag or run the run belongs to
+    pub key: String,
+    /// target label
+    pub target: String,
+    /// column name
+    pub column: String,
+    /// samples with a finite value
+    pub count: usize,
+    /// mean value
+    pub mean: f64,
+    /// 95th percentile
+    pub p95: f64,
+    /// max value
+    pub max: f64,
+}
+
+/// History of one column of one target, one point per key in append order
+#[derive(Clone, Debug, Default)]
+pub struct TrendPoint {
+    /// build, tag or run
+    pub key: String,
+    /// runs of the key
+    pub runs: usize,
+    /// mean of the run means
+    pub mean: f64,
+    /// mean of the run p95s
+    pub p95: f64,
+    /// change of `mean` to the previous key in percent, NaN for the first one
+    pub delta_percent: f64,
+}
+
+// Commas separate the fields of the store
+fn store_safe(value: &str) -> String {
+    value.replace([',', '\n', '\r'], "_")
+}
+
+/// Key of a run: its `build` tag, else `fallback`
+pub fn run_key(data: &TraceData, fallback: &str) -> String {
+    data.tags.iter()
+            .find(|(key, _)| key == TREND_KEY_TAG)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| fallback.to_string())
+}
+
+/// Append the summary of a run to the store, the file is created if needed.
+/// The rows of a run are written at once, so runs never interleave
+pub fn append_run(path: &str, key: &str, target: &str, data: &TraceData, epoch: u64) -> io::Result<usize> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut block = String::new();
+    if file.metadata()?.len() == 0 {
+        block.push_str(TREND_STORE_STAMP);
+        block.push('\n');
+    }
+    let summaries = summarize(data);
+    for s in summaries.iter().filter(|s| s.count > 0) {
+        let _ = writeln!(block, "{},{},{},{},{},{},{},{}", epoch, store_safe(key), store_safe(target),
+                s.name, s.count, s.mean, s.p95, s.max);
+    }
+    file.write_all(block.as_bytes())?;
+    Ok(summaries.len())
+}
+
+/// Read a store, rows which do not parse are skipped
+pub fn load_trend(path: &str) -> io::Result<Vec<TrendEntry>> {
+    let content = read_path(path)?;
+    if !content.starts_with(TREND_STORE_STAMP) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a trend store", path)));
+    }
+    Ok(content.lines().skip(1).filter_map(parse_entry).collect())
+}
+
+fn parse_entry(line: &str) -> Option<TrendEntry> {
+    let fields: Vec<&str> = line.split(',').collect();
+    match fields.as_slice() {
+        [epoch, key, target, column, count, mean, p95, max] => Some(TrendEntry {
+            epoch: epoch.parse().ok()?,
+            key: key.to_string(),
+            target: target.to_string(),
+            column: column.to_string(),
+            count: count.parse().ok()?,
+            mean: mean.parse().ok()?,
+            p95: p95.parse().ok()?,
+            max: max.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+/// Targets of a store, in the order they first appear
+pub fn trend_targets(entries: &[TrendEntry]) -> Vec<String> {
+    let mut targets: Vec<String> = Vec::new();
+    for entry in entries {
+        if !targets.contains(&entry.target) {
+            targets.push(entry.target.clone());
+        }
+    }
+    targets
+}
+
+/// History of a column of a target, keys in the order they first appear
+pub fn trend_history(entries: &[TrendEntry], target: &str, column: &str) -> Vec<TrendPoint> {
+    let mut points: Vec<(TrendPoint, f64, f64)> = Vec::new();
+    for entry in entries.iter().filter(|e| e.target == target && e.column == column && e.mean.is_finite()) {
+        let index = match points.iter().position(|(point, _, _)| point.key == entry.key) {
+            Some(index) => index,
+            None => {
+                points.push((TrendPoint { key: entry.key.clone(), ..Default::default() }, 0.0, 0.0));
+                points.len() - 1
+            },
+        };
+        let (point, mean_sum, p95_sum) = &mut points[index];
+        point.runs += 1;
+        *mean_sum += entry.mean;
+        *p95_sum += entry.p95;
+    }
+    let mut history: Vec<TrendPoint> = points.into_iter()
+            .map(|(point, mean_sum, p95_sum)| TrendPoint {
+                mean: mean_sum / point.runs as f64,
+                p95: p95_sum / point.runs as f64,
+                delta_percent: f64::NAN,
+                ..point
+            })
+            .collect();
+    for i in 1..history.len() {
+        let previous = history[i - 1].mean;
+        if previous != 0.0 {
+            history[i].delta_percent = (history[i].mean - previous) / previous.abs() * 100.0;
+        }
+    }
+    history
+}
+
+/// Render the history of some columns of every target as text tables
+pub fn format_trend(entries: &[TrendEntry], columns: &[&str]) -> String {
+    let mut out = String::new();
+    for target in trend_targets(entries) {
+        for column in columns {
+            let history = trend_history(entries, &target, column);
+            if history.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "{} {}", target, column);
+            let _ = writeln!(out, "  {:<24}{:>6}{:>14}{:>14}{:>10}", "key", "runs", "mean", "p95", "delta%");
+            for point in &history {
+                let delta = match point.delta_percent.is_finite() {
+                    true => format!("{:.1}%", point.delta_percent),
+                    false => "-".to_string(),
+                };
+                let _ = writeln!(out, "  {:<24}{:>6}{:>14.3}{:>14.3}{:>10}",
+                        point.key, point.runs, point.mean, point.p95, delta);
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,147 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+use crate::file_utils::read_node;
+use crate::proc_analysis::RecordItem;
+use libc::{sysconf, _SC_NPROCESSORS_CONF};
+
+const GLOBAL_MEMINFO: &str = "/proc/meminfo";
+const MEM_TOTAL_PREFIX: &str = "MemTotal:";
+
+/// A counter went backwards between two samples
+pub const QUALITY_NEGATIVE_DELTA: u32 = 1 << 0;
+/// RSS or PSS is larger than MemTotal
+pub const QUALITY_RSS_OVER_MEMTOTAL: u32 = 1 << 1;
+/// CPU time is more than all cores could run in the interval
+pub const QUALITY_CPU_OVER_CORES: u32 = 1 << 2;
+/// A derived value is NaN or infinite
+pub const QUALITY_NOT_FINITE: u32 = 1 << 3;
+
+/// What to do with a physically impossible value
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// Keep the value, only set the quality bit
+    #[default]
+    Flag,
+    /// Set the quality bit and clamp the value into the possible range
+    Clamp,
+}
+
+/// Sanity checks of samples against the limits of the device
+#[derive(Clone, Copy, Debug)]
+pub struct Validator {
+    mode: ValidationMode,
+    // kB
+    mem_total: isize,
+    cores: usize,
+}
+
+/// Get MemTotal in kB, 0 if unknown
+pub fn get_mem_total() -> isize {
+    let content = match read_node(GLOBAL_MEMINFO) {
+        Ok(content) => content,
+        Err(_) => return 0,
+    };
+    content.lines()
+            .find(|line| line.starts_with(MEM_TOTAL_PREFIX))
+            .and_then(|line| line
+                    .trim_start_matches(MEM_TOTAL_PREFIX)
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<isize>()
+                    .ok())
+            .unwrap_or(0)
+}
+
+/// Get the number of configured cores, 1 if unknown
+pub fn get_cores() -> usize {
+    // SAFETY:
+    // Safe because sysconf has no memory side effects, errors are checked
+    let cores = unsafe { sysconf(_SC_NPROCESSORS_CONF) };
+    if cores > 0 { cores as usize } else { 1 }
+}
+
+/// Subtract two counters, a counter going backwards gives 0
+pub(crate) fn delta_count(current: usize, last: usize, quality: &mut u32) -> usize {
+    current.checked_sub(last).unwrap_or_else(|| {
+        *quality |= QUALITY_NEGATIVE_DELTA;
+        0
+    })
+}
+
+/// Summarize how much a sample can be trusted, from 0.0 to 1.0
+///
+/// Starts from the share of collectors which succeeded, then is lowered
+/// when retries were needed, values were carried forward or quality bits are set.
+pub(crate) fn confidence(item: &RecordItem, collector_count: u32) -> f64 {
+    if collector_count == 0 {
+        return 0.0;
+    }
+    let mut confidence = item.collectors_ok.min(collector_count) as f64 / collector_count as f64;
+    if item.retries > 0 {
+        confidence *= 0.9;
+    }
+    if item.carried_forward {
+        confidence *= 0.5;
+    }
+    if item.quality_flags != 0 {
+        confidence *= 0.5;
+    }
+    confidence
+}
+
+impl Validator {
+    /// Read the device limits
+    pub fn new(mode: ValidationMode) -> Validator {
+        Validator { mode, mem_total: get_mem_total(), cores: get_cores() }
+    }
+
+    /// Subtract two cumulative times, a negative result is flagged
+    pub(crate) fn delta_time(&self, current: f64, last: f64, quality: &mut u32) -> f64 {
+        let delta = current - last;
+        if delta < 0.0 {
+            *quality |= QUALITY_NEGATIVE_DELTA;
+            if self.mode == ValidationMode::Clamp {
+                return 0.0;
+            }
+        }
+        delta
+    }
+
+    /// Check a delta sample, `interval` is the sampling interval in seconds
+    pub(crate) fn validate(&self, item: &mut RecordItem, interval: i64) {
+        let clamp = self.mode == ValidationMode::Clamp;
+        if self.mem_total > 0 {
+            for value in [&mut item.vm_rss, &mut item.pss] {
+                if *value > self.mem_total {
+                    item.quality_flags |= QUALITY_RSS_OVER_MEMTOTAL;
+                    if clamp {
+                        *value = self.mem_total;
+                    }
+                }
+            }
+        }
+        let max_cpu_time = (self.cores as i64 * interval) as f64;
+        if interval > 0 && item.totalcputime > max_cpu_time {
+            item.quality_flags |= QUALITY_CPU_OVER_CORES;
+            if clamp {
+                item.totalcputime = max_cpu_time;
+            }
+        }
+        if !item.cpu_occupancy_rate.is_finite() {
+            item.quality_flags |= QUALITY_NOT_FINITE;
+            if clamp {
+                item.cpu_occupancy_rate = 0.0;
+            }
+        } else if item.cpu_occupancy_rate > 1.0 {
+            // The process can not run longer than all busy cores
+            item.quality_flags |= QUALITY_CPU_OVER_CORES;
+            if clamp {
+                item.cpu_occupancy_rate = 1.0;
+            }
+        }
+    }
+}
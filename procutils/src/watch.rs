@@ -0,0 +1,306 @@
+// Copyright (c) 2024, 🌟夕元 & 🌟VEA
+// All Rights Reserved
+// 
+// This file is part of LinuxProcessTrace distributed under the BSD 3-Clause License. 
+// See the LICENSE file at the root directory of this project for more details.
+
+
+use libc::pid_t;
+use crate::error::TraceError;
+use crate::output::{OutputFormat, DEFAULT_OUTPUT_FORMATS};
+use crate::proc_analysis::{process_start_time, trace_session_with_options, MetricGroups, MonitorOptions, TraceTarget};
+use crate::session::{now_epoch, SessionState};
+use crate::shutdown::stop_requested;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Index of the occurrences in the output directory of a watch, one
+/// `epoch,pid,occurrence,session` line per occurrence
+pub const WATCH_INDEX_FILE: &str = "watch_index.csv";
+
+/// Tag added to the session of every occurrence, counting from 1
+pub const OCCURRENCE_TAG: &str = "occurrence";
+
+/// One traced occurrence of a watched target
+#[derive(Clone, Debug)]
+pub struct Occurrence {
+    /// 1 for the first occurrence
+    pub index: usize,
+    /// pid of the process
+    pub pid: pid_t,
+    /// session directory of the trace
+    pub dir: String,
+    /// seconds the process was traced
+    pub elapsed: i64,
+    /// error of the trace, e.g. the process exited before its first sample
+    pub error: Option<String>,
+}
+
+/// Finds processes of a target which were not seen before
+///
+/// A pid reused by a new process counts as a new occurrence.
+#[derive(Clone, Debug)]
+pub struct OccurrenceWatcher {
+    target: TraceTarget,
+    // (pid, start time) of the processes seen
+    seen: Vec<(pid_t, i64)>,
+}
+
+impl OccurrenceWatcher {
+    /// Watch `target`, processes already running are new occurrences too
+    pub fn new(target: TraceTarget) -> OccurrenceWatcher {
+        OccurrenceWatcher { target, seen: Vec::new() }
+    }
+
+    /// Mark the processes running now as seen, returns their count
+    pub fn skip_running(&mut self) -> usize {
+        self.poll().len()
+    }
+
+    /// New processes of the target, oldest first
+    pub fn poll(&mut self) -> Vec<pid_t> {
+        // Exited processes are forgotten, their pids may come back
+        self.seen.retain(|(pid, start)| process_start_time(*pid) == Some(*start));
+        let pids = self.target.resolve_pids().unwrap_or_default();
+        let mut found = Vec::new();
+        for pid in pids {
+            let Some(start) = process_start_time(pid) else { continue };
+            if !self.seen.contains(&(pid, start)) {
+                self.seen.push((pid, start));
+                found.push(pid);
+            }
+        }
+        found
+    }
+}
+
+/// Configuration of a watch, which traces every occurrence of a target in a
+/// session of its own until it exits, built with chained setters. Occurrences
+/// running at the same time are traced at the same time
+///
+/// ```ignore
+/// WatchConfig::new(TraceTarget::parse("dex2oat64"))
+///         .output_dir("/data/local/tmp/dex2oat")
+///         .interval(1)
+///         .build()?
+///         .run(&mut |occurrence: &Occurrence| {
+///             println!("{} traced to {}", occurrence.pid, occurrence.dir);
+///             true
+///         })?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct WatchConfig {
+    target: TraceTarget,
+    output_dir: String,
+    interval: i64,
+    poll: Duration,
+    duration: i64,
+    count: usize,
+    skip_running: bool,
+    formats: Vec<OutputFormat>,
+    metrics: MetricGroups,
+    tags: Vec<(String, String)>,
+    options: MonitorOptions,
+}
+
+impl WatchConfig {
+    /// Trace every occurrence of `target` every second into the current
+    /// directory, polling for new ones every 200ms until the observer stops
+    pub fn new(target: TraceTarget) -> WatchConfig {
+        WatchConfig {
+            target,
+            output_dir: ".".to_string(),
+            interval: 1,
+            poll: Duration::from_millis(200),
+            duration: 0,
+            count: 0,
+            skip_running: false,
+            formats: DEFAULT_OUTPUT_FORMATS.to_vec(),
+            metrics: MetricGroups::default(),
+            tags: Vec::new(),
+            options: MonitorOptions::default(),
+        }
+    }
+
+    /// Where the sessions of the occurrences are created
+    pub fn output_dir(mut self, dir: &str) -> WatchConfig {
+        self.output_dir = dir.to_string();
+        self
+    }
+
+    /// Sampling interval of the occurrences in seconds
+    pub fn interval(mut self, seconds: i64) -> WatchConfig {
+        self.interval = seconds;
+        self
+    }
+
+    /// How often procfs is scanned for new occurrences
+    pub fn poll(mut self, poll: Duration) -> WatchConfig {
+        self.poll = poll;
+        self
+    }
+
+    /// Stop waiting for occurrences after this many seconds, <= 0 watches
+    /// until the observer stops. Running traces are finished first
+    pub fn duration(mut self, seconds: i64) -> WatchConfig {
+        self.duration = seconds;
+        self
+    }
+
+    /// Stop waiting after this many occurrences, 0 for no limit
+    pub fn count(mut self, count: usize) -> WatchConfig {
+        self.count = count;
+        self
+    }
+
+    /// Ignore the processes already running when the watch starts
+    pub fn skip_running(mut self, skip: bool) -> WatchConfig {
+        self.skip_running = skip;
+        self
+    }
+
+    /// Output formats of every session
+    pub fn formats(mut self, formats: &[OutputFormat]) -> WatchConfig {
+        self.formats = formats.to_vec();
+        self
+    }
+
+    /// Optional metric groups of every session
+    pub fn metrics(mut self, metrics: MetricGroups) -> WatchConfig {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Tags of every session, the occurrence tag is added to them
+    pub fn tags(mut self, tags: Vec<(String, String)>) -> WatchConfig {
+        self.tags = tags;
+        self
+    }
+
+    /// Monitor options of every trace
+    pub fn options(mut self, options: MonitorOptions) -> WatchConfig {
+        self.options = options;
+        self
+    }
+
+    /// Check the configuration and get a watch to run
+    pub fn build(self) -> Result<WatchTrace, TraceError> {
+        if let TraceTarget::Pid(pid) = self.target {
+            return Err(TraceError::InvalidConfig(format!("pid {} can not occur again, watch a name", pid)));
+        }
+        if self.interval <= 0 {
+            return Err(TraceError::InvalidConfig(format!("interval {} is not positive", self.interval)));
+        }
+        if self.formats.is_empty() {
+            return Err(TraceError::InvalidConfig("no output formats".to_string()));
+        }
+        if self.tags.iter().any(|(key, _)| key == OCCURRENCE_TAG) {
+            return Err(TraceError::InvalidConfig(format!("tag {} is set by the watch", OCCURRENCE_TAG)));
+        }
+        Ok(WatchTrace { config: self })
+    }
+}
+
+/// A checked watch
+#[derive(Clone, Debug)]
+pub struct WatchTrace {
+    config: WatchConfig,
+}
+
+impl WatchTrace {
+    /// Wait for occurrences and trace each one until it exits, the observer
+    /// is called after every trace in the order they finish and returning
+    /// false stops the watch. New occurrences are found while others are
+    /// traced, every one in a thread of its own. Once the watch stops the
+    /// running traces are finished, the first error of a trace is returned
+    /// then. Returns the number of occurrences traced
+    pub fn run(&self, observer: &mut impl FnMut(&Occurrence) -> bool) -> Result<usize, TraceError> {
+        let config = &self.config;
+        let mut watcher = OccurrenceWatcher::new(config.target.clone());
+        if config.skip_running {
+            let running = watcher.skip_running();
+            if running > 0 {
+                println!("Skip {} running processes of {}", running, config.target.spec());
+            }
+        }
+        let deadline = (config.duration > 0).then(|| Instant::now() + Duration::from_secs(config.duration as u64));
+        let (sender, finished) = mpsc::channel();
+        // Dropped once the watch stops, the channel closes with the last trace
+        let mut sender = Some(sender);
+        let mut failure = None;
+        let mut traced = 0;
+        loop {
+            let stop = stop_requested()
+                    || (config.count > 0 && traced >= config.count)
+                    || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if stop {
+                sender = None;
+            }
+            if let Some(sender) = &sender {
+                for pid in watcher.poll() {
+                    if config.count > 0 && traced >= config.count {
+                        break;
+                    }
+                    let trace = self.clone();
+                    let sender = sender.clone();
+                    let index = traced + 1;
+                    let spawned = thread::Builder::new().name(format!("watch-{}", pid)).spawn(move || {
+                        let _ = sender.send(trace.trace_occurrence(index, pid));
+                    });
+                    match spawned {
+                        Ok(_) => traced = index,
+                        Err(e) => {
+                            failure.get_or_insert(TraceError::from(e));
+                            break;
+                        }
+                    }
+                }
+            }
+            if failure.is_some() {
+                sender = None;
+            }
+            match finished.recv_timeout(config.poll) {
+                Ok(Ok(occurrence)) => {
+                    if !observer(&occurrence) {
+                        sender = None;
+                    }
+                }
+                Ok(Err(e)) => {
+                    failure.get_or_insert(e);
+                    sender = None;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(traced),
+        }
+    }
+
+    // Trace one process in a new session until it exits
+    fn trace_occurrence(&self, index: usize, pid: pid_t) -> Result<Occurrence, TraceError> {
+        let config = &self.config;
+        let spec = TraceTarget::Pid(pid).spec();
+        let mut state = SessionState::create(&config.output_dir, 0, config.interval, &[spec.as_str()], &config.formats)?;
+        state.metrics = config.metrics;
+        state.tags = config.tags.clone();
+        state.tags.push((OCCURRENCE_TAG.to_string(), index.to_string()));
+        state.save()?;
+        let dir = state.dir.clone();
+        println!("Occurrence {} of {}: pid {}, session {}", index, config.target.spec(), pid, dir);
+        let start = Instant::now();
+        let error = trace_session_with_options(state, config.options).err().map(|e| e.to_string());
+        let occurrence = Occurrence { index, pid, dir, elapsed: start.elapsed().as_secs() as i64, error };
+        let index_path = format!("{}/{}", config.output_dir.trim_end_matches('/'), WATCH_INDEX_FILE);
+        let mut index_file = OpenOptions::new().create(true).append(true).open(&index_path)?;
+        // One write per line, traces finishing together append to the index at once
+        let line = format!("{},{},{},{}\n", now_epoch(), pid, index, occurrence.dir);
+        index_file.write_all(line.as_bytes())?;
+        Ok(occurrence)
+    }
+}